@@ -0,0 +1,40 @@
+// Detect the compiler version to decide which of the std APIs stabilized
+// after the minimum supported version can be used, see src/compat.rs.
+
+use std::env;
+use std::process::Command;
+
+// cfg names and the minor version of the Rust release that stabilized the
+// corresponding API
+const FEATURES: &[(&str, u32)] = &[
+    ("subprocess_div_ceil", 73),
+    ("subprocess_io_error_other", 74),
+    ("subprocess_inspect_err", 76),
+];
+
+fn rustc_minor_version() -> Option<u32> {
+    let rustc = env::var_os("RUSTC")?;
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let version = String::from_utf8(output.stdout).ok()?;
+    // "rustc 1.74.0 (79e9716c9 2023-11-13)"
+    version
+        .split_whitespace()
+        .nth(1)?
+        .split('.')
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+fn main() {
+    let minor = rustc_minor_version();
+    for &(cfg, since) in FEATURES {
+        println!("cargo:rustc-check-cfg=cfg({})", cfg);
+        // if the version is unknown, assume a recent compiler
+        if minor.map_or(true, |minor| minor >= since) {
+            println!("cargo:rustc-cfg={}", cfg);
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
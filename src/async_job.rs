@@ -0,0 +1,204 @@
+// Async interface to running processes.  Processes are started as with
+// the synchronous API, and the blocking operations are kept off the async
+// executor: communication runs on the runtime's blocking thread pool, and
+// waiting is woken by the exit of the process, see async_runtime.rs.
+// With tokio, the child pipes can also be used directly through AsyncRead
+// and AsyncWrite adapters, on which AsyncCommunicator builds a stream of
+// output chunks.
+
+use std::fs::File;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use crate::async_runtime::AsyncRuntime;
+use crate::builder::CaptureData;
+use crate::communicate::{self, OnOverflow};
+use crate::os_common::ExitStatus;
+use crate::popen::{Popen, PopenError, Result as PopenResult};
+
+#[cfg(feature = "async")]
+mod tokio_io;
+
+#[cfg(feature = "async")]
+pub use self::tokio_io::{AsyncCommunicator, AsyncPipeReader, AsyncPipeWriter};
+
+// The runtime of AsyncJob when none is given.  It is tokio regardless of
+// the enabled features, so that enabling `async` in addition to
+// `async-smol` doesn't change the meaning of `AsyncJob`.
+use crate::async_runtime::TokioRuntime as DefaultRuntime;
+
+/// A process or pipeline started with [`Exec::start_async`] or
+/// [`Pipeline::start_async`], waited for and communicated with through
+/// futures.
+///
+/// The futures are driven by the runtime `R`, tokio by default.  With
+/// tokio, they must run inside a tokio runtime with IO and the time
+/// driver enabled, such as the one set up by `#[tokio::main]`.  Jobs
+/// driven by other runtimes, such as smol, are started with
+/// [`Exec::start_async_on`] and [`Pipeline::start_async_on`].
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # #[cfg(feature = "async")]
+/// # async fn dummy() -> Result<()> {
+/// let c = Exec::cmd("sort").stdin("b\na\n").start_async()?.capture().await?;
+/// assert_eq!(c.stdout_str(), "a\nb\n");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// With tokio, the pipes to the processes can also be taken out of the
+/// job with [`take_stdin`], [`take_stdout`], and [`take_stderr`], and
+/// accessed directly with tokio's `AsyncRead` and `AsyncWrite` traits.
+///
+/// Dropping an `AsyncJob` doesn't block.  Processes that are still
+/// running are waited for in the background.
+///
+/// Available with the `async` or the `async-smol` feature.
+///
+/// [`Exec::start_async`]: struct.Exec.html#method.start_async
+/// [`Pipeline::start_async`]: struct.Pipeline.html#method.start_async
+/// [`Exec::start_async_on`]: struct.Exec.html#method.start_async_on
+/// [`Pipeline::start_async_on`]: struct.Pipeline.html#method.start_async_on
+/// [`take_stdin`]: struct.AsyncJob.html#method.take_stdin
+/// [`take_stdout`]: struct.AsyncJob.html#method.take_stdout
+/// [`take_stderr`]: struct.AsyncJob.html#method.take_stderr
+#[derive(Debug)]
+pub struct AsyncJob<R = DefaultRuntime> {
+    stdin: Option<File>,
+    stdout: Option<File>,
+    stderr: Option<File>,
+    stdin_data: Option<Vec<u8>>,
+    max_output: Option<(usize, OnOverflow)>,
+    communicated: bool,
+    overflow: Option<OnOverflow>,
+    processes: Vec<Popen>,
+    runtime: PhantomData<fn() -> R>,
+}
+
+impl<R: AsyncRuntime> AsyncJob<R> {
+    pub(crate) fn new(
+        mut processes: Vec<Popen>,
+        stdin_data: Option<Vec<u8>>,
+        max_output: Option<(usize, OnOverflow)>,
+    ) -> AsyncJob<R> {
+        let last = processes.len() - 1;
+        AsyncJob {
+            stdin: processes[0].stdin.take(),
+            stdout: processes[last].stdout.take(),
+            stderr: processes[last].stderr.take(),
+            stdin_data,
+            max_output,
+            communicated: false,
+            overflow: None,
+            processes,
+            runtime: PhantomData,
+        }
+    }
+
+    /// Return the started processes, in pipeline order.
+    pub fn processes(&self) -> &[Popen] {
+        &self.processes
+    }
+
+    /// Feed the input data to the standard input and collect the
+    /// standard output and error, until the process closes them.
+    ///
+    /// This is the async counterpart of [`Communicator::read`], and
+    /// observes the limit set with `max_output()`.  Standard output or
+    /// error that wasn't redirected to a pipe is returned as `None`.
+    ///
+    /// Streams taken out of the job with [`take_stdin`] and similar
+    /// methods don't take part in the communication.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PopenError::LogicError` if called more than once.
+    ///
+    /// [`Communicator::read`]: struct.Communicator.html#method.read
+    /// [`take_stdin`]: struct.AsyncJob.html#method.take_stdin
+    pub async fn communicate(&mut self) -> PopenResult<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+        if self.communicated {
+            return Err(PopenError::LogicError(
+                "communicate() called more than once",
+            ));
+        }
+        self.communicated = true;
+        let mut comm = communicate::communicate(
+            self.stdin.take(),
+            self.stdout.take(),
+            self.stderr.take(),
+            self.stdin_data.take(),
+        );
+        if let Some((bytes, on_overflow)) = self.max_output {
+            comm = comm.max_output(bytes, on_overflow);
+        }
+        let (result, overflow) = R::spawn_blocking(move || {
+            let result = comm.read();
+            (result, comm.overflow())
+        })
+        .await?;
+        self.overflow = overflow;
+        if let Some(OnOverflow::Kill) | Some(OnOverflow::Error) = overflow {
+            for p in &mut self.processes {
+                p.kill()?;
+            }
+        }
+        Ok(result?)
+    }
+
+    /// Returns the action taken because the output exceeded the limit set
+    /// by `max_output()`, or `None` if it didn't.
+    pub fn overflow(&self) -> Option<OnOverflow> {
+        self.overflow
+    }
+
+    /// Check whether all processes have finished, without blocking.
+    ///
+    /// Returns the exit status of the last process if all have finished,
+    /// and `None` otherwise.
+    pub fn try_wait(&mut self) -> PopenResult<Option<ExitStatus>> {
+        for p in &mut self.processes {
+            if p.wait_timeout(Duration::from_secs(0))?.is_none() {
+                return Ok(None);
+            }
+        }
+        Ok(self.processes.last().and_then(Popen::exit_status))
+    }
+
+    /// Wait for all processes to finish, and return the exit status of
+    /// the last one.
+    ///
+    /// As with `Popen::wait`, a process whose output is piped but not
+    /// read can fill the pipe and never finish, so call
+    /// [`communicate`] first if the output is captured.
+    ///
+    /// [`communicate`]: struct.AsyncJob.html#method.communicate
+    pub async fn wait(&mut self) -> PopenResult<ExitStatus> {
+        for p in &mut self.processes {
+            if p.wait_timeout(Duration::from_secs(0))?.is_none() {
+                R::process_exit(p).await?;
+            }
+        }
+        let last = self.processes.last_mut().unwrap();
+        last.wait()
+    }
+
+    /// Collect the output and wait for all processes to finish.
+    ///
+    /// This is the async counterpart of [`Exec::capture`] and
+    /// [`Pipeline::capture`].
+    ///
+    /// [`Exec::capture`]: struct.Exec.html#method.capture
+    /// [`Pipeline::capture`]: struct.Pipeline.html#method.capture
+    pub async fn capture(mut self) -> PopenResult<CaptureData> {
+        let (out, err) = self.communicate().await?;
+        let exit_status = self.wait().await?;
+        Ok(CaptureData {
+            stdout: out.unwrap_or_else(Vec::new),
+            stderr: err.unwrap_or_else(Vec::new),
+            exit_status,
+            overflow: self.overflow,
+        })
+    }
+}
@@ -0,0 +1,542 @@
+// Tokio adapters for AsyncJob: the child pipes as AsyncRead and
+// AsyncWrite, the stream of output chunks built on them, and
+// Popen::wait_async.
+
+use std::cmp::min;
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use super::AsyncJob;
+use crate::async_runtime::{AsyncRuntime, TokioRuntime};
+use crate::communicate::{OnOverflow, OutputSource};
+use crate::compat::io_error_other;
+use crate::os_common::ExitStatus;
+use crate::popen::{Popen, PopenError, Result as PopenResult};
+
+#[cfg(unix)]
+type PipeReader = tokio::net::unix::pipe::Receiver;
+#[cfg(unix)]
+type PipeWriter = tokio::net::unix::pipe::Sender;
+// Anonymous pipes on Windows don't support overlapped IO, so they are
+// accessed through tokio's blocking thread pool.
+#[cfg(windows)]
+type PipeReader = tokio::fs::File;
+#[cfg(windows)]
+type PipeWriter = tokio::fs::File;
+
+// Default maximum size of the chunks yielded by AsyncCommunicator.
+const CHUNK_SIZE: usize = 8192;
+
+impl AsyncJob<TokioRuntime> {
+    /// Communicate with the processes, yielding their output and error as
+    /// a stream of chunks, in the order they arrive.
+    ///
+    /// This is like [`communicate`], except that the output is not
+    /// collected, but handed over as soon as it is read.  Each item of the
+    /// stream is a chunk of data tagged with the stream it came from.  The
+    /// input data given to `stdin()` is written to the standard input in
+    /// the background, and the stream ends once the standard output and
+    /// error reach end of file.
+    ///
+    /// ```no_run
+    /// # use subprocess::*;
+    /// # use futures_core::Stream;
+    /// # async fn next<S: Stream + Unpin>(s: &mut S) -> Option<S::Item> {
+    /// #     std::future::poll_fn(|cx| std::pin::Pin::new(&mut *s).poll_next(cx)).await
+    /// # }
+    /// # async fn dummy() -> Result<()> {
+    /// let mut job = Exec::cmd("make").stdout(Redirection::Pipe).start_async()?;
+    /// let mut output = job.communicate_stream()?;
+    /// while let Some(chunk) = next(&mut output).await {
+    ///     let (source, data) = chunk?;
+    ///     println!("{:?}: {}", source, String::from_utf8_lossy(&data));
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `PopenError::LogicError` if communication was already
+    /// started.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from within a tokio runtime with IO enabled.
+    ///
+    /// [`communicate`]: struct.AsyncJob.html#method.communicate
+    pub fn communicate_stream(&mut self) -> PopenResult<AsyncCommunicator<'_>> {
+        if self.communicated {
+            return Err(PopenError::LogicError(
+                "communicate_stream() called after communication started",
+            ));
+        }
+        self.communicated = true;
+        let stdin = self
+            .stdin
+            .take()
+            .map(AsyncPipeWriter::from_file)
+            .transpose()?;
+        let stdout = self
+            .stdout
+            .take()
+            .map(AsyncPipeReader::from_file)
+            .transpose()?;
+        let stderr = self
+            .stderr
+            .take()
+            .map(AsyncPipeReader::from_file)
+            .transpose()?;
+        Ok(AsyncCommunicator {
+            input: self.stdin_data.take().unwrap_or_default(),
+            input_pos: 0,
+            stdin,
+            stdout,
+            stderr,
+            next_source: OutputSource::Stdout,
+            buf: vec![],
+            chunk_size: CHUNK_SIZE,
+            time_limit: None,
+            idle_timeout: None,
+            deadline: None,
+            idle_deadline: None,
+            output_len: 0,
+            error: None,
+            job: self,
+        })
+    }
+
+    /// Take the standard input of the first process, for writing from
+    /// async code.
+    ///
+    /// Returns `None` if the standard input isn't redirected to a pipe,
+    /// if it was already taken, or if input data was given to `stdin()`,
+    /// which is fed to the process by [`communicate`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from within a tokio runtime with IO enabled.
+    ///
+    /// [`communicate`]: struct.AsyncJob.html#method.communicate
+    pub fn take_stdin(&mut self) -> io::Result<Option<AsyncPipeWriter>> {
+        if self.stdin_data.is_some() {
+            return Ok(None);
+        }
+        self.stdin
+            .take()
+            .map(AsyncPipeWriter::from_file)
+            .transpose()
+    }
+
+    /// Take the standard output of the last process, for reading from
+    /// async code.
+    ///
+    /// Returns `None` if the standard output isn't redirected to a pipe,
+    /// or if it was already taken.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from within a tokio runtime with IO enabled.
+    pub fn take_stdout(&mut self) -> io::Result<Option<AsyncPipeReader>> {
+        self.stdout
+            .take()
+            .map(AsyncPipeReader::from_file)
+            .transpose()
+    }
+
+    /// Take the standard error of the last process, for reading from
+    /// async code.
+    ///
+    /// Returns `None` if the standard error isn't redirected to a pipe,
+    /// or if it was already taken.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from within a tokio runtime with IO enabled.
+    pub fn take_stderr(&mut self) -> io::Result<Option<AsyncPipeReader>> {
+        self.stderr
+            .take()
+            .map(AsyncPipeReader::from_file)
+            .transpose()
+    }
+}
+
+impl Popen {
+    /// Wait for the process to finish from async code, and return its exit
+    /// status.
+    ///
+    /// This is the async counterpart of [`wait`].  The future doesn't
+    /// check the process periodically, but is woken when it exits: on
+    /// Linux through a pidfd, on Windows by a thread pool wait on the
+    /// process handle, and on other Unix-like systems by `SIGCHLD`.  This
+    /// makes it suitable for waiting for the process alongside other
+    /// events, for example with tokio's `select!`.  Dropping the future
+    /// leaves the process running.
+    ///
+    /// Available with the `async` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from within a tokio runtime with IO enabled.
+    ///
+    /// [`wait`]: struct.Popen.html#method.wait
+    pub async fn wait_async(&mut self) -> PopenResult<ExitStatus> {
+        if let Some(status) = self.wait_timeout(Duration::from_secs(0))? {
+            return Ok(status);
+        }
+        TokioRuntime::process_exit(self).await?;
+        self.wait()
+    }
+}
+
+/// Communication with an [`AsyncJob`] as a stream of output chunks.
+///
+/// Created by [`AsyncJob::communicate_stream`], it implements `Stream`
+/// from the `futures` crate, whose items are chunks of the standard output
+/// or error, each tagged with its source.  Like [`Communicator`], it can
+/// limit the size of the data read at once and the time spent reading.
+/// The output limit set with `max_output()` is observed as well: output
+/// beyond it is not yielded, and the action taken is reported by
+/// [`AsyncJob::overflow`] once the stream is dropped.
+///
+/// Available with the `async` feature.
+///
+/// [`AsyncJob`]: struct.AsyncJob.html
+/// [`AsyncJob::communicate_stream`]: struct.AsyncJob.html#method.communicate_stream
+/// [`AsyncJob::overflow`]: struct.AsyncJob.html#method.overflow
+/// [`Communicator`]: struct.Communicator.html
+#[derive(Debug)]
+pub struct AsyncCommunicator<'a> {
+    job: &'a mut AsyncJob,
+    input: Vec<u8>,
+    input_pos: usize,
+    stdin: Option<AsyncPipeWriter>,
+    stdout: Option<AsyncPipeReader>,
+    stderr: Option<AsyncPipeReader>,
+    // source read first on the next poll, so that neither starves the other
+    next_source: OutputSource,
+    buf: Vec<u8>,
+    chunk_size: usize,
+    time_limit: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    deadline: Option<Pin<Box<Sleep>>>,
+    idle_deadline: Option<Pin<Box<Sleep>>>,
+    output_len: usize,
+    // error to report after the chunk that preceded it
+    error: Option<io::Error>,
+}
+
+impl AsyncCommunicator<'_> {
+    /// Limit the size of the chunks yielded by the stream.
+    ///
+    /// This is the counterpart of [`Communicator::limit_size`], and bounds
+    /// the memory allocated per chunk.  The default is 8 KiB.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    ///
+    /// [`Communicator::limit_size`]: struct.Communicator.html#method.limit_size
+    pub fn limit_size(mut self, size: usize) -> Self {
+        assert!(size != 0, "chunk size must not be zero");
+        self.chunk_size = size;
+        self
+    }
+
+    /// Limit the amount of time spent reading from the processes.
+    ///
+    /// When the limit is exceeded, the stream yields an error of kind
+    /// `io::ErrorKind::TimedOut`.  As with [`Communicator::limit_time`],
+    /// communication can be resumed by polling the stream again, which
+    /// starts a new period of the same length.
+    ///
+    /// [`Communicator::limit_time`]: struct.Communicator.html#method.limit_time
+    pub fn limit_time(mut self, time: Duration) -> Self {
+        self.time_limit = Some(time);
+        self.deadline = None;
+        self
+    }
+
+    /// Limit the amount of time the processes may go without reading input
+    /// or producing output.
+    ///
+    /// When the limit is exceeded, the stream yields an error of kind
+    /// `io::ErrorKind::TimedOut`, as with [`limit_time`].
+    ///
+    /// [`limit_time`]: struct.AsyncCommunicator.html#method.limit_time
+    pub fn idle_timeout(mut self, time: Duration) -> Self {
+        self.idle_timeout = Some(time);
+        self.idle_deadline = None;
+        self
+    }
+
+    // Write as much input as the pipe accepts, and close it when done.
+    // Returns whether any progress was made.
+    fn poll_stdin(&mut self, cx: &mut Context<'_>) -> io::Result<bool> {
+        let stdin = match self.stdin {
+            Some(ref mut stdin) => stdin,
+            None => return Ok(false),
+        };
+        let mut progress = false;
+        while self.input_pos < self.input.len() {
+            match Pin::new(&mut *stdin).poll_write(cx, &self.input[self.input_pos..]) {
+                Poll::Ready(n) => {
+                    self.input_pos += n?;
+                    progress = true;
+                }
+                Poll::Pending => return Ok(progress),
+            }
+        }
+        if let Poll::Ready(result) = Pin::new(stdin).poll_shutdown(cx) {
+            result?;
+            self.stdin = None;
+            progress = true;
+        }
+        Ok(progress)
+    }
+
+    // Read the next chunk from whichever output has data.  Output beyond
+    // max_output() is handled according to its OnOverflow.
+    fn poll_output(&mut self, cx: &mut Context<'_>) -> io::Result<Option<(OutputSource, Bytes)>> {
+        let order = match self.next_source {
+            OutputSource::Stdout => [OutputSource::Stdout, OutputSource::Stderr],
+            OutputSource::Stderr => [OutputSource::Stderr, OutputSource::Stdout],
+        };
+        self.buf.resize(self.chunk_size, 0);
+        for &source in &order {
+            loop {
+                let reader = match source {
+                    OutputSource::Stdout => &mut self.stdout,
+                    OutputSource::Stderr => &mut self.stderr,
+                };
+                let pipe = match reader {
+                    Some(pipe) => pipe,
+                    None => break,
+                };
+                let mut buf = ReadBuf::new(&mut self.buf);
+                match Pin::new(pipe).poll_read(cx, &mut buf) {
+                    Poll::Ready(result) => result?,
+                    Poll::Pending => break,
+                }
+                let nread = buf.filled().len();
+                if nread == 0 {
+                    *reader = None;
+                    break;
+                }
+                self.reset_idle_deadline();
+                let allowed = match (self.job.max_output, self.job.overflow) {
+                    (Some((max, _)), None) => min(nread, max - self.output_len),
+                    (Some(_), Some(_)) => 0,
+                    (None, _) => nread,
+                };
+                if allowed < nread && self.job.overflow.is_none() {
+                    self.overflow()?;
+                }
+                if allowed != 0 {
+                    self.output_len += allowed;
+                    self.next_source = order[1];
+                    return Ok(Some((source, Bytes::copy_from_slice(&self.buf[..allowed]))));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // Called when the output exceeds max_output().
+    fn overflow(&mut self) -> io::Result<()> {
+        let on_overflow = self.job.max_output.unwrap().1;
+        self.job.overflow = Some(on_overflow);
+        if on_overflow == OnOverflow::Truncate {
+            return Ok(());
+        }
+        self.stdin = None;
+        self.stdout = None;
+        self.stderr = None;
+        for p in &mut self.job.processes {
+            p.kill()?;
+        }
+        if on_overflow == OnOverflow::Error {
+            self.error = Some(io_error_other("output limit exceeded"));
+        }
+        Ok(())
+    }
+
+    fn reset_idle_deadline(&mut self) {
+        if let (Some(sleep), Some(idle)) = (&mut self.idle_deadline, self.idle_timeout) {
+            sleep.as_mut().reset(Instant::now() + idle);
+        }
+    }
+
+    // Check the time limits, starting them if needed.  A limit that was
+    // exceeded starts over, so that communication can be resumed.
+    fn poll_timeout(&mut self, cx: &mut Context<'_>) -> bool {
+        let mut timed_out = false;
+        for (slot, limit) in [
+            (&mut self.deadline, self.time_limit),
+            (&mut self.idle_deadline, self.idle_timeout),
+        ] {
+            if let Some(limit) = limit {
+                let sleep = slot.get_or_insert_with(|| Box::pin(tokio::time::sleep(limit)));
+                if sleep.as_mut().poll(cx).is_ready() {
+                    sleep.as_mut().reset(Instant::now() + limit);
+                    timed_out = true;
+                }
+            }
+        }
+        timed_out
+    }
+
+    // Stop communicating after an error other than a timeout.
+    fn fail(&mut self, error: io::Error) -> Poll<Option<io::Result<(OutputSource, Bytes)>>> {
+        self.stdin = None;
+        self.stdout = None;
+        self.stderr = None;
+        Poll::Ready(Some(Err(error)))
+    }
+}
+
+impl Stream for AsyncCommunicator<'_> {
+    type Item = io::Result<(OutputSource, Bytes)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(error) = this.error.take() {
+            return this.fail(error);
+        }
+        if this.poll_timeout(cx) {
+            return Poll::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "communication timed out",
+            ))));
+        }
+        loop {
+            let wrote = match this.poll_stdin(cx) {
+                Ok(wrote) => wrote,
+                Err(e) => return this.fail(e),
+            };
+            if wrote {
+                this.reset_idle_deadline();
+            }
+            match this.poll_output(cx) {
+                Ok(Some(chunk)) => return Poll::Ready(Some(Ok(chunk))),
+                Ok(None) => (),
+                Err(e) => return this.fail(e),
+            }
+            if let Some(error) = this.error.take() {
+                return this.fail(error);
+            }
+            if this.stdin.is_none() && this.stdout.is_none() && this.stderr.is_none() {
+                return Poll::Ready(None);
+            }
+            if !wrote {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// The reading end of a pipe, such as the standard output of a child
+/// process, implementing tokio's `AsyncRead`.
+///
+/// Obtained from [`AsyncJob::take_stdout`] or [`AsyncJob::take_stderr`],
+/// or created from a pipe such as `Popen::stdout` with [`from_file`].
+///
+/// Available with the `async` feature.
+///
+/// [`AsyncJob::take_stdout`]: struct.AsyncJob.html#method.take_stdout
+/// [`AsyncJob::take_stderr`]: struct.AsyncJob.html#method.take_stderr
+/// [`from_file`]: struct.AsyncPipeReader.html#method.from_file
+#[derive(Debug)]
+pub struct AsyncPipeReader(PipeReader);
+
+impl AsyncPipeReader {
+    /// Create an `AsyncPipeReader` from the reading end of a pipe.
+    ///
+    /// On Unix-like systems the pipe is switched to non-blocking mode and
+    /// registered with the tokio reactor, so reading doesn't occupy a
+    /// thread.  On Windows, reads are performed on tokio's blocking
+    /// thread pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from within a tokio runtime with IO enabled.
+    pub fn from_file(file: File) -> io::Result<AsyncPipeReader> {
+        #[cfg(unix)]
+        let pipe = PipeReader::from_file(file)?;
+        #[cfg(windows)]
+        let pipe = PipeReader::from_std(file);
+        Ok(AsyncPipeReader(pipe))
+    }
+}
+
+impl AsyncRead for AsyncPipeReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+/// The writing end of a pipe, such as the standard input of a child
+/// process, implementing tokio's `AsyncWrite`.
+///
+/// Obtained from [`AsyncJob::take_stdin`], or created from a pipe such as
+/// `Popen::stdin` with [`from_file`].  Dropping it closes the pipe, after
+/// which the child reads end of file, but data written on Windows is
+/// only guaranteed to reach the pipe after a flush or a shutdown.
+///
+/// Available with the `async` feature.
+///
+/// [`AsyncJob::take_stdin`]: struct.AsyncJob.html#method.take_stdin
+/// [`from_file`]: struct.AsyncPipeWriter.html#method.from_file
+#[derive(Debug)]
+pub struct AsyncPipeWriter(PipeWriter);
+
+impl AsyncPipeWriter {
+    /// Create an `AsyncPipeWriter` from the writing end of a pipe.
+    ///
+    /// On Unix-like systems the pipe is switched to non-blocking mode and
+    /// registered with the tokio reactor, so writing doesn't occupy a
+    /// thread.  On Windows, writes are performed on tokio's blocking
+    /// thread pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from within a tokio runtime with IO enabled.
+    pub fn from_file(file: File) -> io::Result<AsyncPipeWriter> {
+        #[cfg(unix)]
+        let pipe = PipeWriter::from_file(file)?;
+        #[cfg(windows)]
+        let pipe = PipeWriter::from_std(file);
+        Ok(AsyncPipeWriter(pipe))
+    }
+}
+
+impl AsyncWrite for AsyncPipeWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,289 @@
+// Runtimes that drive AsyncJob.  AsyncJob only needs a runtime to run
+// communication on a thread where blocking is allowed, to sleep, and to
+// learn when a process exits, so those are the operations of the trait.
+// Waiting for the process handle on Windows goes through the system
+// thread pool and works with any runtime.
+
+use std::cmp::min;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+#[cfg(windows)]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(windows)]
+use std::sync::{Arc, Mutex};
+#[cfg(windows)]
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::popen::{Popen, Result as PopenResult};
+
+const MAX_POLL_DELAY: Duration = Duration::from_millis(50);
+
+/// Async runtime that drives the futures of an [`AsyncJob`].
+///
+/// Implementations are provided for tokio by [`TokioRuntime`], with the
+/// `async` feature, and for smol and async-std by [`SmolRuntime`], with
+/// the `async-smol` feature.  Other runtimes can be supported by
+/// implementing the trait.
+///
+/// [`AsyncJob`]: struct.AsyncJob.html
+/// [`TokioRuntime`]: struct.TokioRuntime.html
+/// [`SmolRuntime`]: struct.SmolRuntime.html
+pub trait AsyncRuntime: 'static {
+    /// Run `f` on a thread where blocking is allowed, and resolve to its
+    /// result.
+    ///
+    /// A panic in `f` is propagated to the caller.  An error is returned if
+    /// the runtime can't run `f` to completion, e.g. because it is
+    /// shutting down.
+    fn spawn_blocking<T, F>(f: F) -> Pin<Box<dyn Future<Output = io::Result<T>> + Send>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static;
+
+    /// Resolve once `duration` has passed.
+    fn sleep(duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+    /// Resolve once the process has exited.
+    ///
+    /// The process may be reaped in the process, making its exit status
+    /// available through `Popen::exit_status()`.  The default
+    /// implementation checks the process with increasing delays between
+    /// checks, like `Popen::wait_timeout()`.
+    fn process_exit(p: &mut Popen) -> Pin<Box<dyn Future<Output = PopenResult<()>> + Send + '_>> {
+        Box::pin(poll_exit::<Self>(p))
+    }
+}
+
+async fn poll_exit<R: AsyncRuntime + ?Sized>(p: &mut Popen) -> PopenResult<()> {
+    let mut delay = Duration::from_millis(1);
+    while p.wait_timeout(Duration::from_secs(0))?.is_none() {
+        R::sleep(delay).await;
+        delay = min(delay * 2, MAX_POLL_DELAY);
+    }
+    Ok(())
+}
+
+/// The tokio runtime.
+///
+/// The futures must run inside a tokio runtime with IO and the time
+/// driver enabled, such as the one set up by `#[tokio::main]`.  Process
+/// exit is detected through a pidfd on Linux, a thread pool wait on the
+/// process handle on Windows, and `SIGCHLD` on other systems.
+///
+/// This is the runtime used by [`Exec::start_async`] and
+/// [`Pipeline::start_async`], and implements [`AsyncRuntime`] with the
+/// `async` feature.
+///
+/// [`Exec::start_async`]: struct.Exec.html#method.start_async
+/// [`Pipeline::start_async`]: struct.Pipeline.html#method.start_async
+/// [`AsyncRuntime`]: trait.AsyncRuntime.html
+//
+// Defined without the `async` feature as well, because it is the default
+// runtime of AsyncJob.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TokioRuntime;
+
+#[cfg(feature = "async")]
+impl AsyncRuntime for TokioRuntime {
+    fn spawn_blocking<T, F>(f: F) -> Pin<Box<dyn Future<Output = io::Result<T>> + Send>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let task = tokio::task::spawn_blocking(f);
+        Box::pin(async move {
+            match task.await {
+                Ok(result) => Ok(result),
+                Err(e) => match e.try_into_panic() {
+                    Ok(payload) => std::panic::resume_unwind(payload),
+                    Err(e) => Err(crate::compat::io_error_other(e)),
+                },
+            }
+        })
+    }
+
+    fn sleep(duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn process_exit(p: &mut Popen) -> Pin<Box<dyn Future<Output = PopenResult<()>> + Send + '_>> {
+        #[cfg(unix)]
+        return Box::pin(tokio_process_exit(p));
+        #[cfg(windows)]
+        return Box::pin(handle_exit(p));
+    }
+}
+
+#[cfg(all(feature = "async", unix))]
+async fn tokio_process_exit(p: &mut Popen) -> PopenResult<()> {
+    use crate::compat::io_error_other;
+    use crate::popen::PopenError;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    #[cfg(all(
+        target_os = "linux",
+        not(any(target_arch = "mips", target_arch = "mips64"))
+    ))]
+    {
+        use tokio::io::unix::AsyncFd;
+        use tokio::io::Interest;
+
+        if let Some(pidfd) = open_pidfd(p)? {
+            let pidfd = AsyncFd::with_interest(pidfd, Interest::READABLE)?;
+            let _ = pidfd.readable().await?;
+            return Ok(());
+        }
+    }
+    // subscribe before checking, so that an exit in between isn't missed
+    let mut sigchld = signal(SignalKind::child())?;
+    while p.wait_timeout(Duration::from_secs(0))?.is_none() {
+        if sigchld.recv().await.is_none() {
+            return Err(PopenError::IoError(io_error_other(
+                "tokio signal driver shut down",
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The runtime of smol and async-std.
+///
+/// Blocking operations run on the thread pool of the `blocking` crate,
+/// and timers and IO readiness are handled by the `async-io` reactor,
+/// which both smol and async-std are built on, so the futures can run on
+/// either runtime, or under `async_io::block_on`.  Process exit is
+/// detected through a pidfd on Linux, a thread pool wait on the process
+/// handle on Windows, and by checking the process with increasing delays
+/// on other systems.
+///
+/// Available with the `async-smol` feature.
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # async fn dummy() -> Result<()> {
+/// let c = Exec::cmd("sort")
+///     .stdin("b\na\n")
+///     .start_async_on::<SmolRuntime>()?
+///     .capture()
+///     .await?;
+/// assert_eq!(c.stdout_str(), "a\nb\n");
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "async-smol")]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SmolRuntime;
+
+#[cfg(feature = "async-smol")]
+impl AsyncRuntime for SmolRuntime {
+    fn spawn_blocking<T, F>(f: F) -> Pin<Box<dyn Future<Output = io::Result<T>> + Send>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let task = blocking::unblock(f);
+        Box::pin(async move { Ok(task.await) })
+    }
+
+    fn sleep(duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async move {
+            async_io::Timer::after(duration).await;
+        })
+    }
+
+    fn process_exit(p: &mut Popen) -> Pin<Box<dyn Future<Output = PopenResult<()>> + Send + '_>> {
+        #[cfg(unix)]
+        return Box::pin(async move {
+            #[cfg(all(
+                target_os = "linux",
+                not(any(target_arch = "mips", target_arch = "mips64"))
+            ))]
+            {
+                if let Some(pidfd) = open_pidfd(p)? {
+                    async_io::Async::new(pidfd)?.readable().await?;
+                    return Ok(());
+                }
+            }
+            poll_exit::<Self>(p).await
+        });
+        #[cfg(windows)]
+        return Box::pin(handle_exit(p));
+    }
+}
+
+// Open a pidfd for the running process `p`, or return None if it has
+// finished or the kernel doesn't support pidfds.
+#[cfg(all(
+    unix,
+    target_os = "linux",
+    not(any(target_arch = "mips", target_arch = "mips64"))
+))]
+fn open_pidfd(p: &Popen) -> io::Result<Option<std::fs::File>> {
+    if let Some(pidfd) = p.pidfd() {
+        return Ok(Some(pidfd.try_clone()?));
+    }
+    let pid = match p.pid() {
+        Some(pid) => pid,
+        None => return Ok(None),
+    };
+    match crate::posix::pidfd_open(pid) {
+        Ok(pidfd) => Ok(Some(pidfd)),
+        // pidfd_open() requires Linux 5.3
+        Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(windows)]
+async fn handle_exit(p: &mut Popen) -> PopenResult<()> {
+    let handle = match p.process_handle() {
+        Some(handle) => handle,
+        None => return Ok(()),
+    };
+    let state = Arc::new(ExitState::default());
+    let context = Arc::as_ptr(&state) as *mut std::ffi::c_void;
+    let wait = unsafe { crate::win32::RegisterWaitForSingleObject(handle, on_exit, context)? };
+    ProcessExit { _wait: wait, state }.await;
+    Ok(())
+}
+
+// State shared with the thread pool callback that reports the exit.
+#[cfg(windows)]
+#[derive(Debug, Default)]
+struct ExitState {
+    exited: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn on_exit(context: *mut std::ffi::c_void, _timed_out: u8) {
+    let state = &*(context as *const ExitState);
+    state.exited.store(true, Ordering::SeqCst);
+    let waker = state.waker.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+#[cfg(windows)]
+struct ProcessExit {
+    // dropped first, so the callback is done with the state before it goes
+    _wait: crate::win32::RegisteredWait,
+    state: Arc<ExitState>,
+}
+
+#[cfg(windows)]
+impl Future for ProcessExit {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        *self.state.waker.lock().unwrap_or_else(|e| e.into_inner()) = Some(cx.waker().clone());
+        if self.state.exited.load(Ordering::SeqCst) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
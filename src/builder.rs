@@ -1,36 +1,322 @@
 #[cfg(unix)]
 mod os {
+    use std::env;
+    use std::ffi::OsString;
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::{Path, PathBuf};
+
     pub const NULL_DEVICE: &str = "/dev/null";
+    #[cfg(not(feature = "no-shell"))]
     pub const SHELL: [&str; 2] = ["sh", "-c"];
+
+    #[cfg(not(feature = "no-shell"))]
+    pub fn shell_program() -> OsString {
+        if cfg!(feature = "no-path-search") {
+            // the shell can't be looked up in PATH, so run it from its
+            // standard location
+            OsString::from("/bin/sh")
+        } else {
+            OsString::from(SHELL[0])
+        }
+    }
+
+    #[cfg(not(feature = "no-path-search"))]
+    pub fn script_interpreter(_script: &Path) -> Option<Vec<OsString>> {
+        // The kernel handles shebang lines, so scripts are executed directly.
+        None
+    }
+
+    pub fn current_exe() -> io::Result<PathBuf> {
+        let exe = env::current_exe()?;
+        // An executable replaced or removed since it was started, e.g. by a
+        // package upgrade, can still be run through /proc.
+        if cfg!(target_os = "linux") && !exe.exists() {
+            return Ok(PathBuf::from("/proc/self/exe"));
+        }
+        Ok(exe)
+    }
+
+    pub fn anonymous_file(temp_path: &Path) -> io::Result<File> {
+        #[cfg(target_os = "linux")]
+        {
+            if let Ok(file) = crate::posix::memfd_create("subprocess-stdin") {
+                return Ok(file);
+            }
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(temp_path)?;
+        fs::remove_file(temp_path)?;
+        Ok(file)
+    }
+
+    // Create a directory accessible only to the current user, like
+    // mkdtemp() does.
+    pub fn create_private_dir(path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::DirBuilderExt;
+        fs::DirBuilder::new().mode(0o700).create(path)
+    }
+
+    // Kill the process along with the processes it started in its process
+    // group, falling back to the process alone if it has none of its own.
+    pub fn kill_tree(p: &mut crate::Popen) -> io::Result<()> {
+        use crate::unix::PopenExt;
+        p.send_signal_group(crate::posix::SIGKILL)
+            .or_else(|_| p.kill())
+    }
+
+    // Find the program in the PATH set in the child's environment, or in
+    // ours if it sets none, the way Popen::create would.
+    pub fn find_in_path(
+        program: &std::ffi::OsStr,
+        child_env: Option<&[(OsString, OsString)]>,
+    ) -> Option<PathBuf> {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::PermissionsExt;
+        if cfg!(feature = "no-path-search") || program.as_bytes().contains(&b'/') {
+            return None;
+        }
+        let child_path = child_env.and_then(|env| env.iter().rev().find(|(k, _)| k == "PATH"));
+        let path = match child_path {
+            Some((_, path)) => Some(path.clone()),
+            None => env::var_os("PATH"),
+        };
+        let path = path.filter(|path| !path.is_empty())?;
+        env::split_paths(&path)
+            .map(|dir| dir.join(program))
+            .find(|candidate| {
+                fs::metadata(candidate)
+                    .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                    .unwrap_or(false)
+            })
+    }
+
+    // Describe an open file given as a redirection.
+    pub fn describe_file(file: &File) -> String {
+        use std::os::unix::io::AsRawFd;
+        format!("open file (fd {})", file.as_raw_fd())
+    }
 }
 
 #[cfg(windows)]
 mod os {
+    use std::env;
+    use std::ffi::OsString;
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    #[cfg(not(feature = "no-path-search"))]
+    use std::io::Read;
+    #[cfg(not(feature = "no-path-search"))]
+    use std::iter;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::path::{Path, PathBuf};
+
+    use winapi::um::winbase::FILE_FLAG_DELETE_ON_CLOSE;
+    use winapi::um::winnt::FILE_ATTRIBUTE_TEMPORARY;
+
     pub const NULL_DEVICE: &str = "nul";
+    #[cfg(not(feature = "no-shell"))]
     pub const SHELL: [&str; 2] = ["cmd.exe", "/c"];
+
+    #[cfg(not(feature = "no-shell"))]
+    pub fn shell_program() -> OsString {
+        #[cfg(feature = "no-path-search")]
+        {
+            // the shell can't be looked up in PATH, so run it from the
+            // system directory
+            if let Ok(dir) = crate::win32::GetSystemDirectory() {
+                return dir.join(SHELL[0]).into_os_string();
+            }
+        }
+        OsString::from(SHELL[0])
+    }
+
+    // Interpreters for script types that CreateProcess can't start itself.
+    #[cfg(not(feature = "no-path-search"))]
+    const INTERPRETERS: &[(&str, &[&str])] = &[
+        ("bat", &["cmd.exe", "/c"]),
+        ("cmd", &["cmd.exe", "/c"]),
+        (
+            "ps1",
+            &[
+                "powershell.exe",
+                "-NoProfile",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-File",
+            ],
+        ),
+        ("py", &["python"]),
+        ("sh", &["sh"]),
+        ("pl", &["perl"]),
+        ("rb", &["ruby"]),
+        ("js", &["node"]),
+    ];
+
+    #[cfg(not(feature = "no-path-search"))]
+    pub fn script_interpreter(script: &Path) -> Option<Vec<OsString>> {
+        if let Some(interpreter) = read_shebang(script) {
+            return Some(interpreter);
+        }
+        let ext = script.extension()?.to_str()?.to_ascii_lowercase();
+        INTERPRETERS
+            .iter()
+            .find(|&&(known_ext, _)| known_ext == ext)
+            .map(|&(_, cmd)| cmd.iter().map(OsString::from).collect())
+    }
+
+    pub fn current_exe() -> io::Result<PathBuf> {
+        // Windows doesn't allow removing a running executable, so the path
+        // obtained from GetModuleFileNameW remains valid.
+        env::current_exe()
+    }
+
+    pub fn anonymous_file(temp_path: &Path) -> io::Result<File> {
+        // The file is removed once the last handle to it, including the
+        // one inherited by the child, is closed.
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .attributes(FILE_ATTRIBUTE_TEMPORARY)
+            .custom_flags(FILE_FLAG_DELETE_ON_CLOSE)
+            .open(temp_path)
+    }
+
+    // The new directory inherits the permissions of the temporary
+    // directory, which is private to the user.
+    pub fn create_private_dir(path: &Path) -> io::Result<()> {
+        std::fs::create_dir(path)
+    }
+
+    // Kill the process along with the processes it started.
+    pub fn kill_tree(p: &mut crate::Popen) -> io::Result<()> {
+        p.kill_tree()
+    }
+
+    // Find the program in the PATH set in the child's environment, or in
+    // ours if it sets none, the way Popen::create would.
+    pub fn find_in_path(
+        program: &std::ffi::OsStr,
+        child_env: Option<&[(OsString, OsString)]>,
+    ) -> Option<PathBuf> {
+        if cfg!(feature = "no-path-search") {
+            return None;
+        }
+        let child_path = child_env.and_then(|env| {
+            env.iter()
+                .rev()
+                .find(|(k, _)| k.eq_ignore_ascii_case("PATH"))
+        });
+        let path = match child_path {
+            Some((_, path)) => path.clone(),
+            None => env::var_os("PATH")?,
+        };
+        env::split_paths(&path)
+            .map(|dir| {
+                // CreateProcess appends .exe only to a name without an
+                // extension.
+                let mut candidate = dir.join(program);
+                if candidate.extension().is_none() {
+                    candidate.set_extension(env::consts::EXE_EXTENSION);
+                }
+                candidate
+            })
+            .find(|candidate| std::fs::metadata(candidate).is_ok())
+    }
+
+    pub fn describe_file(file: &File) -> String {
+        use std::os::windows::io::AsRawHandle;
+        format!("open file (handle {:?})", file.as_raw_handle())
+    }
+
+    // Longest shebang line that is looked at, like the buffer of the
+    // Linux kernel.
+    #[cfg(not(feature = "no-path-search"))]
+    const MAX_SHEBANG: u64 = 256;
+
+    #[cfg(not(feature = "no-path-search"))]
+    fn read_shebang(script: &Path) -> Option<Vec<OsString>> {
+        // only read the start of the file, which can be a large binary
+        let mut head = vec![];
+        File::open(script)
+            .ok()?
+            .take(MAX_SHEBANG)
+            .read_to_end(&mut head)
+            .ok()?;
+        let line = match head.iter().position(|&b| b == b'\n') {
+            Some(end) => &head[..end],
+            None => &head[..],
+        };
+        let line = std::str::from_utf8(line).ok()?;
+        let mut words = line.strip_prefix("#!")?.split_whitespace();
+        // Unix paths like /usr/bin/python3 are meaningless on Windows, so
+        // the interpreter is looked up by name in PATH instead.
+        let mut interpreter = Path::new(words.next()?).file_name()?.to_str()?;
+        let mut args: Vec<&str> = words.collect();
+        if interpreter == "env" {
+            // #!/usr/bin/env [-S] interpreter [args...]
+            if args.first() == Some(&"-S") {
+                args.remove(0);
+            }
+            if args.is_empty() {
+                return None;
+            }
+            interpreter = args.remove(0);
+        }
+        Some(
+            iter::once(interpreter)
+                .chain(args)
+                .map(OsString::from)
+                .collect(),
+        )
+    }
 }
 
-pub use self::exec::{CaptureData, Exec, NullFile};
-pub use self::os::*;
-pub use self::pipeline::Pipeline;
+pub use self::exec::{
+    Capture, CaptureData, CommunicateBuilder, Exec, NullFile, ReadErrAdapter, ReadOutAdapter,
+    SpawnPlan, StreamOutcome, Transcript,
+};
+pub use self::pipeline::{OnStartFailure, Pipeline};
 
 #[cfg(unix)]
 pub use exec::unix;
+#[cfg(windows)]
+pub use exec::windows;
 
 mod exec {
-    use std::borrow::Cow;
+    use std::borrow::{Borrow, Cow};
     use std::collections::HashMap;
     use std::env;
     use std::ffi::{OsStr, OsString};
     use std::fmt;
-    use std::fs::{File, OpenOptions};
+    use std::fs::{self, File, OpenOptions};
+    use std::hash::Hash;
     use std::io::{self, Read, Write};
+    use std::iter;
+    use std::mem;
     use std::ops::BitOr;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
 
-    use crate::communicate::Communicator;
+    #[cfg(any(feature = "async", feature = "async-smol"))]
+    use crate::async_job::AsyncJob;
+    #[cfg(any(feature = "async", feature = "async-smol"))]
+    use crate::async_runtime::AsyncRuntime;
+    use crate::communicate::{CommunicateError, Communicator, OnOverflow, OnTimeout, OutputSource};
     use crate::os_common::ExitStatus;
-    use crate::popen::{Popen, PopenConfig, Redirection, Result as PopenResult};
+    use crate::popen::{
+        env_delta, CancelToken, Popen, PopenConfig, PopenError, Priority, Redirection,
+        Result as PopenResult,
+    };
 
     use super::os::*;
     use super::Pipeline;
@@ -60,6 +346,7 @@ mod exec {
     /// ```no_run
     /// # use subprocess::*;
     /// # fn dummy() -> Result<()> {
+    /// # #[cfg(not(feature = "no-shell"))]
     /// Exec::shell("shutdown -h now").join()?;
     /// # Ok(())
     /// # }
@@ -121,15 +408,28 @@ mod exec {
     ///
     /// [`Popen`]: struct.Popen.html
     /// [`Popen::create`]: struct.Popen.html#method.create
-
     #[must_use]
     pub struct Exec {
         command: OsString,
         args: Vec<OsString>,
         config: PopenConfig,
         stdin_data: Option<Vec<u8>>,
+        stdin_lines: Option<Box<dyn Iterator<Item = String> + Send>>,
+        inherit_stdin: bool,
+        expand_env: bool,
+        create_cwd: bool,
+        temp_cwd: bool,
+        keep_temp_cwd: bool,
+        resolve_program_in_cwd: Option<bool>,
+        max_output: Option<(usize, OnOverflow)>,
+        shell: bool,
+        stdin_file_threshold: usize,
+        fallback: Option<Box<Exec>>,
     }
 
+    // Time given to a process to exit after join_timeout_kill() asks it to.
+    const JOIN_TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+
     impl Exec {
         /// Constructs a new `Exec`, configured to run `command`.
         ///
@@ -147,6 +447,17 @@ mod exec {
                 args: vec![],
                 config: PopenConfig::default(),
                 stdin_data: None,
+                stdin_lines: None,
+                inherit_stdin: false,
+                expand_env: false,
+                create_cwd: false,
+                temp_cwd: false,
+                keep_temp_cwd: false,
+                resolve_program_in_cwd: None,
+                max_output: None,
+                shell: false,
+                stdin_file_threshold: DEFAULT_STDIN_FILE_THRESHOLD,
+                fallback: None,
             }
         }
 
@@ -157,7 +468,9 @@ mod exec {
         /// request.  This command requests the shell to be used; on
         /// Unix-like systems, this is equivalent to
         /// `Exec::cmd("sh").arg("-c").arg(cmdstr)`.  On Windows, it
-        /// runs `Exec::cmd("cmd.exe").arg("/c")`.
+        /// runs `Exec::cmd("cmd.exe").arg("/c")`.  With the
+        /// `no-path-search` feature, the shell is run as `/bin/sh` and
+        /// from the Windows system directory, respectively.
         ///
         /// `shell` is useful for porting code that uses the C
         /// `system` function, which also spawns a shell.
@@ -168,8 +481,95 @@ mod exec {
         /// prone to errors and, if `filename` comes from an untrusted
         /// source, to shell injection attacks.  Instead, use
         /// `Exec::cmd("sort").arg(filename)`.
+        ///
+        /// If a guard has been installed with [`set_shell_guard`], the
+        /// command string is checked against it when the process is
+        /// started.  Use [`ShellCommand`] to build command strings from
+        /// safely quoted arguments.
+        ///
+        /// Not available with the `no-shell` feature, which guarantees at
+        /// build time that the program doesn't run commands through the
+        /// shell.
+        ///
+        /// [`set_shell_guard`]: fn.set_shell_guard.html
+        /// [`ShellCommand`]: struct.ShellCommand.html
+        #[cfg(not(feature = "no-shell"))]
         pub fn shell(cmdstr: impl AsRef<OsStr>) -> Exec {
-            Exec::cmd(SHELL[0]).args(&SHELL[1..]).arg(cmdstr)
+            let mut exec = Exec::cmd(shell_program()).args(&SHELL[1..]).arg(cmdstr);
+            exec.shell = true;
+            exec
+        }
+
+        /// Constructs a new `Exec`, configured to run the script at `path`.
+        ///
+        /// On Unix-like systems this is equivalent to `Exec::cmd(path)`, the
+        /// script being executed directly and its interpreter chosen by the
+        /// `#!` line.  Windows doesn't support `#!` lines, so there the
+        /// script is inspected, and its interpreter is run with the script
+        /// path as argument.  The interpreter is taken from the `#!` line if
+        /// present, using the interpreter's file name to look it up in
+        /// `PATH`, and otherwise from the file extension, e.g. `cmd.exe /c`
+        /// for `.bat` and `.cmd` files, `powershell.exe` for `.ps1`, or
+        /// `python` for `.py`.  If neither identifies an interpreter, the
+        /// script is run directly.
+        ///
+        /// This allows running the same script file on all platforms, for
+        /// example in task runners.
+        ///
+        /// Not available with the `no-path-search` feature, as the
+        /// interpreters are looked up in `PATH`.
+        #[cfg(not(feature = "no-path-search"))]
+        pub fn script(path: impl AsRef<Path>) -> Exec {
+            let path = path.as_ref();
+            match script_interpreter(path) {
+                Some(interpreter) => Exec::cmd(&interpreter[0]).args(&interpreter[1..]).arg(path),
+                None => Exec::cmd(path),
+            }
+        }
+
+        /// Constructs a new `Exec`, configured to run the currently running
+        /// executable.
+        ///
+        /// This supports programs that restart themselves, for example with
+        /// a changed configuration or environment.  The executable is
+        /// located using `std::env::current_exe()` and made absolute, so it
+        /// is unaffected by `cwd()`.  On Linux, an executable that has been
+        /// replaced or removed since the current process started is run
+        /// through `/proc/self/exe`.
+        ///
+        /// As with `cmd()`, the command starts with no arguments and
+        /// inherits the environment.  Use [`current_args`] to pass on the
+        /// arguments of the current process, and the `env` methods to
+        /// modify the environment.
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let status = Exec::current_exe()?
+        ///     .current_args()
+        ///     .env("MYAPP_RESPAWNED", "1")
+        ///     .join()?;
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// [`current_args`]: struct.Exec.html#method.current_args
+        pub fn current_exe() -> PopenResult<Exec> {
+            let mut exe = current_exe()?;
+            if exe.is_relative() {
+                exe = env::current_dir()?.join(exe);
+            }
+            Ok(Exec::cmd(exe))
+        }
+
+        /// Appends the arguments of the current process, excluding the
+        /// program name, to the argument list.
+        ///
+        /// The arguments are taken from `std::env::args_os()`.  To modify
+        /// them, pass them to `args()` instead, for example
+        /// `.args(env::args_os().skip(1).filter(|arg| arg != "--daemon"))`.
+        pub fn current_args(self) -> Exec {
+            self.args(env::args_os().skip(1))
         }
 
         /// Appends `arg` to argument list.
@@ -179,8 +579,12 @@ mod exec {
         }
 
         /// Extends the argument list with `args`.
-        pub fn args(mut self, args: &[impl AsRef<OsStr>]) -> Exec {
-            self.args.extend(args.iter().map(|x| x.as_ref().to_owned()));
+        ///
+        /// `args` can be any iterable of string-like values, such as a
+        /// slice, an array, a vector, or an iterator.
+        pub fn args(mut self, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Exec {
+            self.args
+                .extend(args.into_iter().map(|x| x.as_ref().to_owned()));
             self
         }
 
@@ -194,6 +598,148 @@ mod exec {
             self
         }
 
+        /// Limits the total amount of output and error captured by
+        /// `capture()` and `communicate()` to `bytes`, and specifies what to
+        /// do when the process produces more.
+        ///
+        /// This protects the caller from running out of memory when a
+        /// misbehaving process produces unbounded output.  With
+        /// `OnOverflow::Truncate` the output beyond the limit is discarded,
+        /// with `OnOverflow::Kill` the process is killed, and with
+        /// `OnOverflow::Error` it is killed and an error is returned.  The
+        /// action taken is reported in the [`overflow`] field of
+        /// `CaptureData`, or by [`Communicator::overflow`].
+        ///
+        /// [`overflow`]: struct.CaptureData.html#structfield.overflow
+        /// [`Communicator::overflow`]: struct.Communicator.html#method.overflow
+        pub fn max_output(mut self, bytes: usize, on_overflow: OnOverflow) -> Exec {
+            self.max_output = Some((bytes, on_overflow));
+            self
+        }
+
+        /// Sets the size above which input data given to [`stdin`] is
+        /// passed to the process through a temporary file rather than a
+        /// pipe.
+        ///
+        /// Feeding data through a pipe requires keeping all of it in memory
+        /// until the process has read it, along with a background thread or
+        /// a `Communicator` to write it.  Data larger than the threshold is
+        /// instead written to an anonymous temporary file, which is
+        /// redirected to the standard input, and the data is released
+        /// before the process is started.  On Linux the file is created
+        /// with `memfd_create()`, elsewhere in the temporary directory, from
+        /// which it is removed right away on Unix-like systems and once it
+        /// is closed on Windows.
+        ///
+        /// The default threshold is 64 MiB.  Pass `usize::MAX` to always
+        /// use a pipe.
+        ///
+        /// [`stdin`]: struct.Exec.html#method.stdin
+        pub fn stdin_file_threshold(mut self, bytes: usize) -> Exec {
+            self.stdin_file_threshold = bytes;
+            self
+        }
+
+        /// Specifies that the process is waited for in the background when
+        /// the object that owns it goes out of scope while it is running.
+        ///
+        /// See [`Popen::wait_in_background`] for details.
+        ///
+        /// [`Popen::wait_in_background`]: struct.Popen.html#method.wait_in_background
+        pub fn wait_in_background(mut self) -> Exec {
+            self.config.wait_in_background = true;
+            self
+        }
+
+        /// Specifies that the process is killed when the object that owns
+        /// it goes out of scope while it is running, instead of being
+        /// waited for.
+        ///
+        /// See [`Popen::kill_on_drop`] for details.
+        ///
+        /// [`Popen::kill_on_drop`]: struct.Popen.html#method.kill_on_drop
+        pub fn kill_on_drop(mut self) -> Exec {
+            self.config.kill_on_drop = true;
+            self
+        }
+
+        /// Specifies a command to run instead if the program can't be found.
+        ///
+        /// If starting the process fails because the program doesn't
+        /// exist, the program and arguments of `other` are tried instead,
+        /// such as `python` when `python3` is missing.  Everything else,
+        /// including the redirections, environment, and working directory,
+        /// is taken from this `Exec`, and the other settings of `other` are
+        /// ignored.  Calling `fallback` more than once, or passing an
+        /// `Exec` that has fallbacks of its own, builds a chain of
+        /// alternatives tried in order.  Other errors, such as the program
+        /// existing but not being executable, are reported right away.
+        ///
+        /// The program that was started is available from
+        /// [`Popen::program`].
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let p = Exec::cmd("python3")
+        ///     .arg("script.py")
+        ///     .fallback(Exec::cmd("python").arg("script.py"))
+        ///     .popen()?;
+        /// println!("started {:?}", p.program());
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// [`Popen::program`]: struct.Popen.html#method.program
+        pub fn fallback(mut self, other: Exec) -> Exec {
+            let mut last = &mut self.fallback;
+            while let Some(fallback) = last {
+                last = &mut fallback.fallback;
+            }
+            *last = Some(Box::new(other));
+            self
+        }
+
+        /// Specifies that the process is killed when the current process
+        /// exits, including when it crashes or is killed.
+        ///
+        /// On Linux the kernel sends `SIGKILL` to the process when its
+        /// parent dies, as requested with `PR_SET_PDEATHSIG`, and on
+        /// Windows the process is placed in a job object that kills it
+        /// once the current process is gone.  On other Unix systems, such
+        /// as macOS and the BSDs, a watcher process kills it with `SIGKILL`
+        /// when the current process exits.
+        ///
+        /// See [`PopenConfig::die_with_parent`] for details.
+        ///
+        /// [`PopenConfig::die_with_parent`]: struct.PopenConfig.html#structfield.die_with_parent
+        pub fn die_with_parent(mut self) -> Exec {
+            self.config.die_with_parent = true;
+            self
+        }
+
+        /// Specifies that the process is killed when the current process
+        /// exits.
+        ///
+        /// This is the old name of [`die_with_parent`].
+        ///
+        /// [`die_with_parent`]: struct.Exec.html#method.die_with_parent
+        #[deprecated(note = "use `die_with_parent` instead")]
+        pub fn kill_on_parent_exit(self) -> Exec {
+            self.die_with_parent()
+        }
+
+        /// Specifies the scheduling priority of the process, such as
+        /// `Priority::Idle` for CPU-heavy background work.
+        ///
+        /// See [`PopenConfig::priority`] for details.
+        ///
+        /// [`PopenConfig::priority`]: struct.PopenConfig.html#structfield.priority
+        pub fn priority(mut self, priority: Priority) -> Exec {
+            self.config.priority = Some(priority);
+            self
+        }
+
         fn ensure_env(&mut self) {
             if self.config.env.is_none() {
                 self.config.env = Some(PopenConfig::current_env());
@@ -240,7 +786,7 @@ mod exec {
             self.ensure_env();
             {
                 let envvec = self.config.env.as_mut().unwrap();
-                for &(ref k, ref v) in vars {
+                for (k, v) in vars {
                     envvec.push((k.as_ref().to_owned(), v.as_ref().to_owned()));
                 }
             }
@@ -256,19 +802,247 @@ mod exec {
                 .env
                 .as_mut()
                 .unwrap()
-                .retain(|&(ref k, ref _v)| k != key.as_ref());
+                .retain(|(k, _v)| k != key.as_ref());
+            self
+        }
+
+        // Give the command the environment `base`, with the variables the
+        // command itself set or removed, compared to the `inherited`
+        // environment, applied on top.
+        pub(super) fn overlay_env(
+            &mut self,
+            base: &[(OsString, OsString)],
+            inherited: &[(OsString, OsString)],
+        ) {
+            self.config.env = Some(self.overlaid_env(base, inherited));
+        }
+
+        // The environment set by overlay_env().
+        pub(super) fn overlaid_env(
+            &self,
+            base: &[(OsString, OsString)],
+            inherited: &[(OsString, OsString)],
+        ) -> Vec<(OsString, OsString)> {
+            fn lookup<'a>(vars: &'a [(OsString, OsString)], key: &OsStr) -> Option<&'a OsString> {
+                let key = env_key(key);
+                vars.iter()
+                    .rev()
+                    .find(|(k, _)| env_key(k) == key)
+                    .map(|(_, v)| v)
+            }
+            let mut env = base.to_vec();
+            if let Some(ref own) = self.config.env {
+                env.retain(|(k, _)| lookup(inherited, k).is_none() || lookup(own, k).is_some());
+                for (k, _) in own {
+                    let value = lookup(own, k).unwrap();
+                    if lookup(inherited, k) != Some(value) {
+                        env.push((k.clone(), value.clone()));
+                    }
+                }
+            }
+            env
+        }
+
+        /// Enables expansion of environment variable references in the
+        /// arguments.
+        ///
+        /// When enabled, references of the form `$VAR` and `${VAR}` (and
+        /// additionally `%VAR%` on Windows) found in the arguments are
+        /// replaced by the values the variables have in the environment of
+        /// the child process.  That is the environment configured using
+        /// [`env`] and related methods, or the environment inherited from
+        /// the current process if none was configured.  References to
+        /// undefined variables expand to the empty string, and a `$` or `%`
+        /// not followed by a valid variable name is left as-is.  A literal
+        /// `$` (or `%`) can be written as `$$` (or `%%`).
+        ///
+        /// The expansion is performed just before the process is started.
+        /// Unlike [`Exec::shell`], it doesn't involve the shell, so the
+        /// expanded values are passed to the child exactly as they are,
+        /// without word splitting, globbing or further interpretation.
+        /// Arguments that are not valid Unicode are passed unchanged.
+        ///
+        /// [`env`]: struct.Exec.html#method.env
+        /// [`Exec::shell`]: struct.Exec.html#method.shell
+        pub fn expand_env(mut self, expand: bool) -> Exec {
+            self.expand_env = expand;
+            self
+        }
+
+        // The environment the child process will see, keyed by env_key().
+        fn effective_env(&self) -> HashMap<OsString, OsString> {
+            match self.config.env {
+                // later entries override earlier ones, as in the child
+                Some(ref env) => env.iter().map(|(k, v)| (env_key(k), v.clone())).collect(),
+                None => env::vars_os().map(|(k, v)| (env_key(&k), v)).collect(),
+            }
+        }
+
+        fn expand_args(&mut self) {
+            let env = self.effective_env();
+            let lookup = |name: &str| env.get(&env_key(OsStr::new(name))).cloned();
+            for arg in &mut self.args {
+                *arg = expand_env_refs(arg, lookup);
+            }
+        }
+
+        /// Replaces `{name}` placeholders in the command and arguments with
+        /// values from `map`.
+        ///
+        /// Each placeholder is replaced by the value stored in `map` under
+        /// `name`, which can be any `OsStr`-like value, including paths
+        /// that are not valid Unicode.  The command and arguments don't
+        /// need to be valid Unicode either.  As no shell is involved, the
+        /// value always ends up inside the argument that contained the
+        /// placeholder, regardless of spaces or other special characters
+        /// it might contain.  Placeholders not found in the map are left
+        /// unchanged, and `{{` and `}}` produce literal braces.
+        ///
+        /// The replacement is performed immediately, so only the command
+        /// and arguments specified before the call are affected.  Note
+        /// that using placeholders in the string passed to [`Exec::shell`]
+        /// reintroduces the risk of shell injection, as the substituted
+        /// value is interpreted by the shell.
+        ///
+        /// ```
+        /// # use subprocess::*;
+        /// # use std::collections::HashMap;
+        /// let mut map = HashMap::new();
+        /// map.insert("input", "my photo.png");
+        /// map.insert("output", "thumb.png");
+        /// let exec = Exec::cmd("convert")
+        ///     .args(&["{input}", "-resize", "64x64", "{output}"])
+        ///     .substitute(&map);
+        /// assert_eq!(exec.to_cmdline_lossy(), "convert 'my photo.png' -resize 64x64 thumb.png");
+        /// ```
+        ///
+        /// [`Exec::shell`]: struct.Exec.html#method.shell
+        pub fn substitute<K, V>(mut self, map: &HashMap<K, V>) -> Exec
+        where
+            K: Borrow<str> + Hash + Eq,
+            V: AsRef<OsStr>,
+        {
+            let lookup = |name: &str| map.get(name).map(|v| v.as_ref().to_owned());
+            self.command = substitute_placeholders(&self.command, lookup);
+            for arg in &mut self.args {
+                *arg = substitute_placeholders(arg, lookup);
+            }
             self
         }
 
         /// Specifies the current working directory of the child process.
         ///
         /// If unspecified, the current working directory is inherited
-        /// from the parent.
+        /// from the parent.  If the directory doesn't exist, starting the
+        /// process fails with [`PopenError::CwdNotFound`].
+        ///
+        /// [`PopenError::CwdNotFound`]: enum.PopenError.html#variant.CwdNotFound
         pub fn cwd(mut self, dir: impl AsRef<Path>) -> Exec {
             self.config.cwd = Some(dir.as_ref().as_os_str().to_owned());
+            self.create_cwd = false;
+            self.temp_cwd = false;
+            self
+        }
+
+        /// Specifies the current working directory of the child process,
+        /// creating it if it doesn't exist.
+        ///
+        /// This is like [`cwd`], except the directory is created, along
+        /// with any missing parents, just before the process is started.
+        /// An existing directory is used as-is.  If the directory cannot be
+        /// created, starting the process fails with an IO error that names
+        /// the directory.
+        ///
+        /// [`cwd`]: struct.Exec.html#method.cwd
+        pub fn cwd_create(mut self, dir: impl AsRef<Path>) -> Exec {
+            self.config.cwd = Some(dir.as_ref().as_os_str().to_owned());
+            self.create_cwd = true;
+            self.temp_cwd = false;
+            self
+        }
+
+        /// Runs the child process in a new temporary directory.
+        ///
+        /// A uniquely named directory is created in the system temporary
+        /// directory just before the process is started, and removed with
+        /// its contents once the process has been waited for, e.g. by
+        /// [`join`] or [`capture`], or when its `Popen` is dropped.  To
+        /// keep the directory of a failed process for inspection, use
+        /// [`keep_temp_cwd_on_failure`].  The directory of a running
+        /// process is available from [`Popen::temp_cwd`].
+        ///
+        /// A detached process is not waited for, so its directory is left
+        /// behind.
+        ///
+        /// This overrides the directory specified with [`cwd`].
+        ///
+        /// [`join`]: struct.Exec.html#method.join
+        /// [`capture`]: struct.Exec.html#method.capture
+        /// [`cwd`]: struct.Exec.html#method.cwd
+        /// [`keep_temp_cwd_on_failure`]: struct.Exec.html#method.keep_temp_cwd_on_failure
+        /// [`Popen::temp_cwd`]: struct.Popen.html#method.temp_cwd
+        pub fn temp_cwd(mut self) -> Exec {
+            self.config.cwd = None;
+            self.create_cwd = false;
+            self.temp_cwd = true;
+            self
+        }
+
+        /// Keeps the temporary working directory created by [`temp_cwd`]
+        /// when the process doesn't exit successfully, so that its contents
+        /// can be inspected.
+        ///
+        /// The directory of a successful process is removed regardless.
+        /// Without `temp_cwd`, this has no effect.
+        ///
+        /// [`temp_cwd`]: struct.Exec.html#method.temp_cwd
+        pub fn keep_temp_cwd_on_failure(mut self) -> Exec {
+            self.keep_temp_cwd = true;
+            self
+        }
+
+        /// Specifies how a relative program path is resolved when [`cwd`] is
+        /// used.
+        ///
+        /// If `in_cwd` is true, a program such as `./run.sh` or
+        /// `bin/tool` is looked up relative to the working directory
+        /// specified with `cwd`.  If false, it is looked up relative to the
+        /// current directory of the parent process.  Bare program names
+        /// without a directory component are unaffected and are always
+        /// searched for in `PATH`, or rejected with the `no-path-search`
+        /// feature.
+        ///
+        /// If this method is not called, the resolution is left to the
+        /// operating system, which on Unix-like systems resolves the path
+        /// relative to the new working directory and on Windows relative
+        /// to the parent's.
+        ///
+        /// [`cwd`]: struct.Exec.html#method.cwd
+        pub fn resolve_program_in_cwd(mut self, in_cwd: bool) -> Exec {
+            self.resolve_program_in_cwd = Some(in_cwd);
             self
         }
 
+        fn resolve_program(&mut self, in_cwd: bool) -> io::Result<()> {
+            let program = Path::new(&self.command);
+            let has_dir = program
+                .parent()
+                .map(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(false);
+            let cwd = match self.config.cwd {
+                Some(ref cwd) if has_dir && program.is_relative() => Path::new(cwd),
+                _ => return Ok(()),
+            };
+            let base = if in_cwd {
+                env::current_dir()?.join(cwd)
+            } else {
+                env::current_dir()?
+            };
+            // argv[0] remains as specified by the caller.
+            self.config.executable = Some(base.join(program).into_os_string());
+            Ok(())
+        }
+
         /// Specifies how to set up the standard input of the child process.
         ///
         /// Argument can be:
@@ -276,14 +1050,23 @@ mod exec {
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
         /// * a `Vec<u8>` or `&str`, which will set up a `Redirection::Pipe`
-        ///   for stdin, making sure that `capture` feeds that data into the
-        ///   standard input of the subprocess;
+        ///   for stdin, and feed that data into the standard input of the
+        ///   subprocess, closing it afterwards.  `capture` and `communicate`
+        ///   feed the data along with reading the output, while the other
+        ///   methods that start the process feed it from a background
+        ///   thread.  Data larger than [`stdin_file_threshold`] is passed
+        ///   through a temporary file instead.  Input data cannot be combined
+        ///   with `stream_stdin`;
         /// * [`NullFile`], which will redirect the standard input to read from
-        ///    `/dev/null`.
+        ///   `/dev/null`.
         ///
         /// [`Redirection`]: enum.Redirection.html
         /// [`NullFile`]: struct.NullFile.html
+        /// [`stdin_file_threshold`]: struct.Exec.html#method.stdin_file_threshold
         pub fn stdin(mut self, stdin: impl Into<InputRedirection>) -> Exec {
+            if self.inherit_stdin {
+                panic!("stdin is already set");
+            }
             match (&self.config.stdin, stdin.into()) {
                 (&Redirection::None, InputRedirection::AsRedirection(new)) => {
                     self.config.stdin = new
@@ -298,14 +1081,60 @@ mod exec {
             self
         }
 
+        /// Feeds `lines` to the standard input of the child process, each
+        /// followed by a newline.
+        ///
+        /// This sets up `Redirection::Pipe` for stdin.  The lines are
+        /// written from a background thread and taken from `lines` only as
+        /// the child reads them, so a long or lazily produced sequence is
+        /// never held in memory at once.  Standard input is closed after
+        /// the last line.  Together with [`capture_lines`], this runs a
+        /// filter over a list of items:
+        ///
+        /// ```
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let names = vec!["b".to_string(), "c".to_string(), "a".to_string()];
+        /// let sorted = Exec::cmd("sort").stdin_lines(names).capture_lines()?;
+        /// assert_eq!(sorted, ["a", "b", "c"]);
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// As the lines can be consumed only once, an `Exec` with pending
+        /// lines cannot be cloned, and cloning it panics.
+        ///
+        /// # Panics
+        ///
+        /// Panics if standard input is already set.
+        ///
+        /// [`capture_lines`]: struct.Exec.html#method.capture_lines
+        pub fn stdin_lines<I>(mut self, lines: I) -> Exec
+        where
+            I: IntoIterator<Item = String>,
+            I::IntoIter: Send + 'static,
+        {
+            if self.inherit_stdin || !matches!(self.config.stdin, Redirection::None) {
+                panic!("stdin is already set");
+            }
+            self.config.stdin = Redirection::Pipe;
+            self.stdin_lines = Some(Box::new(lines.into_iter()));
+            self
+        }
+
         /// Specifies how to set up the standard output of the child process.
         ///
         /// Argument can be:
         ///
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
+        /// * a `Path` or `PathBuf`, which is a shorthand for
+        ///   `Redirection::Path(path)`, and appends to the file unless
+        ///   disabled with [`append`];
         /// * [`NullFile`], which will redirect the standard output to go to
-        ///    `/dev/null`.
+        ///   `/dev/null`.
+        ///
+        /// [`append`]: struct.Exec.html#method.append
         ///
         /// [`Redirection`]: enum.Redirection.html
         /// [`NullFile`]: struct.NullFile.html
@@ -324,8 +1153,13 @@ mod exec {
         ///
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
+        /// * a `Path` or `PathBuf`, which is a shorthand for
+        ///   `Redirection::Path(path)`, and appends to the file unless
+        ///   disabled with [`append`];
         /// * [`NullFile`], which will redirect the standard error to go to
-        ///    `/dev/null`.
+        ///   `/dev/null`.
+        ///
+        /// [`append`]: struct.Exec.html#method.append
         ///
         /// [`Redirection`]: enum.Redirection.html
         /// [`NullFile`]: struct.NullFile.html
@@ -338,67 +1172,572 @@ mod exec {
             self
         }
 
-        fn check_no_stdin_data(&self, meth: &str) {
-            if self.stdin_data.is_some() {
-                panic!("{} called with input data specified", meth);
-            }
+        /// Specifies whether output redirected to a path is appended to the
+        /// file.
+        ///
+        /// Appending is the default, so that several commands can log to
+        /// the same file without overwriting each other's output.  If
+        /// `append` is false, the file is truncated instead.  Equivalent to
+        /// setting [`PopenConfig::append`].
+        ///
+        /// [`PopenConfig::append`]: struct.PopenConfig.html#structfield.append
+        pub fn append(mut self, append: bool) -> Exec {
+            self.config.append = append;
+            self
         }
 
-        // Terminators
-
-        /// Starts the process, returning a `Popen` for the running process.
-        pub fn popen(mut self) -> PopenResult<Popen> {
-            self.check_no_stdin_data("popen");
-            self.args.insert(0, self.command);
-            let p = Popen::create(&self.args, self.config)?;
-            Ok(p)
+        /// Specifies whether output redirected to a path can be reopened
+        /// with `Popen::reopen_output` while the process runs.
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let p = Exec::cmd("server")
+        ///     .stdout(std::path::Path::new("server.log"))
+        ///     .reopenable_output(true)
+        ///     .popen()?;
+        /// // ...
+        /// std::fs::rename("server.log", "server.log.1")?;
+        /// p.reopen_output("server.log")?;
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// Equivalent to setting [`PopenConfig::reopenable_output`].
+        ///
+        /// [`PopenConfig::reopenable_output`]: struct.PopenConfig.html#structfield.reopenable_output
+        pub fn reopenable_output(mut self, reopenable: bool) -> Exec {
+            self.config.reopenable_output = reopenable;
+            self
         }
 
-        /// Starts the process, waits for it to finish, and returns
-        /// the exit status.
+        /// Redirects the standard output of the child process to a file
+        /// shared with other redirections.
         ///
-        /// This method will wait for as long as necessary for the process to
-        /// finish.  If a timeout is needed, use
-        /// `<...>.detached().popen()?.wait_timeout(...)` instead.
-        pub fn join(self) -> PopenResult<ExitStatus> {
-            self.check_no_stdin_data("join");
-            self.popen()?.wait()
+        /// Equivalent to `stdout(Redirection::SharedFile(file))`.  This is
+        /// useful for having several commands, possibly started from
+        /// different threads, write to a log file that was opened only
+        /// once.  For the outputs not to overwrite each other, the file
+        /// should be opened in append mode.
+        pub fn stdout_shared(self, file: Arc<File>) -> Exec {
+            self.stdout(Redirection::SharedFile(file))
         }
 
-        /// Starts the process and returns a value implementing the `Read`
-        /// trait that reads from the standard output of the child process.
+        /// Redirects the standard error of the child process to a file
+        /// shared with other redirections.
         ///
-        /// This will automatically set up
-        /// `stdout(Redirection::Pipe)`, so it is not necessary to do
-        /// that beforehand.
+        /// Equivalent to `stderr(Redirection::SharedFile(file))`.  See
+        /// [`stdout_shared`] for details.
         ///
-        /// When the trait object is dropped, it will wait for the
-        /// process to finish.  If this is undesirable, use
-        /// `detached()`.
-        pub fn stream_stdout(self) -> PopenResult<impl Read> {
-            self.check_no_stdin_data("stream_stdout");
-            let p = self.stdout(Redirection::Pipe).popen()?;
-            Ok(ReadOutAdapter(p))
+        /// [`stdout_shared`]: struct.Exec.html#method.stdout_shared
+        pub fn stderr_shared(self, file: Arc<File>) -> Exec {
+            self.stderr(Redirection::SharedFile(file))
         }
 
-        /// Starts the process and returns a value implementing the `Read`
-        /// trait that reads from the standard error of the child process.
-        ///
-        /// This will automatically set up
-        /// `stderr(Redirection::Pipe)`, so it is not necessary to do
-        /// that beforehand.
+        /// Redirects the standard input of the child process to read from
+        /// the null device.
         ///
-        /// When the trait object is dropped, it will wait for the
-        /// process to finish.  If this is undesirable, use
-        /// `detached()`.
-        pub fn stream_stderr(self) -> PopenResult<impl Read> {
-            self.check_no_stdin_data("stream_stderr");
-            let p = self.stderr(Redirection::Pipe).popen()?;
-            Ok(ReadErrAdapter(p))
+        /// Equivalent to `stdin(NullFile)`.
+        pub fn stdin_null(self) -> Exec {
+            self.stdin(NullFile)
         }
 
-        /// Starts the process and returns a value implementing the `Write`
-        /// trait that writes to the standard input of the child process.
+        /// Lets the child process read the standard input of the parent,
+        /// typically the terminal, while its output can still be captured.
+        ///
+        /// This is the default if standard input isn't redirected, but
+        /// makes the intent explicit: [`capture`] and [`communicate`]
+        /// leave the inherited standard input alone, and input data can't
+        /// be given afterwards, either through [`stdin`] or through
+        /// [`CommunicateBuilder::input`].  Like
+        /// [`Pipeline::stdin_inherit`], this replaces standard input set
+        /// earlier, discarding previously given input data.
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # use std::time::Duration;
+        /// # fn dummy() -> Result<()> {
+        /// // the user answers the prompts, the output is collected
+        /// let mut comm = Exec::cmd("./configure")
+        ///     .stdin_inherit()
+        ///     .stdout(Redirection::Pipe)
+        ///     .stderr(Redirection::Pipe)
+        ///     .communicate_with()
+        ///     .limit_time(Duration::from_secs(600))
+        ///     .start()?;
+        /// let (out, err) = comm.read()?;
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// [`capture`]: struct.Exec.html#method.capture
+        /// [`communicate`]: struct.Exec.html#method.communicate
+        /// [`stdin`]: struct.Exec.html#method.stdin
+        /// [`CommunicateBuilder::input`]: struct.CommunicateBuilder.html#method.input
+        /// [`Pipeline::stdin_inherit`]: struct.Pipeline.html#method.stdin_inherit
+        pub fn stdin_inherit(mut self) -> Exec {
+            self.config.stdin = Redirection::None;
+            self.stdin_data = None;
+            self.stdin_lines = None;
+            self.inherit_stdin = true;
+            self
+        }
+
+        /// Runs the child process under a pseudo-terminal.
+        ///
+        /// Connects the standard input, output, and error of the child to a
+        /// newly allocated pty, as described at [`Redirection::Pty`].  Many
+        /// programs, such as `ssh`, `python`, or `top`, only behave
+        /// interactively when attached to a terminal.  The master side of
+        /// the terminal is available as `Popen::stdin` for writing and
+        /// `Popen::stdout` for reading, and `capture()` collects everything
+        /// the child writes to the terminal as standard output.
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let out = Exec::cmd("tty").pty().capture()?.stdout_str();
+        /// assert!(out.starts_with("/dev/"));
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// Only supported on Unix-like systems.
+        ///
+        /// # Panics
+        ///
+        /// Panics if standard input is already set.
+        ///
+        /// [`Redirection::Pty`]: enum.Redirection.html#variant.Pty
+        pub fn pty(self) -> Exec {
+            self.stdin(Redirection::Pty)
+                .stdout(Redirection::Pty)
+                .stderr(Redirection::Pty)
+        }
+
+        /// Redirects the standard output of the child process to the null
+        /// device.
+        ///
+        /// Equivalent to `stdout(NullFile)`.  The output discarded this way
+        /// is not captured by [`capture`], which will return empty
+        /// standard output.
+        ///
+        /// [`capture`]: struct.Exec.html#method.capture
+        pub fn stdout_null(self) -> Exec {
+            self.stdout(NullFile)
+        }
+
+        /// Redirects the standard error of the child process to the null
+        /// device.
+        ///
+        /// Equivalent to `stderr(NullFile)`.
+        pub fn stderr_null(self) -> Exec {
+            self.stderr(NullFile)
+        }
+
+        // Take the input data to feed to the process, first moving it to a
+        // file redirected to stdin if it exceeds the threshold.
+        fn take_stdin_data(&mut self) -> io::Result<Option<Vec<u8>>> {
+            match self.stdin_data.take() {
+                Some(data) if data.len() > self.stdin_file_threshold => {
+                    self.config.stdin = Redirection::File(stdin_file(data)?);
+                    Ok(None)
+                }
+                data => Ok(data),
+            }
+        }
+
+        fn check_no_stdin_data(&self, meth: &str) {
+            if self.stdin_data.is_some() || self.stdin_lines.is_some() {
+                panic!("{} called with input data specified", meth);
+            }
+        }
+
+        // Terminators
+
+        /// Starts the process, returning a `Popen` for the running process.
+        ///
+        /// If input data was specified with [`stdin`], it is written to the
+        /// subprocess by a background thread, which closes the standard
+        /// input when done, so `Popen::stdin` is `None`.
+        ///
+        /// [`stdin`]: struct.Exec.html#method.stdin
+        pub fn popen(mut self) -> PopenResult<Popen> {
+            let stdin_data = self.take_stdin_data()?;
+            let stdin_lines = self.stdin_lines.take();
+            if let (true, Some(cwd)) = (self.create_cwd, &self.config.cwd) {
+                fs::create_dir_all(cwd).map_err(|e| {
+                    // mention the directory, so it isn't mistaken for a failure
+                    // to execute the program
+                    io::Error::new(
+                        e.kind(),
+                        format!(
+                            "cannot create working directory {}: {}",
+                            Path::new(cwd).display(),
+                            e
+                        ),
+                    )
+                })?;
+            }
+            let temp_cwd = if self.temp_cwd {
+                let path = make_temp_cwd()?;
+                self.config.cwd = Some(path.clone().into_os_string());
+                Some((path, self.keep_temp_cwd))
+            } else {
+                None
+            };
+            let mut p = match self.create_with_fallback() {
+                Ok(p) => p,
+                Err(e) => {
+                    if let Some((path, _)) = temp_cwd {
+                        fs::remove_dir_all(path).ok();
+                    }
+                    return Err(e);
+                }
+            };
+            if let Some((path, keep_on_failure)) = temp_cwd {
+                p.set_temp_cwd(path, keep_on_failure);
+            }
+            if let Some(data) = stdin_data {
+                feed_stdin(p.stdin.take(), data);
+            }
+            if let Some(lines) = stdin_lines {
+                feed_stdin_lines(p.stdin.take(), lines);
+            }
+            Ok(p)
+        }
+
+        // Start the program, moving on to the fallbacks while the program
+        // isn't found.
+        fn create_with_fallback(mut self) -> PopenResult<Popen> {
+            let executable = self.config.executable.clone();
+            loop {
+                if self.expand_env {
+                    self.expand_args();
+                }
+                #[cfg(not(feature = "no-shell"))]
+                {
+                    if self.shell {
+                        crate::shell::check_shell_command(&self.args[SHELL.len() - 1])?;
+                    }
+                }
+                if let Some(in_cwd) = self.resolve_program_in_cwd {
+                    self.resolve_program(in_cwd)?;
+                }
+                let fallback = match self.fallback.take() {
+                    Some(fallback) => fallback,
+                    None => {
+                        self.args.insert(0, self.command);
+                        return Popen::create(&self.args, self.config);
+                    }
+                };
+                let mut argv = mem::take(&mut self.args);
+                argv.insert(0, mem::replace(&mut self.command, fallback.command));
+                match Popen::create(&argv, self.config.try_clone()?) {
+                    Err(PopenError::IoError(ref e)) if is_missing_program(e) => {}
+                    result => return result,
+                }
+                self.args = fallback.args;
+                self.shell = fallback.shell;
+                self.fallback = fallback.fallback;
+                self.config.executable = executable.clone();
+            }
+        }
+
+        /// Starts `n` independent processes from the same command.
+        ///
+        /// Each process gets its own pipes for the streams redirected to
+        /// `Redirection::Pipe`, and its own copy of the data passed to
+        /// `stdin()`.  Streams redirected to a file, including
+        /// `NullFile`, are shared by all the processes.
+        ///
+        /// If starting one of the processes fails, those started before it
+        /// are killed and waited for, and the error is returned.  Use
+        /// [`Popen::wait_all`] and [`Popen::terminate_all`] to wait for
+        /// or terminate the returned processes together.
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let mut workers = Exec::cmd("worker").stdout(NullFile).popen_n(4)?;
+        /// let statuses = Popen::wait_all(&mut workers)?;
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// [`Popen::wait_all`]: struct.Popen.html#method.wait_all
+        /// [`Popen::terminate_all`]: struct.Popen.html#method.terminate_all
+        pub fn popen_n(self, n: usize) -> PopenResult<Vec<Popen>> {
+            let mut processes: Vec<Popen> = Vec::with_capacity(n);
+            for _ in 0..n {
+                match self
+                    .try_clone()
+                    .map_err(PopenError::from)
+                    .and_then(Exec::popen)
+                {
+                    Ok(p) => processes.push(p),
+                    Err(e) => {
+                        for p in &mut processes {
+                            let _ = p.kill();
+                            let _ = p.wait();
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(processes)
+        }
+
+        /// Starts the process, waits for it to finish, and returns
+        /// the exit status.
+        ///
+        /// This method will wait for as long as necessary for the process to
+        /// finish.  If a timeout is needed, use [`join_timeout`] instead.
+        ///
+        /// [`join_timeout`]: struct.Exec.html#method.join_timeout
+        pub fn join(self) -> PopenResult<ExitStatus> {
+            self.popen()?.wait()
+        }
+
+        /// Starts the process, waits for it to finish, and returns the exit
+        /// status.
+        ///
+        /// This is the same as [`join`], under the name used by
+        /// `std::process::Command`.  Standard streams that aren't
+        /// redirected are inherited from the current process.
+        ///
+        /// [`join`]: struct.Exec.html#method.join
+        pub fn status(self) -> PopenResult<ExitStatus> {
+            self.join()
+        }
+
+        /// Starts the process fully dissociated from the current process,
+        /// and returns its process ID.
+        ///
+        /// This is meant for launching programs that should outlive the
+        /// current process, such as daemons or a browser opened by a
+        /// command-line tool.  The process is started in a new session on
+        /// Unix, and with `DETACHED_PROCESS` and `CREATE_NEW_PROCESS_GROUP`
+        /// on Windows, so that it has no controlling terminal or console
+        /// and doesn't receive the signals or console events meant for the
+        /// current process.  Standard streams that aren't redirected are
+        /// redirected to the null device, so that the process doesn't hold
+        /// on to the terminal or to pipes inherited from the current
+        /// process, which would keep their readers waiting for the end of
+        /// output.  No pipe or handle to the process is kept; on Unix it is
+        /// reaped in the background once it exits, as with
+        /// [`wait_in_background`].
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let pid = Exec::cmd("xdg-open").arg("https://example.com").spawn_detached()?;
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// # Errors
+        ///
+        /// Returns `PopenError::LogicError` if a standard stream is
+        /// redirected to a pipe or a pseudo-terminal, or reopenable output
+        /// is requested, as those require a connection to the current
+        /// process.
+        ///
+        /// # Panics
+        ///
+        /// Panics if input data was specified with [`stdin`].
+        ///
+        /// [`wait_in_background`]: struct.Exec.html#method.wait_in_background
+        /// [`stdin`]: struct.Exec.html#method.stdin
+        pub fn spawn_detached(mut self) -> PopenResult<u32> {
+            self.check_no_stdin_data("spawn_detached");
+            let connected = [&self.config.stdin, &self.config.stdout, &self.config.stderr]
+                .iter()
+                .any(|r| matches!(r, Redirection::Pipe | Redirection::Pty));
+            if connected || self.config.reopenable_output {
+                return Err(PopenError::LogicError(
+                    "spawn_detached requires streams not connected to the parent",
+                ));
+            }
+            if let (Redirection::None, false) = (&self.config.stdin, self.inherit_stdin) {
+                self = self.stdin(NullFile);
+            }
+            if let Redirection::None = self.config.stdout {
+                self = self.stdout(NullFile);
+            }
+            if let Redirection::None = self.config.stderr {
+                self = self.stderr(NullFile);
+            }
+            #[cfg(unix)]
+            {
+                self.config.setsid = true;
+                self.config.wait_in_background = true;
+            }
+            #[cfg(windows)]
+            {
+                self.config.creation_flags |=
+                    crate::win32::DETACHED_PROCESS | crate::win32::CREATE_NEW_PROCESS_GROUP;
+                self.config.detached = true;
+            }
+            let p = self.popen()?;
+            Ok(p.pid().unwrap())
+        }
+
+        /// Starts the process and waits for it to finish, but for no longer
+        /// than `timeout`.
+        ///
+        /// Returns the exit status if the process finishes in time, and
+        /// `None` otherwise.  A process still running after the timeout is
+        /// left running and waited for in the background, as with
+        /// [`wait_in_background`].  To kill it instead, use
+        /// [`join_timeout_kill`].
+        ///
+        /// [`wait_in_background`]: struct.Exec.html#method.wait_in_background
+        /// [`join_timeout_kill`]: struct.Exec.html#method.join_timeout_kill
+        pub fn join_timeout(self, timeout: Duration) -> PopenResult<Option<ExitStatus>> {
+            let mut p = self.popen()?;
+            let status = p.wait_timeout(timeout)?;
+            if status.is_none() {
+                p.wait_in_background();
+            }
+            Ok(status)
+        }
+
+        /// Like [`join_timeout`], but a process still running after the
+        /// timeout is shut down and waited for before returning `None`.
+        ///
+        /// The process is shut down as with [`Popen::shutdown`]: on Unix it
+        /// is sent the signal set with [`ExecExt::terminate_signal`],
+        /// `SIGTERM` by default, and killed if it is still running a second
+        /// later.
+        ///
+        /// [`join_timeout`]: struct.Exec.html#method.join_timeout
+        /// [`Popen::shutdown`]: struct.Popen.html#method.shutdown
+        /// [`ExecExt::terminate_signal`]: unix/trait.ExecExt.html#tymethod.terminate_signal
+        pub fn join_timeout_kill(self, timeout: Duration) -> PopenResult<Option<ExitStatus>> {
+            let mut p = self.popen()?;
+            let status = p.wait_timeout(timeout)?;
+            if status.is_none() {
+                p.shutdown(JOIN_TIMEOUT_GRACE)?;
+            }
+            Ok(status)
+        }
+
+        /// Starts the process and waits for it to finish, unless cancelled
+        /// through `token`.
+        ///
+        /// This behaves like [`join`], except that it returns
+        /// `Err(PopenError::Cancelled)` once [`CancelToken::cancel`] is
+        /// called on `token` or one of its clones, typically from another
+        /// thread.  The process is then left running and waited for in the
+        /// background, as with [`wait_in_background`], unless
+        /// [`kill_on_drop`] was requested, in which case it is killed.
+        ///
+        /// See [`Popen::wait_cancellable`] for details.
+        ///
+        /// [`join`]: struct.Exec.html#method.join
+        /// [`CancelToken::cancel`]: struct.CancelToken.html#method.cancel
+        /// [`wait_in_background`]: struct.Exec.html#method.wait_in_background
+        /// [`kill_on_drop`]: struct.Exec.html#method.kill_on_drop
+        /// [`Popen::wait_cancellable`]: struct.Popen.html#method.wait_cancellable
+        pub fn join_cancellable(self, token: &CancelToken) -> PopenResult<ExitStatus> {
+            let mut p = self.popen()?;
+            let result = p.wait_cancellable(token);
+            if result.is_err() {
+                p.wait_in_background();
+            }
+            result
+        }
+
+        /// Starts the process and returns a value implementing the `Read`
+        /// trait that reads from the standard output of the child process.
+        ///
+        /// This will automatically set up
+        /// `stdout(Redirection::Pipe)`, so it is not necessary to do
+        /// that beforehand.
+        ///
+        /// When the returned reader is dropped, it will wait for the
+        /// process to finish.  If this is undesirable, use
+        /// `detached()`.  To find out whether the process succeeded, call
+        /// [`ReadOutAdapter::finish`] after reading.
+        ///
+        /// [`ReadOutAdapter::finish`]: struct.ReadOutAdapter.html#method.finish
+        pub fn stream_stdout(self) -> PopenResult<ReadOutAdapter> {
+            let p = self.stdout(Redirection::Pipe).popen()?;
+            Ok(ReadOutAdapter(p))
+        }
+
+        /// Starts the process and returns a value implementing the `Read`
+        /// trait that reads from the standard output of the child process,
+        /// along with a [`StreamOutcome`] that reports how the process
+        /// ended.
+        ///
+        /// This works like [`stream_stdout`], but also collects the
+        /// standard error in a background thread, unless it was explicitly
+        /// redirected elsewhere.  Once the reader reaches the end of the
+        /// output, it waits for the process to finish, and the exit status
+        /// and the collected standard error become available through the
+        /// returned `StreamOutcome`.  The same happens if the reader is
+        /// dropped before reaching the end.
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # use std::io::Read;
+        /// # fn dummy() -> Result<()> {
+        /// let (mut reader, outcome) = Exec::cmd("ls").arg("missing").stream_stdout_with_err()?;
+        /// let mut listing = String::new();
+        /// reader.read_to_string(&mut listing)?;
+        /// if !outcome.exit_status().unwrap().success() {
+        ///     eprintln!("ls failed: {}", outcome.stderr_str().unwrap());
+        /// }
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// [`StreamOutcome`]: struct.StreamOutcome.html
+        /// [`stream_stdout`]: struct.Exec.html#method.stream_stdout
+        pub fn stream_stdout_with_err(mut self) -> PopenResult<(impl Read, StreamOutcome)> {
+            if let Redirection::None = self.config.stderr {
+                self = self.stderr(Redirection::Pipe);
+            }
+            let mut p = self.stdout(Redirection::Pipe).popen()?;
+            let stderr = p.stderr.take().map(|mut stderr| {
+                thread::spawn(move || {
+                    let mut data = vec![];
+                    let _ = stderr.read_to_end(&mut data);
+                    data
+                })
+            });
+            let outcome = StreamOutcome(Arc::new(Mutex::new(None)));
+            let reader = ReadOutWithErrAdapter {
+                popen: p,
+                stderr,
+                outcome: outcome.clone(),
+                finished: false,
+            };
+            Ok((reader, outcome))
+        }
+
+        /// Starts the process and returns a value implementing the `Read`
+        /// trait that reads from the standard error of the child process.
+        ///
+        /// This will automatically set up
+        /// `stderr(Redirection::Pipe)`, so it is not necessary to do
+        /// that beforehand.
+        ///
+        /// When the returned reader is dropped, it will wait for the
+        /// process to finish.  If this is undesirable, use
+        /// `detached()`.  To find out whether the process succeeded, call
+        /// [`ReadErrAdapter::finish`] after reading.
+        ///
+        /// [`ReadErrAdapter::finish`]: struct.ReadErrAdapter.html#method.finish
+        pub fn stream_stderr(self) -> PopenResult<ReadErrAdapter> {
+            let p = self.stderr(Redirection::Pipe).popen()?;
+            Ok(ReadErrAdapter(p))
+        }
+
+        /// Starts the process and returns a value implementing the `Write`
+        /// trait that writes to the standard input of the child process.
         ///
         /// This will automatically set up `stdin(Redirection::Pipe)`,
         /// so it is not necessary to do that beforehand.
@@ -412,16 +1751,31 @@ mod exec {
             Ok(WriteAdapter(p))
         }
 
-        fn setup_communicate(mut self) -> PopenResult<(Communicator, Popen)> {
-            let stdin_data = self.stdin_data.take();
+        // Start the process with the streams set up for communication,
+        // returning it along with the input data to feed to it.
+        fn start_communicate(mut self) -> PopenResult<(Popen, Option<Vec<u8>>)> {
+            let mut stdin_data = self.take_stdin_data()?;
+            if let (Redirection::Pty, None) = (&self.config.stdin, &stdin_data) {
+                // nothing to write to the terminal
+                stdin_data = Some(vec![]);
+            }
             if let (&Redirection::None, &Redirection::None) =
                 (&self.config.stdout, &self.config.stderr)
             {
                 self = self.stdout(Redirection::Pipe);
             }
-            let mut p = self.popen()?;
+            Ok((self.popen()?, stdin_data))
+        }
 
-            Ok((p.communicate_start(stdin_data), p))
+        fn setup_communicate(self) -> PopenResult<(Communicator, Popen)> {
+            let max_output = self.max_output;
+            let (mut p, stdin_data) = self.start_communicate()?;
+
+            let mut comm = p.communicate_start(stdin_data);
+            if let Some((bytes, on_overflow)) = max_output {
+                comm = comm.max_output(bytes, on_overflow);
+            }
+            Ok((comm, p))
         }
 
         /// Starts the process and returns a `Communicator` handle.
@@ -435,10 +1789,49 @@ mod exec {
         ///
         /// [`Popen::communicate`]: struct.Popen.html#method.communicate
         pub fn communicate(self) -> PopenResult<Communicator> {
-            let comm = self.detached().setup_communicate()?.0;
+            let max_output = self.max_output;
+            let (mut comm, mut p) = self.detached().setup_communicate()?;
+            if max_output.is_some() {
+                comm.set_kill(move || {
+                    p.kill().ok();
+                });
+            }
             Ok(comm)
         }
 
+        /// Returns a builder that configures the communication with the
+        /// process before starting it.
+        ///
+        /// This gathers the options otherwise spread between `Exec` and
+        /// `Communicator`, such as input data and time and size limits, and
+        /// adds the choice of what to do with the process on timeout.
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # use std::time::Duration;
+        /// # fn dummy() -> Result<()> {
+        /// let mut comm = Exec::cmd("sort")
+        ///     .communicate_with()
+        ///     .input("b\na\n")
+        ///     .limit_time(Duration::from_secs(10))
+        ///     .idle_timeout(Duration::from_secs(1))
+        ///     .on_timeout(OnTimeout::Kill)
+        ///     .start()?;
+        /// let (out, _) = comm.read_string()?;
+        /// # Ok(())
+        /// # }
+        /// ```
+        pub fn communicate_with(self) -> CommunicateBuilder {
+            CommunicateBuilder {
+                exec: self,
+                input: None,
+                size_limit: None,
+                time_limit: None,
+                idle_timeout: None,
+                on_timeout: OnTimeout::Detach,
+            }
+        }
+
         /// Starts the process, collects its output, and waits for it
         /// to finish.
         ///
@@ -452,11 +1845,290 @@ mod exec {
         /// use `detached()`.
         pub fn capture(self) -> PopenResult<CaptureData> {
             let (mut comm, mut p) = self.setup_communicate()?;
-            let (maybe_out, maybe_err) = comm.read()?;
+            let result = comm.read();
+            if let Some(OnOverflow::Kill) | Some(OnOverflow::Error) = comm.overflow() {
+                p.kill()?;
+            }
+            let (maybe_out, maybe_err) = result?;
             Ok(CaptureData {
                 stdout: maybe_out.unwrap_or_else(Vec::new),
                 stderr: maybe_err.unwrap_or_else(Vec::new),
                 exit_status: p.wait()?,
+                overflow: comm.overflow(),
+            })
+        }
+
+        /// Starts the process, collects its output, and returns it as a
+        /// list of lines.
+        ///
+        /// This is a shorthand for `capture()` followed by splitting
+        /// `stdout_str()` into lines, without their line terminators.  The
+        /// exit status is not checked; use [`capture`] if it matters.
+        ///
+        /// [`capture`]: struct.Exec.html#method.capture
+        pub fn capture_lines(self) -> PopenResult<Vec<String>> {
+            let c = self.capture()?;
+            Ok(c.stdout_str().lines().map(str::to_owned).collect())
+        }
+
+        /// Starts the process, collects both its output and error, and
+        /// waits for it to finish.
+        ///
+        /// This corresponds to `std::process::Command::output()`: unlike
+        /// `capture()`, which only captures standard output unless told
+        /// otherwise, it captures both standard output and standard error,
+        /// unless they are explicitly redirected elsewhere.  Data given to
+        /// [`stdin`] is fed to the process.
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let out = Exec::cmd("ls").arg("/nonexistent").output()?;
+        /// if !out.success() {
+        ///     eprintln!("ls failed: {}", out.stderr_str());
+        /// }
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// [`stdin`]: struct.Exec.html#method.stdin
+        pub fn output(mut self) -> PopenResult<Capture> {
+            if let Redirection::None = self.config.stdout {
+                self = self.stdout(Redirection::Pipe);
+            }
+            if let Redirection::None = self.config.stderr {
+                self = self.stderr(Redirection::Pipe);
+            }
+            self.capture()
+        }
+
+        /// Starts this command and `others` at the same time, and returns
+        /// the capture of the first one to succeed.
+        ///
+        /// Each command is captured as with [`capture`].  As soon as one of
+        /// them exits successfully, the others are killed together with
+        /// the processes they started.  If no command succeeds, the capture
+        /// of the one that finished last is returned, or the error
+        /// encountered while capturing it.  A command is judged once it has
+        /// exited and its output has been read to the end, so a command
+        /// that closes its output early doesn't hold up the others.
+        ///
+        /// On Unix-like systems every command is started in a process group
+        /// of its own, as if `setpgid` were specified, so that the whole
+        /// group can be sent `SIGKILL`.  This is done even for the commands
+        /// that don't request it, and only commands started with `setsid`
+        /// keep the group they lead.
+        ///
+        /// This is useful for redundant lookups, such as querying several
+        /// mirrors and taking the first answer:
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let c = Exec::cmd("curl")
+        ///     .arg("https://mirror1.example.com/file")
+        ///     .race(vec![
+        ///         Exec::cmd("curl").arg("https://mirror2.example.com/file"),
+        ///         Exec::cmd("curl").arg("https://mirror3.example.com/file"),
+        ///     ])?;
+        /// # Ok(())
+        /// # }
+        /// ```
+        ///
+        /// [`capture`]: struct.Exec.html#method.capture
+        pub fn race(self, others: Vec<Exec>) -> PopenResult<Capture> {
+            self.race_by(others, Capture::success)
+        }
+
+        /// Like [`race`], but with `accept` deciding which capture counts
+        /// as a success.
+        ///
+        /// For example, `|c| !c.stdout.is_empty()` accepts the first
+        /// command that produces any output, regardless of its exit
+        /// status.
+        ///
+        /// [`race`]: struct.Exec.html#method.race
+        pub fn race_by(
+            self,
+            others: Vec<Exec>,
+            accept: impl Fn(&Capture) -> bool,
+        ) -> PopenResult<Capture> {
+            let (tx, rx) = mpsc::channel();
+            let mut running = vec![];
+            for (index, exec) in iter::once(self).chain(others).enumerate() {
+                #[cfg(unix)]
+                let exec = {
+                    let mut exec = exec;
+                    // a session leader already has a group of its own
+                    if !exec.config.setsid {
+                        exec.config.setpgid = true;
+                    }
+                    exec
+                };
+                let started = exec.setup_communicate().and_then(|(mut comm, p)| {
+                    let tx = tx.clone();
+                    thread::Builder::new()
+                        .name("subprocess-race".into())
+                        .spawn(move || {
+                            let result = comm.read();
+                            // the receiver is gone once the race is decided
+                            let _ = tx.send((index, result, comm.overflow()));
+                        })?;
+                    Ok(p)
+                });
+                match started {
+                    Ok(p) => running.push(Some(p)),
+                    Err(e) => {
+                        kill_all(&mut running);
+                        return Err(e);
+                    }
+                }
+            }
+            drop(tx);
+
+            type Output = (Option<Vec<u8>>, Option<Vec<u8>>);
+            let mut outputs: Vec<Option<(Result<Output, CommunicateError>, _)>> = vec![];
+            outputs.resize_with(running.len(), || None);
+            // set once all reader threads are gone, after which a missing
+            // output means that its thread has panicked
+            let mut readers_gone = false;
+            let mut last = None;
+            let mut delay = Duration::from_millis(1);
+            loop {
+                for index in 0..running.len() {
+                    let exit_status = match running[index].as_mut().and_then(Popen::poll) {
+                        Some(exit_status) => exit_status,
+                        None => continue,
+                    };
+                    let outcome = match outputs[index].take() {
+                        Some((result, overflow)) => result
+                            .map(|(out, err)| CaptureData {
+                                stdout: out.unwrap_or_else(Vec::new),
+                                stderr: err.unwrap_or_else(Vec::new),
+                                exit_status,
+                                overflow,
+                            })
+                            .map_err(PopenError::from),
+                        None if readers_gone => Err(PopenError::IoError(
+                            crate::compat::io_error_other("output reader thread panicked"),
+                        )),
+                        None => continue,
+                    };
+                    running[index] = None;
+                    match outcome {
+                        Ok(c) if accept(&c) => {
+                            kill_all(&mut running);
+                            return Ok(c);
+                        }
+                        outcome => last = Some(outcome),
+                    }
+                }
+                if running.iter().all(Option::is_none) {
+                    // every command has been judged, and recorded its outcome
+                    return last.unwrap();
+                }
+                match rx.recv_timeout(delay) {
+                    Ok((index, result, overflow)) => {
+                        if let Some(OnOverflow::Kill) | Some(OnOverflow::Error) = overflow {
+                            if let Some(p) = running[index].as_mut() {
+                                if let Err(e) = p.kill() {
+                                    kill_all(&mut running);
+                                    return Err(e.into());
+                                }
+                            }
+                        }
+                        outputs[index] = Some((result, overflow));
+                        delay = Duration::from_millis(1);
+                        continue;
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => (),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        readers_gone = true;
+                        thread::sleep(delay);
+                    }
+                }
+                delay = (delay * 2).min(Duration::from_millis(50));
+            }
+        }
+
+        /// Starts the process and returns an [`AsyncJob`] for communicating
+        /// with it and waiting for it from async code.
+        ///
+        /// The process is set up as with [`communicate`]: data given to
+        /// [`stdin`] is fed to the process by [`AsyncJob::communicate`], and
+        /// if neither standard output nor standard error is redirected,
+        /// standard output is captured.
+        ///
+        /// Available with the `async` feature.
+        ///
+        /// [`AsyncJob`]: struct.AsyncJob.html
+        /// [`AsyncJob::communicate`]: struct.AsyncJob.html#method.communicate
+        /// [`communicate`]: struct.Exec.html#method.communicate
+        /// [`stdin`]: struct.Exec.html#method.stdin
+        #[cfg(feature = "async")]
+        pub fn start_async(self) -> PopenResult<AsyncJob> {
+            self.start_async_on()
+        }
+
+        /// Like [`start_async`], but returns an [`AsyncJob`] driven by the
+        /// async runtime `R` rather than tokio.  This allows writing code
+        /// that runs on any runtime:
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// async fn make<R: AsyncRuntime>() -> Result<ExitStatus> {
+        ///     Exec::cmd("make").start_async_on::<R>()?.wait().await
+        /// }
+        /// ```
+        ///
+        /// Available with the `async` or the `async-smol` feature.
+        ///
+        /// [`start_async`]: struct.Exec.html#method.start_async
+        /// [`AsyncJob`]: struct.AsyncJob.html
+        #[cfg(any(feature = "async", feature = "async-smol"))]
+        pub fn start_async_on<R: AsyncRuntime>(self) -> PopenResult<AsyncJob<R>> {
+            let max_output = self.max_output;
+            let (p, stdin_data) = self.wait_in_background().start_communicate()?;
+            Ok(AsyncJob::new(vec![p], stdin_data, max_output))
+        }
+
+        /// Starts the process, collects its output and error in the order
+        /// they arrive, and waits for it to finish.
+        ///
+        /// Unlike `capture()`, this captures both standard output and
+        /// standard error unless they are explicitly redirected, and
+        /// preserves their relative order, tagging each piece with the
+        /// stream it was read from.  The order reflects the arrival of the
+        /// data to the parent and is only as fine-grained as the writes of
+        /// the child, which may buffer its output.
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # fn dummy() -> Result<()> {
+        /// let transcript = Exec::cmd("make").capture_transcript()?;
+        /// for (source, data) in &transcript.records {
+        ///     println!("{:?}: {}", source, String::from_utf8_lossy(data));
+        /// }
+        /// # Ok(())
+        /// # }
+        /// ```
+        pub fn capture_transcript(mut self) -> PopenResult<Transcript> {
+            if let Redirection::None = self.config.stdout {
+                self = self.stdout(Redirection::Pipe);
+            }
+            if let Redirection::None = self.config.stderr {
+                self = self.stderr(Redirection::Pipe);
+            }
+            let (mut comm, mut p) = self.setup_communicate()?;
+            let result = comm.read_transcript();
+            if let Some(OnOverflow::Kill) | Some(OnOverflow::Error) = comm.overflow() {
+                p.kill()?;
+            }
+            let records = result?;
+            Ok(Transcript {
+                records,
+                exit_status: p.wait()?,
+                overflow: comm.overflow(),
             })
         }
 
@@ -477,27 +2149,22 @@ mod exec {
         }
 
         /// Show Exec as command-line string quoted in the Unix style.
+        ///
+        /// If the environment is modified, only the changes relative to the
+        /// current process are shown, as assignments preceding the command,
+        /// with removed variables assigned the empty string.  The values of
+        /// variables whose names suggest secrets, such as `GITHUB_TOKEN` or
+        /// `DB_PASSWORD`, are shown as `***`.
         pub fn to_cmdline_lossy(&self) -> String {
             let mut out = String::new();
             if let Some(ref cmd_env) = self.config.env {
-                let current: Vec<_> = env::vars_os().collect();
-                let current_map: HashMap<_, _> = current.iter().map(|(x, y)| (x, y)).collect();
-                for (k, v) in cmd_env {
-                    if current_map.get(&k) == Some(&&v) {
-                        continue;
-                    }
-                    out.push_str(&Exec::display_escape(&k.to_string_lossy()));
+                for (name, value) in env_delta(cmd_env) {
+                    out.push_str(&Exec::display_escape(&name));
                     out.push('=');
-                    out.push_str(&Exec::display_escape(&v.to_string_lossy()));
-                    out.push(' ');
-                }
-                let cmd_env: HashMap<_, _> = cmd_env.iter().map(|(k, v)| (k, v)).collect();
-                for (k, _) in current {
-                    if !cmd_env.contains_key(&k) {
-                        out.push_str(&Exec::display_escape(&k.to_string_lossy()));
-                        out.push('=');
-                        out.push(' ');
+                    if let Some(value) = value {
+                        out.push_str(&Exec::display_escape(&value));
                     }
+                    out.push(' ');
                 }
             }
             out.push_str(&Exec::display_escape(&self.command.to_string_lossy()));
@@ -507,6 +2174,218 @@ mod exec {
             }
             out
         }
+
+        /// Describes how the command would be started, without starting it.
+        ///
+        /// The returned [`SpawnPlan`] contains the executable found in
+        /// `PATH`, the final argument vector, the changes to the
+        /// environment, the working directory, where each standard stream
+        /// is connected, and the options that differ from the defaults.
+        /// Printing it shows a report useful for finding out why a command
+        /// doesn't behave as expected:
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// # use std::path::Path;
+        /// let cmd = Exec::cmd("sort").arg("-r").stdout(Path::new("sorted.txt"));
+        /// println!("{}", cmd.explain());
+        /// ```
+        ///
+        /// [`SpawnPlan`]: struct.SpawnPlan.html
+        pub fn explain(&self) -> SpawnPlan {
+            self.plan(None)
+        }
+
+        // Compute the SpawnPlan, with the environment of the command
+        // overridden by the pipeline's.
+        pub(super) fn plan(&self, pipeline_env: Option<&[(OsString, OsString)]>) -> SpawnPlan {
+            let mut exec = Exec {
+                command: self.command.clone(),
+                args: self.args.clone(),
+                config: PopenConfig {
+                    env: match pipeline_env {
+                        Some(base) => Some(self.overlaid_env(base, &PopenConfig::current_env())),
+                        None => self.config.env.clone(),
+                    },
+                    cwd: self.config.cwd.clone(),
+                    executable: self.config.executable.clone(),
+                    ..Default::default()
+                },
+                ..Exec::cmd("")
+            };
+            if self.expand_env {
+                exec.expand_args();
+            }
+            if let Some(in_cwd) = self.resolve_program_in_cwd {
+                exec.resolve_program(in_cwd).ok();
+            }
+            let executable = match exec.config.executable {
+                Some(ref executable) => PathBuf::from(executable),
+                None => find_in_path(&exec.command, exec.config.env.as_deref())
+                    .unwrap_or_else(|| PathBuf::from(&exec.command)),
+            };
+            let mut argv = vec![exec.command];
+            argv.append(&mut exec.args);
+
+            let stdin = if self.inherit_stdin {
+                "inherited".to_owned()
+            } else {
+                match (&self.stdin_data, &self.stdin_lines) {
+                    (Some(data), _) => describe_stdin_data(data, self.stdin_file_threshold),
+                    (None, Some(_)) => "pipe, fed lines".to_owned(),
+                    (None, None) => self.describe_stream(&self.config.stdin, 0),
+                }
+            };
+            let streams = [
+                stdin,
+                self.describe_stream(&self.config.stdout, 1),
+                self.describe_stream(&self.config.stderr, 2),
+            ];
+
+            let config = &self.config;
+            let mut options: Vec<(&'static str, String)> = vec![];
+            let mut flag = |name, set: bool| {
+                if set {
+                    options.push((name, "true".to_owned()));
+                }
+            };
+            flag("detached", config.detached);
+            flag("wait_in_background", config.wait_in_background);
+            flag("kill_on_drop", config.kill_on_drop);
+            flag("die_with_parent", config.die_with_parent);
+            flag("reopenable_output", config.reopenable_output);
+            flag("cwd_create", self.create_cwd);
+            #[cfg(unix)]
+            {
+                flag("setpgid", config.setpgid);
+                flag("setsid", config.setsid);
+                flag("fast_spawn", config.fast_spawn);
+                flag("foreground", config.foreground);
+            }
+            #[cfg(windows)]
+            flag("console_utf8", config.console_utf8);
+            if let Some(priority) = config.priority {
+                options.push(("priority", format!("{:?}", priority)));
+            }
+            if self.temp_cwd {
+                options.push((
+                    "temp_cwd",
+                    format!("keep_on_failure={}", self.keep_temp_cwd),
+                ));
+            }
+            if let Some((bytes, on_overflow)) = self.max_output {
+                options.push(("max_output", format!("{} bytes, {:?}", bytes, on_overflow)));
+            }
+            let mut fallback = &self.fallback;
+            while let Some(exec) = fallback {
+                options.push(("fallback", exec.to_cmdline_lossy()));
+                fallback = &exec.fallback;
+            }
+            #[cfg(unix)]
+            {
+                if let Some(uid) = config.setuid {
+                    options.push(("setuid", uid.to_string()));
+                }
+                if let Some(gid) = config.setgid {
+                    options.push(("setgid", gid.to_string()));
+                }
+                if let Some(mask) = config.umask {
+                    options.push(("umask", format!("{:03o}", mask)));
+                }
+                if config.sigpipe != Default::default() {
+                    options.push(("sigpipe", format!("{:?}", config.sigpipe)));
+                }
+                if config.reset_signals {
+                    options.push(("reset_signals", "true".to_owned()));
+                }
+                if !config.blocked_signals.is_empty() {
+                    options.push(("blocked_signals", format!("{:?}", config.blocked_signals)));
+                }
+                if config.terminate_signal != crate::posix::SIGTERM {
+                    options.push(("terminate_signal", config.terminate_signal.to_string()));
+                }
+                if let Some(ref sandbox) = config.sandbox {
+                    options.push(("sandbox", format!("{:?}", sandbox)));
+                }
+            }
+            #[cfg(target_os = "macos")]
+            {
+                if let Some(ref profile) = config.sandbox_profile {
+                    options.push(("sandbox_profile", profile.clone()));
+                }
+            }
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(priority) = config.io_priority {
+                    options.push(("io_priority", format!("{:?}", priority)));
+                }
+                if let Some(signal) = config.parent_death_signal {
+                    options.push(("parent_death_signal", format!("{:?}", signal)));
+                }
+            }
+            #[cfg(target_os = "openbsd")]
+            {
+                if !config.unveil.is_empty() {
+                    options.push(("unveil", format!("{:?}", config.unveil)));
+                }
+                if let Some(ref pledge) = config.pledge {
+                    options.push(("pledge", pledge.clone()));
+                }
+            }
+            #[cfg(windows)]
+            {
+                if config.creation_flags != 0 {
+                    options.push(("creation_flags", format!("{:#x}", config.creation_flags)));
+                }
+            }
+
+            SpawnPlan {
+                executable,
+                argv,
+                env: exec
+                    .config
+                    .env
+                    .as_deref()
+                    .map(env_delta)
+                    .unwrap_or_default(),
+                cwd: exec.config.cwd.map(PathBuf::from),
+                streams,
+                options,
+            }
+        }
+
+        // Describe where standard stream `fd` of the command is connected
+        // when redirected to `r`, for SpawnPlan.
+        pub(super) fn describe_stream(&self, r: &Redirection, fd: usize) -> String {
+            match *r {
+                Redirection::None => "inherited".to_owned(),
+                Redirection::Pipe => "pipe".to_owned(),
+                Redirection::Merge if fd == 1 => "merged into stderr".to_owned(),
+                Redirection::Merge => "merged into stdout".to_owned(),
+                Redirection::File(ref file) => describe_file(file),
+                Redirection::RcFile(ref file) => describe_file(file),
+                Redirection::SharedFile(ref file) => describe_file(file),
+                Redirection::Path(ref path) if fd == 0 => format!("file {}", path.display()),
+                Redirection::Path(ref path) => format!(
+                    "file {} ({})",
+                    path.display(),
+                    if self.config.append {
+                        "appended"
+                    } else {
+                        "truncated"
+                    }
+                ),
+                Redirection::Pty => "pseudo-terminal".to_owned(),
+            }
+        }
+    }
+
+    pub(super) fn describe_stdin_data(data: &[u8], file_threshold: usize) -> String {
+        if data.len() > file_threshold {
+            format!("temporary file with {} bytes", data.len())
+        } else {
+            format!("pipe, fed {} bytes", data.len())
+        }
     }
 
     impl Clone for Exec {
@@ -517,15 +2396,193 @@ mod exec {
         /// variant.  If a redirection to `File` is present, cloning
         /// that field will use `File::try_clone` method, which
         /// duplicates a file descriptor and can (but is not likely
-        /// to) fail.  In that scenario, `Exec::clone` panics.
+        /// to) fail.  In that scenario, `Exec::clone` panics.  It also
+        /// panics if lines passed to [`stdin_lines`] are pending.
+        ///
+        /// [`stdin_lines`]: struct.Exec.html#method.stdin_lines
         fn clone(&self) -> Exec {
-            Exec {
+            self.try_clone().unwrap()
+        }
+    }
+
+    impl Exec {
+        fn try_clone(&self) -> io::Result<Exec> {
+            if self.stdin_lines.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot clone Exec with pending stdin_lines",
+                ));
+            }
+            Ok(Exec {
                 command: self.command.clone(),
                 args: self.args.clone(),
-                config: self.config.try_clone().unwrap(),
+                config: self.config.try_clone()?,
                 stdin_data: self.stdin_data.as_ref().cloned(),
+                stdin_lines: None,
+                inherit_stdin: self.inherit_stdin,
+                expand_env: self.expand_env,
+                create_cwd: self.create_cwd,
+                temp_cwd: self.temp_cwd,
+                keep_temp_cwd: self.keep_temp_cwd,
+                resolve_program_in_cwd: self.resolve_program_in_cwd,
+                max_output: self.max_output,
+                shell: self.shell,
+                stdin_file_threshold: self.stdin_file_threshold,
+                fallback: match self.fallback {
+                    Some(ref fallback) => Some(Box::new(fallback.try_clone()?)),
+                    None => None,
+                },
+            })
+        }
+    }
+
+    // Whether starting a program failed because it doesn't exist, as
+    // opposed to a failure to set up the child, reported with the step
+    // that failed.
+    fn is_missing_program(e: &io::Error) -> bool {
+        e.kind() == io::ErrorKind::NotFound && e.get_ref().is_none()
+    }
+
+    // Kill and reap the processes still running in a race.
+    fn kill_all(running: &mut [Option<Popen>]) {
+        for p in running.iter_mut().filter_map(Option::as_mut) {
+            kill_tree(p).ok();
+            p.wait().ok();
+        }
+    }
+
+    // Environment variable names are case-insensitive on Windows.
+    fn env_key(key: &OsStr) -> OsString {
+        let mut key = key.to_owned();
+        if cfg!(windows) {
+            key.make_ascii_uppercase();
+        }
+        key
+    }
+
+    // Expand `$VAR`, `${VAR}` and, on Windows, `%VAR%` references in `arg`,
+    // treating `$$` and `%%` as escapes.
+    fn expand_env_refs(arg: &OsStr, lookup: impl Fn(&str) -> Option<OsString>) -> OsString {
+        fn name_len(s: &str) -> usize {
+            let mut len = 0;
+            for (i, c) in s.char_indices() {
+                let valid = c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit());
+                if !valid {
+                    break;
+                }
+                len = i + c.len_utf8();
+            }
+            len
+        }
+
+        let mut rest = match arg.to_str() {
+            Some(s) => s,
+            None => return arg.to_owned(),
+        };
+        let mut out = OsString::new();
+        while let Some(pos) = rest.find(|c| c == '$' || (cfg!(windows) && c == '%')) {
+            out.push(&rest[..pos]);
+            let sigil = &rest[pos..pos + 1];
+            let after = &rest[pos + 1..];
+            if after.starts_with(sigil) {
+                // doubled sigil is an escape for a literal one
+                out.push(sigil);
+                rest = &after[1..];
+                continue;
+            }
+            let (name, consumed) = if sigil == "%" {
+                let len = name_len(after);
+                if len != 0 && after[len..].starts_with('%') {
+                    (&after[..len], len + 1)
+                } else {
+                    ("", 0)
+                }
+            } else if let Some(braced) = after.strip_prefix('{') {
+                let len = name_len(braced);
+                if len != 0 && braced[len..].starts_with('}') {
+                    (&braced[..len], len + 2)
+                } else {
+                    ("", 0)
+                }
+            } else {
+                let len = name_len(after);
+                (&after[..len], len)
+            };
+            if name.is_empty() {
+                out.push(sigil);
+            } else if let Some(value) = lookup(name) {
+                out.push(value);
+            }
+            rest = &after[consumed..];
+        }
+        out.push(rest);
+        out
+    }
+
+    // Replace `{name}` placeholders in `arg`.  The argument is processed as
+    // a sequence of code units, bytes on Unix and UTF-16 units on Windows,
+    // so that the parts that aren't valid Unicode are preserved.  Only the
+    // placeholder names need to be valid Unicode to be looked up.
+    fn substitute_placeholders(arg: &OsStr, lookup: impl Fn(&str) -> Option<OsString>) -> OsString {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::{OsStrExt, OsStringExt};
+            let out = substitute_units(
+                arg.as_bytes(),
+                |name| std::str::from_utf8(name).ok().map(str::to_owned),
+                |value| lookup(value).map(|value| value.into_vec()),
+            );
+            OsString::from_vec(out)
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::ffi::{OsStrExt, OsStringExt};
+            let units: Vec<u16> = arg.encode_wide().collect();
+            let out = substitute_units(
+                &units,
+                |name| String::from_utf16(name).ok(),
+                |value| lookup(value).map(|value| value.encode_wide().collect()),
+            );
+            OsString::from_wide(&out)
+        }
+    }
+
+    fn substitute_units<T: Copy + Eq + From<u8>>(
+        units: &[T],
+        name_str: impl Fn(&[T]) -> Option<String>,
+        lookup: impl Fn(&str) -> Option<Vec<T>>,
+    ) -> Vec<T> {
+        let (open, close) = (T::from(b'{'), T::from(b'}'));
+        let mut out = vec![];
+        let mut rest = units;
+        while let Some(pos) = rest.iter().position(|&u| u == open || u == close) {
+            out.extend_from_slice(&rest[..pos]);
+            let brace = rest[pos];
+            let after = &rest[pos + 1..];
+            if after.first() == Some(&brace) {
+                out.push(brace);
+                rest = &after[1..];
+                continue;
+            }
+            let value = match after.iter().position(|&u| u == close) {
+                Some(end) if brace == open => name_str(&after[..end])
+                    .and_then(|name| lookup(&name))
+                    .map(|value| (value, end)),
+                _ => None,
+            };
+            match value {
+                Some((value, end)) => {
+                    out.extend(value);
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    out.push(brace);
+                    rest = after;
+                }
             }
         }
+        out.extend_from_slice(rest);
+        out
     }
 
     impl BitOr for Exec {
@@ -543,8 +2600,101 @@ mod exec {
         }
     }
 
+    // Size of the stdin data above which it is passed to the subprocess
+    // through a temporary file instead of a pipe fed by a thread.
+    pub(super) const DEFAULT_STDIN_FILE_THRESHOLD: usize = 64 * 1024 * 1024;
+
+    // Write `data` to an anonymous file, positioned at its beginning for
+    // the child to read.
+    pub(super) fn stdin_file(data: Vec<u8>) -> io::Result<File> {
+        use std::io::{Seek, SeekFrom};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let temp_path = env::temp_dir().join(format!(
+            "subprocess-stdin-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = anonymous_file(&temp_path)?;
+        file.write_all(&data)?;
+        drop(data);
+        file.seek(SeekFrom::Start(0))?;
+        Ok(file)
+    }
+
+    // Create a new uniquely named directory for Exec::temp_cwd().
+    fn make_temp_cwd() -> io::Result<PathBuf> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let path = env::temp_dir().join(format!(
+                "subprocess-cwd-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            match create_private_dir(&path) {
+                Ok(()) => return Ok(path),
+                // left behind by an earlier process with the same id
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Write data to the subprocess's stdin from a background thread, and
+    // close it when done.  Errors, such as the subprocess exiting without
+    // reading the data, are ignored, as the exit status reports them.
+    pub(super) fn feed_stdin(stdin: Option<File>, data: Vec<u8>) {
+        if let Some(mut stdin) = stdin {
+            thread::spawn(move || {
+                let _ = stdin.write_all(&data);
+            });
+        }
+    }
+
+    // Like feed_stdin, but write the lines as the subprocess reads them,
+    // taking them from the iterator only once the previous ones have fit
+    // into the pipe.
+    fn feed_stdin_lines(stdin: Option<File>, lines: Box<dyn Iterator<Item = String> + Send>) {
+        if let Some(stdin) = stdin {
+            thread::spawn(move || {
+                let mut stdin = io::BufWriter::new(stdin);
+                for line in lines {
+                    if stdin.write_all(line.as_bytes()).is_err() || stdin.write_all(b"\n").is_err()
+                    {
+                        return;
+                    }
+                }
+                let _ = stdin.flush();
+            });
+        }
+    }
+
+    /// Reader of the standard output of a process, returned by
+    /// [`Exec::stream_stdout`].
+    ///
+    /// Dropping the reader waits for the process to finish, discarding its
+    /// exit status.  Use [`finish`] to obtain it instead.
+    ///
+    /// [`Exec::stream_stdout`]: struct.Exec.html#method.stream_stdout
+    /// [`finish`]: struct.ReadOutAdapter.html#method.finish
     #[derive(Debug)]
-    struct ReadOutAdapter(Popen);
+    pub struct ReadOutAdapter(Popen);
+
+    impl ReadOutAdapter {
+        /// Close the standard output, wait for the process to finish, and
+        /// return its exit status.
+        ///
+        /// Output not read by this point is discarded.  A process still
+        /// writing it will typically be terminated by `SIGPIPE` on
+        /// Unix-like systems, or fail to write on Windows.
+        pub fn finish(mut self) -> PopenResult<ExitStatus> {
+            self.0.stdout.take();
+            self.0.wait()
+        }
+    }
 
     impl Read for ReadOutAdapter {
         fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -552,8 +2702,110 @@ mod exec {
         }
     }
 
+    /// How a process streamed with [`Exec::stream_stdout_with_err`]
+    /// ended.
+    ///
+    /// The information becomes available once the reader returned along
+    /// with this value reaches the end of the output or is dropped, and
+    /// the process has finished.  Until then, the methods return `None`.
+    ///
+    /// [`Exec::stream_stdout_with_err`]: struct.Exec.html#method.stream_stdout_with_err
+    #[derive(Debug, Clone)]
+    pub struct StreamOutcome(Arc<Mutex<Option<(ExitStatus, Vec<u8>)>>>);
+
+    impl StreamOutcome {
+        fn get<T>(&self, f: impl FnOnce(&(ExitStatus, Vec<u8>)) -> T) -> Option<T> {
+            self.0.lock().unwrap().as_ref().map(f)
+        }
+
+        /// Returns the exit status of the process.
+        pub fn exit_status(&self) -> Option<ExitStatus> {
+            self.get(|&(status, _)| status)
+        }
+
+        /// Returns the standard error of the process as bytes.
+        pub fn stderr(&self) -> Option<Vec<u8>> {
+            self.get(|(_, stderr)| stderr.clone())
+        }
+
+        /// Returns the standard error of the process as string, converted
+        /// from bytes using `String::from_utf8_lossy`.
+        pub fn stderr_str(&self) -> Option<String> {
+            self.get(|(_, stderr)| String::from_utf8_lossy(stderr).into_owned())
+        }
+    }
+
+    #[derive(Debug)]
+    struct ReadOutWithErrAdapter {
+        popen: Popen,
+        stderr: Option<JoinHandle<Vec<u8>>>,
+        outcome: StreamOutcome,
+        finished: bool,
+    }
+
+    impl ReadOutWithErrAdapter {
+        fn finish(&mut self) -> io::Result<()> {
+            if self.finished {
+                return Ok(());
+            }
+            let status = self.popen.wait().map_err(|e| match e {
+                PopenError::IoError(e) => e,
+                e => crate::compat::io_error_other(e),
+            })?;
+            let stderr = self
+                .stderr
+                .take()
+                .map(|t| t.join().unwrap_or_default())
+                .unwrap_or_default();
+            *self.outcome.0.lock().unwrap() = Some((status, stderr));
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    impl Read for ReadOutWithErrAdapter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.popen.stdout.as_mut().unwrap().read(buf)?;
+            if n == 0 && !buf.is_empty() {
+                self.finish()?;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Drop for ReadOutWithErrAdapter {
+        fn drop(&mut self) {
+            // close the pipe first, so a child still writing gets SIGPIPE
+            // rather than blocking
+            self.popen.stdout.take();
+            let _ = self.finish();
+        }
+    }
+
+    /// Reader of the standard error of a process, returned by
+    /// [`Exec::stream_stderr`].
+    ///
+    /// Dropping the reader waits for the process to finish, discarding its
+    /// exit status.  Use [`finish`] to obtain it instead.
+    ///
+    /// [`Exec::stream_stderr`]: struct.Exec.html#method.stream_stderr
+    /// [`finish`]: struct.ReadErrAdapter.html#method.finish
     #[derive(Debug)]
-    struct ReadErrAdapter(Popen);
+    pub struct ReadErrAdapter(Popen);
+
+    impl ReadErrAdapter {
+        /// Close the standard error, wait for the process to finish, and
+        /// return its exit status.
+        ///
+        /// Error output not read by this point is discarded, as with
+        /// [`ReadOutAdapter::finish`].
+        ///
+        /// [`ReadOutAdapter::finish`]: struct.ReadOutAdapter.html#method.finish
+        pub fn finish(mut self) -> PopenResult<ExitStatus> {
+            self.0.stderr.take();
+            self.0.wait()
+        }
+    }
 
     impl Read for ReadErrAdapter {
         fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -585,6 +2837,112 @@ mod exec {
         }
     }
 
+    /// Options for communicating with a process, returned by
+    /// [`Exec::communicate_with`].
+    ///
+    /// [`Exec::communicate_with`]: struct.Exec.html#method.communicate_with
+    #[derive(Debug)]
+    #[must_use]
+    pub struct CommunicateBuilder {
+        exec: Exec,
+        input: Option<Vec<u8>>,
+        size_limit: Option<usize>,
+        time_limit: Option<Duration>,
+        idle_timeout: Option<Duration>,
+        on_timeout: OnTimeout,
+    }
+
+    impl CommunicateBuilder {
+        /// Feed `data` to the standard input of the process.
+        ///
+        /// Equivalent to `Exec::stdin` with data.  If standard input is
+        /// inherited with `Exec::stdin_inherit`, `start()` returns an
+        /// error.
+        pub fn input(mut self, data: impl Into<Vec<u8>>) -> CommunicateBuilder {
+            self.input = Some(data.into());
+            self
+        }
+
+        /// Limit the amount of data each `read()` will read from the
+        /// process, as with [`Communicator::limit_size`].
+        ///
+        /// [`Communicator::limit_size`]: struct.Communicator.html#method.limit_size
+        pub fn limit_size(mut self, size: usize) -> CommunicateBuilder {
+            self.size_limit = Some(size);
+            self
+        }
+
+        /// Limit the amount of time each `read()` will spend reading from
+        /// the process, as with [`Communicator::limit_time`].
+        ///
+        /// [`Communicator::limit_time`]: struct.Communicator.html#method.limit_time
+        pub fn limit_time(mut self, time: Duration) -> CommunicateBuilder {
+            self.time_limit = Some(time);
+            self
+        }
+
+        /// Limit the amount of time the process may go without exchanging
+        /// data, as with [`Communicator::idle_timeout`].
+        ///
+        /// [`Communicator::idle_timeout`]: struct.Communicator.html#method.idle_timeout
+        pub fn idle_timeout(mut self, time: Duration) -> CommunicateBuilder {
+            self.idle_timeout = Some(time);
+            self
+        }
+
+        /// Specify what happens to the process when `read()` times out.
+        ///
+        /// The default is `OnTimeout::Detach`, which leaves the process
+        /// running, like [`Exec::communicate`] does.
+        ///
+        /// [`Exec::communicate`]: struct.Exec.html#method.communicate
+        pub fn on_timeout(mut self, on_timeout: OnTimeout) -> CommunicateBuilder {
+            self.on_timeout = on_timeout;
+            self
+        }
+
+        /// Starts the process and returns a `Communicator` configured with
+        /// the options.
+        pub fn start(self) -> PopenResult<Communicator> {
+            let mut exec = self.exec;
+            if let Some(input) = self.input {
+                if exec.inherit_stdin {
+                    return Err(PopenError::LogicError(
+                        "input data given with inherited stdin",
+                    ));
+                }
+                exec = exec.stdin(input);
+            }
+            let max_output = exec.max_output;
+            let (mut comm, mut p) = exec.detached().setup_communicate()?;
+            if let Some(size) = self.size_limit {
+                comm = comm.limit_size(size);
+            }
+            if let Some(time) = self.time_limit {
+                comm = comm.limit_time(time);
+            }
+            if let Some(time) = self.idle_timeout {
+                comm = comm.idle_timeout(time);
+            }
+            let kill_on_timeout = self.on_timeout == OnTimeout::Kill;
+            if max_output.is_some() || kill_on_timeout {
+                comm.set_kill_on_timeout(kill_on_timeout);
+                comm.set_kill(move || {
+                    p.kill().ok();
+                });
+            }
+            Ok(comm)
+        }
+    }
+
+    /// Alternative name for [`CaptureData`].
+    ///
+    /// Both names refer to the same type, so code written against either
+    /// of them works with the values returned by `capture()`.
+    ///
+    /// [`CaptureData`]: struct.CaptureData.html
+    pub type Capture = CaptureData;
+
     /// Data captured by [`Exec::capture`] and [`Pipeline::capture`].
     ///
     /// [`Exec::capture`]: struct.Exec.html#method.capture
@@ -597,6 +2955,9 @@ mod exec {
         pub stderr: Vec<u8>,
         /// Exit status.
         pub exit_status: ExitStatus,
+        /// Action taken because the output exceeded the limit set with
+        /// `max_output()`, if any.
+        pub overflow: Option<OnOverflow>,
     }
 
     impl CaptureData {
@@ -618,6 +2979,114 @@ mod exec {
         }
     }
 
+    /// Output and error of a process or pipeline in the order of arrival,
+    /// returned by [`Exec::capture_transcript`] and
+    /// [`Pipeline::capture_transcript`].
+    ///
+    /// [`Exec::capture_transcript`]: struct.Exec.html#method.capture_transcript
+    /// [`Pipeline::capture_transcript`]: struct.Pipeline.html#method.capture_transcript
+    #[derive(Debug)]
+    pub struct Transcript {
+        /// Pieces of output and error, tagged with the stream they came
+        /// from, as returned by [`Communicator::read_transcript`].
+        ///
+        /// [`Communicator::read_transcript`]: struct.Communicator.html#method.read_transcript
+        pub records: Vec<(OutputSource, Vec<u8>)>,
+        /// Exit status.
+        pub exit_status: ExitStatus,
+        /// Action taken because the output exceeded the limit set with
+        /// `max_output()`, if any.
+        pub overflow: Option<OnOverflow>,
+    }
+
+    impl Transcript {
+        fn collect(&self, source: OutputSource) -> Vec<u8> {
+            self.records
+                .iter()
+                .filter(|(s, _)| *s == source)
+                .flat_map(|(_, data)| data.iter().copied())
+                .collect()
+        }
+
+        /// Returns the standard output as bytes.
+        pub fn stdout(&self) -> Vec<u8> {
+            self.collect(OutputSource::Stdout)
+        }
+
+        /// Returns the standard error as bytes.
+        pub fn stderr(&self) -> Vec<u8> {
+            self.collect(OutputSource::Stderr)
+        }
+
+        /// True if the exit status of the process or pipeline is 0.
+        pub fn success(&self) -> bool {
+            self.exit_status.success()
+        }
+    }
+
+    /// Description of how a command would be started, returned by
+    /// [`Exec::explain`] and [`Pipeline::explain`].
+    ///
+    /// The `Display` implementation shows the description as a report, one
+    /// item per line.
+    ///
+    /// [`Exec::explain`]: struct.Exec.html#method.explain
+    /// [`Pipeline::explain`]: struct.Pipeline.html#method.explain
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SpawnPlan {
+        /// The program to execute, as found in `PATH`.  If it isn't found,
+        /// the program is given as specified, and starting the command is
+        /// expected to fail.
+        pub executable: PathBuf,
+        /// The arguments passed to the program, starting with `argv[0]`.
+        pub argv: Vec<OsString>,
+        /// The changes to the environment relative to the current process,
+        /// with removed variables set to `None`, and secrets redacted as
+        /// in [`Exec::to_cmdline_lossy`].
+        ///
+        /// [`Exec::to_cmdline_lossy`]: struct.Exec.html#method.to_cmdline_lossy
+        pub env: Vec<(String, Option<String>)>,
+        /// The working directory, or `None` if it is inherited.
+        pub cwd: Option<PathBuf>,
+        /// Where standard input, output and error are connected.
+        pub streams: [String; 3],
+        /// The options that differ from their defaults, with their values.
+        pub options: Vec<(&'static str, String)>,
+    }
+
+    impl fmt::Display for SpawnPlan {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            writeln!(f, "executable: {}", self.executable.display())?;
+            let argv: Vec<_> = self
+                .argv
+                .iter()
+                .map(|arg| Exec::display_escape(&arg.to_string_lossy()).into_owned())
+                .collect();
+            writeln!(f, "argv: {}", argv.join(" "))?;
+            for (name, value) in &self.env {
+                match value {
+                    Some(value) => writeln!(f, "env: {}={}", name, value)?,
+                    None => writeln!(f, "env: {} removed", name)?,
+                }
+            }
+            match self.cwd {
+                Some(ref cwd) => writeln!(f, "cwd: {}", cwd.display())?,
+                None => writeln!(f, "cwd: inherited")?,
+            }
+            for (fd, (name, target)) in ["stdin", "stdout", "stderr"]
+                .iter()
+                .zip(&self.streams)
+                .enumerate()
+            {
+                writeln!(f, "{} (fd {}): {}", name, fd, target)?;
+            }
+            for (name, value) in &self.options {
+                writeln!(f, "option {}: {}", name, value)?;
+            }
+            Ok(())
+        }
+    }
+
     #[derive(Debug)]
     pub enum InputRedirection {
         AsRedirection(Redirection),
@@ -653,88 +3122,493 @@ mod exec {
     #[derive(Debug)]
     pub struct NullFile;
 
-    impl From<NullFile> for InputRedirection {
-        fn from(_nf: NullFile) -> Self {
-            let null_file = OpenOptions::new().read(true).open(NULL_DEVICE).unwrap();
-            InputRedirection::AsRedirection(Redirection::File(null_file))
-        }
-    }
+    impl From<NullFile> for InputRedirection {
+        fn from(_nf: NullFile) -> Self {
+            let null_file = OpenOptions::new().read(true).open(NULL_DEVICE).unwrap();
+            InputRedirection::AsRedirection(Redirection::File(null_file))
+        }
+    }
+
+    impl From<Vec<u8>> for InputRedirection {
+        fn from(v: Vec<u8>) -> Self {
+            InputRedirection::FeedData(v)
+        }
+    }
+
+    impl<'a> From<&'a str> for InputRedirection {
+        fn from(s: &'a str) -> Self {
+            InputRedirection::FeedData(s.as_bytes().to_vec())
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct OutputRedirection(Redirection);
+
+    impl OutputRedirection {
+        pub fn into_redirection(self) -> Redirection {
+            self.0
+        }
+    }
+
+    impl From<Redirection> for OutputRedirection {
+        fn from(r: Redirection) -> Self {
+            OutputRedirection(r)
+        }
+    }
+
+    impl From<File> for OutputRedirection {
+        fn from(f: File) -> Self {
+            OutputRedirection(Redirection::File(f))
+        }
+    }
+
+    impl<'a> From<&'a Path> for OutputRedirection {
+        fn from(path: &'a Path) -> Self {
+            OutputRedirection(Redirection::Path(path.to_owned()))
+        }
+    }
+
+    impl From<PathBuf> for OutputRedirection {
+        fn from(path: PathBuf) -> Self {
+            OutputRedirection(Redirection::Path(path))
+        }
+    }
+
+    impl From<NullFile> for OutputRedirection {
+        fn from(_nf: NullFile) -> Self {
+            let null_file = OpenOptions::new().write(true).open(NULL_DEVICE).unwrap();
+            OutputRedirection(Redirection::File(null_file))
+        }
+    }
+
+    #[cfg(unix)]
+    pub mod unix {
+        #[cfg(target_os = "openbsd")]
+        use std::path::Path;
+
+        use super::Exec;
+        use crate::sandbox::{Resource, Sandbox};
+        #[cfg(target_os = "linux")]
+        use crate::unix::IoPriority;
+        use crate::unix::{SigPipePolicy, Signal};
+
+        /// Unix-specific extension methods for `Exec`
+        pub trait ExecExt {
+            /// Set the user ID of the subprocess.
+            ///
+            /// Equivalent to setting [`PopenConfig::setuid`].
+            ///
+            /// [`PopenConfig::setuid`]: ../struct.PopenConfig.html#structfield.setuid
+            fn setuid(self, uid: u32) -> Self;
+
+            /// Set the group ID of the subprocess.
+            ///
+            /// Equivalent to setting [`PopenConfig::setgid`].
+            ///
+            /// [`PopenConfig::setgid`]: ../struct.PopenConfig.html#structfield.setgid
+            fn setgid(self, gid: u32) -> Self;
+
+            /// Make the subprocess belong to a new process group.
+            ///
+            /// Equivalent to setting [`PopenConfig::setpgid`].
+            ///
+            /// [`PopenConfig::setpgid`]: ../struct.PopenConfig.html#structfield.setpgid
+            fn setpgid(self) -> Self;
+
+            /// Make the subprocess the leader of a new session.
+            ///
+            /// Equivalent to setting [`PopenConfig::setsid`].
+            ///
+            /// [`PopenConfig::setsid`]: ../struct.PopenConfig.html#structfield.setsid
+            fn setsid(self) -> Self;
+
+            /// Set the file mode creation mask of the subprocess to `mode`,
+            /// such as `0o077` to keep the files it creates private.
+            ///
+            /// Equivalent to setting [`PopenConfig::umask`].
+            ///
+            /// [`PopenConfig::umask`]: ../struct.PopenConfig.html#structfield.umask
+            fn umask(self, mode: u32) -> Self;
+
+            /// Start the subprocess with `posix_spawn()` instead of `fork()`.
+            ///
+            /// Equivalent to setting [`PopenConfig::fast_spawn`].
+            ///
+            /// [`PopenConfig::fast_spawn`]: ../struct.PopenConfig.html#structfield.fast_spawn
+            fn fast_spawn(self) -> Self;
+
+            /// Choose how the subprocess handles `SIGPIPE`.
+            ///
+            /// Equivalent to setting [`PopenConfig::sigpipe`].
+            ///
+            /// [`PopenConfig::sigpipe`]: ../struct.PopenConfig.html#structfield.sigpipe
+            fn sigpipe(self, policy: SigPipePolicy) -> Self;
+
+            /// Set the signals blocked in the subprocess, and whether the
+            /// handling of the other signals is reset to the default.
+            ///
+            /// Equivalent to setting [`PopenConfig::reset_signals`] to
+            /// `reset_all` and [`PopenConfig::blocked_signals`] to `block`.
+            ///
+            /// [`PopenConfig::reset_signals`]: ../struct.PopenConfig.html#structfield.reset_signals
+            /// [`PopenConfig::blocked_signals`]: ../struct.PopenConfig.html#structfield.blocked_signals
+            fn signal_mask(self, reset_all: bool, block: &[Signal]) -> Self;
+
+            /// Set the signal sent to the subprocess by `Popen::terminate`.
+            ///
+            /// Equivalent to setting [`PopenConfig::terminate_signal`].
+            ///
+            /// [`PopenConfig::terminate_signal`]: ../struct.PopenConfig.html#structfield.terminate_signal
+            fn terminate_signal(self, signal: i32) -> Self;
+
+            /// Run the subprocess in the foreground of the controlling
+            /// terminal, for interactive programs such as editors and
+            /// pagers.
+            ///
+            /// Equivalent to setting [`PopenConfig::foreground`].
+            ///
+            /// [`PopenConfig::foreground`]: ../struct.PopenConfig.html#structfield.foreground
+            fn foreground(self) -> Self;
+
+            /// Isolate the subprocess as configured by `sandbox`.
+            ///
+            /// Equivalent to setting [`PopenConfig::sandbox`].
+            ///
+            /// [`PopenConfig::sandbox`]: ../struct.PopenConfig.html#structfield.sandbox
+            fn sandbox(self, sandbox: Sandbox) -> Self;
+
+            /// Limit the use of `resource` by the subprocess to the `soft`
+            /// limit, which it can raise up to the `hard` limit.
+            ///
+            /// The limit is set with `setrlimit()` before executing the
+            /// program, which avoids a wrapper script calling `ulimit`:
+            ///
+            /// ```no_run
+            /// # use subprocess::*;
+            /// # use subprocess::unix::{ExecExt, Resource};
+            /// # fn dummy() -> Result<()> {
+            /// Exec::cmd("./solver")
+            ///     .limit(Resource::Cpu, 60, 60)
+            ///     .limit(Resource::AddressSpace, 1 << 30, 1 << 30)
+            ///     .join()?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            ///
+            /// Equivalent to [`Sandbox::rlimit`] on the sandbox of the
+            /// subprocess, which is created if needed.  A sandbox set later
+            /// with [`sandbox`] replaces the limits set so far.
+            ///
+            /// [`Sandbox::rlimit`]: struct.Sandbox.html#method.rlimit
+            /// [`sandbox`]: trait.ExecExt.html#tymethod.sandbox
+            fn limit(self, resource: Resource, soft: u64, hard: u64) -> Self;
+
+            /// Apply a Seatbelt sandbox profile to the subprocess.
+            ///
+            /// This restricts the program the way `sandbox-exec -p profile`
+            /// would, without running it through `sandbox-exec`:
+            ///
+            /// ```no_run
+            /// # use subprocess::*;
+            /// # use subprocess::unix::ExecExt;
+            /// # fn dummy() -> Result<()> {
+            /// let profile = r#"
+            ///     (version 1)
+            ///     (allow default)
+            ///     (deny network*)
+            ///     (deny file-write* (require-not (subpath "/tmp/helper")))
+            /// "#;
+            /// Exec::cmd("helper").sandbox_profile(profile).join()?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            ///
+            /// Equivalent to setting [`PopenConfig::sandbox_profile`].
+            /// Only available on macOS.
+            ///
+            /// [`PopenConfig::sandbox_profile`]: ../struct.PopenConfig.html#structfield.sandbox_profile
+            #[cfg(target_os = "macos")]
+            fn sandbox_profile(self, profile: &str) -> Self;
+
+            /// Set the IO scheduling class and priority of the subprocess.
+            ///
+            /// Equivalent to setting [`PopenConfig::io_priority`].  Only
+            /// available on Linux.
+            ///
+            /// [`PopenConfig::io_priority`]: ../struct.PopenConfig.html#structfield.io_priority
+            #[cfg(target_os = "linux")]
+            fn io_priority(self, priority: IoPriority) -> Self;
+
+            /// Send `signal` to the subprocess when the current process
+            /// exits, including when it crashes or is killed.
+            ///
+            /// Equivalent to setting [`PopenConfig::parent_death_signal`].
+            /// Only available on Linux.
+            ///
+            /// [`PopenConfig::parent_death_signal`]: ../struct.PopenConfig.html#structfield.parent_death_signal
+            #[cfg(target_os = "linux")]
+            fn parent_death_signal(self, signal: Signal) -> Self;
+
+            /// Make `path` visible to the subprocess with the given
+            /// `unveil()` permissions, hiding the paths not unveiled.
+            ///
+            /// Can be called multiple times to unveil several paths.
+            /// Equivalent to adding to [`PopenConfig::unveil`], whose
+            /// documentation lists what must be unveiled for the program to
+            /// start.  Only available on OpenBSD.
+            ///
+            /// [`PopenConfig::unveil`]: ../struct.PopenConfig.html#structfield.unveil
+            #[cfg(target_os = "openbsd")]
+            fn unveil(self, path: impl AsRef<Path>, permissions: &str) -> Self;
+
+            /// Restrict the program run as the subprocess to `execpromises`,
+            /// e.g. `"stdio rpath"`.
+            ///
+            /// Equivalent to setting [`PopenConfig::pledge`].  Only
+            /// available on OpenBSD.
+            ///
+            /// [`PopenConfig::pledge`]: ../struct.PopenConfig.html#structfield.pledge
+            #[cfg(target_os = "openbsd")]
+            fn pledge(self, execpromises: &str) -> Self;
+        }
+
+        impl ExecExt for Exec {
+            fn setuid(mut self, uid: u32) -> Exec {
+                self.config.setuid = Some(uid);
+                self
+            }
+
+            fn setgid(mut self, gid: u32) -> Exec {
+                self.config.setgid = Some(gid);
+                self
+            }
+
+            fn setpgid(mut self) -> Exec {
+                self.config.setpgid = true;
+                self
+            }
+
+            fn setsid(mut self) -> Exec {
+                self.config.setsid = true;
+                self
+            }
+
+            fn umask(mut self, mode: u32) -> Exec {
+                self.config.umask = Some(mode);
+                self
+            }
+
+            fn fast_spawn(mut self) -> Exec {
+                self.config.fast_spawn = true;
+                self
+            }
+
+            fn sigpipe(mut self, policy: SigPipePolicy) -> Exec {
+                self.config.sigpipe = policy;
+                self
+            }
+
+            fn signal_mask(mut self, reset_all: bool, block: &[Signal]) -> Exec {
+                self.config.reset_signals = reset_all;
+                self.config.blocked_signals = block.to_vec();
+                self
+            }
+
+            fn terminate_signal(mut self, signal: i32) -> Exec {
+                self.config.terminate_signal = signal;
+                self
+            }
+
+            fn foreground(mut self) -> Exec {
+                self.config.foreground = true;
+                self
+            }
 
-    impl From<Vec<u8>> for InputRedirection {
-        fn from(v: Vec<u8>) -> Self {
-            InputRedirection::FeedData(v)
-        }
-    }
+            fn sandbox(mut self, sandbox: Sandbox) -> Exec {
+                self.config.sandbox = Some(sandbox);
+                self
+            }
 
-    impl<'a> From<&'a str> for InputRedirection {
-        fn from(s: &'a str) -> Self {
-            InputRedirection::FeedData(s.as_bytes().to_vec())
-        }
-    }
+            fn limit(mut self, resource: Resource, soft: u64, hard: u64) -> Exec {
+                let sandbox = self.config.sandbox.take().unwrap_or_default();
+                self.config.sandbox = Some(sandbox.rlimit(resource, soft, hard));
+                self
+            }
 
-    #[derive(Debug)]
-    pub struct OutputRedirection(Redirection);
+            #[cfg(target_os = "macos")]
+            fn sandbox_profile(mut self, profile: &str) -> Exec {
+                self.config.sandbox_profile = Some(profile.to_owned());
+                self
+            }
 
-    impl OutputRedirection {
-        pub fn into_redirection(self) -> Redirection {
-            self.0
-        }
-    }
+            #[cfg(target_os = "linux")]
+            fn io_priority(mut self, priority: IoPriority) -> Exec {
+                self.config.io_priority = Some(priority);
+                self
+            }
 
-    impl From<Redirection> for OutputRedirection {
-        fn from(r: Redirection) -> Self {
-            OutputRedirection(r)
-        }
-    }
+            #[cfg(target_os = "linux")]
+            fn parent_death_signal(mut self, signal: Signal) -> Exec {
+                self.config.parent_death_signal = Some(signal);
+                self
+            }
 
-    impl From<File> for OutputRedirection {
-        fn from(f: File) -> Self {
-            OutputRedirection(Redirection::File(f))
-        }
-    }
+            #[cfg(target_os = "openbsd")]
+            fn unveil(mut self, path: impl AsRef<Path>, permissions: &str) -> Exec {
+                self.config
+                    .unveil
+                    .push((path.as_ref().as_os_str().to_owned(), permissions.to_owned()));
+                self
+            }
 
-    impl From<NullFile> for OutputRedirection {
-        fn from(_nf: NullFile) -> Self {
-            let null_file = OpenOptions::new().write(true).open(NULL_DEVICE).unwrap();
-            OutputRedirection(Redirection::File(null_file))
+            #[cfg(target_os = "openbsd")]
+            fn pledge(mut self, execpromises: &str) -> Exec {
+                self.config.pledge = Some(execpromises.to_owned());
+                self
+            }
         }
     }
 
-    #[cfg(unix)]
-    pub mod unix {
+    #[cfg(windows)]
+    pub mod windows {
+        use std::ffi::OsStr;
+        use std::path::Path;
+
         use super::Exec;
+        use crate::popen::{PopenError, Result as PopenResult};
 
+        /// Windows-specific extension methods for `Exec`
         pub trait ExecExt {
-            fn setuid(self, uid: u32) -> Self;
-            fn setgid(self, gid: u32) -> Self;
+            /// Set the process creation flags of the subprocess.
+            ///
+            /// Equivalent to setting [`PopenConfig::creation_flags`].
+            ///
+            /// [`PopenConfig::creation_flags`]: ../struct.PopenConfig.html#structfield.creation_flags
+            fn creation_flags(self, flags: u32) -> Self;
+
+            /// Constructs a new `Exec` that runs `command` inside the
+            /// given WSL distribution.
+            ///
+            /// This is equivalent to `wsl.exe -d <distro> -- <command>`,
+            /// arguments being added with [`arg`] and [`args`] as usual.
+            /// Paths passed as arguments must be given as seen by the
+            /// distribution; use [`wsl_path`] to translate Windows paths.
+            ///
+            /// [`arg`]: ../struct.Exec.html#method.arg
+            /// [`args`]: ../struct.Exec.html#method.args
+            /// [`wsl_path`]: fn.wsl_path.html
+            fn wsl(distro: impl AsRef<OsStr>, command: impl AsRef<OsStr>) -> Self;
+
+            /// Run the subprocess with the console switched to the UTF-8
+            /// code page, so that the output of console programs can be
+            /// captured as valid UTF-8.
+            ///
+            /// Equivalent to setting [`PopenConfig::console_utf8`], which
+            /// describes the effect on the console shared with the current
+            /// process.
+            ///
+            /// [`PopenConfig::console_utf8`]: ../struct.PopenConfig.html#structfield.console_utf8
+            fn console_utf8(self) -> Self;
         }
 
         impl ExecExt for Exec {
-            fn setuid(mut self, uid: u32) -> Exec {
-                self.config.setuid = Some(uid);
+            fn creation_flags(mut self, flags: u32) -> Exec {
+                self.config.creation_flags = flags;
                 self
             }
 
-            fn setgid(mut self, gid: u32) -> Exec {
-                self.config.setgid = Some(gid);
+            fn wsl(distro: impl AsRef<OsStr>, command: impl AsRef<OsStr>) -> Exec {
+                Exec::cmd("wsl.exe")
+                    .arg("-d")
+                    .arg(distro)
+                    .arg("--")
+                    .arg(command)
+            }
+
+            fn console_utf8(mut self) -> Exec {
+                self.config.console_utf8 = true;
                 self
             }
         }
+
+        /// Translates a Windows path to the path under which it is visible
+        /// inside the given WSL distribution.
+        ///
+        /// This runs `wslpath -a -u` in the distribution, so it honors its
+        /// automount configuration.  Returns an error if `wslpath` fails.
+        pub fn wsl_path(distro: impl AsRef<OsStr>, path: impl AsRef<Path>) -> PopenResult<String> {
+            let c = Exec::wsl(distro, "wslpath")
+                .args(["-a", "-u"])
+                .arg(path.as_ref())
+                .stdout(super::Redirection::Pipe)
+                .stderr(super::Redirection::Pipe)
+                .capture()?;
+            if !c.success() {
+                return Err(PopenError::IoError(crate::compat::io_error_other(format!(
+                    "wslpath failed: {}",
+                    wsl_output_to_string(&c.stderr).trim()
+                ))));
+            }
+            let mut out = wsl_output_to_string(&c.stdout);
+            out.truncate(out.trim_end().len());
+            Ok(out)
+        }
+
+        /// Decodes output produced by WSL tools into a string.
+        ///
+        /// `wsl.exe` itself and some tools invoked through it write UTF-16LE
+        /// rather than UTF-8, which makes [`CaptureData::stdout_str`] return
+        /// text interspersed with NUL characters.  This function detects
+        /// such output by a byte order mark or by NUL bytes in odd
+        /// positions and decodes it accordingly, falling back to lossy
+        /// UTF-8 decoding otherwise.
+        ///
+        /// [`CaptureData::stdout_str`]: ../struct.CaptureData.html#method.stdout_str
+        pub fn wsl_output_to_string(output: &[u8]) -> String {
+            let (data, utf16) = if output.starts_with(&[0xff, 0xfe]) {
+                (&output[2..], true)
+            } else {
+                let pairs = output.chunks_exact(2);
+                let even_len = pairs.remainder().is_empty();
+                (output, even_len && pairs.clone().any(|pair| pair[1] == 0))
+            };
+            if !utf16 {
+                return String::from_utf8_lossy(data).into_owned();
+            }
+            let units: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
     }
 }
 
 mod pipeline {
+    use std::ffi::{OsStr, OsString};
     use std::fmt;
-    use std::fs::File;
+    use std::fs::{File, OpenOptions};
     use std::io::{self, Read, Write};
+    use std::mem;
     use std::ops::BitOr;
     use std::rc::Rc;
+    use std::time::Duration;
 
-    use crate::communicate::{self, Communicator};
+    #[cfg(any(feature = "async", feature = "async-smol"))]
+    use crate::async_job::AsyncJob;
+    #[cfg(any(feature = "async", feature = "async-smol"))]
+    use crate::async_runtime::AsyncRuntime;
+    use crate::communicate::{self, Communicator, OnOverflow};
     use crate::os_common::ExitStatus;
-    use crate::popen::{Popen, Redirection, Result as PopenResult};
+    use crate::popen::{
+        CancelToken, Popen, PopenConfig, PopenError, Redirection, Result as PopenResult,
+    };
 
-    use super::exec::{CaptureData, Exec, InputRedirection, OutputRedirection};
+    use super::exec::{
+        describe_stdin_data, feed_stdin, stdin_file, CaptureData, Exec, InputRedirection, NullFile,
+        OutputRedirection, SpawnPlan, Transcript, DEFAULT_STDIN_FILE_THRESHOLD,
+    };
+    use super::os::NULL_DEVICE;
 
     /// A builder for multiple [`Popen`] instances connected via
     /// pipes.
@@ -758,7 +3632,7 @@ mod pipeline {
     /// # use subprocess::*;
     /// # fn dummy() -> Result<()> {
     /// let exit_status =
-    ///   (Exec::shell("ls *.bak") | Exec::cmd("xargs").arg("rm")).join()?;
+    ///   (Exec::cmd("find").args(&[".", "-name", "*.bak"]) | Exec::cmd("xargs").arg("rm")).join()?;
     /// # Ok(())
     /// # }
     /// ```
@@ -778,14 +3652,18 @@ mod pipeline {
     /// [`Popen`]: struct.Popen.html
     /// [`Exec`]: struct.Exec.html
     /// [`Pipeline`]: struct.Pipeline.html
-
     #[must_use]
     pub struct Pipeline {
         cmds: Vec<Exec>,
+        on_start_failure: OnStartFailure,
         stdin: Redirection,
         stdout: Redirection,
-        stderr_file: Option<File>,
+        stderr: Redirection,
+        stderr_all: Redirection,
         stdin_data: Option<Vec<u8>>,
+        max_output: Option<(usize, OnOverflow)>,
+        env: Option<Vec<(OsString, OsString)>>,
+        stdin_file_threshold: usize,
     }
 
     impl Pipeline {
@@ -795,10 +3673,15 @@ mod pipeline {
         pub fn new(cmd1: Exec, cmd2: Exec) -> Pipeline {
             Pipeline {
                 cmds: vec![cmd1, cmd2],
+                on_start_failure: OnStartFailure::Terminate,
                 stdin: Redirection::None,
                 stdout: Redirection::None,
-                stderr_file: None,
+                stderr: Redirection::None,
+                stderr_all: Redirection::None,
                 stdin_data: None,
+                max_output: None,
+                env: None,
+                stdin_file_threshold: DEFAULT_STDIN_FILE_THRESHOLD,
             }
         }
 
@@ -810,43 +3693,39 @@ mod pipeline {
         /// use subprocess::Exec;
         ///
         /// let commands = vec![
-        ///   Exec::shell("echo tset"),
-        ///   Exec::shell("tr '[:lower:]' '[:upper:]'"),
-        ///   Exec::shell("rev")
+        ///   Exec::cmd("echo").arg("tset"),
+        ///   Exec::cmd("tr").args(&["[:lower:]", "[:upper:]"]),
+        ///   Exec::cmd("rev"),
         /// ];
         ///
         /// let pipeline = subprocess::Pipeline::from_exec_iter(commands);
+        /// # #[cfg(not(feature = "no-path-search"))] {
         /// let output = pipeline.capture().unwrap().stdout_str();
         /// assert_eq!(output, "TEST\n");
+        /// # }
         /// ```
-        /// ```should_panic
-        /// use subprocess::Exec;
-        ///
-        /// let commands = vec![
-        ///   Exec::shell("echo tset"),
-        /// ];
         ///
-        /// // This will panic as the iterator contains less than two (2) items.
-        /// let pipeline = subprocess::Pipeline::from_exec_iter(commands);
-        /// ```
-        /// Errors:
-        ///   - Panics when the passed iterator contains less than two (2) items.
+        /// The iterator may contain any number of commands.  A pipeline
+        /// with a single command runs just that command, and an empty
+        /// pipeline can be extended using the `|` operator, but fails to
+        /// start with `PopenError::LogicError`.
         pub fn from_exec_iter<I>(iterable: I) -> Pipeline
         where
             I: IntoIterator<Item = Exec>,
         {
             let cmds: Vec<_> = iterable.into_iter().collect();
 
-            if cmds.len() < 2 {
-                panic!("iterator needs to contain at least two (2) elements")
-            }
-
             Pipeline {
                 cmds,
+                on_start_failure: OnStartFailure::Terminate,
                 stdin: Redirection::None,
                 stdout: Redirection::None,
-                stderr_file: None,
+                stderr: Redirection::None,
+                stderr_all: Redirection::None,
                 stdin_data: None,
+                max_output: None,
+                env: None,
+                stdin_file_threshold: DEFAULT_STDIN_FILE_THRESHOLD,
             }
         }
 
@@ -858,15 +3737,19 @@ mod pipeline {
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
         /// * a `Vec<u8>` or `&str`, which will set up a `Redirection::Pipe`
-        ///   for stdin, making sure that `capture` feeds that data into the
-        ///   standard input of the subprocess.
+        ///   for stdin of the first command, and feed that data into it as
+        ///   described in [`Exec::stdin`];
         /// * `NullFile`, which will redirect the standard input to read from
-        ///    /dev/null.
+        ///   /dev/null.
         ///
         /// [`Redirection`]: enum.Redirection.html
+        /// [`Exec::stdin`]: struct.Exec.html#method.stdin
         pub fn stdin(mut self, stdin: impl Into<InputRedirection>) -> Pipeline {
             match stdin.into() {
-                InputRedirection::AsRedirection(r) => self.stdin = r,
+                InputRedirection::AsRedirection(r) => {
+                    self.stdin = r;
+                    self.stdin_data = None;
+                }
                 InputRedirection::FeedData(data) => {
                     self.stdin = Redirection::Pipe;
                     self.stdin_data = Some(data);
@@ -883,7 +3766,7 @@ mod pipeline {
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
         /// * `NullFile`, which will redirect the standard output to write to
-        ///    /dev/null.
+        ///   /dev/null.
         ///
         /// [`Redirection`]: enum.Redirection.html
         pub fn stdout(mut self, stdout: impl Into<OutputRedirection>) -> Pipeline {
@@ -891,22 +3774,226 @@ mod pipeline {
             self
         }
 
-        /// Specifies a file to which to redirect the standard error of all
-        /// the commands in the pipeline.
+        /// Specifies how to set up the standard error of the last command
+        /// in the pipeline.
+        ///
+        /// Argument can be anything accepted by [`Exec::stderr`].  This
+        /// takes precedence over [`stderr_all`] for the last command.
+        ///
+        /// [`Exec::stderr`]: struct.Exec.html#method.stderr
+        /// [`stderr_all`]: struct.Pipeline.html#method.stderr_all
+        pub fn stderr(mut self, stderr: impl Into<OutputRedirection>) -> Pipeline {
+            self.stderr = stderr.into().into_redirection();
+            self
+        }
+
+        /// Specifies how to set up the standard error of all the commands
+        /// in the pipeline.
         ///
         /// It is useful for capturing the standard error of the pipeline as a
         /// whole.  Unlike `stdout()`, which only affects the last command in
         /// the pipeline, this affects all commands.  The difference is
         /// because standard output is piped from one command to the next, so
         /// only the output of the last command is "free".  In contrast, the
-        /// standard errors are not connected in any way.  This is also the
-        /// reason only a `File` is supported - it allows for efficient
-        /// sharing of the same file by all commands.
-        pub fn stderr_to(mut self, to: File) -> Pipeline {
-            self.stderr_file = Some(to);
+        /// standard errors are not connected in any way.
+        ///
+        /// Argument can be anything accepted by [`Exec::stderr`], with the
+        /// following meaning:
+        ///
+        /// * `Redirection::Pipe` funnels the standard errors of all commands
+        ///   into a single pipe, whose reading end is available as the
+        ///   `stderr` field of the last `Popen` returned by [`popen`];
+        /// * `Redirection::Merge` merges the standard error of each command
+        ///   into its standard output, like `2>&1` in each command of a
+        ///   shell pipeline;
+        /// * a file is shared by all commands.
+        ///
+        /// Commands appended to the pipeline later with `|` are covered as
+        /// well.  Joining two pipelines with `|` keeps the `stderr_all` of
+        /// the right-hand one if it was specified, and of the left-hand one
+        /// otherwise, for all commands of the result.
+        ///
+        /// [`Exec::stderr`]: struct.Exec.html#method.stderr
+        /// [`popen`]: struct.Pipeline.html#method.popen
+        pub fn stderr_all(mut self, stderr: impl Into<OutputRedirection>) -> Pipeline {
+            self.stderr_all = stderr.into().into_redirection();
+            self
+        }
+
+        /// Specifies a file to which to redirect the standard error of all
+        /// the commands in the pipeline.
+        ///
+        /// Equivalent to `stderr_all(to)`.
+        pub fn stderr_to(self, to: File) -> Pipeline {
+            self.stderr_all(to)
+        }
+
+        /// Limits the total amount of output and error captured by
+        /// `capture()` and `communicate()` to `bytes`, and specifies what to
+        /// do when the pipeline produces more.
+        ///
+        /// See [`Exec::max_output`] for details.  `OnOverflow::Kill` and
+        /// `OnOverflow::Error` kill all commands in the pipeline.
+        ///
+        /// [`Exec::max_output`]: struct.Exec.html#method.max_output
+        pub fn max_output(mut self, bytes: usize, on_overflow: OnOverflow) -> Pipeline {
+            self.max_output = Some((bytes, on_overflow));
+            self
+        }
+
+        /// Specifies that the commands are killed when the objects that own
+        /// them go out of scope while they are running, instead of being
+        /// waited for.
+        ///
+        /// See [`Exec::kill_on_drop`] for details.
+        ///
+        /// [`Exec::kill_on_drop`]: struct.Exec.html#method.kill_on_drop
+        pub fn kill_on_drop(mut self) -> Pipeline {
+            self.cmds = self.cmds.into_iter().map(Exec::kill_on_drop).collect();
+            self
+        }
+
+        /// Specifies what happens to the commands already started when a
+        /// later command of the pipeline fails to start.
+        ///
+        /// The default is `OnStartFailure::Terminate`.
+        pub fn on_start_failure(mut self, action: OnStartFailure) -> Pipeline {
+            self.on_start_failure = action;
+            self
+        }
+
+        /// Sets the size above which input data given to [`stdin`] is
+        /// passed to the first command through a temporary file rather than
+        /// a pipe.
+        ///
+        /// See [`Exec::stdin_file_threshold`] for details.
+        ///
+        /// [`stdin`]: struct.Pipeline.html#method.stdin
+        /// [`Exec::stdin_file_threshold`]: struct.Exec.html#method.stdin_file_threshold
+        pub fn stdin_file_threshold(mut self, bytes: usize) -> Pipeline {
+            self.stdin_file_threshold = bytes;
+            self
+        }
+
+        /// Redirects the standard input of the first command in the
+        /// pipeline to read from the null device.
+        ///
+        /// Equivalent to `stdin(NullFile)`.
+        pub fn stdin_null(self) -> Pipeline {
+            self.stdin(NullFile)
+        }
+
+        /// Lets the first command in the pipeline read the standard input
+        /// of the parent, typically the terminal, while the output can
+        /// still be captured.
+        ///
+        /// Equivalent to `stdin(Redirection::None)`, which also discards
+        /// previously given input data, so that [`capture`] and
+        /// [`communicate`] leave the inherited standard input alone.
+        ///
+        /// [`capture`]: struct.Pipeline.html#method.capture
+        /// [`communicate`]: struct.Pipeline.html#method.communicate
+        pub fn stdin_inherit(self) -> Pipeline {
+            self.stdin(Redirection::None)
+        }
+
+        /// Redirects the standard output of the last command in the
+        /// pipeline to the null device.
+        ///
+        /// Equivalent to `stdout(NullFile)`.  The output discarded this way
+        /// is not captured by [`capture`], which will return empty
+        /// standard output.
+        ///
+        /// [`capture`]: struct.Pipeline.html#method.capture
+        pub fn stdout_null(self) -> Pipeline {
+            self.stdout(NullFile)
+        }
+
+        /// Redirects the standard error of all the commands in the pipeline
+        /// to the null device.
+        ///
+        /// The errors discarded this way are not captured by [`capture`],
+        /// which will return empty standard error.
+        ///
+        /// [`capture`]: struct.Pipeline.html#method.capture
+        pub fn stderr_null(self) -> Pipeline {
+            let null_file = OpenOptions::new().write(true).open(NULL_DEVICE).unwrap();
+            self.stderr_to(null_file)
+        }
+
+        fn ensure_env(&mut self) {
+            if self.env.is_none() {
+                self.env = Some(PopenConfig::current_env());
+            }
+        }
+
+        /// Clears the environment of all commands in the pipeline.
+        ///
+        /// The environment settings of the pipeline apply to every command,
+        /// underneath the settings made on the individual `Exec`s: variables
+        /// set or removed with `Exec::env` and related methods take
+        /// precedence over the pipeline's.
+        pub fn env_clear(mut self) -> Pipeline {
+            self.env = Some(vec![]);
+            self
+        }
+
+        /// Sets an environment variable in all commands in the pipeline.
+        ///
+        /// See [`env_clear`] for how this combines with the environment
+        /// set on the individual commands.
+        ///
+        /// [`env_clear`]: struct.Pipeline.html#method.env_clear
+        pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Pipeline {
+            self.ensure_env();
+            self.env
+                .as_mut()
+                .unwrap()
+                .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+            self
+        }
+
+        /// Sets multiple environment variables in all commands in the
+        /// pipeline.
+        ///
+        /// See [`env_clear`] for how this combines with the environment
+        /// set on the individual commands.
+        ///
+        /// [`env_clear`]: struct.Pipeline.html#method.env_clear
+        pub fn env_extend(mut self, vars: &[(impl AsRef<OsStr>, impl AsRef<OsStr>)]) -> Pipeline {
+            self.ensure_env();
+            {
+                let envvec = self.env.as_mut().unwrap();
+                for (k, v) in vars {
+                    envvec.push((k.as_ref().to_owned(), v.as_ref().to_owned()));
+                }
+            }
+            self
+        }
+
+        /// Removes an environment variable from all commands in the
+        /// pipeline.
+        pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> Pipeline {
+            self.ensure_env();
+            self.env
+                .as_mut()
+                .unwrap()
+                .retain(|(k, _v)| k != key.as_ref());
             self
         }
 
+        // Take the input data to feed to the first command, first moving
+        // it to a file redirected to stdin if it exceeds the threshold.
+        fn take_stdin_data(&mut self) -> io::Result<Option<Vec<u8>>> {
+            match self.stdin_data.take() {
+                Some(data) if data.len() > self.stdin_file_threshold => {
+                    self.stdin = Redirection::File(stdin_file(data)?);
+                    Ok(None)
+                }
+                data => Ok(data),
+            }
+        }
+
         fn check_no_stdin_data(&self, meth: &str) {
             if self.stdin_data.is_some() {
                 panic!("{} called with input data specified", meth);
@@ -918,30 +4005,72 @@ mod pipeline {
         /// Starts all commands in the pipeline, and returns a
         /// `Vec<Popen>` whose members correspond to running commands.
         ///
-        /// If some command fails to start, the remaining commands
-        /// will not be started, and the appropriate error will be
-        /// returned.  The commands that have already started will be
-        /// waited to finish (but will probably exit immediately due
-        /// to missing output), except for the ones for which
-        /// `detached()` was called.  This is equivalent to what the
-        /// shell does.
+        /// If some command fails to start, the remaining commands will not
+        /// be started, and `PopenError::PipelineStart` is returned,
+        /// identifying the command and containing the error.  The commands
+        /// that have already started are handled as requested with
+        /// [`on_start_failure`]: by default, they are terminated and waited
+        /// for, except for the ones for which `detached()` was called,
+        /// which are left running.  Starting an empty pipeline fails with
+        /// `PopenError::LogicError`.
+        ///
+        /// If input data was specified with [`stdin`], it is written to the
+        /// first command by a background thread, as with [`Exec::popen`].
+        ///
+        /// [`on_start_failure`]: struct.Pipeline.html#method.on_start_failure
+        /// [`stdin`]: struct.Pipeline.html#method.stdin
+        /// [`Exec::popen`]: struct.Exec.html#method.popen
         pub fn popen(mut self) -> PopenResult<Vec<Popen>> {
-            self.check_no_stdin_data("popen");
-            assert!(self.cmds.len() >= 2);
+            let stdin_data = self.take_stdin_data()?;
+            if self.cmds.is_empty() {
+                return Err(PopenError::LogicError("pipeline must not be empty"));
+            }
+
+            if let Some(ref env) = self.env {
+                let inherited = PopenConfig::current_env();
+                for cmd in &mut self.cmds {
+                    cmd.overlay_env(env, &inherited);
+                }
+            }
+
+            if let (Redirection::Pipe, Redirection::Pipe) = (&self.stderr, &self.stderr_all) {
+                return Err(PopenError::LogicError(
+                    "cannot pipe stderr of both the last and all commands",
+                ));
+            }
 
-            if let Some(stderr_to) = self.stderr_file {
-                let stderr_to = Rc::new(stderr_to);
-                self.cmds = self
-                    .cmds
-                    .into_iter()
-                    .map(|cmd| cmd.stderr(Redirection::RcFile(Rc::clone(&stderr_to))))
-                    .collect();
+            // Give the same file or pipe to all commands.
+            let mut stderr_read = None;
+            let stderr_all = match mem::replace(&mut self.stderr_all, Redirection::None) {
+                Redirection::Pipe => {
+                    let (read, write) = crate::popen::make_pipe()?;
+                    crate::popen::set_inheritable(&read, false)?;
+                    crate::popen::set_inheritable(&write, false)?;
+                    stderr_read = Some(read);
+                    Redirection::RcFile(Rc::new(write))
+                }
+                Redirection::File(file) => Redirection::RcFile(Rc::new(file)),
+                other => other,
+            };
+            let last_stderr = mem::replace(&mut self.stderr, Redirection::None);
+            let last_idx = self.cmds.len() - 1;
+            let mut cmds = Vec::with_capacity(self.cmds.len());
+            for (idx, cmd) in self.cmds.into_iter().enumerate() {
+                cmds.push(match (&stderr_all, idx == last_idx) {
+                    (_, true) if !matches!(last_stderr, Redirection::None) => cmd,
+                    (&Redirection::None, _) => cmd,
+                    (r, _) => cmd.stderr(r.try_clone()?),
+                });
             }
+            self.cmds = cmds;
 
             let first_cmd = self.cmds.drain(..1).next().unwrap();
             self.cmds.insert(0, first_cmd.stdin(self.stdin));
 
-            let last_cmd = self.cmds.drain(self.cmds.len() - 1..).next().unwrap();
+            let mut last_cmd = self.cmds.drain(self.cmds.len() - 1..).next().unwrap();
+            if !matches!(last_stderr, Redirection::None) {
+                last_cmd = last_cmd.stderr(last_stderr);
+            }
             self.cmds.push(last_cmd.stdout(self.stdout));
 
             let mut ret = Vec::<Popen>::new();
@@ -955,7 +4084,24 @@ mod pipeline {
                 if idx != cnt - 1 {
                     runner = runner.stdout(Redirection::Pipe);
                 }
-                ret.push(runner.popen()?);
+                let command = runner.to_cmdline_lossy();
+                match runner.popen() {
+                    Ok(p) => ret.push(p),
+                    Err(error) => {
+                        self.on_start_failure.clean_up(ret);
+                        return Err(PopenError::PipelineStart {
+                            index: idx,
+                            command,
+                            error: Box::new(error),
+                        });
+                    }
+                }
+            }
+            if let Some(stderr_read) = stderr_read {
+                ret.last_mut().unwrap().stderr = Some(stderr_read);
+            }
+            if let Some(data) = stdin_data {
+                feed_stdin(ret[0].stdin.take(), data);
             }
             Ok(ret)
         }
@@ -963,7 +4109,6 @@ mod pipeline {
         /// Starts the pipeline, waits for it to finish, and returns
         /// the exit status of the last command.
         pub fn join(self) -> PopenResult<ExitStatus> {
-            self.check_no_stdin_data("join");
             let mut v = self.popen()?;
             // Waiting on a pipeline waits for all commands, but
             // returns the status of the last one.  This is how the
@@ -972,6 +4117,32 @@ mod pipeline {
             v.last_mut().unwrap().wait()
         }
 
+        /// Starts the pipeline and waits for it to finish, unless cancelled
+        /// through `token`, returning the exit status of the last command.
+        ///
+        /// This behaves like [`join`], except that it returns
+        /// `Err(PopenError::Cancelled)` once [`CancelToken::cancel`] is
+        /// called on `token` or one of its clones.  The commands still
+        /// running are then left running and waited for in the background,
+        /// unless [`kill_on_drop`] was requested, in which case they are
+        /// killed.
+        ///
+        /// [`join`]: struct.Pipeline.html#method.join
+        /// [`CancelToken::cancel`]: struct.CancelToken.html#method.cancel
+        /// [`kill_on_drop`]: struct.Pipeline.html#method.kill_on_drop
+        pub fn join_cancellable(self, token: &CancelToken) -> PopenResult<ExitStatus> {
+            let mut v = self.popen()?;
+            let statuses: PopenResult<Vec<ExitStatus>> =
+                v.iter_mut().map(|p| p.wait_cancellable(token)).collect();
+            match statuses {
+                Ok(statuses) => Ok(*statuses.last().unwrap()),
+                Err(e) => {
+                    v.iter_mut().for_each(Popen::wait_in_background);
+                    Err(e)
+                }
+            }
+        }
+
         /// Starts the pipeline and returns a value implementing the `Read`
         /// trait that reads from the standard output of the last command.
         ///
@@ -983,7 +4154,6 @@ mod pipeline {
         /// pipeline to finish.  If this is undesirable, use
         /// `detached()`.
         pub fn stream_stdout(self) -> PopenResult<impl Read> {
-            self.check_no_stdin_data("stream_stdout");
             let v = self.stdout(Redirection::Pipe).popen()?;
             Ok(ReadPipelineAdapter(v))
         }
@@ -1003,22 +4173,35 @@ mod pipeline {
             Ok(WritePipelineAdapter(v))
         }
 
-        fn setup_communicate(mut self) -> PopenResult<(Communicator, Vec<Popen>)> {
-            assert!(self.cmds.len() >= 2);
+        // Start the pipeline with the streams set up for communication,
+        // returning it along with the input data to feed to it.
+        fn start_communicate(mut self) -> PopenResult<(Vec<Popen>, Option<Vec<u8>>)> {
+            // Capture the streams that haven't been explicitly redirected.
+            if let (Redirection::None, Redirection::None) = (&self.stderr, &self.stderr_all) {
+                self.stderr_all = Redirection::Pipe;
+            }
+            if let Redirection::None = self.stdout {
+                self = self.stdout(Redirection::Pipe);
+            }
 
-            let (err_read, err_write) = crate::popen::make_pipe()?;
-            self = self.stderr_to(err_write);
+            let stdin_data = self.take_stdin_data()?;
+            Ok((self.popen()?, stdin_data))
+        }
 
-            let stdin_data = self.stdin_data.take();
-            let mut v = self.stdout(Redirection::Pipe).popen()?;
+        fn setup_communicate(self) -> PopenResult<(Communicator, Vec<Popen>)> {
+            let max_output = self.max_output;
+            let (mut v, stdin_data) = self.start_communicate()?;
             let vlen = v.len();
 
-            let comm = communicate::communicate(
+            let mut comm = communicate::communicate(
                 v[0].stdin.take(),
                 v[vlen - 1].stdout.take(),
-                Some(err_read),
+                v[vlen - 1].stderr.take(),
                 stdin_data,
             );
+            if let Some((bytes, on_overflow)) = max_output {
+                comm = comm.max_output(bytes, on_overflow);
+            }
             Ok((comm, v))
         }
 
@@ -1034,7 +4217,15 @@ mod pipeline {
         /// [`Popen::communicate`]: struct.Popen.html#method.communicate
         pub fn communicate(mut self) -> PopenResult<Communicator> {
             self.cmds = self.cmds.into_iter().map(|cmd| cmd.detached()).collect();
-            let comm = self.setup_communicate()?.0;
+            let max_output = self.max_output;
+            let (mut comm, mut v) = self.setup_communicate()?;
+            if max_output.is_some() {
+                comm.set_kill(move || {
+                    for p in &mut v {
+                        p.kill().ok();
+                    }
+                });
+            }
             Ok(comm)
         }
 
@@ -1045,15 +4236,23 @@ mod pipeline {
         /// the combined standard error of all commands, and the exit status
         /// of the last command.  The captured outputs can be accessed as
         /// bytes or strings.
+        /// Output explicitly redirected elsewhere, such as with
+        /// `stdout_null()` or `stderr_all()`, is not captured.
         ///
         /// Unlike `Popen::communicate`, this method actually waits for the
         /// processes to finish, rather than simply waiting for the output to
         /// close.  If this is undesirable, use `detached()`.
         pub fn capture(self) -> PopenResult<CaptureData> {
             let (mut comm, mut v) = self.setup_communicate()?;
-            let (out, err) = comm.read()?;
+            let result = comm.read();
+            if let Some(OnOverflow::Kill) | Some(OnOverflow::Error) = comm.overflow() {
+                for p in &mut v {
+                    p.kill()?;
+                }
+            }
+            let (out, err) = result?;
             let out = out.unwrap_or_else(Vec::new);
-            let err = err.unwrap();
+            let err = err.unwrap_or_else(Vec::new);
 
             let vlen = v.len();
             let status = v[vlen - 1].wait()?;
@@ -1062,8 +4261,153 @@ mod pipeline {
                 stdout: out,
                 stderr: err,
                 exit_status: status,
+                overflow: comm.overflow(),
+            })
+        }
+
+        /// Starts the pipeline and returns an [`AsyncJob`] for
+        /// communicating with it and waiting for it from async code.
+        ///
+        /// The pipeline is set up as with [`communicate`], the output
+        /// of the last command and the error of all commands being
+        /// captured unless explicitly redirected.
+        ///
+        /// Available with the `async` feature.
+        ///
+        /// [`AsyncJob`]: struct.AsyncJob.html
+        /// [`communicate`]: struct.Pipeline.html#method.communicate
+        #[cfg(feature = "async")]
+        pub fn start_async(self) -> PopenResult<AsyncJob> {
+            self.start_async_on()
+        }
+
+        /// Like [`start_async`], but returns an [`AsyncJob`] driven by the
+        /// async runtime `R` rather than tokio.
+        ///
+        /// Available with the `async` or the `async-smol` feature.
+        ///
+        /// [`start_async`]: struct.Pipeline.html#method.start_async
+        /// [`AsyncJob`]: struct.AsyncJob.html
+        #[cfg(any(feature = "async", feature = "async-smol"))]
+        pub fn start_async_on<R: AsyncRuntime>(mut self) -> PopenResult<AsyncJob<R>> {
+            self.cmds = self
+                .cmds
+                .into_iter()
+                .map(|cmd| cmd.wait_in_background())
+                .collect();
+            let max_output = self.max_output;
+            let (v, stdin_data) = self.start_communicate()?;
+            Ok(AsyncJob::new(v, stdin_data, max_output))
+        }
+
+        /// Starts the pipeline, collects the output of the last command and
+        /// the error of all commands in the order they arrive, and waits
+        /// for all commands to finish.
+        ///
+        /// The streams are captured as with `capture()`, but their relative
+        /// order is preserved, each piece being tagged with the stream it
+        /// was read from.  See [`Exec::capture_transcript`] for details.
+        ///
+        /// [`Exec::capture_transcript`]: struct.Exec.html#method.capture_transcript
+        pub fn capture_transcript(self) -> PopenResult<Transcript> {
+            let (mut comm, mut v) = self.setup_communicate()?;
+            let result = comm.read_transcript();
+            if let Some(OnOverflow::Kill) | Some(OnOverflow::Error) = comm.overflow() {
+                for p in &mut v {
+                    p.kill()?;
+                }
+            }
+            let records = result?;
+
+            let vlen = v.len();
+            let status = v[vlen - 1].wait()?;
+
+            Ok(Transcript {
+                records,
+                exit_status: status,
+                overflow: comm.overflow(),
             })
         }
+
+        /// Describes how the commands of the pipeline would be started,
+        /// without starting them.
+        ///
+        /// Returns a [`SpawnPlan`] for each command, as described at
+        /// [`Exec::explain`], with the streams connecting the commands
+        /// shown as pipes to the previous and the next command.
+        ///
+        /// [`SpawnPlan`]: struct.SpawnPlan.html
+        /// [`Exec::explain`]: struct.Exec.html#method.explain
+        pub fn explain(&self) -> Vec<SpawnPlan> {
+            let last = self.cmds.len().saturating_sub(1);
+            let mut plans = vec![];
+            for (idx, cmd) in self.cmds.iter().enumerate() {
+                let mut plan = cmd.plan(self.env.as_deref());
+                if idx != 0 {
+                    // the commands are numbered from 1, as in the report
+                    plan.streams[0] = format!("pipe from command {}", idx);
+                } else if let Some(ref data) = self.stdin_data {
+                    plan.streams[0] = describe_stdin_data(data, self.stdin_file_threshold);
+                } else if !matches!(self.stdin, Redirection::None) {
+                    plan.streams[0] = cmd.describe_stream(&self.stdin, 0);
+                }
+                if idx != last {
+                    plan.streams[1] = format!("pipe to command {}", idx + 2);
+                } else if !matches!(self.stdout, Redirection::None) {
+                    plan.streams[1] = cmd.describe_stream(&self.stdout, 1);
+                }
+                if idx == last && !matches!(self.stderr, Redirection::None) {
+                    plan.streams[2] = cmd.describe_stream(&self.stderr, 2);
+                } else if let Redirection::Pipe = self.stderr_all {
+                    plan.streams[2] = "pipe shared by all commands".to_owned();
+                } else if !matches!(self.stderr_all, Redirection::None) {
+                    plan.streams[2] = cmd.describe_stream(&self.stderr_all, 2);
+                }
+                plans.push(plan);
+            }
+            plans
+        }
+    }
+
+    /// Handling of the started commands of a pipeline whose later command
+    /// failed to start, set with [`Pipeline::on_start_failure`].
+    ///
+    /// [`Pipeline::on_start_failure`]: struct.Pipeline.html#method.on_start_failure
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub enum OnStartFailure {
+        /// Terminate the commands and wait for them to exit, killing the
+        /// ones that are still running after a second.
+        ///
+        /// The commands might otherwise keep running, for example the
+        /// first one waiting for input from the terminal it inherited.
+        /// Commands for which `detached()` was called are left running,
+        /// as the shell does.
+        Terminate,
+        /// Leave the commands running, and wait for them in the
+        /// background, as with [`Popen::wait_in_background`].
+        ///
+        /// [`Popen::wait_in_background`]: struct.Popen.html#method.wait_in_background
+        Detach,
+    }
+
+    // Time given to the started commands to exit after being terminated.
+    const START_FAILURE_GRACE: Duration = Duration::from_secs(1);
+
+    impl OnStartFailure {
+        fn clean_up(self, started: Vec<Popen>) {
+            match self {
+                OnStartFailure::Terminate => {
+                    let mut attached: Vec<_> =
+                        started.into_iter().filter(|p| !p.is_detached()).collect();
+                    Popen::shutdown_all(&mut attached, START_FAILURE_GRACE).ok();
+                }
+                OnStartFailure::Detach => {
+                    for mut p in started {
+                        p.wait_in_background();
+                    }
+                }
+            }
+        }
     }
 
     impl Clone for Pipeline {
@@ -1078,10 +4422,15 @@ mod pipeline {
         fn clone(&self) -> Pipeline {
             Pipeline {
                 cmds: self.cmds.clone(),
+                on_start_failure: self.on_start_failure,
                 stdin: self.stdin.try_clone().unwrap(),
                 stdout: self.stdout.try_clone().unwrap(),
-                stderr_file: self.stderr_file.as_ref().map(|f| f.try_clone().unwrap()),
+                stderr: self.stderr.try_clone().unwrap(),
+                stderr_all: self.stderr_all.try_clone().unwrap(),
                 stdin_data: self.stdin_data.clone(),
+                max_output: self.max_output,
+                env: self.env.clone(),
+                stdin_file_threshold: self.stdin_file_threshold,
             }
         }
     }
@@ -1090,6 +4439,9 @@ mod pipeline {
         type Output = Pipeline;
 
         /// Append a command to the pipeline and return a new pipeline.
+        ///
+        /// The `stderr_all` of the pipeline also applies to the appended
+        /// command.
         fn bitor(mut self, rhs: Exec) -> Pipeline {
             self.cmds.push(rhs);
             self
@@ -1100,9 +4452,16 @@ mod pipeline {
         type Output = Pipeline;
 
         /// Append a pipeline to the pipeline and return a new pipeline.
+        ///
+        /// The `stderr_all` of `rhs`, if specified, replaces that of the
+        /// pipeline and applies to all the commands.
         fn bitor(mut self, rhs: Pipeline) -> Pipeline {
             self.cmds.extend(rhs.cmds);
             self.stdout = rhs.stdout;
+            self.stderr = rhs.stderr;
+            if !matches!(rhs.stderr_all, Redirection::None) {
+                self.stderr_all = rhs.stderr_all;
+            }
             self
         }
     }
@@ -4,12 +4,19 @@ use std::fs::File;
 use std::io::{self, ErrorKind};
 use std::time::{Duration, Instant};
 
+use crate::stats;
+
 #[cfg(unix)]
 mod raw {
+    use super::OutputSource;
     use crate::posix;
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    use crate::uring::{self, Ring};
     use std::cmp::min;
     use std::fs::File;
     use std::io::{self, Read, Write};
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    use std::os::unix::io::AsRawFd;
     use std::time::{Duration, Instant};
 
     fn as_pollfd<'a>(f: Option<&'a File>, for_read: bool) -> posix::PollFd<'a> {
@@ -70,6 +77,18 @@ mod raw {
         stderr: Option<File>,
         input_data: Vec<u8>,
         input_pos: usize,
+        stdin_written: u64,
+        keep_stdin_open: bool,
+        closing: bool,
+        chunk_log: Option<Vec<(OutputSource, usize)>>,
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        uring: Option<Ring>,
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        uring_tried: bool,
+        // Data read by io_uring beyond the size limit, returned by the next
+        // read.
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        leftover: [Vec<u8>; 2],
     }
 
     impl RawCommunicator {
@@ -79,21 +98,59 @@ mod raw {
             stderr: Option<File>,
             input_data: Option<Vec<u8>>,
         ) -> RawCommunicator {
-            let input_data = input_data.unwrap_or_else(Vec::new);
+            let input_data = input_data.unwrap_or_default();
             RawCommunicator {
                 stdin,
                 stdout,
                 stderr,
                 input_data,
                 input_pos: 0,
+                stdin_written: 0,
+                keep_stdin_open: false,
+                closing: false,
+                chunk_log: None,
+                #[cfg(all(target_os = "linux", feature = "io-uring"))]
+                uring: None,
+                #[cfg(all(target_os = "linux", feature = "io-uring"))]
+                uring_tried: false,
+                #[cfg(all(target_os = "linux", feature = "io-uring"))]
+                leftover: [vec![], vec![]],
             }
         }
 
+        pub fn set_chunk_log(&mut self, log: Option<Vec<(OutputSource, usize)>>) {
+            self.chunk_log = log;
+        }
+
+        pub fn take_chunk_log(&mut self) -> Option<Vec<(OutputSource, usize)>> {
+            self.chunk_log.take()
+        }
+
+        pub fn stdin_written(&self) -> u64 {
+            self.stdin_written
+        }
+
+        pub fn set_keep_stdin_open(&mut self, keep: bool) {
+            self.keep_stdin_open = keep;
+        }
+
+        pub fn feed(&mut self, data: &[u8]) {
+            if self.stdin.is_none() || self.closing {
+                panic!("cannot feed input to a closed or non-redirected stdin");
+            }
+            if self.input_pos == self.input_data.len() {
+                self.input_data.clear();
+                self.input_pos = 0;
+            }
+            self.input_data.extend_from_slice(data);
+        }
+
         fn do_read(
             source_ref: &mut Option<&File>,
             dest: &mut Vec<u8>,
             size_limit: Option<usize>,
             total_read: usize,
+            chunk_log: Option<(&mut Vec<(OutputSource, usize)>, OutputSource)>,
         ) -> io::Result<()> {
             let mut buf = &mut [0u8; 4096][..];
             if let Some(size_limit) = size_limit {
@@ -104,9 +161,17 @@ mod raw {
                     buf = &mut buf[0..size_limit - total_read];
                 }
             }
-            let n = source_ref.unwrap().read(buf)?;
+            let n = match source_ref.unwrap().read(buf) {
+                // the master side of a pty reports the closing of the
+                // slave side as an error rather than end of file
+                Err(e) if e.raw_os_error() == Some(posix::EIO) => 0,
+                result => result?,
+            };
             if n != 0 {
                 dest.extend_from_slice(&buf[..n]);
+                if let Some((log, source)) = chunk_log {
+                    log.push((source, n));
+                }
             } else {
                 *source_ref = None;
             }
@@ -116,6 +181,7 @@ mod raw {
         fn read_into(
             &mut self,
             deadline: Option<Instant>,
+            idle_timeout: Option<Duration>,
             size_limit: Option<usize>,
             outvec: &mut Vec<u8>,
             errvec: &mut Vec<u8>,
@@ -124,8 +190,34 @@ mod raw {
             // size.  A large enough write to a pipe deadlocks despite polling.
             const WRITE_SIZE: usize = 4096;
 
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            {
+                if !self.uring_tried {
+                    // fall back to polling if io_uring is unavailable
+                    self.uring_tried = true;
+                    self.uring = Ring::new().ok();
+                }
+                if let Some(mut ring) = self.uring.take() {
+                    let result = self.read_into_uring(
+                        &mut ring,
+                        deadline,
+                        idle_timeout,
+                        size_limit,
+                        outvec,
+                        errvec,
+                    );
+                    if ring.idle() {
+                        self.uring = Some(ring);
+                    }
+                    return result;
+                }
+            }
+
             let mut stdout_ref = self.stdout.as_ref();
             let mut stderr_ref = self.stderr.as_ref();
+            if !self.keep_stdin_open {
+                self.closing = true;
+            }
 
             loop {
                 if let Some(size_limit) = size_limit {
@@ -134,13 +226,28 @@ mod raw {
                     }
                 }
 
-                if let (None, None, None) = (self.stdin.as_ref(), stdout_ref, stderr_ref) {
+                let input_pending = self.input_pos < self.input_data.len();
+                if self.closing && !input_pending && self.stdin.is_some() {
+                    // close stdin when done writing, so the child receives EOF
+                    self.stdin.take();
+                    // deallocate the input data, we don't need it any more
+                    self.input_data = Vec::new();
+                    self.input_pos = 0;
+                }
+                // stdin kept open with nothing to write is not waited on
+                let stdin_ref = self.stdin.as_ref().filter(|_| input_pending);
+
+                if let (None, None, None) = (stdin_ref, stdout_ref, stderr_ref) {
                     // When no stream remains, we are done.
                     break;
                 }
 
-                let (in_ready, out_ready, err_ready) =
-                    maybe_poll(self.stdin.as_ref(), stdout_ref, stderr_ref, deadline)?;
+                let (in_ready, out_ready, err_ready) = maybe_poll(
+                    stdin_ref,
+                    stdout_ref,
+                    stderr_ref,
+                    super::idle_deadline(deadline, idle_timeout),
+                )?;
                 if !in_ready && !out_ready && !err_ready {
                     return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
                 }
@@ -149,12 +256,7 @@ mod raw {
                     let chunk = &input[..min(WRITE_SIZE, input.len())];
                     let n = self.stdin.as_ref().unwrap().write(chunk)?;
                     self.input_pos += n;
-                    if self.input_pos == self.input_data.len() {
-                        // close stdin when done writing, so the child receives EOF
-                        self.stdin.take();
-                        // deallocate the input data, we don't need it any more
-                        self.input_data = Vec::new();
-                    }
+                    self.stdin_written += n as u64;
                 }
                 if out_ready {
                     RawCommunicator::do_read(
@@ -162,6 +264,9 @@ mod raw {
                         outvec,
                         size_limit,
                         outvec.len() + errvec.len(),
+                        self.chunk_log
+                            .as_mut()
+                            .map(|log| (log, OutputSource::Stdout)),
                     )?;
                 }
                 if err_ready {
@@ -170,6 +275,9 @@ mod raw {
                         errvec,
                         size_limit,
                         outvec.len() + errvec.len(),
+                        self.chunk_log
+                            .as_mut()
+                            .map(|log| (log, OutputSource::Stderr)),
                     )?;
                 }
             }
@@ -177,16 +285,185 @@ mod raw {
             Ok(())
         }
 
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        fn read_into_uring(
+            &mut self,
+            ring: &mut Ring,
+            deadline: Option<Instant>,
+            idle_timeout: Option<Duration>,
+            size_limit: Option<usize>,
+            outvec: &mut Vec<u8>,
+            errvec: &mut Vec<u8>,
+        ) -> io::Result<()> {
+            let result = self.drive_uring(ring, deadline, idle_timeout, size_limit, outvec, errvec);
+            // The streams and the input can change between reads, so no
+            // operation is left in flight.
+            for (slot, res) in ring.cancel_all()? {
+                let _ = self.uring_completed(ring, slot, res, size_limit, outvec, errvec);
+            }
+            result
+        }
+
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        fn drive_uring(
+            &mut self,
+            ring: &mut Ring,
+            deadline: Option<Instant>,
+            idle_timeout: Option<Duration>,
+            size_limit: Option<usize>,
+            outvec: &mut Vec<u8>,
+            errvec: &mut Vec<u8>,
+        ) -> io::Result<()> {
+            let mut stdout_open = self.stdout.is_some();
+            let mut stderr_open = self.stderr.is_some();
+            if !self.keep_stdin_open {
+                self.closing = true;
+            }
+            self.deliver(uring::STDOUT, &[], size_limit, outvec, errvec);
+            self.deliver(uring::STDERR, &[], size_limit, outvec, errvec);
+
+            loop {
+                let room = size_limit.map_or(usize::MAX, |size_limit| {
+                    size_limit.saturating_sub(outvec.len() + errvec.len())
+                });
+                if room == 0 {
+                    break;
+                }
+
+                let input_pending = self.input_pos < self.input_data.len();
+                if !ring.busy(uring::STDIN) {
+                    if self.closing && !input_pending && self.stdin.is_some() {
+                        self.stdin.take();
+                        self.input_data = Vec::new();
+                        self.input_pos = 0;
+                    }
+                    if let (Some(stdin), true) = (&self.stdin, input_pending) {
+                        ring.start_write(stdin.as_raw_fd(), &self.input_data[self.input_pos..])?;
+                    }
+                }
+                if let (Some(stdout), true) = (&self.stdout, stdout_open) {
+                    if !ring.busy(uring::STDOUT) {
+                        ring.start_read(uring::STDOUT, stdout.as_raw_fd(), room)?;
+                    }
+                }
+                if let (Some(stderr), true) = (&self.stderr, stderr_open) {
+                    if !ring.busy(uring::STDERR) {
+                        ring.start_read(uring::STDERR, stderr.as_raw_fd(), room)?;
+                    }
+                }
+
+                if ring.idle() {
+                    // When no stream remains, we are done.
+                    break;
+                }
+                if !ring.wait(super::idle_deadline(deadline, idle_timeout))? {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+                }
+                // Process the whole batch before reporting an error, so that
+                // the data of the other completions isn't lost.
+                let mut error = None;
+                for (slot, res) in ring.reap() {
+                    match self.uring_completed(ring, slot, res, size_limit, outvec, errvec) {
+                        Ok(true) => match slot {
+                            uring::STDOUT => stdout_open = false,
+                            _ => stderr_open = false,
+                        },
+                        Ok(false) => (),
+                        Err(e) => {
+                            error.get_or_insert(e);
+                        }
+                    }
+                }
+                if let Some(e) = error {
+                    return Err(e);
+                }
+            }
+
+            Ok(())
+        }
+
+        // Process the result of an io_uring operation, returning true on
+        // end of file.
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        fn uring_completed(
+            &mut self,
+            ring: &Ring,
+            slot: usize,
+            res: i32,
+            size_limit: Option<usize>,
+            outvec: &mut Vec<u8>,
+            errvec: &mut Vec<u8>,
+        ) -> io::Result<bool> {
+            if res < 0 {
+                if res == -libc::ECANCELED {
+                    return Ok(false);
+                }
+                // the master side of a pty reports the closing of the
+                // slave side as an error rather than end of file
+                if res == -posix::EIO && slot != uring::STDIN {
+                    return Ok(true);
+                }
+                return Err(io::Error::from_raw_os_error(-res));
+            }
+            let n = res as usize;
+            if slot == uring::STDIN {
+                self.input_pos += n;
+                self.stdin_written += n as u64;
+                return Ok(false);
+            }
+            if n == 0 {
+                return Ok(true);
+            }
+            self.deliver(slot, ring.data(slot, n), size_limit, outvec, errvec);
+            Ok(false)
+        }
+
+        // Append `data` read from the stream in `slot` to its vector, up to
+        // the size limit, keeping the rest for later.
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        fn deliver(
+            &mut self,
+            slot: usize,
+            data: &[u8],
+            size_limit: Option<usize>,
+            outvec: &mut Vec<u8>,
+            errvec: &mut Vec<u8>,
+        ) {
+            let room = size_limit.map_or(usize::MAX, |size_limit| {
+                size_limit.saturating_sub(outvec.len() + errvec.len())
+            });
+            let (dest, source) = match slot {
+                uring::STDOUT => (outvec, OutputSource::Stdout),
+                _ => (errvec, OutputSource::Stderr),
+            };
+            let leftover = &mut self.leftover[slot - uring::STDOUT];
+            let n = if leftover.is_empty() {
+                let n = min(room, data.len());
+                dest.extend_from_slice(&data[..n]);
+                leftover.extend_from_slice(&data[n..]);
+                n
+            } else {
+                leftover.extend_from_slice(data);
+                let n = min(room, leftover.len());
+                dest.extend(leftover.drain(..n));
+                n
+            };
+            if let (Some(log), true) = (self.chunk_log.as_mut(), n != 0) {
+                log.push((source, n));
+            }
+        }
+
         pub fn read(
             &mut self,
             deadline: Option<Instant>,
+            idle_timeout: Option<Duration>,
             size_limit: Option<usize>,
         ) -> (Option<io::Error>, (Option<Vec<u8>>, Option<Vec<u8>>)) {
             let mut outvec = vec![];
             let mut errvec = vec![];
 
             let err = self
-                .read_into(deadline, size_limit, &mut outvec, &mut errvec)
+                .read_into(deadline, idle_timeout, size_limit, &mut outvec, &mut errvec)
                 .err();
             let output = (
                 self.stdout.as_ref().map(|_| outvec),
@@ -199,11 +476,13 @@ mod raw {
 
 #[cfg(windows)]
 mod raw {
+    use super::OutputSource;
+    use crate::win32;
     use std::fs::File;
     use std::io::{self, Read, Write};
-    use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
     use std::thread;
-    use std::time::Instant;
+    use std::time::{Duration, Instant};
 
     #[derive(Debug, Copy, Clone)]
     enum StreamIdent {
@@ -214,6 +493,7 @@ mod raw {
 
     enum Payload {
         Data(Vec<u8>),
+        Written(usize),
         EOF,
         Err(io::Error),
     }
@@ -221,41 +501,104 @@ mod raw {
     // Messages exchanged between RawCommunicator's helper threads.
     type Message = (StreamIdent, Payload);
 
+    // Bounds of the buffer used for reading output.  The buffer grows while
+    // the child fills it and shrinks when it doesn't, so large outputs are
+    // transmitted in few chunks without penalizing small ones.
+    const MIN_CHUNK: usize = 4 * 1024;
+    const MAX_CHUNK: usize = 256 * 1024;
+
     fn read_and_transmit(mut outfile: File, ident: StreamIdent, sink: SyncSender<Message>) {
-        let mut chunk = [0u8; 4096];
+        let mut capacity = MIN_CHUNK;
         // Note: failing to send to the sink means we're done.  Sending will
         // fail if the main thread drops the RawCommunicator (and with it the
         // receiver) prematurely e.g. because a limit was reached or another
         // helper encountered an IO error.
         loop {
-            match outfile.read(&mut chunk) {
-                Ok(0) => {
-                    let _ = sink.send((ident, Payload::EOF));
-                    break;
-                }
-                Ok(nread) => {
-                    if let Err(_) = sink.send((ident, Payload::Data(chunk[..nread].to_vec()))) {
+            let mut chunk = vec![0u8; capacity];
+            let mut nread = 0;
+            let mut end = None;
+            while nread < chunk.len() {
+                match outfile.read(&mut chunk[nread..]) {
+                    Ok(0) => {
+                        end = Some(Payload::EOF);
                         break;
                     }
+                    Ok(n) => nread += n,
+                    Err(e) => {
+                        end = Some(Payload::Err(e));
+                        break;
+                    }
+                }
+                // Coalesce the data the pipe already holds into the same
+                // chunk, but don't wait for more.
+                if win32::PeekNamedPipe(&outfile).unwrap_or(0) == 0 {
+                    break;
+                }
+            }
+            if nread != 0 {
+                if nread == capacity {
+                    capacity = (capacity * 2).min(MAX_CHUNK);
+                } else if nread < capacity / 4 {
+                    capacity = (capacity / 2).max(MIN_CHUNK);
                 }
-                Err(e) => {
-                    let _ = sink.send((ident, Payload::Err(e)));
+                chunk.truncate(nread);
+                if let Err(_) = sink.send((ident, Payload::Data(chunk))) {
                     break;
                 }
             }
+            if let Some(end) = end {
+                let _ = sink.send((ident, end));
+                break;
+            }
         }
     }
 
+    // Write chunks of input to stdin as they arrive, and close it once the
+    // sending side is dropped.
+    fn write_chunks(mut infile: File, chunks: Receiver<Vec<u8>>, sink: SyncSender<Message>) {
+        for chunk in chunks {
+            if let Err(e) = infile.write_all(&chunk) {
+                let _ = sink.send((StreamIdent::In, Payload::Err(e)));
+                return;
+            }
+            if let Err(_) = sink.send((StreamIdent::In, Payload::Written(chunk.len()))) {
+                return;
+            }
+        }
+        drop(infile);
+        let _ = sink.send((StreamIdent::In, Payload::EOF));
+    }
+
     fn spawn_with_arg<T: Send + 'static>(f: impl FnOnce(T) + Send + 'static, arg: T) {
         thread::spawn(move || f(arg));
     }
 
+    // Default number of messages the helper threads can queue before
+    // waiting for the reader.
+    const DEFAULT_CHANNEL_CAPACITY: usize = 4;
+
+    // Pipes handed over to the helper threads.  The threads are started by
+    // the first read, so that the channel capacity can be set beforehand.
+    #[derive(Debug)]
+    struct Helpers {
+        stdin: Option<(File, Receiver<Vec<u8>>)>,
+        stdout: Option<File>,
+        stderr: Option<File>,
+    }
+
     #[derive(Debug)]
     pub struct RawCommunicator {
-        rx: mpsc::Receiver<Message>,
+        rx: Option<mpsc::Receiver<Message>>,
+        helpers: Option<Helpers>,
+        channel_capacity: usize,
         helper_set: u8,
         requested_streams: u8,
         leftover: Option<(StreamIdent, Vec<u8>)>,
+        input_tx: Option<Sender<Vec<u8>>>,
+        input_len: u64,
+        stdin_written: u64,
+        keep_stdin_open: bool,
+        chunk_log: Option<Vec<(OutputSource, usize)>>,
     }
 
     struct Timeout;
@@ -269,46 +612,119 @@ mod raw {
         ) -> RawCommunicator {
             let mut helper_set = 0u8;
             let mut requested_streams = 0u8;
+            let input_len = input_data.as_ref().map(|d| d.len() as u64).unwrap_or(0);
 
-            let read_stdout = stdout.map(|stdout| {
+            if stdout.is_some() {
                 helper_set |= StreamIdent::Out as u8;
                 requested_streams |= StreamIdent::Out as u8;
-                |tx| read_and_transmit(stdout, StreamIdent::Out, tx)
-            });
-            let read_stderr = stderr.map(|stderr| {
+            }
+            if stderr.is_some() {
                 helper_set |= StreamIdent::Err as u8;
                 requested_streams |= StreamIdent::Err as u8;
-                |tx| read_and_transmit(stderr, StreamIdent::Err, tx)
-            });
-            let write_stdin = stdin.map(|mut stdin| {
+            }
+            let mut input_tx = None;
+            let stdin = stdin.map(|stdin| {
                 let input_data = input_data.expect("must provide input to redirected stdin");
                 helper_set |= StreamIdent::In as u8;
-                move |tx: SyncSender<_>| match stdin.write_all(&input_data) {
-                    Ok(()) => drop(tx.send((StreamIdent::In, Payload::EOF))),
-                    Err(e) => drop(tx.send((StreamIdent::In, Payload::Err(e)))),
+                let (chunk_tx, chunk_rx) = mpsc::channel();
+                if !input_data.is_empty() {
+                    chunk_tx.send(input_data).unwrap();
                 }
+                input_tx = Some(chunk_tx);
+                (stdin, chunk_rx)
             });
 
-            let (tx, rx) = mpsc::sync_channel(0);
-
-            read_stdout.map(|f| spawn_with_arg(f, tx.clone()));
-            read_stderr.map(|f| spawn_with_arg(f, tx.clone()));
-            write_stdin.map(|f| spawn_with_arg(f, tx.clone()));
-
             RawCommunicator {
-                rx,
+                rx: None,
+                helpers: Some(Helpers {
+                    stdin,
+                    stdout,
+                    stderr,
+                }),
+                channel_capacity: DEFAULT_CHANNEL_CAPACITY,
                 helper_set,
                 requested_streams,
                 leftover: None,
+                input_tx,
+                input_len,
+                stdin_written: 0,
+                keep_stdin_open: false,
+                chunk_log: None,
+            }
+        }
+
+        pub fn set_chunk_log(&mut self, log: Option<Vec<(OutputSource, usize)>>) {
+            self.chunk_log = log;
+        }
+
+        pub fn take_chunk_log(&mut self) -> Option<Vec<(OutputSource, usize)>> {
+            self.chunk_log.take()
+        }
+
+        pub fn stdin_written(&self) -> u64 {
+            self.stdin_written
+        }
+
+        pub fn set_keep_stdin_open(&mut self, keep: bool) {
+            self.keep_stdin_open = keep;
+        }
+
+        // Only has an effect before the first read, which starts the
+        // helpers.
+        pub fn set_channel_capacity(&mut self, capacity: usize) {
+            self.channel_capacity = capacity;
+        }
+
+        fn start_helpers(&mut self) {
+            let helpers = match self.helpers.take() {
+                Some(helpers) => helpers,
+                None => return,
+            };
+            let (tx, rx) = mpsc::sync_channel(self.channel_capacity);
+            if let Some(stdout) = helpers.stdout {
+                spawn_with_arg(
+                    |tx| read_and_transmit(stdout, StreamIdent::Out, tx),
+                    tx.clone(),
+                );
+            }
+            if let Some(stderr) = helpers.stderr {
+                spawn_with_arg(
+                    |tx| read_and_transmit(stderr, StreamIdent::Err, tx),
+                    tx.clone(),
+                );
+            }
+            if let Some((stdin, chunk_rx)) = helpers.stdin {
+                spawn_with_arg(move |tx| write_chunks(stdin, chunk_rx, tx), tx);
+            }
+            self.rx = Some(rx);
+        }
+
+        pub fn feed(&mut self, data: &[u8]) {
+            let input_tx = self
+                .input_tx
+                .as_ref()
+                .expect("cannot feed input to a closed or non-redirected stdin");
+            if !data.is_empty() {
+                // the helper only exits after the sender is dropped
+                input_tx.send(data.to_vec()).unwrap();
+                self.input_len += data.len() as u64;
             }
         }
 
+        // The stdin helper counts as finished when stdin is kept open and
+        // everything fed so far has been written.
+        fn waiting_for_helpers(&self) -> bool {
+            let mut pending = self.helper_set;
+            if self.input_tx.is_some() && self.stdin_written == self.input_len {
+                pending &= !(StreamIdent::In as u8);
+            }
+            pending != 0
+        }
+
         fn recv_until(&self, deadline: Option<Instant>) -> Result<Message, Timeout> {
+            let rx = self.rx.as_ref().unwrap();
             if let Some(deadline) = deadline {
-                match self
-                    .rx
-                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
-                {
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
                     Ok(message) => Ok(message),
                     Err(RecvTimeoutError::Timeout) => Err(Timeout),
                     // should never be disconnected, the helper threads always
@@ -316,16 +732,18 @@ mod raw {
                     Err(RecvTimeoutError::Disconnected) => unreachable!(),
                 }
             } else {
-                Ok(self.rx.recv().unwrap())
+                Ok(rx.recv().unwrap())
             }
         }
 
         fn read_into(
             &mut self,
             deadline: Option<Instant>,
+            idle_timeout: Option<Duration>,
             size_limit: Option<usize>,
             outvec: &mut Vec<u8>,
             errvec: &mut Vec<u8>,
+            mut chunk_log: Option<&mut Vec<(OutputSource, usize)>>,
         ) -> io::Result<()> {
             let mut grow_result =
                 |ident, mut data: &[u8], leftover: &mut Option<(StreamIdent, Vec<u8>)>| {
@@ -340,10 +758,19 @@ mod raw {
                             data = &data[..remaining];
                         }
                     }
-                    match ident {
-                        StreamIdent::Out => outvec.extend_from_slice(data),
-                        StreamIdent::Err => errvec.extend_from_slice(data),
+                    let source = match ident {
+                        StreamIdent::Out => {
+                            outvec.extend_from_slice(data);
+                            OutputSource::Stdout
+                        }
+                        StreamIdent::Err => {
+                            errvec.extend_from_slice(data);
+                            OutputSource::Stderr
+                        }
                         StreamIdent::In => unreachable!(),
+                    };
+                    if let Some(ref mut log) = chunk_log {
+                        log.push((source, data.len()));
                     }
                     if let Some(size_limit) = size_limit {
                         if outvec.len() + errvec.len() >= size_limit {
@@ -353,18 +780,30 @@ mod raw {
                     return true;
                 };
 
+            self.start_helpers();
+
+            if !self.keep_stdin_open {
+                // dropping the sender makes the helper close stdin once
+                // it has written everything
+                self.input_tx.take();
+            }
+
             if let Some((ident, data)) = self.leftover.take() {
                 if !grow_result(ident, &data, &mut self.leftover) {
                     return Ok(());
                 }
             }
 
-            while self.helper_set != 0 {
-                match self.recv_until(deadline) {
+            while self.waiting_for_helpers() {
+                match self.recv_until(super::idle_deadline(deadline, idle_timeout)) {
                     Ok((ident, Payload::EOF)) => {
                         self.helper_set &= !(ident as u8);
                         continue;
                     }
+                    Ok((_ident, Payload::Written(n))) => {
+                        self.stdin_written += n as u64;
+                        continue;
+                    }
                     Ok((ident, Payload::Data(data))) => {
                         assert!(data.len() != 0);
                         if !grow_result(ident, &data, &mut self.leftover) {
@@ -385,6 +824,7 @@ mod raw {
         pub fn read(
             &mut self,
             deadline: Option<Instant>,
+            idle_timeout: Option<Duration>,
             size_limit: Option<usize>,
         ) -> (Option<io::Error>, (Option<Vec<u8>>, Option<Vec<u8>>)) {
             // Create both vectors immediately.  This doesn't allocate, and if
@@ -392,9 +832,18 @@ mod raw {
             let mut outvec = vec![];
             let mut errvec = vec![];
 
+            let mut chunk_log = self.chunk_log.take();
             let err = self
-                .read_into(deadline, size_limit, &mut outvec, &mut errvec)
+                .read_into(
+                    deadline,
+                    idle_timeout,
+                    size_limit,
+                    &mut outvec,
+                    &mut errvec,
+                    chunk_log.as_mut(),
+                )
                 .err();
+            self.chunk_log = chunk_log;
             let output = {
                 let (mut o, mut e) = (None, None);
                 if self.requested_streams & StreamIdent::Out as u8 != 0 {
@@ -416,6 +865,16 @@ mod raw {
 
 use raw::RawCommunicator;
 
+// Deadline for the next wait for I/O, which resets the idle timeout each
+// time the subprocess reads or produces some data.
+fn idle_deadline(deadline: Option<Instant>, idle_timeout: Option<Duration>) -> Option<Instant> {
+    let idle_deadline = idle_timeout.map(|idle| Instant::now() + idle);
+    match (deadline, idle_deadline) {
+        (Some(deadline), Some(idle_deadline)) => Some(deadline.min(idle_deadline)),
+        (deadline, idle_deadline) => deadline.or(idle_deadline),
+    }
+}
+
 /// Unattended data exchange with the subprocess.
 ///
 /// When a subprocess both expects input and provides output, care must be
@@ -425,13 +884,102 @@ use raw::RawCommunicator;
 /// writing the input, it cannot read the output and a deadlock occurs.  This
 /// implementation avoids this issue by by reading from and writing to the
 /// subprocess in parallel.  On Unix-like systems this is achieved using
-/// `poll()`, and on Windows using threads.
+/// `poll()`, and on Windows using threads.  With the `io-uring` feature,
+/// Linux kernels 5.11 and later use io_uring instead of `poll()`, which
+/// requires fewer system calls for large transfers.
 #[must_use]
-#[derive(Debug)]
 pub struct Communicator {
     inner: RawCommunicator,
     size_limit: Option<usize>,
     time_limit: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    stats: IoStats,
+    stats_callback: Option<Box<dyn FnMut(IoStats) + Send>>,
+    max_output: Option<(usize, OnOverflow)>,
+    overflow: Option<OnOverflow>,
+    kill: Option<Box<dyn FnMut() + Send>>,
+    kill_on_timeout: bool,
+}
+
+/// Action taken when a subprocess produces more output than allowed.
+///
+/// Used with [`Communicator::max_output`] and [`Exec::max_output`].
+///
+/// [`Communicator::max_output`]: struct.Communicator.html#method.max_output
+/// [`Exec::max_output`]: struct.Exec.html#method.max_output
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OnOverflow {
+    /// Stop reading and kill the subprocess.
+    ///
+    /// The output up to the limit is returned normally.
+    Kill,
+    /// Keep the output up to the limit and discard the rest, letting the
+    /// subprocess run to completion.
+    Truncate,
+    /// Stop reading, kill the subprocess, and return an error of kind
+    /// `ErrorKind::Other`.
+    ///
+    /// The output up to the limit is available in the error.
+    Error,
+}
+
+/// Output stream of a subprocess that a piece of a transcript came from.
+///
+/// Used by [`Communicator::read_transcript`].
+///
+/// [`Communicator::read_transcript`]: struct.Communicator.html#method.read_transcript
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum OutputSource {
+    /// The standard output.
+    Stdout,
+    /// The standard error.
+    Stderr,
+}
+
+/// Action taken on the subprocess when communication times out.
+///
+/// Used with [`CommunicateBuilder::on_timeout`].
+///
+/// [`CommunicateBuilder::on_timeout`]: struct.CommunicateBuilder.html#method.on_timeout
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OnTimeout {
+    /// Kill the subprocess.
+    Kill,
+    /// Leave the subprocess running, without waiting for it.
+    Detach,
+}
+
+// Amount of output read at once when discarding output beyond the limit.
+const DISCARD_CHUNK: usize = 64 * 1024;
+
+/// Number of bytes transferred through the standard streams of a
+/// subprocess.
+///
+/// Returned by [`Communicator::io_stats`].
+///
+/// [`Communicator::io_stats`]: struct.Communicator.html#method.io_stats
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct IoStats {
+    /// Bytes written to the standard input of the subprocess.
+    pub stdin_written: u64,
+    /// Bytes read from the standard output of the subprocess.
+    pub stdout_read: u64,
+    /// Bytes read from the standard error of the subprocess.
+    pub stderr_read: u64,
+}
+
+impl fmt::Debug for Communicator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Communicator")
+            .field("inner", &self.inner)
+            .field("size_limit", &self.size_limit)
+            .field("time_limit", &self.time_limit)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("stats", &self.stats)
+            .field("max_output", &self.max_output)
+            .field("overflow", &self.overflow)
+            .finish()
+    }
 }
 
 impl Communicator {
@@ -445,6 +993,13 @@ impl Communicator {
             inner: RawCommunicator::new(stdin, stdout, stderr, input_data),
             size_limit: None,
             time_limit: None,
+            idle_timeout: None,
+            stats: IoStats::default(),
+            stats_callback: None,
+            max_output: None,
+            overflow: None,
+            kill: None,
+            kill_on_timeout: false,
         }
     }
 
@@ -489,19 +1044,182 @@ impl Communicator {
     /// # Errors
     ///
     /// * `Err(CommunicateError)` if a system call fails.  In case of timeout,
-    /// the underlying error kind will be `ErrorKind::TimedOut`.
+    ///   the underlying error kind will be `ErrorKind::TimedOut`.
     ///
     /// Regardless of the nature of the error, the content prior to the error
     /// can be retrieved using the [`capture`] attribute of the error.
     ///
     /// [`capture`]: struct.CommunicateError.html#structfield.capture
-
     pub fn read(&mut self) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>), CommunicateError> {
-        let deadline = self.time_limit.map(|timeout| Instant::now() + timeout);
-        match self.inner.read(deadline, self.size_limit) {
-            (None, capture) => Ok(capture),
-            (Some(error), capture) => Err(CommunicateError { error, capture }),
+        let start = Instant::now();
+        let deadline = self.time_limit.map(|timeout| start + timeout);
+        let size_limit = match (self.max_output, self.overflow) {
+            (Some((max, _)), None) => {
+                let remaining = max.saturating_sub(self.captured_len());
+                Some(
+                    self.size_limit
+                        .map_or(remaining, |limit| limit.min(remaining)),
+                )
+            }
+            // once output was cut off, nothing more is read
+            (_, Some(OnOverflow::Kill)) | (_, Some(OnOverflow::Error)) => Some(0),
+            _ => self.size_limit,
+        };
+        let (mut error, capture) = self.inner.read(deadline, self.idle_timeout, size_limit);
+        self.update_stats(&capture, start);
+        if let (None, Some(OnOverflow::Error)) = (&error, self.overflow) {
+            error = Some(overflow_error());
+        }
+        if let (None, Some((max, on_overflow)), None) = (&error, self.max_output, self.overflow) {
+            if self.captured_len() >= max {
+                // output read past the limit is not part of the capture
+                let chunk_log = self.inner.take_chunk_log();
+                error = self.check_overflow(deadline, on_overflow);
+                self.inner.set_chunk_log(chunk_log);
+            }
+        }
+        if let Some(ref error) = error {
+            if error.kind() == ErrorKind::TimedOut && self.kill_on_timeout {
+                if let Some(ref mut kill) = self.kill {
+                    kill();
+                }
+            }
+        }
+        match error {
+            None => Ok(capture),
+            Some(error) => Err(CommunicateError { error, capture }),
+        }
+    }
+
+    fn captured_len(&self) -> usize {
+        (self.stats.stdout_read + self.stats.stderr_read) as usize
+    }
+
+    // Called when output has reached the limit, to find out whether the
+    // subprocess has more to say and act accordingly.
+    fn check_overflow(
+        &mut self,
+        deadline: Option<Instant>,
+        on_overflow: OnOverflow,
+    ) -> Option<io::Error> {
+        let total_len = |data: &(Option<Vec<u8>>, Option<Vec<u8>>)| {
+            data.0.as_ref().map_or(0, Vec::len) + data.1.as_ref().map_or(0, Vec::len)
+        };
+        let (error, probe) = self.inner.read(deadline, self.idle_timeout, Some(1));
+        if error.is_some() || total_len(&probe) == 0 {
+            // error, or EOF right at the limit
+            return error;
+        }
+        self.overflow = Some(on_overflow);
+        match on_overflow {
+            OnOverflow::Truncate => loop {
+                let (error, discarded) =
+                    self.inner
+                        .read(deadline, self.idle_timeout, Some(DISCARD_CHUNK));
+                if error.is_some() || total_len(&discarded) == 0 {
+                    return error;
+                }
+            },
+            OnOverflow::Kill => {
+                if let Some(ref mut kill) = self.kill {
+                    kill();
+                }
+                None
+            }
+            OnOverflow::Error => {
+                if let Some(ref mut kill) = self.kill {
+                    kill();
+                }
+                Some(overflow_error())
+            }
+        }
+    }
+
+    fn update_stats(&mut self, capture: &(Option<Vec<u8>>, Option<Vec<u8>>), start: Instant) {
+        let len = |data: &Option<Vec<u8>>| data.as_ref().map(|d| d.len() as u64).unwrap_or(0);
+        let delta = IoStats {
+            stdin_written: self.inner.stdin_written() - self.stats.stdin_written,
+            stdout_read: len(&capture.0),
+            stderr_read: len(&capture.1),
+        };
+        self.stats.stdin_written += delta.stdin_written;
+        self.stats.stdout_read += delta.stdout_read;
+        self.stats.stderr_read += delta.stderr_read;
+        stats::record_communicate(
+            delta.stdin_written + delta.stdout_read + delta.stderr_read,
+            start.elapsed(),
+        );
+        if let Some(ref mut callback) = self.stats_callback {
+            callback(delta);
+        }
+    }
+
+    /// Return the number of bytes exchanged with the subprocess so far.
+    ///
+    /// The counts cover all previous calls to `read()`, including those
+    /// that returned an error.
+    pub fn io_stats(&self) -> IoStats {
+        self.stats
+    }
+
+    /// Call `callback` after every `read()` with the number of bytes
+    /// exchanged by that call.
+    ///
+    /// This is useful for feeding counters of a metrics system.  The
+    /// running totals are available using [`io_stats`].
+    ///
+    /// [`io_stats`]: struct.Communicator.html#method.io_stats
+    pub fn on_io_stats(mut self, callback: impl FnMut(IoStats) + Send + 'static) -> Communicator {
+        self.stats_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Communicate with the subprocess, return its output and error
+    /// contents in the order they were received.
+    ///
+    /// This is like `read()`, except that instead of separating the output
+    /// and error, it returns a transcript of the pieces of data as they
+    /// arrived, each tagged with the stream it came from.  Consecutive
+    /// pieces from the same stream are joined.  This allows presenting the
+    /// output and error interleaved, as they would appear on a terminal,
+    /// without losing track of which is which, as happens with
+    /// `Redirection::Merge`.
+    ///
+    /// The order reflects the arrival of data to the current process,
+    /// which may differ from the order in which the subprocess wrote it,
+    /// for example when the subprocess buffers its output.
+    ///
+    /// # Errors
+    ///
+    /// As with `read()`.  The data captured before the error is available
+    /// in the error, separated by stream.
+    pub fn read_transcript(&mut self) -> Result<Vec<(OutputSource, Vec<u8>)>, CommunicateError> {
+        self.inner.set_chunk_log(Some(vec![]));
+        let result = self.read();
+        let chunk_log = self.inner.take_chunk_log().unwrap_or_default();
+        let (out, err) = result?;
+        let (out, err) = (out.unwrap_or_default(), err.unwrap_or_default());
+        let (mut out_pos, mut err_pos) = (0, 0);
+        let mut transcript: Vec<(OutputSource, Vec<u8>)> = vec![];
+        for (source, len) in chunk_log {
+            let chunk = match source {
+                OutputSource::Stdout => {
+                    out_pos += len;
+                    &out[out_pos - len..out_pos]
+                }
+                OutputSource::Stderr => {
+                    err_pos += len;
+                    &err[err_pos - len..err_pos]
+                }
+            };
+            match transcript.last_mut() {
+                Some((last_source, data)) if *last_source == source => {
+                    data.extend_from_slice(chunk)
+                }
+                _ => transcript.push((source, chunk.to_vec())),
+            }
         }
+        Ok(transcript)
     }
 
     /// Return the subprocess's output and error contents as strings.
@@ -527,6 +1245,140 @@ impl Communicator {
         self.time_limit = Some(time);
         self
     }
+
+    /// Limit the amount of time the subprocess may go without reading input
+    /// or producing output during `read()`.
+    ///
+    /// Unlike `limit_time()`, which limits the total duration of `read()`,
+    /// this detects a subprocess that has stopped making progress.  When the
+    /// limit is exceeded, `read()` returns an error of kind
+    /// `io::ErrorKind::TimedOut`, as with `limit_time()`.
+    pub fn idle_timeout(mut self, time: Duration) -> Communicator {
+        self.idle_timeout = Some(time);
+        self
+    }
+
+    /// Set the number of chunks of output that can be read ahead of
+    /// `read()`.
+    ///
+    /// On Windows, the standard streams of the subprocess are serviced by
+    /// helper threads, which hand the data over through a bounded channel.
+    /// A larger capacity lets a subprocess that produces output quickly
+    /// keep running while earlier output is being processed, at the cost
+    /// of buffering more of it in memory.  A capacity of 0 makes each
+    /// helper wait until its chunk is received.  The default is 4.
+    ///
+    /// The helper threads are started by the first `read()`, so setting
+    /// the capacity after that has no effect.
+    #[cfg(windows)]
+    pub fn channel_capacity(mut self, capacity: usize) -> Communicator {
+        self.inner.set_channel_capacity(capacity);
+        self
+    }
+
+    /// Keep the standard input of the subprocess open after writing the
+    /// input data, so that more input can be provided with [`feed`].
+    ///
+    /// By default, `read()` closes the standard input once all the input
+    /// data has been written, so that the subprocess sees end-of-file.
+    /// With this option, the subprocess keeps waiting for input, and
+    /// `read()` no longer returns at end-of-file of its output, so each
+    /// round of interaction is typically read with [`limit_size`] or
+    /// [`limit_time`].  Call [`close_stdin`] when done.
+    ///
+    /// ```no_run
+    /// # use subprocess::*;
+    /// # fn dummy() -> Result<()> {
+    /// let mut p = Exec::cmd("cat").stdin(Redirection::Pipe).stdout(Redirection::Pipe).popen()?;
+    /// let mut comm = p
+    ///     .communicate_start(Some(b"hello\n".to_vec()))
+    ///     .keep_stdin_open(true)
+    ///     .limit_size(6);
+    /// assert_eq!(comm.read()?.0.unwrap(), b"hello\n");
+    /// comm.feed(b"again\n");
+    /// assert_eq!(comm.read()?.0.unwrap(), b"again\n");
+    /// comm.close_stdin();
+    /// assert_eq!(comm.read()?.0.unwrap(), b"");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`feed`]: struct.Communicator.html#method.feed
+    /// [`limit_size`]: struct.Communicator.html#method.limit_size
+    /// [`limit_time`]: struct.Communicator.html#method.limit_time
+    /// [`close_stdin`]: struct.Communicator.html#method.close_stdin
+    pub fn keep_stdin_open(mut self, keep: bool) -> Communicator {
+        self.inner.set_keep_stdin_open(keep);
+        self
+    }
+
+    /// Queue `data` to be written to the standard input of the subprocess
+    /// by subsequent calls to `read()`.
+    ///
+    /// # Panics
+    ///
+    /// If `stdin` was not redirected to a pipe, or if it has been closed,
+    /// which happens on the first `read()` unless
+    /// [`keep_stdin_open`] is in effect.
+    ///
+    /// [`keep_stdin_open`]: struct.Communicator.html#method.keep_stdin_open
+    pub fn feed(&mut self, data: &[u8]) {
+        self.inner.feed(data);
+    }
+
+    /// Close the standard input of the subprocess once the data fed so far
+    /// has been written by `read()`.
+    ///
+    /// This undoes [`keep_stdin_open`].
+    ///
+    /// [`keep_stdin_open`]: struct.Communicator.html#method.keep_stdin_open
+    pub fn close_stdin(&mut self) {
+        self.inner.set_keep_stdin_open(false);
+    }
+
+    /// Limit the total amount of output and error read from the subprocess
+    /// to `bytes`, and specify what to do with output beyond that.
+    ///
+    /// Unlike `limit_size()`, which only splits reading into several
+    /// `read()` calls, this protects the caller from a subprocess producing
+    /// more output than it is prepared to hold.  The action taken, if any,
+    /// is reported by [`overflow`].
+    ///
+    /// The `Communicator` doesn't own the subprocess, so `OnOverflow::Kill`
+    /// and `OnOverflow::Error` only stop reading, which closes the pipes
+    /// once the `Communicator` is dropped.  Killing the subprocess is left
+    /// to the caller, except for communicators obtained from
+    /// [`Exec::communicate`] and [`Pipeline::communicate`].
+    ///
+    /// [`overflow`]: struct.Communicator.html#method.overflow
+    /// [`Exec::communicate`]: struct.Exec.html#method.communicate
+    /// [`Pipeline::communicate`]: struct.Pipeline.html#method.communicate
+    pub fn max_output(mut self, bytes: usize, on_overflow: OnOverflow) -> Communicator {
+        self.max_output = Some((bytes, on_overflow));
+        self
+    }
+
+    /// Returns the action taken because the output exceeded the limit set
+    /// by [`max_output`], or `None` if it didn't.
+    ///
+    /// [`max_output`]: struct.Communicator.html#method.max_output
+    pub fn overflow(&self) -> Option<OnOverflow> {
+        self.overflow
+    }
+
+    // Called on overflow with OnOverflow::Kill or OnOverflow::Error, and on
+    // timeout if requested with set_kill_on_timeout().
+    pub(crate) fn set_kill(&mut self, kill: impl FnMut() + Send + 'static) {
+        self.kill = Some(Box::new(kill));
+    }
+
+    pub(crate) fn set_kill_on_timeout(&mut self, kill_on_timeout: bool) {
+        self.kill_on_timeout = kill_on_timeout;
+    }
+}
+
+fn overflow_error() -> io::Error {
+    crate::compat::io_error_other("output limit exceeded")
 }
 
 /// Like String::from_utf8_lossy(), but takes `Vec<u8>` and reuses its storage if
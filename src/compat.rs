@@ -0,0 +1,59 @@
+// Shims for std APIs stabilized after the minimum supported Rust version
+// declared in Cargo.toml.  build.rs enables the cfg of each API the
+// compiler provides, and the fallback is used otherwise, so that the crate
+// builds with older compilers while using std where it can.
+
+use std::error::Error;
+use std::io;
+
+/// `io::Error::other()`, stable since Rust 1.74.
+pub fn io_error_other<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    #[cfg(subprocess_io_error_other)]
+    #[allow(clippy::incompatible_msrv)]
+    {
+        io::Error::other(error)
+    }
+    #[cfg(not(subprocess_io_error_other))]
+    {
+        io::Error::new(io::ErrorKind::Other, error)
+    }
+}
+
+/// `usize::div_ceil()`, stable since Rust 1.73.
+#[cfg(feature = "serde")]
+pub fn div_ceil(lhs: usize, rhs: usize) -> usize {
+    #[cfg(subprocess_div_ceil)]
+    #[allow(clippy::incompatible_msrv)]
+    {
+        lhs.div_ceil(rhs)
+    }
+    #[cfg(not(subprocess_div_ceil))]
+    {
+        (lhs + rhs - 1) / rhs
+    }
+}
+
+pub trait ResultExt<E> {
+    /// `Result::inspect_err()`, stable since Rust 1.76.
+    fn inspect_error(self, f: impl FnOnce(&E)) -> Self;
+}
+
+impl<T, E> ResultExt<E> for Result<T, E> {
+    fn inspect_error(self, f: impl FnOnce(&E)) -> Self {
+        #[cfg(subprocess_inspect_err)]
+        #[allow(clippy::incompatible_msrv)]
+        {
+            self.inspect_err(f)
+        }
+        #[cfg(not(subprocess_inspect_err))]
+        {
+            if let Err(ref e) = self {
+                f(e);
+            }
+            self
+        }
+    }
+}
@@ -0,0 +1,264 @@
+// Ctrl-C handling for a job of running processes.  The signal handler only
+// touches atomics, so that it is async-signal-safe; the mutex serializes
+// registration and unregistration of guards.
+
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::os_common::ExitStatus;
+use crate::popen::{Popen, PopenError, Result as PopenResult};
+
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+pub(crate) mod os {
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Mutex;
+
+    use libc::c_int;
+
+    use super::INTERRUPTS;
+    use crate::popen::{PopenError, Result as PopenResult};
+    use crate::posix;
+    use crate::posix::SigAction;
+
+    const MAX_TARGETS: usize = 64;
+
+    // Pids of the guarded processes, 0 denoting a free slot.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicI32 = AtomicI32::new(0);
+    static TARGETS: [AtomicI32; MAX_TARGETS] = [EMPTY; MAX_TARGETS];
+
+    // Number of live guards, and the SIGINT action to restore once the
+    // last of them is gone.
+    static REGISTRY: Mutex<(usize, Option<SigAction>)> = Mutex::new((0, None));
+
+    pub(crate) extern "C" fn on_sigint(_signum: c_int) {
+        posix::preserving_errno(|| {
+            INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+            let own_pgrp = posix::getpgrp();
+            for target in TARGETS.iter() {
+                let pid = target.load(Ordering::SeqCst);
+                if pid == 0 {
+                    continue;
+                }
+                // Processes in our group have received the signal along
+                // with us if it came from the terminal, and we must not
+                // signal our own group.
+                if let Ok(pgid) = posix::getpgid(pid as u32) {
+                    if pgid != own_pgrp {
+                        let _ = posix::killpg(pgid, posix::SIGINT);
+                    }
+                }
+            }
+        })
+    }
+
+    pub type Slot = usize;
+
+    pub fn register(pids: &[u32]) -> PopenResult<Vec<Slot>> {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        let free = TARGETS
+            .iter()
+            .filter(|t| t.load(Ordering::SeqCst) == 0)
+            .count();
+        if free < pids.len() {
+            return Err(PopenError::LogicError(
+                "too many processes guarded against Ctrl-C",
+            ));
+        }
+        if registry.0 == 0 {
+            registry.1 = Some(posix::set_signal_handler(posix::SIGINT, on_sigint)?);
+        }
+        registry.0 += 1;
+        let mut slots = vec![];
+        let mut free_slots = (0..MAX_TARGETS).filter(|&i| TARGETS[i].load(Ordering::SeqCst) == 0);
+        for &pid in pids {
+            let slot = free_slots.next().unwrap();
+            TARGETS[slot].store(pid as i32, Ordering::SeqCst);
+            slots.push(slot);
+        }
+        Ok(slots)
+    }
+
+    pub fn release(slot: Slot) {
+        TARGETS[slot].store(0, Ordering::SeqCst);
+    }
+
+    pub fn unregister() {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        registry.0 -= 1;
+        if registry.0 == 0 {
+            if let Some(old) = registry.1.take() {
+                let _ = posix::restore_signal_handler(posix::SIGINT, &old);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod os {
+    use std::sync::atomic::Ordering;
+    use std::sync::Mutex;
+
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+
+    use super::INTERRUPTS;
+    use crate::popen::Result as PopenResult;
+    use crate::win32;
+
+    // Number of live guards.
+    static REGISTRY: Mutex<usize> = Mutex::new(0);
+
+    unsafe extern "system" fn on_ctrl(ctrl_type: DWORD) -> BOOL {
+        if ctrl_type == win32::CTRL_C_EVENT || ctrl_type == win32::CTRL_BREAK_EVENT {
+            // The processes attached to our console receive the event
+            // themselves, we just need to survive it.
+            INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+            return TRUE;
+        }
+        FALSE
+    }
+
+    pub type Slot = ();
+
+    pub fn register(pids: &[u32]) -> PopenResult<Vec<Slot>> {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        if *registry == 0 {
+            win32::SetConsoleCtrlHandler(on_ctrl, true)?;
+        }
+        *registry += 1;
+        Ok(vec![(); pids.len()])
+    }
+
+    pub fn release(_slot: Slot) {}
+
+    pub fn unregister() {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        *registry -= 1;
+        if *registry == 0 {
+            let _ = win32::SetConsoleCtrlHandler(on_ctrl, false);
+        }
+    }
+}
+
+/// Guard that forwards Ctrl-C to a job of processes, returned by
+/// [`ctrlc_guard`].
+///
+/// While the guard exists, Ctrl-C no longer terminates the current process,
+/// which gives it a chance to wait for the job to exit in response to the
+/// signal.  Dropping the guard waits for all the processes of the job and
+/// then restores the previous handling of Ctrl-C.
+///
+/// [`ctrlc_guard`]: fn.ctrlc_guard.html
+#[derive(Debug)]
+pub struct CtrlcGuard {
+    processes: Vec<Popen>,
+    slots: Vec<Option<os::Slot>>,
+    start: usize,
+    registered: bool,
+}
+
+/// Forward Ctrl-C to the processes of `job` until they are reaped.
+///
+/// The job is typically the result of [`Exec::popen`] or
+/// [`Pipeline::popen`], and is owned by the returned guard for as long as
+/// the handler is registered.  On Unix-like systems, `SIGINT` is forwarded
+/// to the process group of each process that was placed in a group other
+/// than that of the current process, for example with
+/// [`ExecExt::setpgid`].  Processes sharing the group of the current
+/// process, like processes started on Windows, receive Ctrl-C typed at the
+/// terminal along with the current process and need no forwarding.
+///
+/// The handler is process-wide, and guards may be nested or created from
+/// different threads.  Only available with the `ctrlc-guard` feature.
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # fn dummy() -> Result<()> {
+/// let job = (Exec::cmd("make") | Exec::cmd("tee").arg("build.log")).popen()?;
+/// let guard = ctrlc_guard(job)?;
+/// let status = guard.wait()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Exec::popen`]: struct.Exec.html#method.popen
+/// [`Pipeline::popen`]: struct.Pipeline.html#method.popen
+/// [`ExecExt::setpgid`]: unix/trait.ExecExt.html#tymethod.setpgid
+pub fn ctrlc_guard(job: Vec<Popen>) -> PopenResult<CtrlcGuard> {
+    if job.is_empty() {
+        return Err(PopenError::LogicError("ctrlc_guard requires a process"));
+    }
+    let pids: Vec<u32> = job.iter().filter_map(Popen::pid).collect();
+    let mut slots = os::register(&pids)?.into_iter();
+    let slots = job
+        .iter()
+        .map(|p| p.pid().and_then(|_| slots.next()))
+        .collect();
+    Ok(CtrlcGuard {
+        processes: job,
+        slots,
+        start: INTERRUPTS.load(Ordering::SeqCst),
+        registered: true,
+    })
+}
+
+impl CtrlcGuard {
+    /// Return the guarded processes.
+    pub fn processes(&mut self) -> &mut [Popen] {
+        &mut self.processes
+    }
+
+    /// Return true if Ctrl-C was received since the guard was created.
+    pub fn interrupted(&self) -> bool {
+        INTERRUPTS.load(Ordering::SeqCst) != self.start
+    }
+
+    /// Wait for all the processes to finish, and return the exit status
+    /// of the last one.
+    pub fn wait(mut self) -> PopenResult<ExitStatus> {
+        let mut status = None;
+        for i in 0..self.processes.len() {
+            status = Some(self.wait_one(i)?);
+        }
+        Ok(status.unwrap())
+    }
+
+    /// Unregister the guard without waiting for the processes, and return
+    /// them.
+    pub fn into_inner(mut self) -> Vec<Popen> {
+        self.unregister();
+        mem::take(&mut self.processes)
+    }
+
+    fn wait_one(&mut self, i: usize) -> PopenResult<ExitStatus> {
+        let status = self.processes[i].wait()?;
+        // Once reaped, the pid may be reused by an unrelated process.
+        if let Some(slot) = self.slots[i].take() {
+            os::release(slot);
+        }
+        Ok(status)
+    }
+
+    fn unregister(&mut self) {
+        for slot in self.slots.iter_mut() {
+            if let Some(slot) = slot.take() {
+                os::release(slot);
+            }
+        }
+        if self.registered {
+            self.registered = false;
+            os::unregister();
+        }
+    }
+}
+
+impl Drop for CtrlcGuard {
+    fn drop(&mut self) {
+        for i in 0..self.processes.len() {
+            let _ = self.wait_one(i);
+        }
+        self.unregister();
+    }
+}
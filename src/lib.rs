@@ -54,7 +54,7 @@
 //! # use subprocess::*;
 //! # fn dummy() -> Result<()> {
 //! let dir_checksum = {
-//!     Exec::shell("find . -type f") | Exec::cmd("sort") | Exec::cmd("sha1sum")
+//!     Exec::cmd("find").args(&[".", "-type", "f"]) | Exec::cmd("sort") | Exec::cmd("sha1sum")
 //! }.capture()?.stdout_str();
 //! # Ok(())
 //! # }
@@ -75,34 +75,125 @@
 #![warn(missing_debug_implementations, rust_2018_idioms, missing_docs)]
 #![allow(clippy::type_complexity, clippy::single_match)]
 
+#[cfg(any(feature = "async", feature = "async-smol"))]
+mod async_job;
+#[cfg(any(feature = "async", feature = "async-smol"))]
+mod async_runtime;
 mod builder;
 mod communicate;
+mod compat;
+#[cfg(feature = "ctrlc-guard")]
+mod ctrlc;
 mod popen;
+mod protocol;
+mod recording;
+mod ringbuf;
+mod select;
 
 #[cfg(unix)]
 mod posix;
+#[cfg(unix)]
+mod sandbox;
+#[cfg(unix)]
+mod sigchld;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
 
 #[cfg(windows)]
 mod win32;
 
 mod os_common;
 
-pub use self::builder::{CaptureData, Exec, NullFile, Pipeline};
-pub use self::communicate::{CommunicateError, Communicator};
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(not(feature = "no-shell"))]
+mod shell;
+mod stats;
+
+#[cfg(any(feature = "async", feature = "async-smol"))]
+pub use self::async_job::AsyncJob;
+#[cfg(feature = "async")]
+pub use self::async_job::{AsyncCommunicator, AsyncPipeReader, AsyncPipeWriter};
+#[cfg(any(feature = "async", feature = "async-smol"))]
+pub use self::async_runtime::AsyncRuntime;
+#[cfg(feature = "async-smol")]
+pub use self::async_runtime::SmolRuntime;
+#[cfg(feature = "async")]
+pub use self::async_runtime::TokioRuntime;
+pub use self::builder::{
+    Capture, CaptureData, CommunicateBuilder, Exec, NullFile, OnStartFailure, Pipeline,
+    ReadErrAdapter, ReadOutAdapter, SpawnPlan, StreamOutcome, Transcript,
+};
+pub use self::communicate::{
+    CommunicateError, Communicator, IoStats, OnOverflow, OnTimeout, OutputSource,
+};
+#[cfg(feature = "ctrlc-guard")]
+pub use self::ctrlc::{ctrlc_guard, CtrlcGuard};
 pub use self::os_common::ExitStatus;
-pub use self::popen::{make_pipe, Popen, PopenConfig, PopenError, Redirection, Result};
+pub use self::popen::{
+    make_pipe, make_pipe_with, set_fd_audit, CancelToken, PipeOptions, Popen, PopenConfig,
+    PopenError, Priority, Redirection, Result, Waitable,
+};
+#[cfg(feature = "expect")]
+pub use self::protocol::{Expect, ExpectError, ExpectMatch};
+pub use self::protocol::{FramedProtocol, LineProtocol};
+pub use self::recording::{RecordedEvent, RecordedStream, Recorder, Recording};
+pub use self::ringbuf::{output_ring, RingReader};
+pub use self::select::{select, Selected};
+#[cfg(feature = "serde")]
+pub use self::serialize::{OutputEncoding, SerializableCapture};
+#[cfg(not(feature = "no-shell"))]
+pub use self::shell::{set_shell_guard, ShellGuard};
+#[cfg(all(unix, not(feature = "no-shell")))]
+pub use self::shell::{shell_quote, ShellCommand};
+#[cfg(feature = "stats")]
+pub use self::stats::{reset_stats, stats, Histogram, Stats};
+
+pub mod os_util;
 
 /// Subprocess extensions for Unix platforms.
 pub mod unix {
+    #[cfg(unix)]
+    pub use super::builder::unix::*;
     pub use super::popen::os_ext::*;
+    #[cfg(unix)]
+    pub use super::sandbox::{Namespace, Resource, Sandbox};
+    #[cfg(unix)]
+    pub use super::sigchld::install_sigchld_notifier;
 }
 
+/// Subprocess extensions for Windows platforms.
+#[cfg(windows)]
+pub mod windows {
+    pub use super::builder::windows::*;
+}
+
+// The tests keep passing arguments as borrowed slices, the form used
+// before Popen::create and Exec::args accepted any IntoIterator, to make
+// sure it still compiles.
 #[cfg(test)]
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod tests {
+    #[cfg(feature = "async")]
+    mod async_job;
+    #[cfg(all(unix, feature = "async-smol"))]
+    mod async_smol;
     mod builder;
     mod common;
+    #[cfg(all(unix, feature = "expect"))]
+    mod expect;
+    #[cfg(feature = "no-path-search")]
+    mod no_path_search;
     #[cfg(unix)]
     mod posix;
+    mod protocol;
+    #[cfg(unix)]
+    mod recording;
+    mod select;
+    #[cfg(feature = "serde")]
+    mod serialize;
+    #[cfg(feature = "stats")]
+    mod stats;
     #[cfg(windows)]
     mod win32;
 }
@@ -23,13 +23,28 @@ pub enum ExitStatus {
     /// It is known that the process has completed, but its exit
     /// status is unavailable.
     ///
-    /// This should not occur in normal operation, but is possible if
-    /// for example some foreign code calls `waitpid()` on the PID of
-    /// the child process.
+    /// This should not occur in normal operation.
     Undetermined,
+
+    /// The process has completed, but it was reaped by someone else, so
+    /// its exit status is unavailable.
+    ///
+    /// This happens if foreign code, such as a `SIGCHLD` handler, calls
+    /// `waitpid()` on the PID of the child process before `Popen` gets to
+    /// obtain its exit status.  On Linux the exit status is obtained
+    /// without reaping the process first, which makes this less likely.
+    ReapedElsewhere,
 }
 
 impl ExitStatus {
+    /// Create an exit status of a process that exited with the specified
+    /// exit code.
+    ///
+    /// This is equivalent to `ExitStatus::Exited(code)`.
+    pub fn from_code(code: u32) -> ExitStatus {
+        ExitStatus::Exited(code)
+    }
+
     /// True if the exit status of the process is 0.
     pub fn success(self) -> bool {
         matches!(self, ExitStatus::Exited(0))
@@ -48,6 +63,27 @@ impl ExitStatus {
     }
 }
 
+/// Compare the exit code of a process that exited normally.
+///
+/// This makes it possible to write `assert_eq!(status, 42)`.  Only the
+/// `Exited` variant can compare equal to an integer.
+impl PartialEq<u32> for ExitStatus {
+    fn eq(&self, other: &u32) -> bool {
+        matches!(*self, ExitStatus::Exited(code) if code == *other)
+    }
+}
+
+/// Compare the exit code of a process that exited normally.
+///
+/// The comparison is by bit pattern, so on Windows a negative number
+/// matches the corresponding large exit code, as in the `NTSTATUS` values
+/// reported for crashed processes.
+impl PartialEq<i32> for ExitStatus {
+    fn eq(&self, other: &i32) -> bool {
+        matches!(*self, ExitStatus::Exited(code) if code as i32 == *other)
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 #[allow(dead_code)]
 pub enum StandardStream {
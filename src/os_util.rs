@@ -0,0 +1,76 @@
+//! Low-level operating system utilities.
+//!
+//! This module exposes safe wrappers over some of the system calls the
+//! crate uses internally.  They are useful when connecting processes in
+//! ways not covered by [`Popen`] and [`Exec`], without having to wrap the
+//! calls again with `libc` or `winapi`.
+//!
+//! [`Popen`]: ../struct.Popen.html
+//! [`Exec`]: ../struct.Exec.html
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::AsRawHandle;
+#[cfg(windows)]
+use std::time::Duration;
+
+use std::io;
+
+#[cfg(unix)]
+use crate::posix;
+#[cfg(windows)]
+use crate::win32;
+
+pub use crate::popen::{make_pipe, make_pipe_with, PipeOptions};
+
+/// Send `signal` to the process with the given `pid`.
+///
+/// The signal numbers are best obtained from the [`libc`] crate.
+///
+/// [`libc`]: https://docs.rs/libc/
+#[cfg(unix)]
+pub fn kill(pid: u32, signal: i32) -> io::Result<()> {
+    posix::kill(pid, signal)
+}
+
+/// Send `signal` to all processes in the process group `pgid`.
+///
+/// The signal numbers are best obtained from the [`libc`] crate.
+///
+/// [`libc`]: https://docs.rs/libc/
+#[cfg(unix)]
+pub fn killpg(pgid: u32, signal: i32) -> io::Result<()> {
+    posix::killpg(pgid, signal)
+}
+
+/// Make the descriptor `target` refer to the same open file as `file`.
+///
+/// If `target` was open, it is closed first.  The new descriptor doesn't
+/// have the close-on-exec flag set, so it is inherited by the processes
+/// started afterwards.  This is typically used to set up the standard
+/// streams before `exec`.
+///
+/// # Safety
+///
+/// Closing `target` invalidates any `File` or other object that owns it,
+/// so the caller must ensure that `target` is either not open, or not
+/// owned by anything that will use or close it later.
+#[cfg(unix)]
+pub unsafe fn dup2(file: &impl AsRawFd, target: RawFd) -> io::Result<()> {
+    posix::dup2(file.as_raw_fd(), target)
+}
+
+/// Wait for the object referred to by `handle`, such as a process, to be
+/// signaled, for at most `timeout`.
+///
+/// Returns `true` if the object is signaled, and `false` if the timeout
+/// has expired first.  If `timeout` is `None`, waits for as long as
+/// necessary.
+#[cfg(windows)]
+pub fn wait_for_object(handle: &impl AsRawHandle, timeout: Option<Duration>) -> io::Result<bool> {
+    match win32::WaitForSingleObject(handle, timeout)? {
+        win32::WaitEvent::TIMEOUT => Ok(false),
+        win32::WaitEvent::OBJECT_0 | win32::WaitEvent::ABANDONED => Ok(true),
+    }
+}
@@ -1,21 +1,30 @@
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::error::Error;
 use std::ffi::{OsStr, OsString};
 use std::fmt;
-use std::fs::File;
-use std::io;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::result;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::communicate;
 use crate::os_common::{ExitStatus, StandardStream};
+use crate::stats;
 
 use self::ChildState::*;
 
 pub use self::os::ext as os_ext;
-pub use self::os::make_pipe;
+pub(crate) use self::os::set_inheritable;
+pub use self::os::{make_pipe, make_pipe_with};
 pub use communicate::Communicator;
 
 /// Interface to a running subprocess.
@@ -46,7 +55,6 @@ pub use communicate::Communicator;
 /// [`create`]: struct.Popen.html#method.create
 /// [`communicate`]: struct.Popen.html#method.communicate
 /// [`detach`]: struct.Popen.html#method.detach
-
 #[derive(Debug)]
 pub struct Popen {
     /// If `stdin` was specified as `Redirection::Pipe`, this will
@@ -66,6 +74,75 @@ pub struct Popen {
 
     child_state: ChildState,
     detached: bool,
+    wait_in_background: bool,
+    kill_on_drop: bool,
+    start_time: Instant,
+    #[cfg(unix)]
+    terminate_signal: i32,
+    reopenable: Vec<ReopenableOutput>,
+    // the child's ends of pipes, checked by assert_no_child_fds()
+    #[cfg(unix)]
+    audited_ends: Vec<(i32, crate::posix::FileId)>,
+    exit_watch: Option<Arc<ExitWatch>>,
+    temp_cwd: Option<TempCwd>,
+    program: Option<OsString>,
+}
+
+// Working directory created by Exec::temp_cwd(), removed once the exit
+// status of the child is known.  Dropping a detached Popen doesn't remove
+// it, as the child may still be running in it.
+#[derive(Debug)]
+struct TempCwd {
+    path: PathBuf,
+    keep_on_failure: bool,
+}
+
+// Output redirected to a path through a pipe, copied to the file by a
+// relay thread, so that the file can be reopened while the child runs.
+#[derive(Debug)]
+struct ReopenableOutput {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+// Receivers of the exit status handed out by exit_channel(), notified by
+// whoever observes the exit first: the watcher thread, wait() and friends
+// on the Popen, or the reaper.
+#[derive(Debug, Default)]
+struct ExitWatch {
+    state: Mutex<ExitWatchState>,
+}
+
+#[derive(Debug, Default)]
+struct ExitWatchState {
+    exit_status: Option<ExitStatus>,
+    senders: Vec<Sender<ExitStatus>>,
+}
+
+impl ExitWatch {
+    fn subscribe(&self) -> Receiver<ExitStatus> {
+        let (tx, rx) = mpsc::channel();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.exit_status {
+            Some(exit_status) => {
+                let _ = tx.send(exit_status);
+            }
+            None => state.senders.push(tx),
+        }
+        rx
+    }
+
+    fn notify(&self, exit_status: ExitStatus) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.exit_status.is_some() {
+            return;
+        }
+        state.exit_status = Some(exit_status);
+        for tx in state.senders.drain(..) {
+            // the receiver may have been dropped
+            let _ = tx.send(exit_status);
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -86,7 +163,7 @@ enum ChildState {
 ///
 /// ```
 /// # use subprocess::*;
-/// # let argv = &["true"];
+/// # let argv = &[if cfg!(feature = "no-path-search") { "/bin/sh" } else { "true" }];
 /// Popen::create(argv, PopenConfig {
 ///      stdout: Redirection::Pipe,
 ///      detached: true,
@@ -104,8 +181,6 @@ enum ChildState {
 /// [`Popen::create`]: struct.Popen.html#method.create
 /// [`Exec`]: struct.Exec.html
 /// [`Default`]: https://doc.rust-lang.org/core/default/trait.Default.html
-
-#[derive(Debug)]
 pub struct PopenConfig {
     /// How to configure the executed program's standard input.
     pub stdin: Redirection,
@@ -116,6 +191,82 @@ pub struct PopenConfig {
     /// Whether the `Popen` instance is initially detached.
     pub detached: bool,
 
+    /// Whether output redirected to a [`Redirection::Path`] is appended to
+    /// the file.
+    ///
+    /// If true, which is the default, the file is opened in append mode
+    /// (`O_APPEND` on Unix, `FILE_APPEND_DATA` on Windows), so that
+    /// writes from several processes redirected to the same path are
+    /// added to the end of the file instead of overwriting each other.
+    /// If false, the file is truncated when opened.
+    ///
+    /// [`Redirection::Path`]: enum.Redirection.html#variant.Path
+    pub append: bool,
+
+    /// Whether output redirected to a [`Redirection::Path`] can be
+    /// reopened while the subprocess runs.
+    ///
+    /// If true, the child writes such output to a pipe, from which a
+    /// background thread copies it to the file, and
+    /// [`Popen::reopen_output`] switches the copying to a newly opened
+    /// file.  This allows rotating the logs of a long-running
+    /// subprocess without restarting it.  As the copying is done in the
+    /// background, the output can reach the file shortly after the
+    /// subprocess has exited.
+    ///
+    /// [`Redirection::Path`]: enum.Redirection.html#variant.Path
+    /// [`Popen::reopen_output`]: struct.Popen.html#method.reopen_output
+    pub reopenable_output: bool,
+
+    /// Whether the `Popen` instance initially waits for the subprocess
+    /// in the background when dropped.
+    ///
+    /// See [`Popen::wait_in_background`] for details.
+    ///
+    /// [`Popen::wait_in_background`]: struct.Popen.html#method.wait_in_background
+    pub wait_in_background: bool,
+
+    /// Whether the `Popen` instance initially kills the subprocess when
+    /// dropped.
+    ///
+    /// See [`Popen::kill_on_drop`] for details.
+    ///
+    /// [`Popen::kill_on_drop`]: struct.Popen.html#method.kill_on_drop
+    pub kill_on_drop: bool,
+
+    /// Kill the subprocess when the current process exits.
+    ///
+    /// This keeps long-running subprocesses from outliving a parent that
+    /// crashed or was killed before it could terminate them.
+    ///
+    /// On Linux, the child requests `SIGKILL` on the death of its parent
+    /// with `prctl(PR_SET_PDEATHSIG)` before execing the program.  Note
+    /// that Linux sends the signal when the *thread* that started the
+    /// subprocess exits, and doesn't send it to programs with the
+    /// set-user-ID or set-group-ID bit.  On Windows, the subprocess is
+    /// assigned to a job object that kills its processes when the current
+    /// process exits, which also covers the processes started by the
+    /// subprocess.  On other Unix systems, such as macOS and the BSDs, a
+    /// watcher process is started along with the subprocess, which waits
+    /// for the current process to exit and then kills the subprocess with
+    /// `SIGKILL`.  The watcher exits once the subprocess has been waited
+    /// for.  Together with `fast_spawn`, starting the subprocess fails with
+    /// `PopenError::LogicError`.
+    ///
+    /// On Linux, a signal other than `SIGKILL` can be requested with
+    /// `parent_death_signal`.
+    pub die_with_parent: bool,
+
+    /// Scheduling priority of the subprocess.
+    ///
+    /// On Unix, the child sets its nice value with `setpriority()` before
+    /// execing the program, and on Windows the corresponding priority
+    /// class is added to the process creation flags.  Priorities above
+    /// normal usually require privileges, without which starting the
+    /// subprocess fails.  `fast_spawn` doesn't support this option.  `None`
+    /// means inherit the priority of the current process.
+    pub priority: Option<Priority>,
+
     /// Executable to run.
     ///
     /// If provided, this executable will be used to run the program
@@ -131,23 +282,34 @@ pub struct PopenConfig {
     /// process. Otherwise, the specified variables are used instead.
     ///
     /// Duplicates are eliminated, with the value taken from the
-    /// variable appearing later in the vector.
+    /// variable appearing later in the vector.  If `PATH` is among the
+    /// variables, the program is looked up in it instead of in the `PATH`
+    /// of the calling process.
     pub env: Option<Vec<(OsString, OsString)>>,
 
     /// Initial current working directory of the subprocess.
     ///
-    /// None means inherit the working directory from the parent.
+    /// None means inherit the working directory from the parent.  If the
+    /// directory doesn't exist, `Popen::create` returns
+    /// `Err(PopenError::CwdNotFound)`.
     pub cwd: Option<OsString>,
 
     /// Set user ID for the subprocess.
     ///
-    /// If specified, calls `setuid()` before execing the child process.
+    /// If specified, calls `setuid()` before execing the child process.  If
+    /// the call fails, `Popen::create` returns
+    /// `Err(PopenError::SetIdFailed)`.  It is called after `setgid()`,
+    /// which can require the privileges it gives up, and when running as
+    /// root, the supplementary groups are dropped beforehand, so that
+    /// the subprocess doesn't keep those of root.
     #[cfg(unix)]
     pub setuid: Option<u32>,
 
     /// Set group ID for the subprocess.
     ///
-    /// If specified, calls `setgid()` before execing the child process.
+    /// If specified, calls `setgid()` before execing the child process.  If
+    /// the call fails, `Popen::create` returns
+    /// `Err(PopenError::SetIdFailed)`.
     ///
     /// Not to be confused with similarly named `setpgid`.
     #[cfg(unix)]
@@ -161,6 +323,192 @@ pub struct PopenConfig {
     #[cfg(unix)]
     pub setpgid: bool,
 
+    /// Make the subprocess the leader of a new session.
+    ///
+    /// If specified, calls `setsid()` before execing the child process,
+    /// which also puts it in a new process group and dissociates it from
+    /// the controlling terminal of the parent.
+    #[cfg(unix)]
+    pub setsid: bool,
+
+    /// File mode creation mask of the subprocess.
+    ///
+    /// If specified, calls `umask()` before execing the child process, so
+    /// that the files it creates get predictable permissions, without
+    /// changing the umask of the current process, which is shared by all
+    /// of its threads.
+    #[cfg(unix)]
+    pub umask: Option<u32>,
+
+    /// Start the subprocess with `posix_spawn()` instead of `fork()`.
+    ///
+    /// `posix_spawn()` creates the child with `vfork()` or an equivalent
+    /// where the system provides one, which avoids duplicating the page
+    /// tables of the parent.  This speeds up programs that start
+    /// thousands of short-lived subprocesses, especially when the parent
+    /// uses a lot of memory.
+    ///
+    /// Only the redirections and `setpgid` can be combined with this
+    /// option; starting a subprocess that also requests `cwd`, `setuid`,
+    /// `setgid`, `setsid`, `umask`, `foreground`, `die_with_parent`,
+    /// `priority`, a sandbox, `SigPipePolicy::Ignore`, or `Redirection::Pty`
+    /// fails with `PopenError::LogicError`.
+    #[cfg(unix)]
+    pub fast_spawn: bool,
+
+    /// Handling of `SIGPIPE` in the subprocess.
+    ///
+    /// Defaults to `SigPipePolicy::Default`, which resets it to the
+    /// default handling most programs expect instead of inheriting the
+    /// ignored `SIGPIPE` of the Rust runtime.
+    #[cfg(unix)]
+    pub sigpipe: crate::unix::SigPipePolicy,
+
+    /// Reset the handling of all signals in the subprocess to the default.
+    ///
+    /// Signal handlers are reset by executing the program, but ignored
+    /// signals stay ignored, which confuses programs that expect the usual
+    /// handling, e.g. of `SIGINT` or `SIGCHLD`.  `SIGPIPE` is still handled
+    /// as requested by `sigpipe`.
+    #[cfg(unix)]
+    pub reset_signals: bool,
+
+    /// Signals blocked in the subprocess.
+    ///
+    /// The signal mask of the subprocess is set to these signals instead of
+    /// being inherited from the thread that starts it, whose mask can be
+    /// arbitrary under async runtimes and signal-handling libraries.  By
+    /// default no signals are blocked.
+    #[cfg(unix)]
+    pub blocked_signals: Vec<crate::unix::Signal>,
+
+    /// Signal sent to the subprocess by [`Popen::terminate`].
+    ///
+    /// Defaults to `SIGTERM`.  Programs that only clean up or flush their
+    /// output on interrupt can be given `SIGINT` instead.
+    ///
+    /// [`Popen::terminate`]: struct.Popen.html#method.terminate
+    #[cfg(unix)]
+    pub terminate_signal: i32,
+
+    /// Run the subprocess in the foreground of the controlling terminal.
+    ///
+    /// If the parent has a controlling terminal and is in its foreground
+    /// process group, the subprocess is put in a new process group, as
+    /// with `setpgid`, which is then made the terminal's foreground
+    /// process group using `tcsetpgrp()`.  This allows interactive
+    /// programs such as editors and pagers to read from the terminal and
+    /// receive the signals generated by keyboard.  The parent's process
+    /// group is restored to the foreground once the subprocess has been
+    /// waited for, or when the `Popen` is dropped.
+    ///
+    /// Otherwise, this option has no effect.
+    #[cfg(unix)]
+    pub foreground: bool,
+
+    /// Isolation applied to the subprocess before execing it.
+    ///
+    /// The sandbox is validated when the subprocess is started, and an
+    /// invalid or unsupported configuration makes starting it fail.  The
+    /// cgroup and namespaces are entered before changing the user and
+    /// group ID with `setuid` and `setgid`, and the remaining
+    /// restrictions are applied immediately before exec.  See
+    /// [`Sandbox`] for details.
+    ///
+    /// [`Sandbox`]: unix/struct.Sandbox.html
+    #[cfg(unix)]
+    pub sandbox: Option<crate::unix::Sandbox>,
+
+    /// Seatbelt sandbox profile applied to the subprocess.
+    ///
+    /// If specified, the profile, written in the sandbox profile language
+    /// used by `sandbox-exec`, is applied with `sandbox_init()` before
+    /// execing the child process, so that the program runs with the
+    /// restrictions from its first instruction.  If the profile cannot be
+    /// applied, for example because it doesn't parse, starting the
+    /// subprocess fails with `EINVAL`.  `fast_spawn` doesn't support this
+    /// option.
+    ///
+    /// `sandbox_init()` compiles the profile in the child, which
+    /// allocates memory between `fork()` and exec.  This is the one
+    /// exception to the child not allocating, described in
+    /// [`Popen::create`], so a subprocess with a profile shouldn't be
+    /// started while other threads might be holding the allocator lock.
+    ///
+    /// [`Popen::create`]: struct.Popen.html#method.create
+    #[cfg(target_os = "macos")]
+    pub sandbox_profile: Option<String>,
+
+    /// IO scheduling class and priority of the subprocess.
+    ///
+    /// If specified, calls `ioprio_set()` before execing the child
+    /// process, so that e.g. a backup doesn't starve the current process of
+    /// disk bandwidth.  A level above 7 is rejected with
+    /// `PopenError::LogicError`.  `fast_spawn` doesn't support this option.
+    #[cfg(target_os = "linux")]
+    pub io_priority: Option<crate::unix::IoPriority>,
+
+    /// Signal sent to the subprocess when the current process exits.
+    ///
+    /// If specified, the child requests the signal on the death of its
+    /// parent with `prctl(PR_SET_PDEATHSIG)` before execing the program,
+    /// and sends it to itself right away if the parent has already died.
+    /// This is like [`die_with_parent`] with a signal other than
+    /// `SIGKILL`, such as `SIGTERM` to let the subprocess clean up, and
+    /// has the same caveats.  It takes precedence over
+    /// `die_with_parent` if both are specified.  `fast_spawn` doesn't
+    /// support this option.
+    ///
+    /// [`die_with_parent`]: #structfield.die_with_parent
+    #[cfg(target_os = "linux")]
+    pub parent_death_signal: Option<crate::unix::Signal>,
+
+    /// Paths made visible to the subprocess with `unveil()`, along with
+    /// their permissions such as `"r"` or `"rx"`.
+    ///
+    /// If not empty, `unveil()` is called for each path before execing the
+    /// child process, and the list is then locked.  Note that the program
+    /// itself, the dynamic linker, and the shared libraries it needs must be
+    /// among the unveiled paths for the exec to succeed.  Whether the
+    /// restrictions remain in effect in the executed program follows the
+    /// rules described in unveil(2).
+    #[cfg(target_os = "openbsd")]
+    pub unveil: Vec<(OsString, String)>,
+
+    /// Promises the program executed as the subprocess is restricted to.
+    ///
+    /// If specified, calls `pledge(NULL, execpromises)` before execing the
+    /// child process, so that the program runs pledged from the start,
+    /// without having to call `pledge()` itself.
+    #[cfg(target_os = "openbsd")]
+    pub pledge: Option<String>,
+
+    /// Process creation flags for the subprocess.
+    ///
+    /// The value is passed as the `dwCreationFlags` argument of
+    /// `CreateProcessW`, with `CREATE_UNICODE_ENVIRONMENT` always added.
+    /// Useful values include `CREATE_NEW_PROCESS_GROUP`, `CREATE_NO_WINDOW`
+    /// and `DETACHED_PROCESS`.
+    #[cfg(windows)]
+    pub creation_flags: u32,
+
+    /// Switch the console to the UTF-8 code page while the subprocess runs.
+    ///
+    /// Many console programs, including the standard Windows tools, encode
+    /// their output in the console's output code page, which is usually a
+    /// legacy OEM code page.  If this is set and the current process has a
+    /// console, its input and output code pages are set to `CP_UTF8`
+    /// before starting the subprocess, and restored once the subprocess
+    /// has been waited for, or when the `Popen` is dropped.  As the code
+    /// pages belong to the console, the change also applies to the current
+    /// process and to other processes sharing the console.  While several
+    /// such subprocesses run, the code pages are restored after the last
+    /// one finishes.
+    ///
+    /// Without a console, this option has no effect.
+    #[cfg(windows)]
+    pub console_utf8: bool,
+
     // Add this field to force construction using ..Default::default() for
     // backward compatibility.  Unfortunately we can't mark this non-public
     // because then ..Default::default() wouldn't work either.
@@ -184,6 +532,12 @@ impl PopenConfig {
             stdout: self.stdout.try_clone()?,
             stderr: self.stderr.try_clone()?,
             detached: self.detached,
+            append: self.append,
+            reopenable_output: self.reopenable_output,
+            wait_in_background: self.wait_in_background,
+            kill_on_drop: self.kill_on_drop,
+            die_with_parent: self.die_with_parent,
+            priority: self.priority,
             executable: self.executable.as_ref().cloned(),
             env: self.env.clone(),
             cwd: self.cwd.clone(),
@@ -193,6 +547,38 @@ impl PopenConfig {
             setgid: self.setgid,
             #[cfg(unix)]
             setpgid: self.setpgid,
+            #[cfg(unix)]
+            setsid: self.setsid,
+            #[cfg(unix)]
+            umask: self.umask,
+            #[cfg(unix)]
+            fast_spawn: self.fast_spawn,
+            #[cfg(unix)]
+            sigpipe: self.sigpipe,
+            #[cfg(unix)]
+            reset_signals: self.reset_signals,
+            #[cfg(unix)]
+            blocked_signals: self.blocked_signals.clone(),
+            #[cfg(unix)]
+            terminate_signal: self.terminate_signal,
+            #[cfg(unix)]
+            foreground: self.foreground,
+            #[cfg(unix)]
+            sandbox: self.sandbox.clone(),
+            #[cfg(target_os = "macos")]
+            sandbox_profile: self.sandbox_profile.clone(),
+            #[cfg(target_os = "linux")]
+            io_priority: self.io_priority,
+            #[cfg(target_os = "linux")]
+            parent_death_signal: self.parent_death_signal,
+            #[cfg(target_os = "openbsd")]
+            unveil: self.unveil.clone(),
+            #[cfg(target_os = "openbsd")]
+            pledge: self.pledge.clone(),
+            #[cfg(windows)]
+            creation_flags: self.creation_flags,
+            #[cfg(windows)]
+            console_utf8: self.console_utf8,
             _use_default_to_construct: (),
         })
     }
@@ -213,6 +599,12 @@ impl Default for PopenConfig {
             stdout: Redirection::None,
             stderr: Redirection::None,
             detached: false,
+            append: true,
+            reopenable_output: false,
+            wait_in_background: false,
+            kill_on_drop: false,
+            die_with_parent: false,
+            priority: None,
             executable: None,
             env: None,
             cwd: None,
@@ -222,11 +614,248 @@ impl Default for PopenConfig {
             setgid: None,
             #[cfg(unix)]
             setpgid: false,
+            #[cfg(unix)]
+            setsid: false,
+            #[cfg(unix)]
+            umask: None,
+            #[cfg(unix)]
+            fast_spawn: false,
+            #[cfg(unix)]
+            sigpipe: Default::default(),
+            #[cfg(unix)]
+            reset_signals: false,
+            #[cfg(unix)]
+            blocked_signals: vec![],
+            #[cfg(unix)]
+            terminate_signal: crate::posix::SIGTERM,
+            #[cfg(unix)]
+            foreground: false,
+            #[cfg(unix)]
+            sandbox: None,
+            #[cfg(target_os = "macos")]
+            sandbox_profile: None,
+            #[cfg(target_os = "linux")]
+            io_priority: None,
+            #[cfg(target_os = "linux")]
+            parent_death_signal: None,
+            #[cfg(target_os = "openbsd")]
+            unveil: vec![],
+            #[cfg(target_os = "openbsd")]
+            pledge: None,
+            #[cfg(windows)]
+            creation_flags: 0,
+            #[cfg(windows)]
+            console_utf8: false,
             _use_default_to_construct: (),
         }
     }
 }
 
+// Written out rather than derived so that only the changes to the
+// environment are shown, with secrets redacted, instead of all of it.
+impl fmt::Debug for PopenConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("PopenConfig");
+        s.field("stdin", &self.stdin)
+            .field("stdout", &self.stdout)
+            .field("stderr", &self.stderr)
+            .field("detached", &self.detached)
+            .field("append", &self.append)
+            .field("reopenable_output", &self.reopenable_output)
+            .field("wait_in_background", &self.wait_in_background)
+            .field("kill_on_drop", &self.kill_on_drop)
+            .field("die_with_parent", &self.die_with_parent)
+            .field("priority", &self.priority)
+            .field("executable", &self.executable)
+            .field("env", &self.env.as_deref().map(EnvDelta))
+            .field("cwd", &self.cwd);
+        #[cfg(unix)]
+        s.field("setuid", &self.setuid)
+            .field("setgid", &self.setgid)
+            .field("setpgid", &self.setpgid)
+            .field("setsid", &self.setsid)
+            .field("umask", &self.umask)
+            .field("fast_spawn", &self.fast_spawn)
+            .field("sigpipe", &self.sigpipe)
+            .field("reset_signals", &self.reset_signals)
+            .field("blocked_signals", &self.blocked_signals)
+            .field("terminate_signal", &self.terminate_signal)
+            .field("foreground", &self.foreground)
+            .field("sandbox", &self.sandbox);
+        #[cfg(target_os = "macos")]
+        s.field("sandbox_profile", &self.sandbox_profile);
+        #[cfg(target_os = "linux")]
+        s.field("io_priority", &self.io_priority)
+            .field("parent_death_signal", &self.parent_death_signal);
+        #[cfg(target_os = "openbsd")]
+        s.field("unveil", &self.unveil)
+            .field("pledge", &self.pledge);
+        #[cfg(windows)]
+        s.field("creation_flags", &self.creation_flags)
+            .field("console_utf8", &self.console_utf8);
+        s.finish()
+    }
+}
+
+// Environment shown as its changes relative to the current process.
+struct EnvDelta<'a>(&'a [(OsString, OsString)]);
+
+impl fmt::Debug for EnvDelta<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(env_delta(self.0)).finish()
+    }
+}
+
+// Value shown in place of the values of variables that look like secrets.
+const REDACTED: &str = "***";
+
+// Whether the environment variable `name` looks like it holds a secret,
+// such as GITHUB_TOKEN or AWS_SECRET_ACCESS_KEY.
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    [
+        "TOKEN",
+        "SECRET",
+        "PASSWORD",
+        "PASSWD",
+        "CREDENTIAL",
+        "PRIVATE",
+    ]
+    .iter()
+    .any(|word| name.contains(word))
+        || name == "KEY"
+        || name.ends_with("_KEY")
+}
+
+// Differences between `env` and the environment of the current process, for
+// display: the added or changed variables with their values, followed by
+// the removed ones with None.  Values of variables that look like secrets
+// are redacted.
+pub(crate) fn env_delta(env: &[(OsString, OsString)]) -> Vec<(String, Option<String>)> {
+    // names are case-insensitive on Windows
+    fn key(name: &OsStr) -> OsString {
+        let mut key = name.to_owned();
+        if cfg!(windows) {
+            key.make_ascii_uppercase();
+        }
+        key
+    }
+
+    let current: Vec<(OsString, OsString)> = env::vars_os().collect();
+    let current_map: HashMap<OsString, &OsString> =
+        current.iter().map(|(k, v)| (key(k), v)).collect();
+    // later entries override earlier ones, as when starting the process
+    let new_map: HashMap<OsString, &OsString> = env.iter().map(|(k, v)| (key(k), v)).collect();
+    let mut shown = HashSet::new();
+    let mut delta = vec![];
+    for (k, _) in env {
+        let value = new_map[&key(k)];
+        if current_map.get(&key(k)) == Some(&value) || !shown.insert(key(k)) {
+            continue;
+        }
+        let name = k.to_string_lossy().into_owned();
+        let value = if is_secret_name(&name) {
+            REDACTED.to_owned()
+        } else {
+            value.to_string_lossy().into_owned()
+        };
+        delta.push((name, Some(value)));
+    }
+    for (k, _) in &current {
+        if !new_map.contains_key(&key(k)) {
+            delta.push((k.to_string_lossy().into_owned(), None));
+        }
+    }
+    delta
+}
+
+/// Options for creating a pipe with [`make_pipe_with`].
+///
+/// [`make_pipe_with`]: fn.make_pipe_with.html
+#[derive(Debug, Clone, Default)]
+pub struct PipeOptions {
+    /// Requested size of the pipe buffer in bytes, or 0 for the system
+    /// default.
+    ///
+    /// On Linux the size is set with `F_SETPIPE_SZ`, which rounds it up
+    /// to a power of two number of pages.  On Windows it is passed to the
+    /// pipe creation as a hint.  It is ignored on other systems.
+    pub buffer_size: u32,
+
+    /// Create the pipe in message mode rather than byte mode.
+    ///
+    /// In message mode each write is delivered to the reader as a
+    /// separate message.
+    #[cfg(windows)]
+    pub message_mode: bool,
+
+    /// Open the reading end for overlapped (asynchronous) I/O.
+    ///
+    /// An overlapped handle must be used with overlapped I/O calls, so
+    /// this is only useful for an end kept by the parent and read with
+    /// code that performs its own overlapped I/O.  Such an end must not
+    /// be passed to `Communicator` or read with blocking `Read` calls.
+    #[cfg(windows)]
+    pub overlapped_read: bool,
+
+    /// Open the writing end for overlapped (asynchronous) I/O.
+    ///
+    /// The same caveats apply as for `overlapped_read`.
+    #[cfg(windows)]
+    pub overlapped_write: bool,
+
+    // Add this field to force construction using ..Default::default() for
+    // backward compatibility.  Unfortunately we can't mark this non-public
+    // because then ..Default::default() wouldn't work either.
+    #[doc(hidden)]
+    pub _use_default_to_construct: (),
+}
+
+/// Scheduling priority of a subprocess, see [`PopenConfig::priority`].
+///
+/// On Unix the priorities correspond to nice values from 19 for `Idle` to
+/// -10 for `High`, and on Windows to the priority classes of the same
+/// names.
+///
+/// [`PopenConfig::priority`]: struct.PopenConfig.html#structfield.priority
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Priority {
+    /// Only run when the system is otherwise idle (nice value 19).
+    Idle,
+    /// Lower than normal (nice value 10).
+    BelowNormal,
+    /// The default priority (nice value 0).
+    Normal,
+    /// Higher than normal (nice value -5).
+    AboveNormal,
+    /// Much higher than normal (nice value -10).
+    High,
+}
+
+impl Priority {
+    #[cfg(unix)]
+    fn nice(self) -> i32 {
+        match self {
+            Priority::Idle => 19,
+            Priority::BelowNormal => 10,
+            Priority::Normal => 0,
+            Priority::AboveNormal => -5,
+            Priority::High => -10,
+        }
+    }
+
+    #[cfg(windows)]
+    fn creation_flag(self) -> u32 {
+        match self {
+            Priority::Idle => crate::win32::IDLE_PRIORITY_CLASS,
+            Priority::BelowNormal => crate::win32::BELOW_NORMAL_PRIORITY_CLASS,
+            Priority::Normal => crate::win32::NORMAL_PRIORITY_CLASS,
+            Priority::AboveNormal => crate::win32::ABOVE_NORMAL_PRIORITY_CLASS,
+            Priority::High => crate::win32::HIGH_PRIORITY_CLASS,
+        }
+    }
+}
+
 /// Instruction what to do with a stream in the child process.
 ///
 /// `Redirection` values are used for the `stdin`, `stdout`, and
@@ -234,7 +863,6 @@ impl Default for PopenConfig {
 /// `Popen::create` how to set up the standard streams in the child
 /// process and the corresponding fields of the `Popen` struct in the
 /// parent.
-
 #[derive(Debug)]
 pub enum Redirection {
     /// Do nothing with the stream.
@@ -290,6 +918,112 @@ pub enum Redirection {
     ///
     /// This allows the same file to be used in multiple redirections.
     RcFile(Rc<File>),
+
+    /// Like `File`, but the file is specified as `Arc`.
+    ///
+    /// This allows the same file to be used in redirections of processes
+    /// started from different threads, for example to have several
+    /// commands append to a common log file opened only once.
+    SharedFile(Arc<File>),
+
+    /// Redirect the stream to the file at the specified path.
+    ///
+    /// The file is opened when the process is started.  Standard input
+    /// is opened for reading.  For standard output and error, the file is
+    /// created if it doesn't exist, and is appended to or truncated
+    /// according to [`PopenConfig::append`].
+    ///
+    /// The field in `Popen` corresponding to the stream will be
+    /// `None`.
+    ///
+    /// [`PopenConfig::append`]: struct.PopenConfig.html#structfield.append
+    Path(PathBuf),
+
+    /// Connect the stream to a pseudo-terminal.
+    ///
+    /// A pty pair is allocated for the process, shared by all of its
+    /// streams redirected to `Pty`.  The child is made the leader of a
+    /// new session, with the slave side as its controlling terminal, so
+    /// that programs which only behave interactively when attached to a
+    /// terminal, such as shells and interpreters, do so.
+    ///
+    /// The field in `Popen` corresponding to the stream will be
+    /// `Some(file)`, `File` being the master side of the terminal.  When
+    /// both standard output and standard error are redirected to `Pty`,
+    /// they are indistinguishable and both arrive on `Popen::stdout`,
+    /// `Popen::stderr` being `None`.
+    ///
+    /// Unlike a pipe, the terminal echoes the input written to it and
+    /// translates line endings.  Closing the master side of standard
+    /// input doesn't signal end of file to the child; writing the
+    /// terminal's end-of-file character, typically `\x04`, does.  Once
+    /// all handles to the master side are closed, the terminal is hung
+    /// up and a child that is still running receives `SIGHUP`.
+    ///
+    /// Only supported on Unix-like systems.  On Windows, and together
+    /// with `PopenConfig::foreground`, starting the process fails with
+    /// `PopenError::LogicError`.
+    Pty,
+}
+
+// File given to the child process as one of its standard streams.
+#[derive(Debug, Clone)]
+enum ChildStream {
+    Rc(Rc<File>),
+    Arc(Arc<File>),
+}
+
+#[cfg(unix)]
+impl ChildStream {
+    fn ptr(&self) -> *const File {
+        &**self
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            ChildStream::Rc(ref file) => Rc::strong_count(file),
+            ChildStream::Arc(ref file) => Arc::strong_count(file),
+        }
+    }
+}
+
+impl Deref for ChildStream {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        match *self {
+            ChildStream::Rc(ref file) => file,
+            ChildStream::Arc(ref file) => file,
+        }
+    }
+}
+
+fn open_output(path: &Path, append: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
+    }
+    options.open(path)
+}
+
+// Copy the output read from `source` to the current `file`, until the
+// child closes it.  Failure to write is ignored, so that the child
+// doesn't block on a full pipe.
+fn relay_output(mut source: File, file: &Mutex<File>) {
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = match source.read(&mut buf) {
+            Ok(0) => return,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = file.write_all(&buf[..n]);
+    }
 }
 
 impl Redirection {
@@ -302,7 +1036,10 @@ impl Redirection {
             Redirection::Pipe => Redirection::Pipe,
             Redirection::Merge => Redirection::Merge,
             Redirection::File(ref f) => Redirection::File(f.try_clone()?),
-            Redirection::RcFile(ref f) => Redirection::RcFile(Rc::clone(&f)),
+            Redirection::RcFile(ref f) => Redirection::RcFile(Rc::clone(f)),
+            Redirection::SharedFile(ref f) => Redirection::SharedFile(Arc::clone(f)),
+            Redirection::Path(ref path) => Redirection::Path(path.clone()),
+            Redirection::Pty => Redirection::Pty,
         })
     }
 }
@@ -310,9 +1047,10 @@ impl Redirection {
 impl Popen {
     /// Execute an external program in a new process.
     ///
-    /// `argv` is a slice containing the program followed by its
-    /// arguments, such as `&["ps", "x"]`. `config` specifies details
-    /// how to create and interface to the process.
+    /// `argv` is the program followed by its arguments, such as `&["ps",
+    /// "x"]`.  It can be given as any iterable of string-like values,
+    /// including slices, arrays, vectors and iterators.  `config`
+    /// specifies details how to create and interface to the process.
     ///
     /// For example, this launches the `cargo update` command:
     ///
@@ -324,6 +1062,40 @@ impl Popen {
     /// # }
     /// ```
     ///
+    /// # Descriptors
+    ///
+    /// The descriptors given to the child as its standard streams are
+    /// handled so that the current process doesn't keep the child's ends
+    /// open behind its back, which would keep the readers of the child's
+    /// output, or the child's own children left running in the background,
+    /// waiting for an end of file that never comes:
+    ///
+    /// * The child's ends of pipes, and the files opened for it or handed
+    ///   over with `Redirection::File` and `NullFile`, are closed in the
+    ///   current process as soon as the child has started.  On Unix they
+    ///   are also close-on-exec until then, so they aren't inherited by
+    ///   processes started concurrently by other threads.
+    /// * Files still held by the caller, given with `Redirection::RcFile`
+    ///   and `Redirection::SharedFile`, are left as they are: the child
+    ///   receives a copy, and the caller's descriptor isn't made
+    ///   inheritable by subsequently started processes.
+    ///
+    /// [`assert_no_child_fds`] and [`set_fd_audit`] check this contract.
+    ///
+    /// [`assert_no_child_fds`]: struct.Popen.html#method.assert_no_child_fds
+    /// [`set_fd_audit`]: fn.set_fd_audit.html
+    ///
+    /// # Threads
+    ///
+    /// On Unix, the child created by `fork()` only contains the thread that
+    /// started it, and the locks held by the other threads, such as that of
+    /// the memory allocator, stay locked forever.  Everything the child
+    /// needs is therefore prepared in advance, and the child doesn't
+    /// allocate or free memory or take locks before executing the program,
+    /// so processes can be safely started while other threads are running.
+    /// The exception is `sandbox_profile` on macOS, which the child has to
+    /// compile itself.
+    ///
     /// # Errors
     ///
     /// If the external program cannot be executed for any reason, an
@@ -333,22 +1105,99 @@ impl Popen {
     /// program running and then exiting with a failure code - this
     /// can be detected by calling the `wait` method to obtain its
     /// exit status.
-    pub fn create(argv: &[impl AsRef<OsStr>], config: PopenConfig) -> Result<Popen> {
+    ///
+    /// On Unix, an error in preparing the child for executing the program,
+    /// such as changing to the working directory or applying the sandbox,
+    /// keeps the kind of the underlying error, and its message names the
+    /// step that failed.
+    ///
+    /// With the `no-path-search` feature, the program is never looked up
+    /// in `PATH`, and a program name without a directory component, such
+    /// as `"ls"` rather than `"/bin/ls"` or `"./ls"`, is rejected with
+    /// `PopenError::LogicError`.  The check is made at run time, as the
+    /// program is an ordinary string whose value the compiler doesn't
+    /// know; only the functions that exist to search `PATH`, such as
+    /// `Exec::script`, are removed at build time.
+    pub fn create(
+        argv: impl IntoIterator<Item = impl AsRef<OsStr>>,
+        config: PopenConfig,
+    ) -> Result<Popen> {
+        let argv: Vec<OsString> = argv.into_iter().map(|p| p.as_ref().to_owned()).collect();
         if argv.is_empty() {
             return Err(PopenError::LogicError("argv must not be empty"));
         }
-        let argv: Vec<OsString> = argv.iter().map(|p| p.as_ref().to_owned()).collect();
+        #[cfg(feature = "no-path-search")]
+        {
+            let program = Path::new(config.executable.as_ref().unwrap_or(&argv[0]));
+            if !program
+                .parent()
+                .map_or(false, |dir| !dir.as_os_str().is_empty())
+            {
+                return Err(PopenError::LogicError(
+                    "program must be given by path when PATH search is disabled",
+                ));
+            }
+        }
+        if let Some(ref cwd) = config.cwd {
+            // Detect a missing working directory up front, as the OS would
+            // report it with the same error as a missing program.
+            if let Err(e) = fs::metadata(cwd) {
+                if e.kind() == io::ErrorKind::NotFound {
+                    return Err(PopenError::CwdNotFound(PathBuf::from(cwd)));
+                }
+            }
+        }
         let mut inst = Popen {
             stdin: None,
             stdout: None,
             stderr: None,
             child_state: ChildState::Preparing,
             detached: config.detached,
+            wait_in_background: config.wait_in_background,
+            kill_on_drop: config.kill_on_drop,
+            start_time: Instant::now(),
+            #[cfg(unix)]
+            terminate_signal: config.terminate_signal,
+            reopenable: vec![],
+            #[cfg(unix)]
+            audited_ends: vec![],
+            exit_watch: None,
+            temp_cwd: None,
+            program: Some(argv[0].clone()),
         };
         inst.os_start(argv, config)?;
         Ok(inst)
     }
 
+    // Create a Popen that stands in for a process that has finished with
+    // `exit_status`, whose streams are connected to the given files
+    // rather than to a child, such as when replaying a recording.
+    pub(crate) fn finished(
+        stdin: Option<File>,
+        stdout: Option<File>,
+        stderr: Option<File>,
+        exit_status: ExitStatus,
+    ) -> Popen {
+        Popen {
+            stdin,
+            stdout,
+            stderr,
+            child_state: ChildState::Finished(exit_status),
+            detached: false,
+            wait_in_background: false,
+            kill_on_drop: false,
+            start_time: Instant::now(),
+            #[cfg(unix)]
+            terminate_signal: crate::posix::SIGTERM,
+            reopenable: vec![],
+            #[cfg(unix)]
+            audited_ends: vec![],
+            exit_watch: None,
+            temp_cwd: None,
+            program: None,
+        }
+    }
+
     // Create the pipes requested by stdin, stdout, and stderr from
     // the PopenConfig used to construct us, and return the Files to
     // be given to the child process.
@@ -358,17 +1207,27 @@ impl Popen {
     // of the pipe.
     //
     // For Redirection::File, this transfers the ownership of the File
-    // to the corresponding child.
+    // to the corresponding child.  For Redirection::Path, this opens the
+    // file, appending to output files if `append` is true, and for
+    // reopenable output starts the thread copying to it from a pipe.  For
+    // Redirection::Pty, this stores a handle to the master side of the
+    // pty allocated for the process, and returns its slave side.
     fn setup_streams(
         &mut self,
         stdin: Redirection,
         stdout: Redirection,
         stderr: Redirection,
-    ) -> Result<(Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>)> {
+        append: bool,
+        reopenable: bool,
+    ) -> Result<(
+        Option<ChildStream>,
+        Option<ChildStream>,
+        Option<ChildStream>,
+    )> {
         fn prepare_pipe(
             parent_writes: bool,
             parent_ref: &mut Option<File>,
-            child_ref: &mut Option<Rc<File>>,
+            child_ref: &mut Option<ChildStream>,
         ) -> Result<()> {
             // Store the parent's end of the pipe into the given
             // reference, and store the child end.
@@ -379,34 +1238,89 @@ impl Popen {
                 (read, write)
             };
             os::set_inheritable(&parent_end, false)?;
+            os::prepare_child_end(&child_end)?;
             *parent_ref = Some(parent_end);
-            *child_ref = Some(Rc::new(child_end));
+            *child_ref = Some(ChildStream::Rc(Rc::new(child_end)));
+            Ok(())
+        }
+        fn prepare_file(file: File, child_ref: &mut Option<ChildStream>) -> io::Result<()> {
+            // Store the File for use in the child.
+            os::prepare_child_end(&file)?;
+            *child_ref = Some(ChildStream::Rc(Rc::new(file)));
             Ok(())
         }
-        fn prepare_file(file: File, child_ref: &mut Option<Rc<File>>) -> io::Result<()> {
-            // Make the File inheritable and store it for use in the child.
-            os::set_inheritable(&file, true)?;
-            *child_ref = Some(Rc::new(file));
+        fn prepare_rc_file(file: Rc<File>, child_ref: &mut Option<ChildStream>) -> io::Result<()> {
+            // Like prepare_file, but for Rc<File> still held by the caller
+            *child_ref = Some(os::share_child_end(ChildStream::Rc(file))?);
             Ok(())
         }
-        fn prepare_rc_file(file: Rc<File>, child_ref: &mut Option<Rc<File>>) -> io::Result<()> {
-            // Like prepare_file, but for Rc<File>
-            os::set_inheritable(&file, true)?;
-            *child_ref = Some(file);
+        fn prepare_shared_file(
+            file: Arc<File>,
+            child_ref: &mut Option<ChildStream>,
+        ) -> io::Result<()> {
+            // Like prepare_file, but for Arc<File> still held by the caller
+            *child_ref = Some(os::share_child_end(ChildStream::Arc(file))?);
+            Ok(())
+        }
+        fn prepare_pty(
+            pty: &mut Option<(File, Rc<File>)>,
+            parent_ref: Option<&mut Option<File>>,
+            child_ref: &mut Option<ChildStream>,
+        ) -> Result<()> {
+            // Allocate the pty on first use, and share it among the
+            // streams redirected to it.
+            if pty.is_none() {
+                let (master, slave) = os::open_pty()?;
+                *pty = Some((master, Rc::new(slave)));
+            }
+            let (master, slave) = pty.as_ref().unwrap();
+            if let Some(parent_ref) = parent_ref {
+                *parent_ref = Some(master.try_clone()?);
+            }
+            *child_ref = Some(ChildStream::Rc(Rc::clone(slave)));
             Ok(())
         }
+        fn prepare_reopenable(
+            path: PathBuf,
+            append: bool,
+            outputs: &mut Vec<ReopenableOutput>,
+            child_ref: &mut Option<ChildStream>,
+        ) -> io::Result<()> {
+            let file = Arc::new(Mutex::new(open_output(&path, append)?));
+            let (read, write) = os::make_pipe()?;
+            os::set_inheritable(&read, false)?;
+            os::prepare_child_end(&write)?;
+            let relay_file = Arc::clone(&file);
+            thread::Builder::new()
+                .name("subprocess-relay".into())
+                .spawn(move || relay_output(read, &relay_file))?;
+            outputs.push(ReopenableOutput { path, file });
+            *child_ref = Some(ChildStream::Rc(Rc::new(write)));
+            Ok(())
+        }
+        fn prepare_output(
+            path: PathBuf,
+            append: bool,
+            reopenable: Option<&mut Vec<ReopenableOutput>>,
+            child_ref: &mut Option<ChildStream>,
+        ) -> io::Result<()> {
+            match reopenable {
+                Some(outputs) => prepare_reopenable(path, append, outputs, child_ref),
+                None => prepare_file(open_output(&path, append)?, child_ref),
+            }
+        }
         fn reuse_stream(
-            dest: &mut Option<Rc<File>>,
-            src: &mut Option<Rc<File>>,
+            dest: &mut Option<ChildStream>,
+            src: &mut Option<ChildStream>,
             src_id: StandardStream,
         ) -> io::Result<()> {
             // For Redirection::Merge, make stdout and stderr refer to
             // the same File.  If the file is unavailable, use the
             // appropriate system output stream.
             if src.is_none() {
-                *src = Some(get_standard_stream(src_id)?);
+                *src = Some(ChildStream::Rc(get_standard_stream(src_id)?));
             }
-            *dest = Some(Rc::clone(src.as_ref().unwrap()));
+            *dest = src.clone();
             Ok(())
         }
 
@@ -418,11 +1332,16 @@ impl Popen {
         let mut merge: MergeKind = MergeKind::None;
 
         let (mut child_stdin, mut child_stdout, mut child_stderr) = (None, None, None);
+        let mut pty = None;
+        let stdout_pty = matches!(stdout, Redirection::Pty);
 
         match stdin {
             Redirection::Pipe => prepare_pipe(true, &mut self.stdin, &mut child_stdin)?,
             Redirection::File(file) => prepare_file(file, &mut child_stdin)?,
             Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stdin)?,
+            Redirection::SharedFile(file) => prepare_shared_file(file, &mut child_stdin)?,
+            Redirection::Path(path) => prepare_file(File::open(path)?, &mut child_stdin)?,
+            Redirection::Pty => prepare_pty(&mut pty, Some(&mut self.stdin), &mut child_stdin)?,
             Redirection::Merge => {
                 return Err(PopenError::LogicError(
                     "Redirection::Merge not valid for stdin",
@@ -434,6 +1353,14 @@ impl Popen {
             Redirection::Pipe => prepare_pipe(false, &mut self.stdout, &mut child_stdout)?,
             Redirection::File(file) => prepare_file(file, &mut child_stdout)?,
             Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stdout)?,
+            Redirection::SharedFile(file) => prepare_shared_file(file, &mut child_stdout)?,
+            Redirection::Path(path) => prepare_output(
+                path,
+                append,
+                Some(&mut self.reopenable).filter(|_| reopenable),
+                &mut child_stdout,
+            )?,
+            Redirection::Pty => prepare_pty(&mut pty, Some(&mut self.stdout), &mut child_stdout)?,
             Redirection::Merge => merge = MergeKind::OutToErr,
             Redirection::None => (),
         };
@@ -441,6 +1368,23 @@ impl Popen {
             Redirection::Pipe => prepare_pipe(false, &mut self.stderr, &mut child_stderr)?,
             Redirection::File(file) => prepare_file(file, &mut child_stderr)?,
             Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stderr)?,
+            Redirection::SharedFile(file) => prepare_shared_file(file, &mut child_stderr)?,
+            Redirection::Path(path) => prepare_output(
+                path,
+                append,
+                Some(&mut self.reopenable).filter(|_| reopenable),
+                &mut child_stderr,
+            )?,
+            Redirection::Pty => {
+                // with stdout on the same terminal, its output can't be
+                // told apart, so it's all read from stdout
+                let parent_ref = if stdout_pty {
+                    None
+                } else {
+                    Some(&mut self.stderr)
+                };
+                prepare_pty(&mut pty, parent_ref, &mut child_stderr)?
+            }
             Redirection::Merge => merge = MergeKind::ErrToOut,
             Redirection::None => (),
         };
@@ -465,6 +1409,65 @@ impl Popen {
         Ok((child_stdin, child_stdout, child_stderr))
     }
 
+    /// Panic if the child's end of a pipe connected to one of its standard
+    /// streams is open in the current process.
+    ///
+    /// Such a descriptor keeps the pipe from reporting end of file to the
+    /// reader of the child's output, or a broken pipe to the child writing
+    /// to it, for as long as the current process holds it.  `create`
+    /// closes the child's ends as soon as the child has started, as
+    /// described under [Descriptors]; this checks that no copy of them has
+    /// found its way into the current process since, e.g. by duplicating
+    /// the file passed with `Redirection::File`.
+    ///
+    /// Like `debug_assert!`, the check is only performed in debug builds.
+    /// It is currently only implemented on Unix, where it inspects the
+    /// open descriptors of the process; elsewhere it does nothing.
+    ///
+    /// [Descriptors]: struct.Popen.html#descriptors
+    pub fn assert_no_child_fds(&self) {
+        #[cfg(all(unix, debug_assertions))]
+        os::audit_check(&self.audited_ends);
+    }
+
+    /// Reopen the file at `path` to which output of the subprocess is
+    /// redirected, and continue writing the output to the new file.
+    ///
+    /// This is intended for log rotation: after the log file has been
+    /// renamed, `reopen_output` creates a new file at the original path,
+    /// and the subsequent output goes to it.  The file is opened for
+    /// appending.  Both standard output and error are reopened if both
+    /// are redirected to `path`.
+    ///
+    /// Only available for output redirected with
+    /// `Redirection::Path(path)` and [`PopenConfig::reopenable_output`]
+    /// set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if no reopenable output is
+    /// redirected to `path`, or the error encountered when opening the
+    /// file.  In the latter case the output continues to go to the
+    /// previous file.
+    ///
+    /// [`PopenConfig::reopenable_output`]: struct.PopenConfig.html#structfield.reopenable_output
+    pub fn reopen_output(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut found = false;
+        for output in self.reopenable.iter().filter(|o| o.path == path) {
+            let file = open_output(path, true)?;
+            *output.file.lock().unwrap_or_else(|e| e.into_inner()) = file;
+            found = true;
+        }
+        if !found {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no reopenable output redirected to {}", path.display()),
+            ));
+        }
+        Ok(())
+    }
+
     /// Mark the process as detached.
     ///
     /// This method has no effect on the OS level, it simply tells
@@ -476,6 +1479,133 @@ impl Popen {
         self.detached = true;
     }
 
+    /// Undo the effect of [`detach`], or of [`PopenConfig::detached`].
+    ///
+    /// Going out of scope, the `Popen` will again wait for the subprocess
+    /// to finish.
+    ///
+    /// [`detach`]: struct.Popen.html#method.detach
+    /// [`PopenConfig::detached`]: struct.PopenConfig.html#structfield.detached
+    pub fn attach(&mut self) {
+        self.detached = false;
+    }
+
+    pub(crate) fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    /// Mark all the `processes` as detached.
+    ///
+    /// This is a shorthand for calling [`detach`] on each process, useful
+    /// for turning a started pipeline, such as the result of
+    /// [`Pipeline::popen`], into fire-and-forget.
+    ///
+    /// [`detach`]: struct.Popen.html#method.detach
+    /// [`Pipeline::popen`]: struct.Pipeline.html#method.popen
+    pub fn detach_all(processes: &mut [Popen]) {
+        processes.iter_mut().for_each(Popen::detach);
+    }
+
+    /// Undo the effect of [`detach_all`], calling [`attach`] on each of
+    /// the `processes`.
+    ///
+    /// [`detach_all`]: struct.Popen.html#method.detach_all
+    /// [`attach`]: struct.Popen.html#method.attach
+    pub fn attach_all(processes: &mut [Popen]) {
+        processes.iter_mut().for_each(Popen::attach);
+    }
+
+    /// Wait for all the `processes` to finish, and return their exit
+    /// statuses in order.
+    ///
+    /// This calls [`wait`] on each process in turn, stopping at the first
+    /// error.
+    ///
+    /// [`wait`]: struct.Popen.html#method.wait
+    pub fn wait_all(processes: &mut [Popen]) -> Result<Vec<ExitStatus>> {
+        processes.iter_mut().map(Popen::wait).collect()
+    }
+
+    /// Terminate all the `processes`.
+    ///
+    /// This calls [`terminate`] on each process.  All the processes are
+    /// terminated even if some of the calls fail, in which case the first
+    /// error is returned.
+    ///
+    /// [`terminate`]: struct.Popen.html#method.terminate
+    pub fn terminate_all(processes: &mut [Popen]) -> io::Result<()> {
+        let mut result = Ok(());
+        for p in processes {
+            let r = p.terminate();
+            if result.is_ok() {
+                result = r;
+            }
+        }
+        result
+    }
+
+    /// Shut down all the `processes`, giving them `grace` time to exit
+    /// before killing them, and return their exit statuses in order.
+    ///
+    /// This is like calling [`shutdown`] on each process, except that
+    /// all of them are asked to exit first, and then share the same
+    /// grace period.
+    ///
+    /// [`shutdown`]: struct.Popen.html#method.shutdown
+    pub fn shutdown_all(processes: &mut [Popen], grace: Duration) -> Result<Vec<ExitStatus>> {
+        for p in processes.iter_mut() {
+            // a failure to signal is reported by kill() below
+            p.os_request_exit().ok();
+        }
+        let deadline = Instant::now() + grace;
+        processes
+            .iter_mut()
+            .map(|p| {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match p.wait_timeout(remaining)? {
+                    Some(exit_status) => Ok(exit_status),
+                    None => {
+                        p.kill()?;
+                        p.wait()
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Wait for the subprocess in a background thread when going out of
+    /// scope.
+    ///
+    /// By default, dropping a `Popen` whose subprocess is still running
+    /// blocks until the subprocess finishes, while a detached `Popen`
+    /// leaves behind a zombie process on Unix-like systems.  After calling
+    /// this method, dropping a `Popen` whose subprocess is still running
+    /// hands the subprocess over to a thread that waits for it to finish,
+    /// so that neither happens.  The thread is shared by all processes
+    /// and started when first needed.
+    ///
+    /// This has no effect on detached instances.
+    pub fn wait_in_background(&mut self) {
+        self.wait_in_background = true;
+    }
+
+    /// Kill the subprocess when going out of scope.
+    ///
+    /// By default, dropping a `Popen` whose subprocess is still running
+    /// blocks until the subprocess finishes, which is wrong for code that
+    /// abandons subprocesses whose results are no longer needed, such as
+    /// on cancellation or error.  After calling this method, dropping a
+    /// `Popen` whose subprocess is still running kills the subprocess
+    /// with [`kill`] and then waits for it to exit, which doesn't take
+    /// long.
+    ///
+    /// This has no effect on detached instances.
+    ///
+    /// [`kill`]: struct.Popen.html#method.kill
+    pub fn kill_on_drop(&mut self) {
+        self.kill_on_drop = true;
+    }
+
     /// Return the PID of the subprocess, if it is known to be still running.
     ///
     /// Note that this method won't actually *check* whether the child
@@ -490,6 +1620,18 @@ impl Popen {
         }
     }
 
+    /// Return the instant at which the subprocess was started.
+    pub fn start_time(&self) -> Instant {
+        self.start_time
+    }
+
+    /// Return the time elapsed since the subprocess was started.
+    ///
+    /// The time keeps increasing after the subprocess has finished.
+    pub fn elapsed(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+
     /// Return the exit status of the subprocess, if it is known to have finished.
     ///
     /// Note that this method won't actually *check* whether the child
@@ -503,6 +1645,100 @@ impl Popen {
         }
     }
 
+    /// Return a channel that receives the exit status of the subprocess.
+    ///
+    /// The exit status is sent once the subprocess has finished, so that
+    /// other threads can block on or select over the exit without owning
+    /// the `Popen`.  The channel can be requested any number of times, and
+    /// if the subprocess has already been waited for, the status is
+    /// available immediately.
+    ///
+    /// On Linux and Windows the exit is detected by a helper thread that
+    /// doesn't reap the child, so `wait()` still reports the same status
+    /// to the owner.  On other systems the status is sent when the exit is
+    /// observed by `wait()`, `wait_timeout()` or `poll()`, or by the
+    /// background reaper after [`wait_in_background`].  If the exit can no
+    /// longer be observed, e.g. because the `Popen` was dropped after
+    /// [`detach`], the channel is disconnected without a message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the helper thread cannot be started.
+    ///
+    /// [`wait_in_background`]: struct.Popen.html#method.wait_in_background
+    /// [`detach`]: struct.Popen.html#method.detach
+    pub fn exit_channel(&mut self) -> io::Result<Receiver<ExitStatus>> {
+        let watch = match self.exit_watch {
+            Some(ref watch) => Arc::clone(watch),
+            None => {
+                let watch = Arc::new(ExitWatch::default());
+                if let Running { .. } = self.child_state {
+                    self.os_watch_exit(Arc::clone(&watch))?;
+                }
+                self.exit_watch = Some(Arc::clone(&watch));
+                watch
+            }
+        };
+        self.after_exit();
+        Ok(watch.subscribe())
+    }
+
+    // Once the exit status is known, pass it to the receivers of
+    // exit_channel() and remove the temporary working directory.
+    fn after_exit(&mut self) {
+        let exit_status = match self.child_state {
+            Finished(exit_status) => exit_status,
+            _ => return,
+        };
+        if let Some(ref watch) = self.exit_watch {
+            watch.notify(exit_status);
+        }
+        if let Some(temp_cwd) = self.temp_cwd.take() {
+            if exit_status.success() || !temp_cwd.keep_on_failure {
+                // Should we log error if one occurs?
+                fs::remove_dir_all(&temp_cwd.path).ok();
+            }
+        }
+    }
+
+    // Make the Popen responsible for removing `path`, the temporary working
+    // directory of the child, once the child has finished.
+    pub(crate) fn set_temp_cwd(&mut self, path: PathBuf, keep_on_failure: bool) {
+        self.temp_cwd = Some(TempCwd {
+            path,
+            keep_on_failure,
+        });
+    }
+
+    /// Return the temporary working directory of the subprocess, if it has
+    /// one.
+    ///
+    /// The directory is created by [`Exec::temp_cwd`], and is available
+    /// until the subprocess has been waited for.  It is removed once the
+    /// exit status of the subprocess has been obtained, which dropping the
+    /// `Popen` does unless it is detached.  The directory of a detached
+    /// subprocess, or of a `Popen` that is leaked with `mem::forget`, is
+    /// left behind, as the subprocess may still be using it.
+    ///
+    /// [`Exec::temp_cwd`]: struct.Exec.html#method.temp_cwd
+    pub fn temp_cwd(&self) -> Option<&Path> {
+        self.temp_cwd
+            .as_ref()
+            .map(|temp_cwd| temp_cwd.path.as_path())
+    }
+
+    /// Return the program the subprocess was started with, as given in
+    /// the first element of `argv`.
+    ///
+    /// This tells which of the commands given with [`Exec::fallback`]
+    /// was run.  It is `None` for processes that weren't started by
+    /// `Popen`, such as those replayed from a recording.
+    ///
+    /// [`Exec::fallback`]: struct.Exec.html#method.fallback
+    pub fn program(&self) -> Option<&OsStr> {
+        self.program.as_deref()
+    }
+
     /// Prepare to communicate with the subprocess.
     ///
     /// Communicating refers to unattended data exchange with the subprocess.
@@ -635,7 +1871,9 @@ impl Popen {
     /// Returns an `Err` if a system call fails in an unpredicted way.
     /// This should not happen in normal usage.
     pub fn wait(&mut self) -> Result<ExitStatus> {
-        self.os_wait()
+        let result = self.os_wait();
+        self.after_exit();
+        result
     }
 
     /// Wait for the process to finish, timing out after the specified duration.
@@ -648,17 +1886,50 @@ impl Popen {
     /// `waitpid(..., WNOHANG)` in a loop with adaptive sleep
     /// intervals between iterations.
     pub fn wait_timeout(&mut self, dur: Duration) -> Result<Option<ExitStatus>> {
-        self.os_wait_timeout(dur)
+        let result = self.os_wait_timeout(dur);
+        self.after_exit();
+        result
+    }
+
+    /// Wait for the process to finish, unless cancelled through `token`.
+    ///
+    /// This function behaves like `wait()`, except that it returns
+    /// `Err(PopenError::Cancelled)` once [`CancelToken::cancel`] is
+    /// called on `token` or one of its clones, typically from another
+    /// thread.  The subprocess is left running and can be waited on
+    /// again.
+    ///
+    /// Cancellation is detected by waiting in short intervals, so it can
+    /// take a few tens of milliseconds to take effect.
+    ///
+    /// [`CancelToken::cancel`]: struct.CancelToken.html#method.cancel
+    pub fn wait_cancellable(&mut self, token: &CancelToken) -> Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.poll() {
+                return Ok(status);
+            }
+            if token.is_cancelled() {
+                return Err(PopenError::Cancelled);
+            }
+            if let Some(status) = self.wait_timeout(CANCEL_CHECK_INTERVAL)? {
+                return Ok(status);
+            }
+        }
     }
 
     /// Terminate the subprocess.
     ///
     /// On Unix-like systems, this sends the `SIGTERM` signal to the
     /// child process, which can be caught by the child in order to
-    /// perform cleanup before exiting.  On Windows, it is equivalent
-    /// to `kill()`.
+    /// perform cleanup before exiting.  A different signal can be
+    /// requested with [`PopenConfig::terminate_signal`].  On Windows,
+    /// it is equivalent to `kill()`.
+    ///
+    /// [`PopenConfig::terminate_signal`]: struct.PopenConfig.html#structfield.terminate_signal
     pub fn terminate(&mut self) -> io::Result<()> {
-        self.os_terminate()
+        let result = self.os_terminate();
+        self.after_exit();
+        result
     }
 
     /// Kill the subprocess.
@@ -673,6 +1944,216 @@ impl Popen {
     pub fn kill(&mut self) -> io::Result<()> {
         self.os_kill()
     }
+
+    /// Ask the subprocess to exit, killing it if it doesn't within
+    /// `grace`, and return its exit status.
+    ///
+    /// On Unix-like systems, the subprocess is first sent the signal of
+    /// [`terminate`], `SIGTERM` unless configured otherwise, which gives
+    /// it a chance to clean up.  On Windows, a subprocess started with
+    /// `CREATE_NEW_PROCESS_GROUP` is sent Ctrl-Break, which console
+    /// programs can handle; other subprocesses can't be asked to exit,
+    /// and are terminated right away.  If the subprocess is still running
+    /// after `grace`, it is killed with [`kill`].  Either way, it is then
+    /// waited for.
+    ///
+    /// ```no_run
+    /// # use subprocess::*;
+    /// # use std::time::Duration;
+    /// # fn dummy() -> Result<()> {
+    /// let mut p = Exec::cmd("server").popen()?;
+    /// // ...
+    /// let status = p.shutdown(Duration::from_secs(5))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// To shut down several processes, such as the commands of a
+    /// pipeline, use [`shutdown_all`].
+    ///
+    /// [`terminate`]: struct.Popen.html#method.terminate
+    /// [`kill`]: struct.Popen.html#method.kill
+    /// [`shutdown_all`]: struct.Popen.html#method.shutdown_all
+    pub fn shutdown(&mut self, grace: Duration) -> Result<ExitStatus> {
+        let mut statuses = Popen::shutdown_all(std::slice::from_mut(self), grace)?;
+        Ok(statuses.pop().unwrap())
+    }
+
+    /// Terminate the subprocess along with the processes it started,
+    /// directly or indirectly.
+    ///
+    /// Unlike [`terminate`], which leaves the grandchildren running, this
+    /// first looks up the descendants of the subprocess by walking `/proc`
+    /// on Linux, by running `ps` on other Unix-like systems, and with a
+    /// Toolhelp snapshot on Windows.  It then terminates the subprocess as
+    /// with `terminate()`, and sends the same signal to each descendant,
+    /// parents before their children.  On Windows, the descendants are
+    /// ended with `TerminateProcess`.
+    ///
+    /// Descendants that exit in the meantime are skipped without error.
+    /// Processes started after the lookup, or that were no longer in the
+    /// tree because their parent had already exited, are not reached.
+    ///
+    /// If the child process is known to have finished, this will do
+    /// nothing and return `Ok`.
+    ///
+    /// [`terminate`]: struct.Popen.html#method.terminate
+    pub fn terminate_tree(&mut self) -> io::Result<()> {
+        self.signal_tree(false)
+    }
+
+    /// Kill the subprocess along with the processes it started, directly
+    /// or indirectly.
+    ///
+    /// This is like [`terminate_tree`], except the processes are killed
+    /// as with [`kill`].
+    ///
+    /// [`terminate_tree`]: struct.Popen.html#method.terminate_tree
+    /// [`kill`]: struct.Popen.html#method.kill
+    pub fn kill_tree(&mut self) -> io::Result<()> {
+        self.signal_tree(true)
+    }
+
+    fn signal_tree(&mut self, kill: bool) -> io::Result<()> {
+        let pid = match self.pid() {
+            Some(pid) => pid,
+            None => return Ok(()),
+        };
+        // looked up beforehand, as the descendants of an exited process
+        // are reparented away from the tree
+        let descendants = descendants(pid, &self.os_process_parents()?);
+        let result = if kill { self.kill() } else { self.terminate() };
+        for pid in descendants {
+            self.os_signal_other(pid, kill).ok();
+        }
+        result
+    }
+}
+
+// Return the descendants of `pid`, parents before their children, given
+// the pids of the running processes paired with the pids of their parents.
+fn descendants(pid: u32, processes: &[(u32, u32)]) -> Vec<u32> {
+    let mut found = vec![pid];
+    let mut next = 0;
+    while next < found.len() {
+        let parent = found[next];
+        for &(child, ppid) in processes {
+            // the check also protects against cycles from reused pids
+            if ppid == parent && !found.contains(&child) {
+                found.push(child);
+            }
+        }
+        next += 1;
+    }
+    found.remove(0);
+    found
+}
+
+/// A handle to a running process that can be waited for and killed.
+///
+/// The methods are named after those of `std::process::Child`, so code
+/// written against such interfaces can be made generic over the process
+/// handles of this crate:
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # use std::time::Duration;
+/// fn reap<W: Waitable>(w: &mut W) -> Result<ExitStatus> {
+///     match w.wait_timeout(Duration::from_secs(5))? {
+///         Some(status) => Ok(status),
+///         None => {
+///             w.kill()?;
+///             w.wait()
+///         }
+///     }
+/// }
+/// ```
+pub trait Waitable {
+    /// Return the exit status if the process has finished, or `None` if
+    /// it is still running, without blocking.
+    ///
+    /// Unlike `Popen::poll`, errors of the underlying system calls are
+    /// reported rather than ignored.
+    fn try_wait(&mut self) -> Result<Option<ExitStatus>>;
+
+    /// Wait for the process to finish, and return its exit status.
+    fn wait(&mut self) -> Result<ExitStatus>;
+
+    /// Wait for the process to finish, returning `None` if it is still
+    /// running after `dur`.
+    fn wait_timeout(&mut self, dur: Duration) -> Result<Option<ExitStatus>>;
+
+    /// Forcibly kill the process.
+    fn kill(&mut self) -> io::Result<()>;
+}
+
+impl Waitable for Popen {
+    fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        Popen::wait_timeout(self, Duration::from_secs(0))
+    }
+
+    fn wait(&mut self) -> Result<ExitStatus> {
+        Popen::wait(self)
+    }
+
+    fn wait_timeout(&mut self, dur: Duration) -> Result<Option<ExitStatus>> {
+        Popen::wait_timeout(self, dur)
+    }
+
+    fn kill(&mut self) -> io::Result<()> {
+        Popen::kill(self)
+    }
+}
+
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Token used to cancel [`Popen::wait_cancellable`] from another thread.
+///
+/// Clones of a token share its state, so a clone can be handed to the
+/// thread that decides when to stop waiting.  Once cancelled, a token
+/// stays cancelled.
+///
+/// [`Popen::wait_cancellable`]: struct.Popen.html#method.wait_cancellable
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Create a new token that is not cancelled.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancel the waits using this token or any of its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true if `cancel` has been called on this token or any of
+    /// its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+static FD_AUDIT: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable the audit of child-end descriptors.
+///
+/// When enabled, every spawn verifies that the descriptors passed to the
+/// child as its standard streams, other than those still shared with the
+/// caller, are no longer open in the parent once the child has started.
+/// A descriptor that stays open is a leak that can keep pipes from
+/// reporting EOF, and is reported by panicking, like a failed debug
+/// assertion.
+///
+/// The audit is meant for test suites, as it inspects the process's open
+/// descriptors after each spawn.  It is currently only implemented on
+/// Unix, where it walks `/proc/self/fd` or `/dev/fd`; elsewhere enabling
+/// it has no effect.
+pub fn set_fd_audit(enabled: bool) {
+    FD_AUDIT.store(enabled, Ordering::SeqCst);
 }
 
 trait PopenOs {
@@ -681,6 +2162,10 @@ trait PopenOs {
     fn os_wait_timeout(&mut self, dur: Duration) -> Result<Option<ExitStatus>>;
     fn os_terminate(&mut self) -> io::Result<()>;
     fn os_kill(&mut self) -> io::Result<()>;
+    fn os_watch_exit(&self, watch: Arc<ExitWatch>) -> io::Result<()>;
+    fn os_signal_other(&self, pid: u32, kill: bool) -> io::Result<()>;
+    fn os_process_parents(&self) -> io::Result<Vec<(u32, u32)>>;
+    fn os_request_exit(&mut self) -> io::Result<()>;
 }
 
 #[cfg(unix)]
@@ -689,46 +2174,226 @@ mod os {
 
     use crate::posix;
     use std::collections::HashSet;
-    use std::ffi::OsString;
-    use std::fs::File;
+    use std::ffi::{CStr, OsString};
+    use std::fs::{File, OpenOptions};
     use std::io::{self, Read, Write};
     use std::os::unix::io::AsRawFd;
     use std::time::{Duration, Instant};
 
+    use crate::compat::ResultExt;
     use crate::os_common::ExitStatus;
-    use crate::unix::PopenExt;
+    use crate::sandbox::PreparedSandbox;
+    #[cfg(target_os = "linux")]
+    use crate::unix::IoPriority;
+    use crate::unix::{PopenExt, SigPipePolicy, Signal};
+
+    // The foreground is only held so that dropping it returns the terminal
+    // to the parent.  The pidfd of the child is available if the child was
+    // created by clone3(), and is used to signal it.  Without
+    // PR_SET_PDEATHSIG, a child started with die_with_parent is watched by
+    // a watcher process, released once the child is reaped.
+    #[derive(Debug)]
+    pub struct ExtChildState(
+        #[allow(dead_code)] Option<Foreground>,
+        #[cfg_attr(not(target_os = "linux"), allow(dead_code))] Option<File>,
+        #[cfg(not(target_os = "linux"))] Option<posix::ParentWatch>,
+    );
+
+    impl ExtChildState {
+        fn child_reaped(&mut self) {
+            #[cfg(not(target_os = "linux"))]
+            if let Some(watch) = self.2.take() {
+                watch.release();
+            }
+        }
+    }
+
+    // The controlling terminal handed over to a subprocess, and the
+    // process group to return it to.
+    #[derive(Debug)]
+    struct Foreground {
+        tty: File,
+        pgrp: u32,
+    }
+
+    impl Foreground {
+        fn acquire() -> Option<Foreground> {
+            let tty = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/tty")
+                .ok()?;
+            let pgrp = posix::tcgetpgrp(tty.as_raw_fd()).ok()?;
+            if pgrp != posix::getpgrp() {
+                // we're not in the foreground ourselves
+                return None;
+            }
+            Some(Foreground { tty, pgrp })
+        }
+
+        fn hand_over(&self, pid: u32) {
+            // The child does the same before exec; doing it in both
+            // processes ensures it's done before either proceeds.
+            posix::setpgid(pid, pid).ok();
+            posix::with_sigttou_blocked(|| posix::tcsetpgrp(self.tty.as_raw_fd(), pid)).ok();
+        }
+    }
+
+    fn signal_numbers(signals: &[Signal]) -> Vec<i32> {
+        signals.iter().map(|&signal| signal.number()).collect()
+    }
 
-    pub type ExtChildState = ();
+    impl Drop for Foreground {
+        fn drop(&mut self) {
+            posix::with_sigttou_blocked(|| posix::tcsetpgrp(self.tty.as_raw_fd(), self.pgrp)).ok();
+        }
+    }
 
     impl super::PopenOs for Popen {
         fn os_start(&mut self, argv: Vec<OsString>, config: PopenConfig) -> Result<()> {
             let mut exec_fail_pipe = posix::pipe()?;
             set_inheritable(&exec_fail_pipe.0, false)?;
             set_inheritable(&exec_fail_pipe.1, false)?;
+            let mut audited = vec![];
+            let fork_start;
+            // the first standard stream connected to a pty, through which
+            // the child acquires it as its controlling terminal
+            let controlling_tty = [&config.stdin, &config.stdout, &config.stderr]
+                .iter()
+                .position(|r| matches!(r, Redirection::Pty))
+                .map(|fd| fd as i32);
+            if controlling_tty.is_some() && config.foreground {
+                return Err(PopenError::LogicError(
+                    "Redirection::Pty not valid with foreground",
+                ));
+            }
+            if config.setsid && config.foreground {
+                return Err(PopenError::LogicError("setsid not valid with foreground"));
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(IoPriority::Realtime(level) | IoPriority::BestEffort(level)) =
+                config.io_priority
             {
-                let child_ends = self.setup_streams(config.stdin, config.stdout, config.stderr)?;
+                if level > 7 {
+                    return Err(PopenError::LogicError(
+                        "io_priority level must be between 0 and 7",
+                    ));
+                }
+            }
+            if config.fast_spawn {
+                return self.fast_spawn(argv, config);
+            }
+            {
+                let child_ends = self.setup_streams(
+                    config.stdin,
+                    config.stdout,
+                    config.stderr,
+                    config.append,
+                    config.reopenable_output,
+                )?;
+                if cfg!(debug_assertions) || super::FD_AUDIT.load(super::Ordering::SeqCst) {
+                    audited = audit_snapshot(&child_ends);
+                }
                 let child_env = config.env.as_deref().map(format_env);
                 let cmd_to_exec = config.executable.as_ref().unwrap_or(&argv[0]);
-                let just_exec = posix::prep_exec(cmd_to_exec, &argv, child_env.as_deref())?;
+                let mut just_exec = posix::prep_exec(cmd_to_exec, &argv, child_env.as_deref())?;
+                let cwd = config
+                    .cwd
+                    .as_deref()
+                    .map(posix::os_to_cstring)
+                    .transpose()?;
+                #[cfg(target_os = "macos")]
+                let sandbox_profile = config
+                    .sandbox_profile
+                    .as_deref()
+                    .map(posix::SandboxProfile::new)
+                    .transpose()?;
+                #[cfg(target_os = "openbsd")]
+                let restrictions =
+                    posix::ExecRestrictions::new(&config.unveil, config.pledge.as_deref())?;
+                let sandbox = config
+                    .sandbox
+                    .as_ref()
+                    .map(PreparedSandbox::new)
+                    .transpose()?;
+                let foreground = if config.foreground {
+                    Foreground::acquire()
+                } else {
+                    None
+                };
+                #[cfg(target_os = "linux")]
+                let parent_death_signal = match (config.parent_death_signal, config.die_with_parent)
+                {
+                    (Some(signal), _) => Some((signal.number(), posix::getpid())),
+                    (None, true) => Some((posix::SIGKILL, posix::getpid())),
+                    (None, false) => None,
+                };
+                #[cfg(target_os = "linux")]
+                let io_priority = config.io_priority.map(IoPriority::class_and_level);
+                let blocked_signals = signal_numbers(&config.blocked_signals);
+                let nice = config.priority.map(Priority::nice);
+                // the latency covers creating the child and executing the
+                // program, not the preparation above
+                fork_start = Instant::now();
                 unsafe {
                     // unsafe because after the call to fork() the
                     // child is not allowed to allocate
-                    match posix::fork()? {
-                        Some(child_pid) => {
+                    match fork().inspect_error(|_| stats::record_fork_failure())? {
+                        Some((child_pid, pidfd)) => {
+                            if let Some(ref foreground) = foreground {
+                                foreground.hand_over(child_pid);
+                            }
+                            #[cfg(not(target_os = "linux"))]
+                            let watch = if config.die_with_parent {
+                                match posix::ParentWatch::start(child_pid) {
+                                    Ok(watch) => Some(watch),
+                                    Err(e) => {
+                                        posix::kill(child_pid, posix::SIGKILL).ok();
+                                        posix::waitpid(child_pid, 0).ok();
+                                        return Err(e.into());
+                                    }
+                                }
+                            } else {
+                                None
+                            };
                             self.child_state = Running {
                                 pid: child_pid,
-                                ext: (),
+                                ext: ExtChildState(
+                                    foreground,
+                                    pidfd,
+                                    #[cfg(not(target_os = "linux"))]
+                                    watch,
+                                ),
                             };
                         }
                         None => {
                             drop(exec_fail_pipe.0);
+                            let mut stage = ExecStage::Exec;
                             let result = Popen::do_exec(
-                                just_exec,
-                                child_ends,
-                                config.cwd.as_deref(),
+                                &mut stage,
+                                &mut just_exec,
+                                &child_ends,
+                                cwd.as_deref(),
                                 config.setuid,
                                 config.setgid,
                                 config.setpgid,
+                                config.setsid,
+                                foreground.as_ref().map(|f| f.tty.as_raw_fd()),
+                                controlling_tty,
+                                sandbox.as_ref(),
+                                #[cfg(target_os = "linux")]
+                                parent_death_signal,
+                                #[cfg(target_os = "linux")]
+                                io_priority,
+                                &blocked_signals,
+                                config.reset_signals,
+                                config.sigpipe,
+                                nice,
+                                config.umask,
+                                #[cfg(target_os = "macos")]
+                                sandbox_profile.as_ref(),
+                                #[cfg(target_os = "openbsd")]
+                                &restrictions,
                             );
                             // If we are here, it means that exec has failed.  Notify
                             // the parent and exit.
@@ -743,6 +2408,7 @@ mod os {
                                     (error_code >> 8) as u8,
                                     (error_code >> 16) as u8,
                                     (error_code >> 24) as u8,
+                                    stage as u8,
                                 ])
                                 .ok();
                             posix::_exit(127);
@@ -751,18 +2417,41 @@ mod os {
                 }
             }
             drop(exec_fail_pipe.1);
-            let mut error_buf = [0u8; 4];
+            if super::FD_AUDIT.load(super::Ordering::SeqCst) {
+                audit_check(&audited);
+            }
+            // unlike files, pipes can be recognized later
+            audited.retain(|(_, id)| id.is_fifo);
+            self.audited_ends = audited;
+            let mut error_buf = [0u8; 5];
             let read_cnt = exec_fail_pipe.0.read(&mut error_buf)?;
             if read_cnt == 0 {
+                stats::record_spawn(fork_start.elapsed());
                 Ok(())
-            } else if read_cnt == 4 {
+            } else if read_cnt == 5 {
+                stats::record_exec_failure();
                 let error_code: u32 = error_buf[0] as u32
                     | (error_buf[1] as u32) << 8
                     | (error_buf[2] as u32) << 16
                     | (error_buf[3] as u32) << 24;
-                Err(PopenError::from(io::Error::from_raw_os_error(
-                    error_code as i32,
-                )))
+                let error = io::Error::from_raw_os_error(error_code as i32);
+                Err(match ExecStage::from_u8(error_buf[4]) {
+                    ExecStage::Setuid => PopenError::SetIdFailed {
+                        call: "setuid",
+                        id: config.setuid.unwrap(),
+                        error,
+                    },
+                    ExecStage::Setgid => PopenError::SetIdFailed {
+                        call: "setgid",
+                        id: config.setgid.unwrap(),
+                        error,
+                    },
+                    ExecStage::Exec => PopenError::from(error),
+                    stage => PopenError::from(io::Error::new(
+                        error.kind(),
+                        ChildSetupError { stage, error },
+                    )),
+                })
             } else {
                 Err(PopenError::LogicError("invalid read_count from exec pipe"))
             }
@@ -783,6 +2472,16 @@ mod os {
             }
 
             let deadline = Instant::now() + dur;
+            while let Some(generation) = crate::sigchld::generation() {
+                self.waitpid(false)?;
+                if let Finished(exit_status) = self.child_state {
+                    return Ok(Some(exit_status));
+                }
+                if !crate::sigchld::wait_for_change(generation, deadline) {
+                    return Ok(None);
+                }
+            }
+
             // double delay at every iteration, maxing at 100ms
             let mut delay = Duration::from_millis(1);
 
@@ -802,12 +2501,146 @@ mod os {
         }
 
         fn os_terminate(&mut self) -> io::Result<()> {
-            self.send_signal(posix::SIGTERM)
+            self.send_signal(self.terminate_signal)
         }
 
         fn os_kill(&mut self) -> io::Result<()> {
             self.send_signal(posix::SIGKILL)
         }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        fn os_watch_exit(&self, watch: Arc<ExitWatch>) -> io::Result<()> {
+            let pid = match self.child_state {
+                Running { pid, .. } => pid,
+                _ => return Ok(()),
+            };
+            // waitid() with WNOWAIT leaves the child for wait() to reap.  If
+            // wait() gets to it first, waitid() fails with ECHILD and the
+            // exit is reported from there.
+            thread::Builder::new()
+                .name("subprocess-exit".into())
+                .spawn(move || loop {
+                    match posix::waitid_nowait(pid, true) {
+                        Ok(Some(exit_status)) => return watch.notify(exit_status),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        _ => return,
+                    }
+                })?;
+            Ok(())
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        fn os_watch_exit(&self, _watch: Arc<ExitWatch>) -> io::Result<()> {
+            // without a way to wait for the child without reaping it, the
+            // exit is reported when observed through the Popen
+            Ok(())
+        }
+
+        fn os_signal_other(&self, pid: u32, kill: bool) -> io::Result<()> {
+            let signal = if kill {
+                posix::SIGKILL
+            } else {
+                self.terminate_signal
+            };
+            posix::kill(pid, signal)
+        }
+
+        fn os_process_parents(&self) -> io::Result<Vec<(u32, u32)>> {
+            // the children of an exited process are reparented, so the
+            // parent pids are never stale
+            process_parents()
+        }
+
+        fn os_request_exit(&mut self) -> io::Result<()> {
+            self.os_terminate()
+        }
+    }
+
+    // Return the pids of the running processes, each paired with the pid
+    // of its parent.
+    #[cfg(target_os = "linux")]
+    pub fn process_parents() -> io::Result<Vec<(u32, u32)>> {
+        let mut processes = vec![];
+        for entry in fs::read_dir("/proc")? {
+            let pid = match entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+            // the process can exit after the directory was listed
+            let stat = match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+                Ok(stat) => stat,
+                Err(_) => continue,
+            };
+            // The command name in parentheses can itself contain spaces
+            // and parentheses, so look for the state and the parent pid
+            // after the last closing one.
+            let ppid = stat
+                .rfind(')')
+                .and_then(|pos| stat[pos + 1..].split_whitespace().nth(1))
+                .and_then(|ppid| ppid.parse().ok());
+            if let Some(ppid) = ppid {
+                processes.push((pid, ppid));
+            }
+        }
+        Ok(processes)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn process_parents() -> io::Result<Vec<(u32, u32)>> {
+        fn io_error(e: PopenError) -> io::Error {
+            match e {
+                PopenError::IoError(e) => e,
+                e => crate::compat::io_error_other(e.to_string()),
+            }
+        }
+        // without procfs, ask ps, whose options used here are in POSIX
+        let mut ps = Popen::create(
+            ["ps", "-A", "-o", "pid=", "-o", "ppid="],
+            PopenConfig {
+                stdout: Redirection::Pipe,
+                ..Default::default()
+            },
+        )
+        .map_err(io_error)?;
+        let (out, _) = ps.communicate(None)?;
+        if !ps.wait().map_err(io_error)?.success() {
+            return Err(crate::compat::io_error_other("ps failed"));
+        }
+        Ok(out
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace().map(|f| f.parse().ok());
+                Some((fields.next()??, fields.next()??))
+            })
+            .collect())
+    }
+
+    // Fork the child, using clone3() where available to also obtain its
+    // pidfd.
+    //
+    // marked unsafe because the child must not allocate before exec-ing
+    unsafe fn fork() -> io::Result<Option<(u32, Option<File>)>> {
+        #[cfg(all(
+            target_os = "linux",
+            not(any(target_arch = "mips", target_arch = "mips64"))
+        ))]
+        {
+            static CLONE3_UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+            if !CLONE3_UNAVAILABLE.load(Ordering::Relaxed) {
+                match posix::fork_pidfd() {
+                    Ok(forked) => return Ok(forked.map(|(pid, pidfd)| (pid, Some(pidfd)))),
+                    // missing from older kernels, or blocked by seccomp
+                    Err(e)
+                        if matches!(e.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) =>
+                    {
+                        CLONE3_UNAVAILABLE.store(true, Ordering::Relaxed);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(posix::fork()?.map(|pid| (pid, None)))
     }
 
     fn format_env(env: &[(OsString, OsString)]) -> Vec<OsString> {
@@ -829,93 +2662,460 @@ mod os {
         formatted
     }
 
+    // Step of do_exec() that failed, sent to the parent after the error
+    // code so that the failure can be attributed.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    #[repr(u8)]
+    enum ExecStage {
+        Exec,
+        Setuid,
+        Setgid,
+        Chdir,
+        Dup2,
+        Signals,
+        Setsid,
+        ControllingTty,
+        Sandbox,
+        Priority,
+        Setpgid,
+        Foreground,
+        ParentDeathSignal,
+        IoPriority,
+        Setgroups,
+    }
+
+    impl ExecStage {
+        fn from_u8(stage: u8) -> ExecStage {
+            match stage {
+                1 => ExecStage::Setuid,
+                2 => ExecStage::Setgid,
+                3 => ExecStage::Chdir,
+                4 => ExecStage::Dup2,
+                5 => ExecStage::Signals,
+                6 => ExecStage::Setsid,
+                7 => ExecStage::ControllingTty,
+                8 => ExecStage::Sandbox,
+                9 => ExecStage::Priority,
+                10 => ExecStage::Setpgid,
+                11 => ExecStage::Foreground,
+                12 => ExecStage::ParentDeathSignal,
+                13 => ExecStage::IoPriority,
+                14 => ExecStage::Setgroups,
+                _ => ExecStage::Exec,
+            }
+        }
+
+        fn describe(self) -> &'static str {
+            match self {
+                ExecStage::Exec => "exec",
+                ExecStage::Setuid => "setuid",
+                ExecStage::Setgid => "setgid",
+                ExecStage::Chdir => "changing to the working directory",
+                ExecStage::Dup2 => "setting up the standard streams",
+                ExecStage::Signals => "resetting the signal handling",
+                ExecStage::Setsid => "setsid",
+                ExecStage::ControllingTty => "acquiring the controlling terminal",
+                ExecStage::Sandbox => "applying the sandbox",
+                ExecStage::Priority => "setting the priority",
+                ExecStage::Setpgid => "setpgid",
+                ExecStage::Foreground => "moving to the foreground of the terminal",
+                ExecStage::ParentDeathSignal => "setting the parent death signal",
+                ExecStage::IoPriority => "setting the IO priority",
+                ExecStage::Setgroups => "dropping the supplementary groups",
+            }
+        }
+    }
+
+    // Error of a step of preparing the child for exec, which keeps the
+    // kind of the underlying error.
+    #[derive(Debug)]
+    struct ChildSetupError {
+        stage: ExecStage,
+        error: io::Error,
+    }
+
+    impl fmt::Display for ChildSetupError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "{} failed in the subprocess: {}",
+                self.stage.describe(),
+                self.error
+            )
+        }
+    }
+
+    impl std::error::Error for ChildSetupError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.error)
+        }
+    }
+
     trait PopenOsImpl: super::PopenOs {
+        // Set up the forked child and exec the program, returning only on
+        // failure.  Runs after fork(), so it must not allocate, free, or
+        // lock: everything is prepared by the parent, and passed by
+        // reference so that returning an error drops nothing.  The only
+        // exception is sandbox_init(), which compiles the macOS sandbox
+        // profile.
+        #[allow(clippy::too_many_arguments)]
         fn do_exec(
-            just_exec: impl FnOnce() -> io::Result<()>,
-            child_ends: (Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>),
-            cwd: Option<&OsStr>,
+            stage: &mut ExecStage,
+            just_exec: &mut impl FnMut() -> io::Result<()>,
+            child_ends: &(
+                Option<ChildStream>,
+                Option<ChildStream>,
+                Option<ChildStream>,
+            ),
+            cwd: Option<&CStr>,
             setuid: Option<u32>,
             setgid: Option<u32>,
             setpgid: bool,
+            setsid: bool,
+            foreground_tty: Option<i32>,
+            controlling_tty: Option<i32>,
+            sandbox: Option<&PreparedSandbox>,
+            #[cfg(target_os = "linux")] parent_death_signal: Option<(i32, u32)>,
+            #[cfg(target_os = "linux")] io_priority: Option<(i32, i32)>,
+            blocked_signals: &[i32],
+            reset_signals: bool,
+            sigpipe: SigPipePolicy,
+            nice: Option<i32>,
+            umask: Option<u32>,
+            #[cfg(target_os = "macos")] sandbox_profile: Option<&posix::SandboxProfile>,
+            #[cfg(target_os = "openbsd")] restrictions: &posix::ExecRestrictions,
         ) -> io::Result<()>;
+        fn fast_spawn(&mut self, argv: Vec<OsString>, config: PopenConfig) -> Result<()>;
         fn waitpid(&mut self, block: bool) -> io::Result<()>;
     }
 
     impl PopenOsImpl for Popen {
+        #[allow(clippy::too_many_arguments)]
         fn do_exec(
-            just_exec: impl FnOnce() -> io::Result<()>,
-            child_ends: (Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>),
-            cwd: Option<&OsStr>,
+            stage: &mut ExecStage,
+            just_exec: &mut impl FnMut() -> io::Result<()>,
+            child_ends: &(
+                Option<ChildStream>,
+                Option<ChildStream>,
+                Option<ChildStream>,
+            ),
+            cwd: Option<&CStr>,
             setuid: Option<u32>,
             setgid: Option<u32>,
             setpgid: bool,
+            setsid: bool,
+            foreground_tty: Option<i32>,
+            controlling_tty: Option<i32>,
+            sandbox: Option<&PreparedSandbox>,
+            #[cfg(target_os = "linux")] parent_death_signal: Option<(i32, u32)>,
+            #[cfg(target_os = "linux")] io_priority: Option<(i32, i32)>,
+            blocked_signals: &[i32],
+            reset_signals: bool,
+            sigpipe: SigPipePolicy,
+            nice: Option<i32>,
+            umask: Option<u32>,
+            #[cfg(target_os = "macos")] sandbox_profile: Option<&posix::SandboxProfile>,
+            #[cfg(target_os = "openbsd")] restrictions: &posix::ExecRestrictions,
         ) -> io::Result<()> {
             if let Some(cwd) = cwd {
-                env::set_current_dir(cwd)?;
+                *stage = ExecStage::Chdir;
+                posix::chdir(cwd)?;
             }
 
+            // The child ends are close-on-exec, which dup2() clears on the
+            // copy; an end that is already in place is cleared explicitly.
+            *stage = ExecStage::Dup2;
             let (stdin, stdout, stderr) = child_ends;
-            if let Some(stdin) = stdin {
-                if stdin.as_raw_fd() != 0 {
-                    posix::dup2(stdin.as_raw_fd(), 0)?;
+            for (end, fd) in [(stdin, 0), (stdout, 1), (stderr, 2)] {
+                if let Some(end) = end {
+                    if end.as_raw_fd() != fd {
+                        posix::dup2(end.as_raw_fd(), fd)?;
+                    } else {
+                        set_inheritable(end, true)?;
+                    }
                 }
             }
-            if let Some(stdout) = stdout {
-                if stdout.as_raw_fd() != 1 {
-                    posix::dup2(stdout.as_raw_fd(), 1)?;
-                }
+            *stage = ExecStage::Signals;
+            posix::reset_signals(
+                blocked_signals,
+                reset_signals,
+                match sigpipe {
+                    SigPipePolicy::Default => Some(posix::SIG_DFL),
+                    SigPipePolicy::Ignore => Some(posix::SIG_IGN),
+                    SigPipePolicy::Inherit => None,
+                },
+            )?;
+            if let Some(mask) = umask {
+                posix::umask(mask);
             }
-            if let Some(stderr) = stderr {
-                if stderr.as_raw_fd() != 2 {
-                    posix::dup2(stderr.as_raw_fd(), 2)?;
-                }
+            let setsid = setsid || controlling_tty.is_some();
+            if setsid {
+                // a new session leader is also the leader of a new process
+                // group, so setpgid below isn't needed (and would fail)
+                *stage = ExecStage::Setsid;
+                posix::setsid()?;
+            }
+            if let Some(fd) = controlling_tty {
+                *stage = ExecStage::ControllingTty;
+                posix::set_controlling_tty(fd)?;
             }
-            posix::reset_sigpipe()?;
 
-            if let Some(uid) = setuid {
-                posix::setuid(uid)?;
+            if let Some(sandbox) = sandbox {
+                *stage = ExecStage::Sandbox;
+                sandbox.enter()?;
+            }
+            // before setuid, which can take away the privilege to raise it
+            if let Some(nice) = nice {
+                *stage = ExecStage::Priority;
+                posix::setpriority(nice)?;
             }
+            #[cfg(target_os = "linux")]
+            if let Some((class, level)) = io_priority {
+                *stage = ExecStage::IoPriority;
+                posix::ioprio_set(class, level)?;
+            }
+            // before setuid, which gives up the privilege to change them
             if let Some(gid) = setgid {
+                *stage = ExecStage::Setgid;
                 posix::setgid(gid)?;
             }
-            if setpgid {
+            if let Some(uid) = setuid {
+                if posix::getuid() == 0 {
+                    *stage = ExecStage::Setgroups;
+                    posix::clear_groups()?;
+                }
+                *stage = ExecStage::Setuid;
+                posix::setuid(uid)?;
+            }
+            if (setpgid || foreground_tty.is_some()) && !setsid {
+                *stage = ExecStage::Setpgid;
                 posix::setpgid(0, 0)?;
             }
+            if let Some(tty) = foreground_tty {
+                *stage = ExecStage::Foreground;
+                posix::with_sigttou_blocked(|| posix::tcsetpgrp(tty, posix::getpid()))?;
+            }
+            // after setuid and setgid, which clear the parent death signal
+            #[cfg(target_os = "linux")]
+            if let Some((signal, parent_pid)) = parent_death_signal {
+                *stage = ExecStage::ParentDeathSignal;
+                posix::set_parent_death_signal(signal, parent_pid)?;
+            }
+            *stage = ExecStage::Sandbox;
+            #[cfg(target_os = "macos")]
+            {
+                if let Some(profile) = sandbox_profile {
+                    profile.apply()?;
+                }
+            }
+            #[cfg(target_os = "openbsd")]
+            restrictions.apply()?;
+            if let Some(sandbox) = sandbox {
+                sandbox.restrict()?;
+            }
+            *stage = ExecStage::Exec;
             just_exec()?;
             unreachable!();
         }
 
+        fn fast_spawn(&mut self, argv: Vec<OsString>, config: PopenConfig) -> Result<()> {
+            let unsupported = config.cwd.is_some()
+                || config.setuid.is_some()
+                || config.setgid.is_some()
+                || config.setsid
+                || config.umask.is_some()
+                || config.foreground
+                || config.sandbox.is_some()
+                || config.die_with_parent
+                || config.priority.is_some()
+                || config.sigpipe == SigPipePolicy::Ignore
+                || [&config.stdin, &config.stdout, &config.stderr]
+                    .iter()
+                    .any(|r| matches!(r, Redirection::Pty));
+            #[cfg(target_os = "macos")]
+            let unsupported = unsupported || config.sandbox_profile.is_some();
+            #[cfg(target_os = "linux")]
+            let unsupported =
+                unsupported || config.io_priority.is_some() || config.parent_death_signal.is_some();
+            #[cfg(target_os = "openbsd")]
+            let unsupported = unsupported || !config.unveil.is_empty() || config.pledge.is_some();
+            if unsupported {
+                return Err(PopenError::LogicError(
+                    "fast_spawn only valid with redirections and setpgid",
+                ));
+            }
+            let mut audited = vec![];
+            {
+                let child_ends = self.setup_streams(
+                    config.stdin,
+                    config.stdout,
+                    config.stderr,
+                    config.append,
+                    config.reopenable_output,
+                )?;
+                if cfg!(debug_assertions) || super::FD_AUDIT.load(super::Ordering::SeqCst) {
+                    audited = audit_snapshot(&child_ends);
+                }
+                let child_env = config.env.as_deref().map(format_env);
+                let cmd_to_exec = config.executable.as_ref().unwrap_or(&argv[0]);
+                let fds = [&child_ends.0, &child_ends.1, &child_ends.2]
+                    .map(|end| end.as_ref().map(|end| end.as_raw_fd()));
+                let spawn_start = Instant::now();
+                let pid = posix::spawn(
+                    cmd_to_exec,
+                    &argv,
+                    child_env.as_deref(),
+                    fds,
+                    config.setpgid,
+                    &signal_numbers(&config.blocked_signals),
+                    config.reset_signals,
+                    config.sigpipe == SigPipePolicy::Default,
+                )
+                .inspect_error(|_| stats::record_exec_failure())?;
+                self.child_state = Running {
+                    pid,
+                    ext: ExtChildState(
+                        None,
+                        None,
+                        #[cfg(not(target_os = "linux"))]
+                        None,
+                    ),
+                };
+                stats::record_spawn(spawn_start.elapsed());
+            }
+            if super::FD_AUDIT.load(super::Ordering::SeqCst) {
+                audit_check(&audited);
+            }
+            audited.retain(|(_, id)| id.is_fifo);
+            self.audited_ends = audited;
+            Ok(())
+        }
+
         fn waitpid(&mut self, block: bool) -> io::Result<()> {
             match self.child_state {
                 Preparing => panic!("child_state == Preparing"),
-                Running { pid, .. } => {
-                    match posix::waitpid(pid, if block { 0 } else { posix::WNOHANG }) {
-                        Err(e) => {
-                            if let Some(errno) = e.raw_os_error() {
-                                if errno == posix::ECHILD {
-                                    // Someone else has waited for the child
-                                    // (another thread, a signal handler...).
-                                    // The PID no longer exists and we cannot
-                                    // find its exit status.
-                                    self.child_state = Finished(ExitStatus::Undetermined);
-                                    return Ok(());
-                                }
-                            }
-                            return Err(e);
-                        }
-                        Ok((pid_out, exit_status)) => {
-                            if pid_out == pid {
-                                self.child_state = Finished(exit_status);
-                            }
-                        }
+                Running { pid, ref mut ext } => match reap(pid, block) {
+                    Ok(Some(exit_status)) => {
+                        ext.child_reaped();
+                        self.child_state = Finished(exit_status);
                     }
-                }
+                    Ok(None) => (),
+                    Err(e) if e.raw_os_error() == Some(posix::ECHILD) => {
+                        // Someone else has waited for the child (another
+                        // thread, a signal handler...).  The PID no longer
+                        // exists and we cannot find its exit status.
+                        ext.child_reaped();
+                        self.child_state = Finished(ExitStatus::ReapedElsewhere);
+                    }
+                    Err(e) => return Err(e),
+                },
                 Finished(..) => (),
             }
             Ok(())
         }
     }
 
+    // Wait for the child to exit and reap it.  Returns None if the child is
+    // still running and `block` is false.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn reap(pid: u32, block: bool) -> io::Result<Option<ExitStatus>> {
+        // Obtain the exit status without reaping the child first, so that we
+        // know it even if foreign code reaps the child before we do.
+        let exit_status = match posix::waitid_nowait(pid, block)? {
+            Some(exit_status) => exit_status,
+            None => return Ok(None),
+        };
+        match posix::waitpid(pid, posix::WNOHANG) {
+            Err(e) if e.raw_os_error() != Some(posix::ECHILD) => Err(e),
+            _ => Ok(Some(exit_status)),
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn reap(pid: u32, block: bool) -> io::Result<Option<ExitStatus>> {
+        let (pid_out, exit_status) = posix::waitpid(pid, if block { 0 } else { posix::WNOHANG })?;
+        Ok(if pid_out == pid {
+            Some(exit_status)
+        } else {
+            None
+        })
+    }
+
+    impl Popen {
+        // Pidfd of the running process obtained when it was created, awaited
+        // by the async runtimes.
+        #[cfg(all(
+            any(feature = "async", feature = "async-smol"),
+            target_os = "linux",
+            not(any(target_arch = "mips", target_arch = "mips64"))
+        ))]
+        pub(crate) fn pidfd(&self) -> Option<&File> {
+            match self.child_state {
+                Running {
+                    ext: ExtChildState(_, ref pidfd),
+                    ..
+                } => pidfd.as_ref(),
+                _ => None,
+            }
+        }
+    }
+
+    // Record the child ends that nothing but the child is supposed to hold
+    // on to, i.e. those not shared with the caller.
+    fn audit_snapshot(
+        child_ends: &(
+            Option<ChildStream>,
+            Option<ChildStream>,
+            Option<ChildStream>,
+        ),
+    ) -> Vec<(i32, posix::FileId)> {
+        let ends: Vec<&ChildStream> = [&child_ends.0, &child_ends.1, &child_ends.2]
+            .iter()
+            .filter_map(|end| end.as_ref())
+            .collect();
+        let mut audited = vec![];
+        for end in &ends {
+            let refs_here = ends.iter().filter(|e| e.ptr() == end.ptr()).count();
+            if end.strong_count() > refs_here {
+                continue;
+            }
+            let fd = end.as_raw_fd();
+            if let Ok(id) = posix::file_id(fd) {
+                if !audited.contains(&(fd, id)) {
+                    audited.push((fd, id));
+                }
+            }
+        }
+        audited
+    }
+
+    // Panic if any of the audited child ends is still open in the parent,
+    // either under its original descriptor or, for pipes whose identity is
+    // unique, under any descriptor.
+    pub fn audit_check(audited: &[(i32, posix::FileId)]) {
+        if audited.is_empty() {
+            return;
+        }
+        let fds = match posix::open_fds() {
+            Ok(fds) => fds,
+            Err(_) => return,
+        };
+        for fd in fds {
+            let id = match posix::file_id(fd) {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            for &(orig_fd, orig_id) in audited {
+                if id == orig_id && (fd == orig_fd || id.is_fifo) {
+                    panic!(
+                        "subprocess: child-end descriptor {} still open in the parent as {}",
+                        orig_fd, fd
+                    );
+                }
+            }
+        }
+    }
+
     pub fn set_inheritable(f: &File, inheritable: bool) -> io::Result<()> {
         if inheritable {
             // Unix pipes are inheritable by default.
@@ -927,6 +3127,19 @@ mod os {
         Ok(())
     }
 
+    // Prepare a child end owned by the spawn.  It is kept close-on-exec,
+    // so that processes spawned concurrently by other threads don't
+    // inherit it; the child gets it through dup2(), which clears the flag.
+    pub fn prepare_child_end(f: &File) -> io::Result<()> {
+        set_inheritable(f, false)
+    }
+
+    // A child end shared with the caller is passed as is, leaving its
+    // flags alone.
+    pub fn share_child_end(end: ChildStream) -> io::Result<ChildStream> {
+        Ok(end)
+    }
+
     /// Create a pipe.
     ///
     /// This is a safe wrapper over `libc::pipe` or
@@ -936,37 +3149,275 @@ mod os {
         posix::pipe()
     }
 
+    pub(crate) fn open_pty() -> Result<(File, File)> {
+        let (master, slave) = posix::openpty()?;
+        set_inheritable(&master, false)?;
+        set_inheritable(&slave, false)?;
+        Ok((master, slave))
+    }
+
+    /// Create a pipe with the specified options.
+    ///
+    /// Like [`make_pipe`], but allows setting the pipe's buffer size and,
+    /// on Windows, its mode and the use of overlapped I/O.
+    ///
+    /// [`make_pipe`]: fn.make_pipe.html
+    pub fn make_pipe_with(options: &PipeOptions) -> io::Result<(File, File)> {
+        let (read, write) = posix::pipe()?;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            if options.buffer_size != 0 {
+                posix::set_pipe_size(&write, options.buffer_size)?;
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let _ = options;
+        Ok((read, write))
+    }
+
     pub mod ext {
         use crate::popen::ChildState::*;
         use crate::popen::Popen;
         use crate::posix;
         use std::io;
 
+        /// A signal that can be sent to a child process.
+        ///
+        /// The common signals have their own variants, and any other signal
+        /// can be specified by number with `Custom`.  Signal numbers convert
+        /// into `Signal` and back, so functions accepting `Into<Signal>` also
+        /// accept raw numbers such as those from the [`libc`] crate.
+        ///
+        /// [`libc`]: https://docs.rs/libc/
+        #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+        pub enum Signal {
+            /// `SIGTERM`, request to terminate.
+            Term,
+            /// `SIGKILL`, forced termination.
+            Kill,
+            /// `SIGINT`, interrupt from the keyboard.
+            Int,
+            /// `SIGHUP`, hangup of the controlling terminal.
+            Hup,
+            /// `SIGUSR1`, user-defined signal 1.
+            Usr1,
+            /// `SIGUSR2`, user-defined signal 2.
+            Usr2,
+            /// `SIGWINCH`, change of terminal window size.
+            Winch,
+            /// Signal given by its number.
+            Custom(i32),
+        }
+
+        impl Signal {
+            /// Return the signal number.
+            pub fn number(self) -> i32 {
+                match self {
+                    Signal::Term => libc::SIGTERM,
+                    Signal::Kill => libc::SIGKILL,
+                    Signal::Int => libc::SIGINT,
+                    Signal::Hup => libc::SIGHUP,
+                    Signal::Usr1 => libc::SIGUSR1,
+                    Signal::Usr2 => libc::SIGUSR2,
+                    Signal::Winch => libc::SIGWINCH,
+                    Signal::Custom(signal) => signal,
+                }
+            }
+        }
+
+        impl From<i32> for Signal {
+            fn from(signal: i32) -> Signal {
+                match signal {
+                    libc::SIGTERM => Signal::Term,
+                    libc::SIGKILL => Signal::Kill,
+                    libc::SIGINT => Signal::Int,
+                    libc::SIGHUP => Signal::Hup,
+                    libc::SIGUSR1 => Signal::Usr1,
+                    libc::SIGUSR2 => Signal::Usr2,
+                    libc::SIGWINCH => Signal::Winch,
+                    _ => Signal::Custom(signal),
+                }
+            }
+        }
+
+        impl From<Signal> for i32 {
+            fn from(signal: Signal) -> i32 {
+                signal.number()
+            }
+        }
+
+        /// Handling of `SIGPIPE` in the child process, set with
+        /// [`PopenConfig::sigpipe`].
+        ///
+        /// [`PopenConfig::sigpipe`]: ../struct.PopenConfig.html#structfield.sigpipe
+        #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+        pub enum SigPipePolicy {
+            /// Reset `SIGPIPE` to the default handling, which terminates
+            /// the process when it writes to a pipe without readers.
+            ///
+            /// This is what most Unix programs expect, e.g. for `yes | head`
+            /// to finish.
+            #[default]
+            Default,
+            /// Ignore `SIGPIPE`, so that writing to a pipe without readers
+            /// fails with `EPIPE` instead.
+            Ignore,
+            /// Keep the handling of the current process.
+            ///
+            /// Rust programs ignore `SIGPIPE` unless they arrange otherwise,
+            /// in which case this is the same as `Ignore`.
+            Inherit,
+        }
+
+        /// IO scheduling class and priority of the child process, set with
+        /// [`PopenConfig::io_priority`].
+        ///
+        /// The level of the `Realtime` and `BestEffort` classes ranges from
+        /// 0, the highest priority, to 7, the lowest.  See ioprio_set(2) for
+        /// how the classes are scheduled.
+        ///
+        /// [`PopenConfig::io_priority`]: ../struct.PopenConfig.html#structfield.io_priority
+        #[cfg(target_os = "linux")]
+        #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+        pub enum IoPriority {
+            /// Served before all other IO, which requires `CAP_SYS_ADMIN`.
+            Realtime(u8),
+            /// The default class, served by priority in a round-robin
+            /// fashion.
+            BestEffort(u8),
+            /// Served only when no other process needs the disk, such as for
+            /// backups and scans.
+            Idle,
+        }
+
+        #[cfg(target_os = "linux")]
+        impl IoPriority {
+            // Return the class and level passed to ioprio_set().
+            pub(crate) fn class_and_level(self) -> (i32, i32) {
+                match self {
+                    IoPriority::Realtime(level) => (1, level as i32),
+                    IoPriority::BestEffort(level) => (2, level as i32),
+                    IoPriority::Idle => (3, 0),
+                }
+            }
+        }
+
         /// Unix-specific extension methods for `Popen`
         pub trait PopenExt {
             /// Send the specified signal to the child process.
             ///
-            /// The signal numbers are best obtained from the [`libc`]
-            /// crate.
+            /// The signal is either a [`Signal`] or a raw signal number,
+            /// e.g. one obtained from the [`libc`] crate.
             ///
             /// If the child process is known to have finished (due to e.g.
             /// a previous call to [`wait`] or [`poll`]), this will do
             /// nothing and return `Ok`.
             ///
+            /// [`Signal`]: enum.Signal.html
             /// [`poll`]: ../struct.Popen.html#method.poll
             /// [`wait`]: ../struct.Popen.html#method.wait
             /// [`libc`]: https://docs.rs/libc/
-            fn send_signal(&self, signal: i32) -> io::Result<()>;
+            fn send_signal(&self, signal: impl Into<Signal>) -> io::Result<()>;
+
+            /// Send the specified signal to the process group of the child
+            /// process.
+            ///
+            /// The group is looked up with `getpgid()` right before
+            /// signaling it.  Since the child is not reaped before a
+            /// successful [`wait`] or [`poll`], neither its pid nor its
+            /// group can be reused by unrelated processes in the meantime.
+            /// If the child is still in the group of the current process,
+            /// e.g. because it hasn't yet been moved to its own group, an
+            /// error is returned instead of signaling the current group.
+            ///
+            /// If the child process is known to have finished, this will do
+            /// nothing and return `Ok`.
+            ///
+            /// [`poll`]: ../struct.Popen.html#method.poll
+            /// [`wait`]: ../struct.Popen.html#method.wait
+            fn send_signal_group(&self, signal: impl Into<Signal>) -> io::Result<()>;
         }
         impl PopenExt for Popen {
-            fn send_signal(&self, signal: i32) -> io::Result<()> {
+            fn send_signal(&self, signal: impl Into<Signal>) -> io::Result<()> {
+                match self.child_state {
+                    Preparing => panic!("child_state == Preparing"),
+                    #[cfg(all(
+                        target_os = "linux",
+                        not(any(target_arch = "mips", target_arch = "mips64"))
+                    ))]
+                    Running {
+                        ext: super::ExtChildState(_, Some(ref pidfd)),
+                        ..
+                    } => posix::pidfd_send_signal(pidfd, signal.into().number()),
+                    Running { pid, .. } => posix::kill(pid, signal.into().number()),
+                    Finished(..) => Ok(()),
+                }
+            }
+
+            fn send_signal_group(&self, signal: impl Into<Signal>) -> io::Result<()> {
                 match self.child_state {
                     Preparing => panic!("child_state == Preparing"),
-                    Running { pid, .. } => posix::kill(pid, signal),
+                    Running { pid, .. } => {
+                        let pgid = posix::getpgid(pid)?;
+                        if pgid == posix::getpgrp() {
+                            return Err(crate::compat::io_error_other(
+                                "child is in the process group of the current process",
+                            ));
+                        }
+                        posix::killpg(pgid, signal.into().number())
+                    }
                     Finished(..) => Ok(()),
                 }
             }
         }
+
+        /// Unix-specific extension methods for a job of processes, such as
+        /// the one returned by [`Pipeline::popen`].
+        ///
+        /// [`Pipeline::popen`]: ../struct.Pipeline.html#method.popen
+        pub trait JobExt {
+            /// Send the specified signal to the process groups of the
+            /// processes in the job, and report which processes were
+            /// signaled.
+            ///
+            /// Each group is verified as with
+            /// [`PopenExt::send_signal_group`] and signaled only once, even
+            /// if it contains several processes of the job.  The returned
+            /// vector holds one entry per process, `true` if its group was
+            /// successfully signaled.  Processes that have finished, that
+            /// share the group of the current process, or whose group could
+            /// not be signaled, are reported as `false`.
+            ///
+            /// [`PopenExt::send_signal_group`]: trait.PopenExt.html#tymethod.send_signal_group
+            fn signal_group_checked(&self, signal: impl Into<Signal>) -> Vec<bool>;
+        }
+        impl JobExt for [Popen] {
+            fn signal_group_checked(&self, signal: impl Into<Signal>) -> Vec<bool> {
+                let signal = signal.into().number();
+                let own_pgrp = posix::getpgrp();
+                // Groups signaled so far, with the outcome of signaling.
+                let mut signaled: Vec<(u32, bool)> = vec![];
+                self.iter()
+                    .map(|p| {
+                        let pid = match p.child_state {
+                            Preparing => panic!("child_state == Preparing"),
+                            Running { pid, .. } => pid,
+                            Finished(..) => return false,
+                        };
+                        let pgid = match posix::getpgid(pid) {
+                            Ok(pgid) if pgid != own_pgrp => pgid,
+                            _ => return false,
+                        };
+                        if let Some(&(_, ok)) = signaled.iter().find(|&&(g, _)| g == pgid) {
+                            return ok;
+                        }
+                        let ok = posix::killpg(pgid, signal).is_ok();
+                        signaled.push((pgid, ok));
+                        ok
+                    })
+                    .collect()
+            }
+        }
     }
 }
 
@@ -974,51 +3425,150 @@ mod os {
 mod os {
     use super::*;
 
-    use std::collections::HashSet;
-    use std::env;
+    use std::collections::{HashMap, HashSet};
     use std::ffi::{OsStr, OsString};
-    use std::fs::{self, File};
+    use std::fs::File;
     use std::io;
     use std::os::windows::ffi::{OsStrExt, OsStringExt};
     use std::os::windows::io::{AsRawHandle, RawHandle};
+    use std::sync::Mutex;
     use std::time::Duration;
 
+    use crate::compat::ResultExt;
     use crate::os_common::{ExitStatus, StandardStream};
     use crate::win32;
 
+    // The console code pages saved by the first ConsoleUtf8 and restored
+    // when the last one is dropped, along with the number of live ones.
+    static CONSOLE_UTF8: Mutex<(usize, u32, u32)> = Mutex::new((0, 0, 0));
+
+    // Only held so that dropping it restores the console code pages.
+    #[derive(Debug)]
+    struct ConsoleUtf8;
+
+    impl ConsoleUtf8 {
+        fn acquire() -> Option<ConsoleUtf8> {
+            let mut state = CONSOLE_UTF8.lock().unwrap_or_else(|e| e.into_inner());
+            if state.0 == 0 {
+                let (input_cp, output_cp) = (win32::GetConsoleCP(), win32::GetConsoleOutputCP());
+                if input_cp == 0 || output_cp == 0 {
+                    // no console
+                    return None;
+                }
+                win32::SetConsoleCP(win32::CP_UTF8).ok()?;
+                if win32::SetConsoleOutputCP(win32::CP_UTF8).is_err() {
+                    win32::SetConsoleCP(input_cp).ok();
+                    return None;
+                }
+                state.1 = input_cp;
+                state.2 = output_cp;
+            }
+            state.0 += 1;
+            Some(ConsoleUtf8)
+        }
+    }
+
+    impl Drop for ConsoleUtf8 {
+        fn drop(&mut self) {
+            let mut state = CONSOLE_UTF8.lock().unwrap_or_else(|e| e.into_inner());
+            state.0 -= 1;
+            if state.0 == 0 {
+                win32::SetConsoleCP(state.1).ok();
+                win32::SetConsoleOutputCP(state.2).ok();
+            }
+        }
+    }
+
+    // Job object of the subprocesses started with die_with_parent.
+    // Its handle is never closed by us, but by the system when the current
+    // process exits, which kills the processes in the job.
+    static KILL_ON_CLOSE_JOB: Mutex<Option<&'static win32::Handle>> = Mutex::new(None);
+
+    fn kill_on_close_job() -> io::Result<&'static win32::Handle> {
+        let mut job = KILL_ON_CLOSE_JOB.lock().unwrap_or_else(|e| e.into_inner());
+        if job.is_none() {
+            *job = Some(Box::leak(Box::new(win32::CreateKillOnCloseJob()?)));
+        }
+        Ok(job.unwrap())
+    }
+
     #[derive(Debug)]
-    pub struct ExtChildState(win32::Handle);
+    pub struct ExtChildState(
+        win32::Handle,
+        #[allow(dead_code)] Option<ConsoleUtf8>,
+        // started in a new process group, which can be sent Ctrl-Break
+        bool,
+    );
 
     impl super::PopenOs for Popen {
         fn os_start(&mut self, argv: Vec<OsString>, config: PopenConfig) -> Result<()> {
-            fn raw(opt: &Option<Rc<File>>) -> Option<RawHandle> {
+            fn raw(opt: &Option<ChildStream>) -> Option<RawHandle> {
                 opt.as_ref().map(|f| f.as_raw_handle())
             }
-            let (mut child_stdin, mut child_stdout, mut child_stderr) =
-                self.setup_streams(config.stdin, config.stdout, config.stderr)?;
+            let (mut child_stdin, mut child_stdout, mut child_stderr) = self.setup_streams(
+                config.stdin,
+                config.stdout,
+                config.stderr,
+                config.append,
+                config.reopenable_output,
+            )?;
             ensure_child_stream(&mut child_stdin, StandardStream::Input)?;
             ensure_child_stream(&mut child_stdout, StandardStream::Output)?;
             ensure_child_stream(&mut child_stderr, StandardStream::Error)?;
+            // CreateProcess doesn't search for appname in the PATH, and
+            // searches for the program in our PATH rather than the child's.
+            // We do it ourselves to match the Unix behavior.
+            #[cfg(not(feature = "no-path-search"))]
+            let executable = {
+                let child_path = config.env.as_deref().and_then(path_in_env);
+                match config.executable {
+                    Some(executable) => Some(locate_in_path(executable, child_path)),
+                    None if child_path.is_some() && !has_dir_component(&argv[0]) => {
+                        let program = argv[0].clone();
+                        Some(locate_in_path(program, child_path)).filter(|exe| *exe != argv[0])
+                    }
+                    None => None,
+                }
+            };
+            // Without appname, CreateProcess would search for the program
+            // in the PATH, so pass it explicitly.
+            #[cfg(feature = "no-path-search")]
+            let executable = config.executable.or_else(|| Some(argv[0].clone()));
             let cmdline = assemble_cmdline(argv)?;
             let env_block = config.env.map(|env| format_env_block(&env));
-            // CreateProcess doesn't search for appname in the PATH.
-            // We do it ourselves to match the Unix behavior.
-            let executable = config.executable.map(locate_in_path);
+            let console_utf8 = if config.console_utf8 {
+                ConsoleUtf8::acquire()
+            } else {
+                None
+            };
+            let job = if config.die_with_parent {
+                Some(kill_on_close_job()?)
+            } else {
+                None
+            };
+            let create_start = Instant::now();
             let (handle, pid) = win32::CreateProcess(
                 executable.as_ref().map(OsString::as_ref),
                 &cmdline,
                 &env_block,
                 &config.cwd.as_deref(),
                 true,
-                0,
+                config.creation_flags | config.priority.map_or(0, Priority::creation_flag),
                 raw(&child_stdin),
                 raw(&child_stdout),
                 raw(&child_stderr),
                 win32::STARTF_USESTDHANDLES,
-            )?;
+                job,
+            )
+            .inspect_error(|_| stats::record_exec_failure())?;
+            stats::record_spawn(create_start.elapsed());
             self.child_state = Running {
                 pid: pid as u32,
-                ext: ExtChildState(handle),
+                ext: ExtChildState(
+                    handle,
+                    console_utf8,
+                    config.creation_flags & win32::CREATE_NEW_PROCESS_GROUP != 0,
+                ),
             };
             Ok(())
         }
@@ -1048,7 +3598,7 @@ mod os {
         fn os_terminate(&mut self) -> io::Result<()> {
             let mut new_child_state = None;
             if let Running {
-                ext: ExtChildState(ref handle),
+                ext: ExtChildState(ref handle, ..),
                 ..
             } = self.child_state
             {
@@ -1075,6 +3625,80 @@ mod os {
         fn os_kill(&mut self) -> io::Result<()> {
             self.terminate()
         }
+
+        fn os_signal_other(&self, pid: u32, _kill: bool) -> io::Result<()> {
+            win32::TerminateProcessId(pid, 1)
+        }
+
+        fn os_process_parents(&self) -> io::Result<Vec<(u32, u32)>> {
+            // Windows doesn't reparent the children of an exited process,
+            // so a process that reused its id would be taken for their
+            // parent.  Only children created after their parent are kept.
+            let (root, root_created) = match self.child_state {
+                Running {
+                    pid,
+                    ext: ExtChildState(ref handle, ..),
+                } => (pid, win32::GetProcessCreationTime(handle)?),
+                _ => return Ok(vec![]),
+            };
+            let processes = win32::ProcessParents()?;
+            let created: HashMap<u32, u64> = processes
+                .iter()
+                .filter_map(|&(pid, _)| {
+                    let handle =
+                        win32::OpenProcess(win32::PROCESS_QUERY_LIMITED_INFORMATION, pid).ok()?;
+                    Some((pid, win32::GetProcessCreationTime(&handle).ok()?))
+                })
+                .collect();
+            Ok(processes
+                .into_iter()
+                .filter(|&(pid, ppid)| {
+                    let parent_created = if ppid == root {
+                        Some(root_created)
+                    } else {
+                        created.get(&ppid).copied()
+                    };
+                    match (created.get(&pid), parent_created) {
+                        (Some(&child), Some(parent)) => child > parent,
+                        _ => false,
+                    }
+                })
+                .collect())
+        }
+
+        fn os_request_exit(&mut self) -> io::Result<()> {
+            match self.child_state {
+                Running {
+                    pid,
+                    ext: ExtChildState(_, _, true),
+                } => win32::GenerateConsoleCtrlEvent(win32::CTRL_BREAK_EVENT, pid),
+                // without its own process group, the subprocess can't be
+                // asked to exit
+                _ => self.os_terminate(),
+            }
+        }
+
+        fn os_watch_exit(&self, watch: Arc<ExitWatch>) -> io::Result<()> {
+            let handle = match self.child_state {
+                Running {
+                    ext: ExtChildState(ref handle, ..),
+                    ..
+                } => handle.try_clone()?,
+                _ => return Ok(()),
+            };
+            thread::Builder::new()
+                .name("subprocess-exit".into())
+                .spawn(move || {
+                    if let Ok(win32::WaitEvent::OBJECT_0) =
+                        win32::WaitForSingleObject(&handle, None)
+                    {
+                        if let Ok(exit_code) = win32::GetExitCodeProcess(&handle) {
+                            watch.notify(ExitStatus::Exited(exit_code));
+                        }
+                    }
+                })?;
+            Ok(())
+        }
     }
 
     fn format_env_block(env: &[(OsString, OsString)]) -> Vec<u16> {
@@ -1110,6 +3734,20 @@ mod os {
         block
     }
 
+    impl Popen {
+        // Handle of the running process, awaited by the async runtimes.
+        #[cfg(any(feature = "async", feature = "async-smol"))]
+        pub(crate) fn process_handle(&self) -> Option<&win32::Handle> {
+            match self.child_state {
+                Running {
+                    ext: ExtChildState(ref handle, ..),
+                    ..
+                } => Some(handle),
+                _ => None,
+            }
+        }
+    }
+
     trait PopenOsImpl {
         fn wait_handle(&mut self, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>>;
     }
@@ -1118,7 +3756,7 @@ mod os {
         fn wait_handle(&mut self, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
             let mut new_child_state = None;
             if let Running {
-                ext: ExtChildState(ref handle),
+                ext: ExtChildState(ref handle, ..),
                 ..
             } = self.child_state
             {
@@ -1135,14 +3773,17 @@ mod os {
         }
     }
 
-    fn ensure_child_stream(stream: &mut Option<Rc<File>>, which: StandardStream) -> io::Result<()> {
+    fn ensure_child_stream(
+        stream: &mut Option<ChildStream>,
+        which: StandardStream,
+    ) -> io::Result<()> {
         // If no stream is sent to CreateProcess, the child doesn't
         // get a valid stream.  This results in e.g.
         // Exec("sh").arg("-c").arg("echo foo >&2").stream_stderr()
         // failing because the shell tries to redirect stdout to
         // stderr, but fails because it didn't receive a valid stdout.
         if stream.is_none() {
-            *stream = Some(get_standard_stream(which)?);
+            *stream = Some(ChildStream::Rc(get_standard_stream(which)?));
         }
         Ok(())
     }
@@ -1156,6 +3797,20 @@ mod os {
         Ok(())
     }
 
+    // Child ends must be inheritable to be passed to CreateProcess.
+    pub fn prepare_child_end(f: &File) -> io::Result<()> {
+        set_inheritable(f, true)
+    }
+
+    // Rather than making the caller's handle inheritable, which would leak
+    // it into every process started afterwards, pass an inheritable
+    // duplicate, which is closed once the child is started.
+    pub fn share_child_end(end: ChildStream) -> io::Result<ChildStream> {
+        let dup = end.try_clone()?;
+        set_inheritable(&dup, true)?;
+        Ok(ChildStream::Rc(Rc::new(dup)))
+    }
+
     /// Create a pipe.
     ///
     /// This is a safe wrapper over `libc::pipe` or
@@ -1165,12 +3820,61 @@ mod os {
         win32::CreatePipe(true)
     }
 
-    fn locate_in_path(executable: OsString) -> OsString {
-        if let Some(path) = env::var_os("PATH") {
+    pub(crate) fn open_pty() -> Result<(File, File)> {
+        Err(PopenError::LogicError(
+            "Redirection::Pty is not supported on Windows",
+        ))
+    }
+
+    /// Create a pipe with the specified options.
+    ///
+    /// Like [`make_pipe`], but allows setting the pipe's buffer size and,
+    /// on Windows, its mode and the use of overlapped I/O.
+    ///
+    /// [`make_pipe`]: fn.make_pipe.html
+    pub fn make_pipe_with(options: &PipeOptions) -> io::Result<(File, File)> {
+        if !options.message_mode && !options.overlapped_read && !options.overlapped_write {
+            return win32::CreatePipeSized(true, options.buffer_size);
+        }
+        win32::CreateNamedPipePair(
+            true,
+            options.buffer_size,
+            options.message_mode,
+            options.overlapped_read,
+            options.overlapped_write,
+        )
+    }
+
+    // The PATH set in the child's environment, if any.
+    #[cfg(not(feature = "no-path-search"))]
+    fn path_in_env(env: &[(OsString, OsString)]) -> Option<&OsStr> {
+        env.iter()
+            .rev()
+            .find(|(k, _)| k.eq_ignore_ascii_case("PATH"))
+            .map(|(_, v)| v.as_os_str())
+    }
+
+    #[cfg(not(feature = "no-path-search"))]
+    fn has_dir_component(program: &OsStr) -> bool {
+        program
+            .encode_wide()
+            .any(|c| c == '/' as u16 || c == '\\' as u16)
+    }
+
+    #[cfg(not(feature = "no-path-search"))]
+    fn locate_in_path(executable: OsString, child_path: Option<&OsStr>) -> OsString {
+        let path = match child_path {
+            Some(path) => Some(path.to_owned()),
+            None => env::var_os("PATH"),
+        };
+        if let Some(path) = path {
             for path in env::split_paths(&path) {
-                let path = path
-                    .join(&executable)
-                    .with_extension(::std::env::consts::EXE_EXTENSION);
+                let mut path = path.join(&executable);
+                // like CreateProcess, only add .exe to a name without an
+                // extension
+                if path.extension().is_none() {
+                    path.set_extension(::std::env::consts::EXE_EXTENSION);
+                }
                 if fs::metadata(&path).is_ok() {
                     return path.into_os_string();
                 }
@@ -1249,15 +3953,101 @@ mod os {
 
 impl Drop for Popen {
     // Wait for the process to exit.  To avoid the wait, call
-    // detach().
+    // detach(), or kill_on_drop() to stop the process first.
     fn drop(&mut self) {
         if let (false, &Running { .. }) = (self.detached, &self.child_state) {
+            if self.kill_on_drop {
+                self.kill().ok();
+            }
+            if self.wait_in_background && reaper::send(self) {
+                return;
+            }
             // Should we log error if one occurs during drop()?
             self.wait().ok();
         }
     }
 }
 
+mod reaper {
+    use std::mem;
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SendError, Sender};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{ChildState, ExitStatus, Popen};
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    static REAPER: Mutex<Option<Sender<Popen>>> = Mutex::new(None);
+
+    // Transfer the running child of `popen` to the reaper thread, starting
+    // the thread if needed.  Returns false if the thread cannot be started.
+    pub fn send(popen: &mut Popen) -> bool {
+        let mut reaper = REAPER.lock().unwrap_or_else(|e| e.into_inner());
+        if reaper.is_none() {
+            let (tx, rx) = mpsc::channel();
+            let spawned = thread::Builder::new()
+                .name("subprocess-reaper".into())
+                .spawn(move || run(rx));
+            if spawned.is_err() {
+                return false;
+            }
+            *reaper = Some(tx);
+        }
+        // The standard streams stay with the original, which closes them
+        // when dropped.
+        let child = Popen {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            child_state: mem::replace(&mut popen.child_state, gone()),
+            detached: false,
+            wait_in_background: false,
+            kill_on_drop: false,
+            start_time: popen.start_time,
+            #[cfg(unix)]
+            terminate_signal: popen.terminate_signal,
+            reopenable: vec![],
+            #[cfg(unix)]
+            audited_ends: vec![],
+            exit_watch: popen.exit_watch.take(),
+            temp_cwd: popen.temp_cwd.take(),
+            program: None,
+        };
+        if let Err(SendError(mut child)) = reaper.as_ref().unwrap().send(child) {
+            // the thread has died, take the child back
+            popen.child_state = mem::replace(&mut child.child_state, gone());
+            popen.exit_watch = child.exit_watch.take();
+            popen.temp_cwd = child.temp_cwd.take();
+            return false;
+        }
+        true
+    }
+
+    // State of a Popen whose child has been moved elsewhere.
+    fn gone() -> ChildState {
+        ChildState::Finished(ExitStatus::Undetermined)
+    }
+
+    fn run(rx: Receiver<Popen>) {
+        let mut pending: Vec<Popen> = vec![];
+        loop {
+            let received = if pending.is_empty() {
+                rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                rx.recv_timeout(POLL_INTERVAL)
+            };
+            match received {
+                Ok(child) => pending.push(child),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            pending.retain_mut(|child| child.poll().is_none());
+        }
+    }
+}
+
 thread_local! {
     static STREAMS: RefCell<[Option<Rc<File>>; 3]> = RefCell::default();
 }
@@ -1270,7 +4060,7 @@ use crate::win32::make_standard_stream;
 fn get_standard_stream(which: StandardStream) -> io::Result<Rc<File>> {
     STREAMS.with(|streams| {
         if let Some(ref stream) = streams.borrow()[which as usize] {
-            return Ok(Rc::clone(&stream));
+            return Ok(Rc::clone(stream));
         }
         let stream = make_standard_stream(which)?;
         streams.borrow_mut()[which as usize] = Some(Rc::clone(&stream));
@@ -1281,7 +4071,6 @@ fn get_standard_stream(which: StandardStream) -> io::Result<Rc<File>> {
 /// Error in [`Popen`] calls.
 ///
 /// [`Popen`]: struct.Popen.html
-
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum PopenError {
@@ -1289,6 +4078,32 @@ pub enum PopenError {
     IoError(io::Error),
     /// A logical error was made, e.g. invalid arguments detected at run-time.
     LogicError(&'static str),
+    /// The requested working directory of the subprocess does not exist.
+    CwdNotFound(PathBuf),
+    /// Waiting for the subprocess was cancelled through a `CancelToken`.
+    Cancelled,
+    /// The command passed to `Exec::shell` was rejected by the guard
+    /// installed with `set_shell_guard`, for the given reason.
+    UnsafeShellCommand(String),
+    /// Changing the user or group ID of the subprocess, as requested with
+    /// `PopenConfig::setuid` or `PopenConfig::setgid`, failed.
+    SetIdFailed {
+        /// The call that failed, `"setuid"` or `"setgid"`.
+        call: &'static str,
+        /// The requested user or group ID.
+        id: u32,
+        /// The error reported by the call.
+        error: io::Error,
+    },
+    /// A command of a pipeline failed to start.
+    PipelineStart {
+        /// Index of the command in the pipeline, starting from 0.
+        index: usize,
+        /// The command line of the command.
+        command: String,
+        /// The error that prevented the command from starting.
+        error: Box<PopenError>,
+    },
 }
 
 impl From<io::Error> for PopenError {
@@ -1308,6 +4123,11 @@ impl Error for PopenError {
         match *self {
             PopenError::IoError(ref err) => Some(err),
             PopenError::LogicError(_msg) => None,
+            PopenError::CwdNotFound(_) => None,
+            PopenError::Cancelled => None,
+            PopenError::UnsafeShellCommand(_) => None,
+            PopenError::SetIdFailed { ref error, .. } => Some(error),
+            PopenError::PipelineStart { ref error, .. } => Some(&**error),
         }
     }
 }
@@ -1317,6 +4137,43 @@ impl fmt::Display for PopenError {
         match *self {
             PopenError::IoError(ref err) => fmt::Display::fmt(err, f),
             PopenError::LogicError(desc) => f.write_str(desc),
+            PopenError::CwdNotFound(ref dir) => {
+                write!(f, "working directory {} does not exist", dir.display())
+            }
+            PopenError::Cancelled => f.write_str("wait cancelled"),
+            PopenError::UnsafeShellCommand(ref reason) => {
+                write!(f, "shell command rejected: {}", reason)
+            }
+            PopenError::SetIdFailed {
+                call,
+                id,
+                ref error,
+            } => {
+                write!(f, "{}({}) failed in the subprocess: {}", call, id, error)?;
+                if error.kind() == io::ErrorKind::PermissionDenied {
+                    let (what, capability) = match call {
+                        "setuid" => ("user", "CAP_SETUID"),
+                        _ => ("group", "CAP_SETGID"),
+                    };
+                    write!(
+                        f,
+                        " (changing the {} ID requires running as root or the {} capability)",
+                        what, capability
+                    )?;
+                }
+                Ok(())
+            }
+            PopenError::PipelineStart {
+                index,
+                ref command,
+                ref error,
+            } => write!(
+                f,
+                "command {} of the pipeline ({}) failed to start: {}",
+                index + 1,
+                command,
+                error
+            ),
         }
     }
 }
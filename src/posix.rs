@@ -7,6 +7,7 @@ use std::marker::PhantomData;
 use std::mem;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
 use std::ptr;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
@@ -15,7 +16,7 @@ use libc::{c_char, c_int};
 
 use crate::os_common::{ExitStatus, StandardStream};
 
-pub use libc::{ECHILD, ENOSPC};
+pub use libc::{ECHILD, EIO};
 
 fn check_err<T: Ord + Default>(num: T) -> Result<T> {
     if num < T::default() {
@@ -50,12 +51,237 @@ pub fn setgid(gid: u32) -> Result<()> {
     Ok(())
 }
 
+// Drop the supplementary groups of the calling process.
+pub fn clear_groups() -> Result<()> {
+    check_err(unsafe { libc::setgroups(0, ptr::null()) })?;
+    Ok(())
+}
+
 pub fn setpgid(pid: u32, pgid: u32) -> Result<()> {
     check_err(unsafe { libc::setpgid(pid as _, pgid as _) })?;
     Ok(())
 }
 
-fn os_to_cstring(s: &OsStr) -> Result<CString> {
+pub fn setpriority(nice: i32) -> Result<()> {
+    check_err(unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) })?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn ioprio_set(class: i32, level: i32) -> Result<()> {
+    const IOPRIO_WHO_PROCESS: c_int = 1;
+    const IOPRIO_CLASS_SHIFT: c_int = 13;
+    check_err(unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            class << IOPRIO_CLASS_SHIFT | level,
+        )
+    } as c_int)?;
+    Ok(())
+}
+
+pub fn umask(mask: u32) {
+    unsafe {
+        libc::umask(mask as libc::mode_t);
+    }
+}
+
+pub fn chdir(dir: &std::ffi::CStr) -> Result<()> {
+    check_err(unsafe { libc::chdir(dir.as_ptr()) })?;
+    Ok(())
+}
+
+pub fn setsid() -> Result<()> {
+    check_err(unsafe { libc::setsid() })?;
+    Ok(())
+}
+
+// Make the terminal open as `fd` the controlling terminal of the calling
+// process, which must be a session leader without one.
+pub fn set_controlling_tty(fd: i32) -> Result<()> {
+    check_err(unsafe { libc::ioctl(fd, libc::TIOCSCTTY as _, 0) })?;
+    Ok(())
+}
+
+// Allocate a pseudo-terminal, returning its master and slave ends.
+pub fn openpty() -> Result<(File, File)> {
+    let (mut master, mut slave) = (0 as c_int, 0 as c_int);
+    check_err(unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    })?;
+    Ok(unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) })
+}
+
+// unveil() and pledge() restrictions applied in the child before exec,
+// converted to C strings in advance so that applying them doesn't allocate.
+#[cfg(target_os = "openbsd")]
+#[derive(Debug)]
+pub struct ExecRestrictions {
+    unveil: Vec<(CString, CString)>,
+    execpromises: Option<CString>,
+}
+
+#[cfg(target_os = "openbsd")]
+impl ExecRestrictions {
+    pub fn new(unveil: &[(OsString, String)], execpromises: Option<&str>) -> Result<Self> {
+        let cstring = |s: &[u8]| {
+            CString::new(s).map_err(|_| {
+                Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "nul byte in unveil or pledge argument",
+                )
+            })
+        };
+        Ok(ExecRestrictions {
+            unveil: unveil
+                .iter()
+                .map(|(path, perms)| Ok((cstring(path.as_bytes())?, cstring(perms.as_bytes())?)))
+                .collect::<Result<_>>()?,
+            execpromises: execpromises.map(|p| cstring(p.as_bytes())).transpose()?,
+        })
+    }
+
+    pub fn apply(&self) -> Result<()> {
+        if !self.unveil.is_empty() {
+            for (path, perms) in &self.unveil {
+                check_err(unsafe { libc::unveil(path.as_ptr(), perms.as_ptr()) })?;
+            }
+            // lock the list, so the program can't unveil anything else
+            check_err(unsafe { libc::unveil(ptr::null(), ptr::null()) })?;
+        }
+        if let Some(ref execpromises) = self.execpromises {
+            check_err(unsafe { libc::pledge(ptr::null(), execpromises.as_ptr()) })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+extern "C" {
+    fn sandbox_init(profile: *const c_char, flags: u64, errorbuf: *mut *mut c_char) -> c_int;
+}
+
+// Seatbelt profile, checked for nul bytes in the parent.  sandbox_init()
+// has no variant that takes a profile compiled in advance, so the child
+// compiles it, which allocates: the one exception to the child not
+// allocating before exec.
+#[cfg(target_os = "macos")]
+#[derive(Debug)]
+pub struct SandboxProfile(CString);
+
+#[cfg(target_os = "macos")]
+impl SandboxProfile {
+    pub fn new(profile: &str) -> Result<SandboxProfile> {
+        let profile = CString::new(profile).map_err(|_| {
+            Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "nul byte in sandbox profile",
+            )
+        })?;
+        Ok(SandboxProfile(profile))
+    }
+
+    // Called in the child before exec, so the error message is not
+    // retrieved and a failure is reported as EINVAL.
+    pub fn apply(&self) -> Result<()> {
+        let mut errorbuf = ptr::null_mut();
+        if unsafe { sandbox_init(self.0.as_ptr(), 0, &mut errorbuf) } != 0 {
+            return Err(Error::from_raw_os_error(libc::EINVAL));
+        }
+        Ok(())
+    }
+}
+
+// Watcher process that kills a child once the current process exits, for
+// systems without PR_SET_PDEATHSIG.  The watcher is started with a double
+// fork so that it is reparented to init and never needs to be reaped, and
+// waits for EOF on a pipe whose write end only the current process holds.
+// A byte written to the pipe instead tells it that the child has been
+// reaped, and that its pid may be reused.
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub struct ParentWatch(RawFd);
+
+#[cfg(not(target_os = "linux"))]
+impl ParentWatch {
+    pub fn start(child_pid: u32) -> Result<ParentWatch> {
+        use std::os::unix::io::IntoRawFd;
+
+        let (read_end, write_end) = pipe()?;
+        for fd in [read_end.as_raw_fd(), write_end.as_raw_fd()] {
+            let flags = fcntl(fd, F_GETFD, None)?;
+            fcntl(fd, F_SETFD, Some(flags | FD_CLOEXEC))?;
+        }
+        let watch_fd = read_end.as_raw_fd();
+        let max_fd = match unsafe { libc::sysconf(libc::_SC_OPEN_MAX) } {
+            n if n > 0 => n.min(65536) as c_int,
+            _ => 1024,
+        };
+        // the forked processes only make async-signal-safe calls
+        let pid = check_err(unsafe { libc::fork() })?;
+        if pid == 0 {
+            match unsafe { libc::fork() } {
+                0 => unsafe { run_watcher(child_pid, watch_fd, max_fd) },
+                -1 => _exit(1),
+                _ => _exit(0),
+            }
+        }
+        drop(read_end);
+        loop {
+            match waitpid(pid as u32, 0) {
+                Ok((_, ExitStatus::Exited(0))) => break,
+                Ok(_) => return Err(Error::from_raw_os_error(libc::EAGAIN)),
+                Err(e) if e.raw_os_error() == Some(libc::EINTR) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(ParentWatch(write_end.into_raw_fd()))
+    }
+
+    // Tell the watcher that the child is gone.  A ParentWatch that is
+    // dropped without being released keeps watching, so that a detached
+    // child is still killed when the current process exits.
+    pub fn release(self) {
+        unsafe {
+            libc::write(self.0, b"x".as_ptr() as *const libc::c_void, 1);
+            libc::close(self.0);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn run_watcher(child_pid: u32, watch_fd: c_int, max_fd: c_int) -> ! {
+    // Keep no descriptors of the parent open, so that the watcher doesn't
+    // hold the pipes of other children, and leave its session so that it
+    // isn't signaled along with the parent's process group.
+    for fd in 0..max_fd {
+        if fd != watch_fd {
+            libc::close(fd);
+        }
+    }
+    libc::setsid();
+    let mut byte = 0u8;
+    loop {
+        match libc::read(watch_fd, &mut byte as *mut u8 as *mut libc::c_void, 1) {
+            -1 if Error::last_os_error().raw_os_error() == Some(libc::EINTR) => continue,
+            0 => {
+                libc::kill(child_pid as libc::pid_t, libc::SIGKILL);
+                _exit(0);
+            }
+            _ => _exit(0),
+        }
+    }
+}
+
+pub fn os_to_cstring(s: &OsStr) -> Result<CString> {
     // Like CString::new, but returns an io::Result for consistency with
     // everything else.
     CString::new(s.as_bytes()).map_err(|_| Error::from_raw_os_error(libc::EINVAL))
@@ -113,35 +339,6 @@ fn split_path(mut path: &OsStr) -> impl Iterator<Item = &OsStr> {
     })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::split_path;
-    use std;
-    use std::ffi::OsStr;
-    use std::os::unix::ffi::OsStrExt;
-
-    fn s(s: &str) -> Vec<&str> {
-        split_path(OsStr::new(s))
-            .map(|osstr| std::str::from_utf8(osstr.as_bytes()).unwrap())
-            .collect()
-    }
-
-    #[test]
-    fn test_split_path() {
-        let empty = Vec::<&OsStr>::new();
-
-        assert_eq!(s("a:b"), vec!["a", "b"]);
-        assert_eq!(s("one:twothree"), vec!["one", "twothree"]);
-        assert_eq!(s("a:"), vec!["a"]);
-        assert_eq!(s(""), empty);
-        assert_eq!(s(":"), empty);
-        assert_eq!(s("::"), empty);
-        assert_eq!(s(":::"), empty);
-        assert_eq!(s("a::b"), vec!["a", "b"]);
-        assert_eq!(s(":a::::b:"), vec!["a", "b"]);
-    }
-}
-
 struct PrepExec {
     cmd: OsString,
     argvec: CVec,
@@ -178,19 +375,29 @@ impl PrepExec {
         }
     }
 
-    fn exec(mut self) -> Result<()> {
-        // Invoked after fork() - no heap allocation allowed
-        let mut exe = std::mem::take(&mut self.prealloc_exe);
+    fn exec(&mut self) -> Result<()> {
+        // Invoked after fork() - no heap allocation or deallocation allowed,
+        // so the buffers are borrowed rather than taken, and nothing is
+        // dropped if the exec fails.
+        let PrepExec {
+            ref cmd,
+            ref argvec,
+            ref envvec,
+            ref search_path,
+            prealloc_exe: ref mut exe,
+        } = *self;
+        let envvec = envvec.as_ref();
 
-        if let Some(ref search_path) = self.search_path {
+        if let Some(ref search_path) = search_path {
             let mut err = Ok(());
             // POSIX requires execvp and execve, but not execvpe (although
             // glibc provides one), so we have to iterate over PATH ourselves
             for dir in split_path(search_path.as_os_str()) {
-                err = self.libc_exec(PrepExec::assemble_exe(
-                    &mut exe,
-                    &[dir.as_bytes(), b"/", self.cmd.as_bytes()],
-                ));
+                err = PrepExec::libc_exec(
+                    PrepExec::assemble_exe(exe, &[dir.as_bytes(), b"/", cmd.as_bytes()]),
+                    argvec,
+                    envvec,
+                );
                 // if exec succeeds, we won't run anymore; if we're here, it failed
                 assert!(err.is_err());
             }
@@ -199,7 +406,11 @@ impl PrepExec {
             return err;
         }
 
-        self.libc_exec(PrepExec::assemble_exe(&mut exe, &[self.cmd.as_bytes()]))?;
+        PrepExec::libc_exec(
+            PrepExec::assemble_exe(exe, &[cmd.as_bytes()]),
+            argvec,
+            envvec,
+        )?;
 
         // failed exec can only return Err(..)
         unreachable!();
@@ -215,30 +426,61 @@ impl PrepExec {
         storage.as_slice()
     }
 
-    fn libc_exec(&self, exe: &[u8]) -> Result<()> {
+    fn libc_exec(exe: &[u8], argvec: &CVec, envvec: Option<&CVec>) -> Result<()> {
         unsafe {
-            match self.envvec.as_ref() {
+            match envvec {
                 Some(envvec) => {
-                    libc::execve(exe.as_ptr() as _, self.argvec.as_c_vec(), envvec.as_c_vec())
+                    libc::execve(exe.as_ptr() as _, argvec.as_c_vec(), envvec.as_c_vec())
                 }
-                None => libc::execv(exe.as_ptr() as _, self.argvec.as_c_vec()),
+                None => libc::execv(exe.as_ptr() as _, argvec.as_c_vec()),
             }
         };
         Err(Error::last_os_error())
     }
 }
 
+// The PATH set in the child's `env`, if any.
+fn child_path(env: Option<&[impl AsRef<OsStr>]>) -> Option<&OsStr> {
+    env?.iter().find_map(|kv| {
+        let value = kv.as_ref().as_bytes().strip_prefix(b"PATH=")?;
+        Some(OsStr::from_bytes(value))
+    })
+}
+
+// The PATH to look up `cmd` in, which is the child's if its `env` sets
+// one, and ours otherwise.
+fn search_path(cmd: &OsStr, env: Option<&[impl AsRef<OsStr>]>) -> Option<OsString> {
+    if cfg!(feature = "no-path-search") || cmd.as_bytes().contains(&b'/') {
+        return None;
+    }
+    let path = match child_path(env) {
+        Some(path) => Some(path.to_owned()),
+        None => env::var_os("PATH"),
+    };
+    // treat empty path as non-existent
+    path.filter(|p| !p.is_empty())
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
 /// Prepare everything needed to `exec()` the provided `cmd` after `fork()`.
 ///
 /// Since code executed in the child after a `fork()` is not allowed to
 /// allocate (because the lock might be held), this allocates everything
-/// beforehand.
-
+/// beforehand.  The returned closure borrows the prepared data, so that
+/// calling it, and failing to exec, doesn't deallocate either.  A `cmd`
+/// without a slash is looked up in the `PATH` set in `env`, or in ours if
+/// `env` doesn't set one.
 pub fn prep_exec(
     cmd: impl AsRef<OsStr>,
     args: &[impl AsRef<OsStr>],
     env: Option<&[impl AsRef<OsStr>]>,
-) -> Result<impl FnOnce() -> Result<()>> {
+) -> Result<impl FnMut() -> Result<()>> {
     let cmd = cmd.as_ref().to_owned();
     let argvec = CVec::new(args)?;
     let envvec = if let Some(env) = env {
@@ -247,19 +489,169 @@ pub fn prep_exec(
         None
     };
 
-    let search_path = if !cmd.as_bytes().iter().any(|&b| b == b'/') {
-        env::var_os("PATH")
-            // treat empty path as non-existent
-            .and_then(|p| if p.len() == 0 { None } else { Some(p) })
-    } else {
-        None
-    };
+    let search_path = search_path(&cmd, env);
 
     // Allocate now and return a closure that just does the exec.
-    let prep = PrepExec::new(cmd, argvec, envvec, search_path);
+    let mut prep = PrepExec::new(cmd, argvec, envvec, search_path);
     Ok(move || prep.exec())
 }
 
+/// Start `cmd` with `posix_spawn()`, which creates the child using
+/// `vfork()` or an equivalent where available, instead of duplicating the
+/// page tables of the parent like `fork()`.
+///
+/// `fds` are installed as the standard streams of the child, and
+/// `setpgid` puts it in a new process group.  As after `fork()`, the child
+/// starts with the signals in `blocked` as its signal mask, with the
+/// default handling of `SIGPIPE` if `default_sigpipe` is true, and with
+/// the default handling of the other signals if `reset_all` is true.
+/// The command is looked up in `PATH` the same way as by `prep_exec()`.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn(
+    cmd: impl AsRef<OsStr>,
+    args: &[impl AsRef<OsStr>],
+    env: Option<&[impl AsRef<OsStr>]>,
+    fds: [Option<RawFd>; 3],
+    setpgid: bool,
+    blocked: &[i32],
+    reset_all: bool,
+    default_sigpipe: bool,
+) -> Result<u32> {
+    let cmd = cmd.as_ref();
+    let search_path = search_path(cmd, env);
+    // posix_spawnp() searches our PATH rather than the child's, so a
+    // command whose environment sets PATH is looked up here.
+    let child_sets_path = child_path(env).is_some();
+    let exe = match search_path {
+        Some(ref path) if child_sets_path => {
+            let found = split_path(path)
+                .map(|dir| Path::new(dir).join(cmd))
+                .find(|candidate| is_executable(candidate))
+                .ok_or_else(|| Error::from_raw_os_error(libc::ENOENT))?;
+            os_to_cstring(found.as_os_str())?
+        }
+        _ => os_to_cstring(cmd)?,
+    };
+    let use_spawnp = search_path.is_some() && !child_sets_path;
+    let argvec = CVec::new(args)?;
+    let envvec = match env {
+        Some(env) => CVec::new(env)?,
+        None => CVec::new(
+            &env::vars_os()
+                .map(|(k, v)| {
+                    let mut kv = k;
+                    kv.push("=");
+                    kv.push(v);
+                    kv
+                })
+                .collect::<Vec<_>>(),
+        )?,
+    };
+    // A child end already in place is close-on-exec, which dup2() onto
+    // the same descriptor doesn't reliably clear in spawn file actions, so
+    // it is installed from a duplicate.
+    let mut dups = vec![];
+    let mut fds = fds;
+    for (target, fd) in fds.iter_mut().enumerate() {
+        if *fd == Some(target as RawFd) {
+            let dup = check_err(unsafe { libc::fcntl(target as c_int, libc::F_DUPFD_CLOEXEC, 3) })?;
+            let dup = unsafe { File::from_raw_fd(dup) };
+            *fd = Some(dup.as_raw_fd());
+            dups.push(dup);
+        }
+    }
+
+    unsafe {
+        let mut actions = mem::MaybeUninit::<libc::posix_spawn_file_actions_t>::uninit();
+        check_spawn(libc::posix_spawn_file_actions_init(actions.as_mut_ptr()))?;
+        let mut attr = mem::MaybeUninit::<libc::posix_spawnattr_t>::uninit();
+        if let Err(e) = check_spawn(libc::posix_spawnattr_init(attr.as_mut_ptr())) {
+            libc::posix_spawn_file_actions_destroy(actions.as_mut_ptr());
+            return Err(e);
+        }
+        let result = (|| {
+            for (target, fd) in fds.iter().enumerate() {
+                if let Some(fd) = *fd {
+                    check_spawn(libc::posix_spawn_file_actions_adddup2(
+                        actions.as_mut_ptr(),
+                        fd,
+                        target as c_int,
+                    ))?;
+                }
+            }
+            #[allow(unused_mut)]
+            let mut flags = libc::POSIX_SPAWN_SETSIGMASK | libc::POSIX_SPAWN_SETSIGDEF;
+            // ignored by glibc 2.24 and later, which always spawns that way
+            #[cfg(all(target_os = "linux", target_env = "gnu"))]
+            {
+                flags |= libc::POSIX_SPAWN_USEVFORK as c_int;
+            }
+            if setpgid {
+                flags |= libc::POSIX_SPAWN_SETPGROUP;
+                check_spawn(libc::posix_spawnattr_setpgroup(attr.as_mut_ptr(), 0))?;
+            }
+            check_spawn(libc::posix_spawnattr_setflags(
+                attr.as_mut_ptr(),
+                flags as libc::c_short,
+            ))?;
+            let mut set = mem::MaybeUninit::<libc::sigset_t>::uninit();
+            check_err(libc::sigemptyset(set.as_mut_ptr()))?;
+            for &signal in blocked {
+                check_err(libc::sigaddset(set.as_mut_ptr(), signal))?;
+            }
+            check_spawn(libc::posix_spawnattr_setsigmask(
+                attr.as_mut_ptr(),
+                set.as_ptr(),
+            ))?;
+            check_err(libc::sigemptyset(set.as_mut_ptr()))?;
+            if reset_all {
+                for signal in 1..=MAX_SIGNAL {
+                    if signal != libc::SIGPIPE && signal != libc::SIGKILL && signal != libc::SIGSTOP
+                    {
+                        // numbers that aren't signals are skipped
+                        libc::sigaddset(set.as_mut_ptr(), signal);
+                    }
+                }
+            }
+            if default_sigpipe {
+                check_err(libc::sigaddset(set.as_mut_ptr(), libc::SIGPIPE))?;
+            }
+            check_spawn(libc::posix_spawnattr_setsigdefault(
+                attr.as_mut_ptr(),
+                set.as_ptr(),
+            ))?;
+            let spawn_fn = if use_spawnp {
+                libc::posix_spawnp
+            } else {
+                libc::posix_spawn
+            };
+            let mut pid = 0;
+            check_spawn(spawn_fn(
+                &mut pid,
+                exe.as_ptr(),
+                actions.as_ptr(),
+                attr.as_ptr(),
+                argvec.as_c_vec() as _,
+                envvec.as_c_vec() as _,
+            ))?;
+            Ok(pid as u32)
+        })();
+        libc::posix_spawnattr_destroy(attr.as_mut_ptr());
+        libc::posix_spawn_file_actions_destroy(actions.as_mut_ptr());
+        result
+    }
+}
+
+// posix_spawn() and friends return the error number instead of setting
+// errno.
+fn check_spawn(rc: c_int) -> Result<()> {
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(Error::from_raw_os_error(rc))
+    }
+}
+
 pub fn _exit(status: u8) -> ! {
     unsafe { libc::_exit(status as c_int) }
 }
@@ -278,6 +670,28 @@ pub fn waitpid(pid: u32, flags: i32) -> Result<(u32, ExitStatus)> {
     Ok((pid as u32, decode_exit_status(status)))
 }
 
+// Like waitpid(), but leaves the child in a waitable state, so its status
+// can be obtained again by a subsequent waitpid().  Returns None if the child
+// is still running and `block` is false.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn waitid_nowait(pid: u32, block: bool) -> Result<Option<ExitStatus>> {
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    let mut flags = libc::WEXITED | libc::WNOWAIT;
+    if !block {
+        flags |= libc::WNOHANG;
+    }
+    check_err(unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) })?;
+    let (si_pid, si_status) = unsafe { (info.si_pid(), info.si_status()) };
+    if si_pid == 0 {
+        return Ok(None);
+    }
+    Ok(Some(match info.si_code {
+        libc::CLD_EXITED => ExitStatus::Exited(si_status as u32),
+        libc::CLD_KILLED | libc::CLD_DUMPED => ExitStatus::Signaled(si_status as u8),
+        _ => ExitStatus::Other(si_status),
+    }))
+}
+
 fn decode_exit_status(status: i32) -> ExitStatus {
     if libc::WIFEXITED(status) {
         ExitStatus::Exited(libc::WEXITSTATUS(status) as u32)
@@ -295,6 +709,198 @@ pub fn kill(pid: u32, signal: i32) -> Result<()> {
     Ok(())
 }
 
+pub fn killpg(pgid: u32, signal: i32) -> Result<()> {
+    check_err(unsafe { libc::killpg(pgid as c_int, signal) })?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn memfd_create(name: &str) -> Result<File> {
+    let name = CString::new(name).unwrap();
+    let fd = check_err(unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) })?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+pub fn getpid() -> u32 {
+    unsafe { libc::getpid() as u32 }
+}
+
+pub fn getpgrp() -> u32 {
+    unsafe { libc::getpgrp() as u32 }
+}
+
+pub fn getpgid(pid: u32) -> Result<u32> {
+    Ok(check_err(unsafe { libc::getpgid(pid as libc::pid_t) })? as u32)
+}
+
+pub fn tcgetpgrp(fd: i32) -> Result<u32> {
+    Ok(check_err(unsafe { libc::tcgetpgrp(fd) })? as u32)
+}
+
+pub fn tcsetpgrp(fd: i32, pgrp: u32) -> Result<()> {
+    check_err(unsafe { libc::tcsetpgrp(fd, pgrp as libc::pid_t) })?;
+    Ok(())
+}
+
+// Call `f` with SIGTTOU blocked in the current thread.  This allows a
+// process outside the terminal's foreground process group to change it
+// with tcsetpgrp() without being stopped.  Doesn't allocate, so it can be
+// used after fork().
+pub fn with_sigttou_blocked<T>(f: impl FnOnce() -> T) -> T {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        let mut old: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGTTOU);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, &mut old);
+        let ret = f();
+        libc::pthread_sigmask(libc::SIG_SETMASK, &old, ptr::null_mut());
+        ret
+    }
+}
+
+pub fn getuid() -> u32 {
+    unsafe { libc::getuid() as u32 }
+}
+
+#[cfg(target_os = "linux")]
+pub fn getgid() -> u32 {
+    unsafe { libc::getgid() as u32 }
+}
+
+// Limit is passed as u64::MAX for RLIM_INFINITY.
+pub fn setrlimit(resource: i32, soft: u64, hard: u64) -> Result<()> {
+    let to_rlim = |limit: u64| {
+        if limit == u64::MAX {
+            libc::RLIM_INFINITY
+        } else {
+            limit as libc::rlim_t
+        }
+    };
+    let rlim = libc::rlimit {
+        rlim_cur: to_rlim(soft),
+        rlim_max: to_rlim(hard),
+    };
+    check_err(unsafe { libc::setrlimit(resource as _, &rlim) })?;
+    Ok(())
+}
+
+// Write `data` to the file at `path`, which must exist.  Doesn't
+// allocate, so it can be used after fork().
+#[cfg(target_os = "linux")]
+pub fn write_file(path: &std::ffi::CStr, data: &[u8]) -> Result<()> {
+    let fd = check_err(unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) })?;
+    let ret = unsafe { libc::write(fd, data.as_ptr() as *const _, data.len()) };
+    let result = if ret < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    };
+    unsafe {
+        libc::close(fd);
+    }
+    result
+}
+
+// Mark all file descriptors starting with `lowfd` close-on-exec.  Unlike
+// closing them, this keeps them usable until exec, and doesn't allocate,
+// so it can be used after fork().
+pub fn set_cloexec_from(lowfd: i32) -> Result<()> {
+    #[cfg(all(
+        target_os = "linux",
+        not(any(target_arch = "mips", target_arch = "mips64"))
+    ))]
+    {
+        const SYS_CLOSE_RANGE: libc::c_long = 436;
+        const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+        let ret = unsafe {
+            libc::syscall(
+                SYS_CLOSE_RANGE,
+                lowfd as libc::c_uint,
+                libc::c_uint::MAX,
+                CLOSE_RANGE_CLOEXEC,
+            )
+        };
+        if ret == 0 {
+            return Ok(());
+        }
+        // ENOSYS or EINVAL on kernels older than 5.11
+    }
+    let mut rlim: libc::rlimit = unsafe { mem::zeroed() };
+    check_err(unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) })?;
+    let maxfd = if rlim.rlim_cur == libc::RLIM_INFINITY {
+        65536
+    } else {
+        rlim.rlim_cur.min(1 << 20) as i32
+    };
+    for fd in lowfd..maxfd {
+        if let Ok(flags) = fcntl(fd, F_GETFD, None) {
+            fcntl(fd, F_SETFD, Some(flags | FD_CLOEXEC))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn unshare(flags: i32) -> Result<()> {
+    check_err(unsafe { libc::unshare(flags) })?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_no_new_privs() -> Result<()> {
+    check_err(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    Ok(())
+}
+
+// Have the kernel send `signal` to the calling process once its parent,
+// whose pid is `parent_pid`, exits.
+#[cfg(target_os = "linux")]
+pub fn set_parent_death_signal(signal: i32, parent_pid: u32) -> Result<()> {
+    check_err(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, signal as libc::c_ulong, 0, 0, 0) })?;
+    // If the parent died before the prctl(), we've been reparented and
+    // the signal will never arrive, so deliver it ourselves.
+    if unsafe { libc::getppid() } as u32 != parent_pid {
+        kill(getpid(), signal)?;
+    }
+    Ok(())
+}
+
+// Classic BPF instruction, struct sock_filter from <linux/filter.h>.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SockFilter {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct SockFprog {
+    len: libc::c_ushort,
+    filter: *const SockFilter,
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_seccomp_filter(filter: &[SockFilter]) -> Result<()> {
+    const SECCOMP_MODE_FILTER: libc::c_ulong = 2;
+    let prog = SockFprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_ptr(),
+    };
+    check_err(unsafe {
+        libc::prctl(
+            libc::PR_SET_SECCOMP,
+            SECCOMP_MODE_FILTER,
+            &prog as *const SockFprog,
+        )
+    })?;
+    Ok(())
+}
+
 pub const F_GETFD: i32 = libc::F_GETFD;
 pub const F_SETFD: i32 = libc::F_SETFD;
 pub const FD_CLOEXEC: i32 = libc::FD_CLOEXEC;
@@ -308,6 +914,45 @@ pub fn fcntl(fd: i32, cmd: i32, arg1: Option<i32>) -> Result<i32> {
     })
 }
 
+pub const F_GETFL: i32 = libc::F_GETFL;
+pub const O_ACCMODE: i32 = libc::O_ACCMODE;
+
+// Identity of an open file description, as far as it can be determined
+// from user space: the underlying file and the access mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileId {
+    dev: u64,
+    ino: u64,
+    accmode: i32,
+    pub is_fifo: bool,
+}
+
+pub fn file_id(fd: i32) -> Result<FileId> {
+    let mut st: libc::stat = unsafe { mem::zeroed() };
+    check_err(unsafe { libc::fstat(fd, &mut st) })?;
+    let accmode = fcntl(fd, F_GETFL, None)? & O_ACCMODE;
+    #[allow(clippy::unnecessary_cast, clippy::useless_conversion)]
+    Ok(FileId {
+        dev: st.st_dev as u64,
+        ino: st.st_ino as u64,
+        accmode,
+        is_fifo: st.st_mode & libc::S_IFMT == libc::S_IFIFO,
+    })
+}
+
+pub fn open_fds() -> Result<Vec<i32>> {
+    let dir = std::fs::read_dir("/proc/self/fd").or_else(|_| std::fs::read_dir("/dev/fd"))?;
+    Ok(dir
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn set_pipe_size(f: &File, size: u32) -> Result<()> {
+    fcntl(f.as_raw_fd(), libc::F_SETPIPE_SZ, Some(size as i32))?;
+    Ok(())
+}
+
 pub fn dup2(oldfd: i32, newfd: i32) -> Result<()> {
     check_err(unsafe { libc::dup2(oldfd, newfd) })?;
     Ok(())
@@ -322,9 +967,146 @@ pub fn make_standard_stream(which: StandardStream) -> Result<Rc<File>> {
     Ok(stream)
 }
 
-pub fn reset_sigpipe() -> Result<()> {
-    // This is called after forking to reset SIGPIPE handling to the
-    // defaults that Unix programs expect.  Quoting
+pub type SigAction = libc::sigaction;
+
+// Install `handler` for `signum`, returning the previous action.
+#[cfg(feature = "ctrlc-guard")]
+pub fn set_signal_handler(signum: i32, handler: extern "C" fn(c_int)) -> Result<SigAction> {
+    unsafe {
+        let mut action: SigAction = mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        check_err(libc::sigemptyset(&mut action.sa_mask))?;
+        let mut old: SigAction = mem::zeroed();
+        check_err(libc::sigaction(signum, &action, &mut old))?;
+        Ok(old)
+    }
+}
+
+#[cfg(feature = "ctrlc-guard")]
+pub fn restore_signal_handler(signum: i32, old: &SigAction) -> Result<()> {
+    check_err(unsafe { libc::sigaction(signum, old, ptr::null_mut()) })?;
+    Ok(())
+}
+
+#[cfg(feature = "ctrlc-guard")]
+pub use libc::SIGINT;
+
+pub type SigInfoHandler = extern "C" fn(c_int, *mut libc::siginfo_t, *mut libc::c_void);
+
+pub fn sigchld_action() -> Result<SigAction> {
+    unsafe {
+        let mut current: SigAction = mem::zeroed();
+        check_err(libc::sigaction(libc::SIGCHLD, ptr::null(), &mut current))?;
+        Ok(current)
+    }
+}
+
+// Install `handler` for SIGCHLD.  The handler receives the siginfo and
+// context, so that it can pass them on to the previous handler.
+pub fn set_sigchld_handler(handler: SigInfoHandler) -> Result<()> {
+    unsafe {
+        let mut action: SigAction = mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART | libc::SA_NOCLDSTOP;
+        check_err(libc::sigemptyset(&mut action.sa_mask))?;
+        check_err(libc::sigaction(libc::SIGCHLD, &action, ptr::null_mut()))?;
+        Ok(())
+    }
+}
+
+// Invoke the handler of `action` from a signal handler, unless it's the
+// default or ignoring the signal.
+pub unsafe fn call_signal_handler(
+    action: &SigAction,
+    signum: c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    let handler = action.sa_sigaction;
+    if handler == libc::SIG_DFL || handler == libc::SIG_IGN {
+        return;
+    }
+    if action.sa_flags & libc::SA_SIGINFO != 0 {
+        let handler: SigInfoHandler = mem::transmute(handler);
+        handler(signum, info, context);
+    } else {
+        let handler: extern "C" fn(c_int) = mem::transmute(handler);
+        handler(signum);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "emscripten"))]
+unsafe fn errno_location() -> Option<*mut c_int> {
+    Some(libc::__errno_location())
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+))]
+unsafe fn errno_location() -> Option<*mut c_int> {
+    Some(libc::__error())
+}
+
+#[cfg(any(target_os = "android", target_os = "openbsd", target_os = "netbsd"))]
+unsafe fn errno_location() -> Option<*mut c_int> {
+    Some(libc::__errno())
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "emscripten",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "android",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+unsafe fn errno_location() -> Option<*mut c_int> {
+    None
+}
+
+// Run `f`, restoring errno afterwards where its location is known, as
+// signal handlers must.
+pub fn preserving_errno<T>(f: impl FnOnce() -> T) -> T {
+    unsafe {
+        let location = errno_location();
+        let saved = location.map(|location| *location);
+        let result = f();
+        if let (Some(location), Some(saved)) = (location, saved) {
+            *location = saved;
+        }
+        result
+    }
+}
+
+pub fn set_nonblocking(f: &File) -> Result<()> {
+    let flags = fcntl(f.as_raw_fd(), libc::F_GETFL, None)?;
+    fcntl(f.as_raw_fd(), libc::F_SETFL, Some(flags | libc::O_NONBLOCK))?;
+    Ok(())
+}
+
+pub use libc::{sighandler_t, SIG_DFL, SIG_IGN};
+
+// Upper bound of the signal numbers on the supported systems, which
+// includes the real-time signals of Linux.
+const MAX_SIGNAL: c_int = 64;
+
+pub fn reset_signals(
+    blocked: &[i32],
+    reset_all: bool,
+    sigpipe: Option<sighandler_t>,
+) -> Result<()> {
+    // This is called after forking to reset the signal mask to `blocked`
+    // and SIGPIPE handling to the defaults that Unix programs expect,
+    // unless `sigpipe` requests otherwise (`None` keeps the inherited
+    // handling).  With `reset_all`, the other ignored signals are reset
+    // as well - handlers are reset by exec anyway.  Quoting
     // std::process::Command::do_exec:
     //
     // """
@@ -338,15 +1120,28 @@ pub fn reset_sigpipe() -> Result<()> {
     unsafe {
         let mut set: mem::MaybeUninit<libc::sigset_t> = mem::MaybeUninit::uninit();
         check_err(libc::sigemptyset(set.as_mut_ptr()))?;
+        for &signal in blocked {
+            check_err(libc::sigaddset(set.as_mut_ptr(), signal))?;
+        }
         let set = set.assume_init();
         check_err(libc::pthread_sigmask(
             libc::SIG_SETMASK,
             &set,
             ptr::null_mut(),
         ))?;
-        match libc::signal(libc::SIGPIPE, libc::SIG_DFL) {
-            libc::SIG_ERR => return Err(Error::last_os_error()),
-            _ => (),
+        if reset_all {
+            for signal in 1..=MAX_SIGNAL {
+                // fails for SIGKILL, SIGSTOP and numbers that aren't
+                // signals on this system, which is fine
+                if signal != libc::SIGPIPE {
+                    libc::signal(signal, libc::SIG_DFL);
+                }
+            }
+        }
+        if let Some(handler) = sigpipe {
+            if libc::signal(libc::SIGPIPE, handler) == libc::SIG_ERR {
+                return Err(Error::last_os_error());
+            }
         }
     }
     Ok(())
@@ -372,7 +1167,7 @@ impl PollFd<'_> {
     }
 }
 
-pub use libc::{POLLERR, POLLHUP, POLLIN, POLLNVAL, POLLOUT, POLLPRI};
+pub use libc::{POLLHUP, POLLIN, POLLOUT};
 
 pub fn poll(fds: &mut [PollFd<'_>], mut timeout: Option<Duration>) -> Result<usize> {
     let deadline = timeout.map(|timeout| Instant::now() + timeout);
@@ -383,24 +1178,132 @@ pub fn poll(fds: &mut [PollFd<'_>], mut timeout: Option<Duration>) -> Result<usi
         let (timeout_ms, overflow) = timeout
             .map(|timeout| {
                 let timeout = timeout.as_millis();
-                if timeout <= i32::max_value() as u128 {
+                if timeout <= i32::MAX as u128 {
                     (timeout as i32, false)
                 } else {
-                    (i32::max_value(), true)
+                    (i32::MAX, true)
                 }
             })
             .unwrap_or((-1, false));
         let fds_ptr = fds.as_ptr() as *mut libc::pollfd;
-        let cnt = unsafe { check_err(libc::poll(fds_ptr, fds.len() as libc::nfds_t, timeout_ms))? };
-        if cnt != 0 || !overflow {
-            return Ok(cnt as usize);
+        match unsafe { libc::poll(fds_ptr, fds.len() as libc::nfds_t, timeout_ms) } {
+            // poll() is not restarted after a signal handler even with
+            // SA_RESTART, such as that of install_sigchld_notifier()
+            -1 if Error::last_os_error().raw_os_error() == Some(libc::EINTR) => (),
+            -1 => return Err(Error::last_os_error()),
+            cnt if cnt != 0 || !overflow => return Ok(cnt as usize),
+            _ => (),
         }
 
-        let deadline = deadline.unwrap();
-        let now = Instant::now();
-        if now >= deadline {
-            return Ok(0);
+        if let Some(deadline) = deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(0);
+            }
+            timeout = Some(deadline - now);
         }
-        timeout = Some(deadline - now);
+    }
+}
+
+// Like fork(), but also return a pidfd of the child in the parent.  The
+// pidfd is created by clone3() together with the child, so unlike one
+// obtained by pidfd_open() afterwards, it can't refer to an unrelated
+// process that reused the pid.  Requires Linux 5.3.
+//
+// marked unsafe because the child must not allocate before exec-ing
+#[cfg(all(
+    target_os = "linux",
+    not(any(target_arch = "mips", target_arch = "mips64"))
+))]
+pub unsafe fn fork_pidfd() -> Result<Option<(u32, File)>> {
+    #[repr(C)]
+    #[derive(Default)]
+    struct CloneArgs {
+        flags: u64,
+        pidfd: u64,
+        child_tid: u64,
+        parent_tid: u64,
+        exit_signal: u64,
+        stack: u64,
+        stack_size: u64,
+        tls: u64,
+    }
+    const SYS_CLONE3: libc::c_long = 435;
+
+    let mut pidfd: c_int = -1;
+    let mut args = CloneArgs {
+        flags: libc::CLONE_PIDFD as u64,
+        pidfd: &mut pidfd as *mut c_int as u64,
+        exit_signal: libc::SIGCHLD as u64,
+        ..Default::default()
+    };
+    let pid = check_err(libc::syscall(
+        SYS_CLONE3,
+        &mut args as *mut CloneArgs,
+        mem::size_of::<CloneArgs>(),
+    ))?;
+    if pid == 0 {
+        Ok(None) // child
+    } else {
+        Ok(Some((pid as u32, File::from_raw_fd(pidfd)))) // parent
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    not(any(target_arch = "mips", target_arch = "mips64"))
+))]
+pub fn pidfd_send_signal(pidfd: &File, signal: i32) -> Result<()> {
+    const SYS_PIDFD_SEND_SIGNAL: libc::c_long = 424;
+    check_err(unsafe {
+        libc::syscall(
+            SYS_PIDFD_SEND_SIGNAL,
+            pidfd.as_raw_fd(),
+            signal,
+            ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    })?;
+    Ok(())
+}
+
+// Open a file descriptor referring to the process, which becomes readable
+// when the process exits.  Requires Linux 5.3.
+#[cfg(all(
+    any(feature = "async", feature = "async-smol"),
+    target_os = "linux",
+    not(any(target_arch = "mips", target_arch = "mips64"))
+))]
+pub fn pidfd_open(pid: u32) -> Result<File> {
+    const SYS_PIDFD_OPEN: libc::c_long = 434;
+    let fd = check_err(unsafe { libc::syscall(SYS_PIDFD_OPEN, pid as libc::pid_t, 0) })?;
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_path;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn s(s: &str) -> Vec<&str> {
+        split_path(OsStr::new(s))
+            .map(|osstr| std::str::from_utf8(osstr.as_bytes()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_split_path() {
+        let empty = Vec::<&OsStr>::new();
+
+        assert_eq!(s("a:b"), vec!["a", "b"]);
+        assert_eq!(s("one:twothree"), vec!["one", "twothree"]);
+        assert_eq!(s("a:"), vec!["a"]);
+        assert_eq!(s(""), empty);
+        assert_eq!(s(":"), empty);
+        assert_eq!(s("::"), empty);
+        assert_eq!(s(":::"), empty);
+        assert_eq!(s("a::b"), vec!["a", "b"]);
+        assert_eq!(s(":a::::b:"), vec!["a", "b"]);
     }
 }
@@ -0,0 +1,704 @@
+// Request/response exchanges with interactive subprocesses.  The output
+// of the subprocess is read and split into messages by a helper thread,
+// which hands them over through a channel, allowing to wait for them with
+// a timeout on all platforms.
+
+use std::collections::VecDeque;
+#[cfg(feature = "expect")]
+use std::error::Error;
+#[cfg(feature = "expect")]
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::popen::{Popen, PopenError, Result as PopenResult};
+
+/// Line-oriented request/response exchange with a subprocess.
+///
+/// Many interactive programs, such as chess engines speaking UCI or
+/// debuggers speaking GDB/MI, read commands one line at a time and answer
+/// each with a number of lines, the last of which is recognizable.
+/// `LineProtocol` writes a request line to the standard input of such a
+/// program and collects the response lines from its standard output
+/// until the caller-supplied predicate recognizes the last one:
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # use std::time::Duration;
+/// # fn dummy() -> Result<()> {
+/// let mut p = Exec::cmd("stockfish")
+///     .stdin(Redirection::Pipe)
+///     .stdout(Redirection::Pipe)
+///     .popen()?;
+/// let mut engine = LineProtocol::new(&mut p)?.timeout(Duration::from_secs(5));
+/// engine.request("uci", |line| line == "uciok")?;
+/// engine.send("position startpos moves e2e4")?;
+/// let lines = engine.request("go depth 10", |line| line.starts_with("bestmove"))?;
+/// println!("{}", lines.last().unwrap());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Lines are returned without the line terminator, either `\n` or
+/// `\r\n`.  Output that isn't valid UTF-8 is converted lossily.
+#[derive(Debug)]
+pub struct LineProtocol {
+    stdin: Option<File>,
+    lines: Receiver<io::Result<String>>,
+    // lines received but not yet returned, such as those of a response
+    // that timed out
+    pending: VecDeque<String>,
+    timeout: Option<Duration>,
+}
+
+impl LineProtocol {
+    /// Start exchanging lines with the subprocess.
+    ///
+    /// The standard input and output of `p`, which must be redirected to
+    /// pipes, are taken over by the protocol, and the output starts being
+    /// read in the background.  `p` can still be used to wait for the
+    /// subprocess or to terminate it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PopenError::LogicError` if the standard input or output
+    /// isn't redirected to a pipe.
+    pub fn new(p: &mut Popen) -> PopenResult<LineProtocol> {
+        let (stdin, lines) = take_pipes(
+            p,
+            "LineProtocol requires piped stdin and stdout",
+            |stdout| {
+                let mut line = vec![];
+                if stdout.read_until(b'\n', &mut line)? == 0 {
+                    return Ok(None);
+                }
+                if line.last() == Some(&b'\n') {
+                    line.pop();
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                }
+                Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+            },
+        )?;
+        Ok(LineProtocol {
+            stdin: Some(stdin),
+            lines,
+            pending: VecDeque::new(),
+            timeout: None,
+        })
+    }
+
+    /// Limit the time spent waiting for output.
+    ///
+    /// The limit applies to each call to [`read_line`], and to the whole
+    /// response collected by [`request`].  By default there is no limit.
+    ///
+    /// [`read_line`]: struct.LineProtocol.html#method.read_line
+    /// [`request`]: struct.LineProtocol.html#method.request
+    pub fn timeout(mut self, timeout: Duration) -> LineProtocol {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Write `line` to the standard input of the subprocess, followed by a
+    /// newline, without waiting for a response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails, or of kind `BrokenPipe` if the
+    /// standard input was closed with [`close_stdin`].
+    ///
+    /// [`close_stdin`]: struct.LineProtocol.html#method.close_stdin
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        let mut data = Vec::with_capacity(line.len() + 1);
+        data.extend_from_slice(line.as_bytes());
+        data.push(b'\n');
+        write_stdin(&mut self.stdin, &data)
+    }
+
+    /// Return the next line of output, or `None` once the subprocess has
+    /// closed its standard output.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `TimedOut` if no line arrives within the
+    /// time set with [`timeout`], or the error encountered while reading.
+    ///
+    /// [`timeout`]: struct.LineProtocol.html#method.timeout
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.next_line(deadline)
+    }
+
+    fn next_line(&mut self, deadline: Option<Instant>) -> io::Result<Option<String>> {
+        if let Some(line) = self.pending.pop_front() {
+            return Ok(Some(line));
+        }
+        receive(&self.lines, deadline)
+    }
+
+    /// Send `line` as a request, and collect the response lines up to and
+    /// including the one for which `is_last` returns true.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `TimedOut` if the response isn't complete
+    /// within the time set with [`timeout`], and of kind `UnexpectedEof`
+    /// if the subprocess closes its standard output before.  In both
+    /// cases, the lines received so far are returned by subsequent calls
+    /// to [`read_line`] and `request`.
+    ///
+    /// [`timeout`]: struct.LineProtocol.html#method.timeout
+    /// [`read_line`]: struct.LineProtocol.html#method.read_line
+    pub fn request(
+        &mut self,
+        line: &str,
+        mut is_last: impl FnMut(&str) -> bool,
+    ) -> io::Result<Vec<String>> {
+        self.send(line)?;
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut response = vec![];
+        loop {
+            let error = match self.next_line(deadline) {
+                Ok(Some(line)) => {
+                    let last = is_last(&line);
+                    response.push(line);
+                    if last {
+                        return Ok(response);
+                    }
+                    continue;
+                }
+                Ok(None) => io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "output closed before the end of the response",
+                ),
+                Err(e) => e,
+            };
+            for line in response.into_iter().rev() {
+                self.pending.push_front(line);
+            }
+            return Err(error);
+        }
+    }
+
+    /// Close the standard input of the subprocess, signaling that no more
+    /// requests will be sent.
+    ///
+    /// The output can still be read with [`read_line`].
+    ///
+    /// [`read_line`]: struct.LineProtocol.html#method.read_line
+    pub fn close_stdin(&mut self) {
+        self.stdin = None;
+    }
+}
+
+/// Exchange of messages framed with a `Content-Length` header with a
+/// subprocess.
+///
+/// This is the framing used by the Language Server Protocol and the Debug
+/// Adapter Protocol: each message is preceded by a header, which consists
+/// of `Name: value` fields terminated by `\r\n` and ends with an empty
+/// line, and whose `Content-Length` field gives the length of the message
+/// in bytes.  `FramedProtocol` adds the header to the messages it sends,
+/// and parses it to read the messages from the standard output of the
+/// subprocess, leaving the content, typically JSON, to the caller:
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # use std::time::Duration;
+/// # fn dummy() -> Result<()> {
+/// let mut p = Exec::cmd("rust-analyzer")
+///     .stdin(Redirection::Pipe)
+///     .stdout(Redirection::Pipe)
+///     .popen()?;
+/// let mut server = FramedProtocol::new(&mut p)?.timeout(Duration::from_secs(10));
+/// server.send(br#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#)?;
+/// let response = server.read_message()?.unwrap();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Header fields other than `Content-Length` are ignored.
+#[derive(Debug)]
+pub struct FramedProtocol {
+    stdin: Option<File>,
+    messages: Receiver<io::Result<Vec<u8>>>,
+    timeout: Option<Duration>,
+}
+
+impl FramedProtocol {
+    /// Start exchanging messages with the subprocess.
+    ///
+    /// The standard input and output of `p`, which must be redirected to
+    /// pipes, are taken over by the protocol, and the output starts being
+    /// read in the background.  `p` can still be used to wait for the
+    /// subprocess or to terminate it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PopenError::LogicError` if the standard input or output
+    /// isn't redirected to a pipe.
+    pub fn new(p: &mut Popen) -> PopenResult<FramedProtocol> {
+        let (stdin, messages) = take_pipes(
+            p,
+            "FramedProtocol requires piped stdin and stdout",
+            read_frame,
+        )?;
+        Ok(FramedProtocol {
+            stdin: Some(stdin),
+            messages,
+            timeout: None,
+        })
+    }
+
+    /// Limit the time [`read_message`] spends waiting for a message.  By
+    /// default there is no limit.
+    ///
+    /// [`read_message`]: struct.FramedProtocol.html#method.read_message
+    pub fn timeout(mut self, timeout: Duration) -> FramedProtocol {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Write `message` to the standard input of the subprocess, preceded
+    /// by its header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails, or of kind `BrokenPipe` if the
+    /// standard input was closed with [`close_stdin`].
+    ///
+    /// [`close_stdin`]: struct.FramedProtocol.html#method.close_stdin
+    pub fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        let mut data = format!("Content-Length: {}\r\n\r\n", message.len()).into_bytes();
+        data.extend_from_slice(message);
+        write_stdin(&mut self.stdin, &data)
+    }
+
+    /// Return the content of the next message, or `None` once the
+    /// subprocess has closed its standard output.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `TimedOut` if no complete message arrives
+    /// within the time set with [`timeout`], after which reading can be
+    /// retried.  Returns an error of kind `InvalidData` if the header is
+    /// malformed or lacks `Content-Length`, and of kind `UnexpectedEof`
+    /// if the output ends in the middle of a message; no more messages
+    /// are read after such errors.
+    ///
+    /// [`timeout`]: struct.FramedProtocol.html#method.timeout
+    pub fn read_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        receive(&self.messages, deadline)
+    }
+
+    /// Close the standard input of the subprocess, signaling that no more
+    /// messages will be sent.
+    ///
+    /// Messages can still be read with [`read_message`].
+    ///
+    /// [`read_message`]: struct.FramedProtocol.html#method.read_message
+    pub fn close_stdin(&mut self) {
+        self.stdin = None;
+    }
+}
+
+/// Interaction with a program that is driven by waiting for its output
+/// to match a pattern, in the manner of the classic `expect` tool.
+///
+/// Unlike [`LineProtocol`], `Expect` doesn't require the output to
+/// consist of lines, so it can wait for prompts that aren't followed by a
+/// newline.  Each wait has its own timeout, and when it expires, the
+/// output received so far is returned with the error, rather than the
+/// whole interaction being limited as with `Communicator::limit_time()`.
+/// Programs that only prompt on a terminal can be run under a
+/// pseudo-terminal with [`Exec::pty`]:
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # use std::time::Duration;
+/// # use regex::Regex;
+/// # fn dummy() -> std::result::Result<(), Box<dyn std::error::Error>> {
+/// let mut p = Exec::cmd("ftp").arg("ftp.example.com").pty().popen()?;
+/// let mut session = Expect::new(&mut p)?.timeout(Duration::from_secs(10));
+/// session.expect_regex(&Regex::new(r"Name.*: $")?)?;
+/// session.send_line("anonymous")?;
+/// session.expect_regex(&Regex::new(r"(?i)password: ?$")?)?;
+/// session.send_line("guest")?;
+/// let m = session.expect_regex_timeout(&Regex::new(r"^(\d{3}) ")?, Duration::from_secs(30))?;
+/// println!("server replied with {}", m.groups[1].as_ref().unwrap());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Output that isn't valid UTF-8 is converted lossily.
+///
+/// Available with the `expect` feature.
+///
+/// [`LineProtocol`]: struct.LineProtocol.html
+/// [`Exec::pty`]: struct.Exec.html#method.pty
+#[cfg(feature = "expect")]
+#[derive(Debug)]
+pub struct Expect {
+    stdin: Option<File>,
+    output: Receiver<io::Result<String>>,
+    // output received but not yet consumed by a match
+    buffer: String,
+    timeout: Option<Duration>,
+}
+
+/// Successful match of [`Expect::expect_regex`].
+///
+/// [`Expect::expect_regex`]: struct.Expect.html#method.expect_regex
+#[cfg(feature = "expect")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectMatch {
+    /// The output received before the match.
+    pub before: String,
+    /// The text matched by the regular expression.
+    pub matched: String,
+    /// The text matched by the capture groups of the regular expression,
+    /// with the whole match at index 0, and `None` for groups that didn't
+    /// participate in the match.
+    pub groups: Vec<Option<String>>,
+}
+
+/// Error while waiting for output with [`Expect`].
+///
+/// It holds the underlying `io::Error` in the `error` field, of kind
+/// `TimedOut` if the wait timed out and `UnexpectedEof` if the output was
+/// closed, and also provides the output received since the last match in
+/// the `buffer` field.
+///
+/// The error description and cause are taken from the underlying IO error.
+///
+/// [`Expect`]: struct.Expect.html
+#[cfg(feature = "expect")]
+#[derive(Debug)]
+pub struct ExpectError {
+    /// The underlying `io::Error`.
+    pub error: io::Error,
+    /// The output received since the last match.
+    pub buffer: String,
+}
+
+#[cfg(feature = "expect")]
+impl ExpectError {
+    /// Returns the corresponding IO `ErrorKind` for this error.
+    ///
+    /// Equivalent to `self.error.kind()`.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.error.kind()
+    }
+}
+
+#[cfg(feature = "expect")]
+impl Error for ExpectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+#[cfg(feature = "expect")]
+impl fmt::Display for ExpectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+#[cfg(feature = "expect")]
+impl From<ExpectError> for io::Error {
+    fn from(err: ExpectError) -> io::Error {
+        err.error
+    }
+}
+
+#[cfg(feature = "expect")]
+impl Expect {
+    /// Start interacting with the subprocess.
+    ///
+    /// The standard input and output of `p`, which must be redirected to
+    /// pipes or to a pseudo-terminal, are taken over by `Expect`, and the
+    /// output starts being read in the background.  `p` can still be used
+    /// to wait for the subprocess or to terminate it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PopenError::LogicError` if the standard input or output
+    /// isn't redirected.
+    pub fn new(p: &mut Popen) -> PopenResult<Expect> {
+        let mut undecoded = vec![];
+        let (stdin, output) = take_pipes(
+            p,
+            "Expect requires redirected stdin and stdout",
+            move |stdout| loop {
+                let n = match stdout.fill_buf() {
+                    Ok(chunk) => {
+                        undecoded.extend_from_slice(chunk);
+                        chunk.len()
+                    }
+                    // reading from a pseudo-terminal whose slave side is
+                    // closed fails with EIO
+                    #[cfg(unix)]
+                    Err(e) if e.raw_os_error() == Some(crate::posix::EIO) => 0,
+                    Err(e) => return Err(e),
+                };
+                stdout.consume(n);
+                if n == 0 && undecoded.is_empty() {
+                    return Ok(None);
+                }
+                let text = decode_utf8(&mut undecoded, n == 0);
+                if !text.is_empty() {
+                    return Ok(Some(text));
+                }
+            },
+        )?;
+        Ok(Expect {
+            stdin: Some(stdin),
+            output,
+            buffer: String::new(),
+            timeout: None,
+        })
+    }
+
+    /// Set the time [`expect_regex`] waits for a match.  By default there
+    /// is no limit.
+    ///
+    /// [`expect_regex`]: struct.Expect.html#method.expect_regex
+    pub fn timeout(mut self, timeout: Duration) -> Expect {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Write `text` to the standard input of the subprocess.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails, or of kind `BrokenPipe` if the
+    /// standard input was closed with [`close_stdin`].
+    ///
+    /// [`close_stdin`]: struct.Expect.html#method.close_stdin
+    pub fn send(&mut self, text: &str) -> io::Result<()> {
+        write_stdin(&mut self.stdin, text.as_bytes())
+    }
+
+    /// Write `line` to the standard input of the subprocess, followed by a
+    /// newline.
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        let mut data = Vec::with_capacity(line.len() + 1);
+        data.extend_from_slice(line.as_bytes());
+        data.push(b'\n');
+        write_stdin(&mut self.stdin, &data)
+    }
+
+    /// Wait for the output to match `re`, for at most the time set with
+    /// [`timeout`].
+    ///
+    /// The output up to the end of the match is consumed, so that the
+    /// next call only sees the output that follows it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `TimedOut` if the output doesn't match
+    /// within the time limit, and of kind `UnexpectedEof` if the
+    /// subprocess closes its standard output before it does.  The error
+    /// provides the unmatched output, which is also kept for subsequent
+    /// calls.
+    ///
+    /// [`timeout`]: struct.Expect.html#method.timeout
+    pub fn expect_regex(&mut self, re: &regex::Regex) -> Result<ExpectMatch, ExpectError> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.expect_until(re, deadline)
+    }
+
+    /// Like [`expect_regex`], but wait for at most `timeout` instead of
+    /// the time set with [`timeout`].
+    ///
+    /// [`expect_regex`]: struct.Expect.html#method.expect_regex
+    /// [`timeout`]: struct.Expect.html#method.timeout
+    pub fn expect_regex_timeout(
+        &mut self,
+        re: &regex::Regex,
+        timeout: Duration,
+    ) -> Result<ExpectMatch, ExpectError> {
+        self.expect_until(re, Some(Instant::now() + timeout))
+    }
+
+    fn expect_until(
+        &mut self,
+        re: &regex::Regex,
+        deadline: Option<Instant>,
+    ) -> Result<ExpectMatch, ExpectError> {
+        loop {
+            if let Some(caps) = re.captures(&self.buffer) {
+                let whole = caps.get(0).unwrap();
+                let result = ExpectMatch {
+                    before: self.buffer[..whole.start()].to_owned(),
+                    matched: whole.as_str().to_owned(),
+                    groups: caps
+                        .iter()
+                        .map(|group| group.map(|group| group.as_str().to_owned()))
+                        .collect(),
+                };
+                self.buffer.drain(..whole.end());
+                return Ok(result);
+            }
+            let error = match receive(&self.output, deadline) {
+                Ok(Some(text)) => {
+                    self.buffer.push_str(&text);
+                    continue;
+                }
+                Ok(None) => io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "output closed before matching",
+                ),
+                Err(e) => e,
+            };
+            return Err(ExpectError {
+                error,
+                buffer: self.buffer.clone(),
+            });
+        }
+    }
+
+    /// Close the standard input of the subprocess.
+    ///
+    /// The output can still be waited for with [`expect_regex`].
+    ///
+    /// [`expect_regex`]: struct.Expect.html#method.expect_regex
+    pub fn close_stdin(&mut self) {
+        self.stdin = None;
+    }
+}
+
+// Decode the UTF-8 in `bytes`, replacing invalid sequences, and leave an
+// incomplete sequence at the end in `bytes` unless at end of file.
+#[cfg(feature = "expect")]
+fn decode_utf8(bytes: &mut Vec<u8>, eof: bool) -> String {
+    let mut text = String::new();
+    let mut rest = &bytes[..];
+    while let Err(e) = std::str::from_utf8(rest) {
+        let (valid, after) = rest.split_at(e.valid_up_to());
+        text.push_str(std::str::from_utf8(valid).unwrap());
+        match e.error_len() {
+            Some(len) => rest = &after[len..],
+            None if eof => rest = &[],
+            None => {
+                *bytes = after.to_vec();
+                return text;
+            }
+        }
+        text.push('\u{FFFD}');
+    }
+    text.push_str(std::str::from_utf8(rest).unwrap());
+    bytes.clear();
+    text
+}
+
+// Read a message framed with a Content-Length header, or return None at
+// end of file before the header.
+fn read_frame(stdout: &mut BufReader<File>) -> io::Result<Option<Vec<u8>>> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut content_length = None;
+    let mut at_start = true;
+    loop {
+        let mut line = vec![];
+        if stdout.read_until(b'\n', &mut line)? == 0 {
+            if at_start {
+                return Ok(None);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "output closed in the middle of a message header",
+            ));
+        }
+        at_start = false;
+        let line = std::str::from_utf8(&line).map_err(|_| invalid("message header not UTF-8"))?;
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("malformed message header"))?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            let len = value
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| invalid("invalid Content-Length"))?;
+            content_length = Some(len);
+        }
+    }
+    let len = content_length.ok_or_else(|| invalid("message header lacks Content-Length"))?;
+    let mut content = vec![];
+    stdout.take(len as u64).read_to_end(&mut content)?;
+    if content.len() < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "output closed in the middle of a message",
+        ));
+    }
+    Ok(Some(content))
+}
+
+// Take the standard input and output of `p`, and start reading items from
+// the output with `read_item` in a helper thread, until it returns None
+// or an error.
+fn take_pipes<T: Send + 'static>(
+    p: &mut Popen,
+    error: &'static str,
+    mut read_item: impl FnMut(&mut BufReader<File>) -> io::Result<Option<T>> + Send + 'static,
+) -> PopenResult<(File, Receiver<io::Result<T>>)> {
+    if p.stdin.is_none() || p.stdout.is_none() {
+        return Err(PopenError::LogicError(error));
+    }
+    let mut stdout = BufReader::new(p.stdout.take().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new()
+        .name("subprocess-protocol".into())
+        .spawn(move || loop {
+            let item = match read_item(&mut stdout) {
+                Ok(Some(item)) => Ok(item),
+                Ok(None) => return,
+                Err(e) => Err(e),
+            };
+            let failed = item.is_err();
+            if tx.send(item).is_err() || failed {
+                return;
+            }
+        })?;
+    Ok((p.stdin.take().unwrap(), rx))
+}
+
+fn write_stdin(stdin: &mut Option<File>, data: &[u8]) -> io::Result<()> {
+    let stdin = stdin
+        .as_mut()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "standard input is closed"))?;
+    stdin.write_all(data)?;
+    stdin.flush()
+}
+
+// Receive the next item read by the helper thread, or None if it has
+// finished.
+fn receive<T>(items: &Receiver<io::Result<T>>, deadline: Option<Instant>) -> io::Result<Option<T>> {
+    let received = match deadline {
+        None => items.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        Some(deadline) => {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            items.recv_timeout(timeout)
+        }
+    };
+    match received {
+        Ok(item) => item.map(Some),
+        Err(RecvTimeoutError::Disconnected) => Ok(None),
+        Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timed out waiting for output",
+        )),
+    }
+}
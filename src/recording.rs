@@ -0,0 +1,266 @@
+// Recording of the data exchanged with a subprocess, and its replay.  The
+// recorder interposes a pipe between each stream of the child and the
+// parent, with a thread that copies the data between them and notes it
+// down.  The replay connects the parent to a thread that plays the part
+// of the child.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::os_common::ExitStatus;
+use crate::popen::{make_pipe, Popen, Result as PopenResult};
+
+/// Standard stream of a subprocess through which recorded data went.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum RecordedStream {
+    /// Data written to the standard input of the subprocess.
+    Stdin,
+    /// Data read from the standard output of the subprocess.
+    Stdout,
+    /// Data read from the standard error of the subprocess.
+    Stderr,
+}
+
+/// A piece of data exchanged with a subprocess, part of a [`Recording`].
+///
+/// [`Recording`]: struct.Recording.html
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RecordedEvent {
+    /// Time elapsed between the start of the recording and the transfer.
+    pub time: Duration,
+    /// Stream through which the data went.
+    pub stream: RecordedStream,
+    /// The data.
+    pub data: Vec<u8>,
+}
+
+/// Data exchanged with a subprocess, recorded by [`Recorder`].
+///
+/// The recording can be [replayed] to stand in for the subprocess in
+/// tests of code that interacts with it.  As its fields are public, a
+/// recording can also be constructed by hand, or stored and loaded in a
+/// format of choice.
+///
+/// [`Recorder`]: struct.Recorder.html
+/// [replayed]: struct.Recording.html#method.replay
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Recording {
+    /// The streams of the subprocess that were redirected to pipes.
+    pub streams: Vec<RecordedStream>,
+    /// The data exchanged, in the order of the transfers.
+    pub events: Vec<RecordedEvent>,
+    /// The exit status of the subprocess.
+    pub exit_status: ExitStatus,
+}
+
+/// Recorder of the data exchanged with a subprocess through its pipes.
+///
+/// The recorder is attached to a `Popen` right after it's created, and
+/// records the data exchanged through its `stdin`, `stdout` and `stderr`
+/// pipes from then on, regardless of whether they are used directly,
+/// through a `Communicator`, or through helpers such as [`LineProtocol`]:
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # fn dummy() -> Result<()> {
+/// let mut p = Exec::cmd("bc")
+///     .stdin(Redirection::Pipe)
+///     .stdout(Redirection::Pipe)
+///     .popen()?;
+/// let recorder = Recorder::attach(&mut p)?;
+/// let mut bc = LineProtocol::new(&mut p)?;
+/// assert_eq!(bc.request("2 + 2", |_| true)?, ["4"]);
+/// bc.close_stdin();
+/// let recording = recorder.finish(&mut p)?;
+///
+/// // later, without running bc
+/// let mut p = recording.replay()?;
+/// let mut bc = LineProtocol::new(&mut p)?;
+/// assert_eq!(bc.request("2 + 2", |_| true)?, ["4"]);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`LineProtocol`]: struct.LineProtocol.html
+#[derive(Debug)]
+pub struct Recorder {
+    streams: Vec<RecordedStream>,
+    events: Arc<Mutex<Vec<RecordedEvent>>>,
+    output_threads: Vec<JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// Start recording the data exchanged through the pipes of `p`.
+    ///
+    /// The pipes of `p` are replaced by pipes to the recorder, which
+    /// passes the data on.  Data that was already transferred isn't
+    /// recorded.
+    pub fn attach(p: &mut Popen) -> io::Result<Recorder> {
+        let events = Arc::new(Mutex::new(vec![]));
+        let start = Instant::now();
+        let mut recorder = Recorder {
+            streams: vec![],
+            events,
+            output_threads: vec![],
+        };
+        if let Some(child_stdin) = p.stdin.take() {
+            let (read, write) = make_pipe()?;
+            recorder.relay(RecordedStream::Stdin, read, child_stdin, start)?;
+            p.stdin = Some(write);
+        }
+        for (stream, pipe) in [
+            (RecordedStream::Stdout, &mut p.stdout),
+            (RecordedStream::Stderr, &mut p.stderr),
+        ] {
+            if let Some(child_output) = pipe.take() {
+                let (read, write) = make_pipe()?;
+                let handle = recorder.relay(stream, child_output, write, start)?;
+                recorder.output_threads.push(handle);
+                *pipe = Some(read);
+            }
+        }
+        Ok(recorder)
+    }
+
+    // Copy data from `source` to `sink` in a thread, recording it, until
+    // the end of file or an error.  Errors are not reported, but stop the
+    // copying, so that the parent and the child see the stream closed as
+    // they would without the recorder.
+    fn relay(
+        &mut self,
+        stream: RecordedStream,
+        mut source: File,
+        mut sink: File,
+        start: Instant,
+    ) -> io::Result<JoinHandle<()>> {
+        self.streams.push(stream);
+        let events = Arc::clone(&self.events);
+        thread::Builder::new()
+            .name("subprocess-record".into())
+            .spawn(move || {
+                let mut buf = [0u8; 8192];
+                loop {
+                    let n = match source.read(&mut buf) {
+                        Ok(0) => return,
+                        Ok(n) => n,
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(_) => return,
+                    };
+                    {
+                        let mut events = events.lock().unwrap_or_else(|e| e.into_inner());
+                        events.push(RecordedEvent {
+                            time: start.elapsed(),
+                            stream,
+                            data: buf[..n].to_vec(),
+                        });
+                    }
+                    if sink.write_all(&buf[..n]).is_err() {
+                        return;
+                    }
+                }
+            })
+    }
+
+    /// Wait for the subprocess to finish and for its output to be
+    /// recorded, and return the recording.
+    ///
+    /// Input written after this call isn't recorded.
+    pub fn finish(self, p: &mut Popen) -> PopenResult<Recording> {
+        let exit_status = p.wait()?;
+        for handle in self.output_threads {
+            let _ = handle.join();
+        }
+        let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(Recording {
+            streams: self.streams,
+            events: mem::take(&mut *events),
+            exit_status,
+        })
+    }
+}
+
+impl Recording {
+    /// Return a `Popen` that replays the recording in place of the
+    /// subprocess.
+    ///
+    /// The returned `Popen` has pipes for the recorded streams, and a
+    /// background thread plays the part of the subprocess: it writes the
+    /// recorded output with the recorded delays, and waits for the
+    /// recorded input before continuing, checking that it matches.  If
+    /// the input differs or its pipe is closed before all of it is read,
+    /// the replay stops and the output pipes are closed.
+    ///
+    /// The `Popen` reports the recorded exit status right away, and has no
+    /// process id.
+    pub fn replay(&self) -> io::Result<Popen> {
+        let has = |stream| self.streams.contains(&stream);
+        let mut stdin = None;
+        let mut stdout = None;
+        let mut stderr = None;
+        let mut player = Player {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+        };
+        if has(RecordedStream::Stdin) {
+            let (read, write) = make_pipe()?;
+            player.stdin = Some(read);
+            stdin = Some(write);
+        }
+        if has(RecordedStream::Stdout) {
+            let (read, write) = make_pipe()?;
+            player.stdout = Some(write);
+            stdout = Some(read);
+        }
+        if has(RecordedStream::Stderr) {
+            let (read, write) = make_pipe()?;
+            player.stderr = Some(write);
+            stderr = Some(read);
+        }
+        let events = self.events.clone();
+        thread::Builder::new()
+            .name("subprocess-replay".into())
+            .spawn(move || {
+                let _ = player.play(&events);
+            })?;
+        Ok(Popen::finished(stdin, stdout, stderr, self.exit_status))
+    }
+}
+
+// The child's end of the pipes of a replayed recording.
+struct Player {
+    stdin: Option<File>,
+    stdout: Option<File>,
+    stderr: Option<File>,
+}
+
+impl Player {
+    fn play(&mut self, events: &[RecordedEvent]) -> io::Result<()> {
+        let mismatch = || io::Error::new(io::ErrorKind::InvalidData, "unexpected input");
+        let mut last_time = Duration::from_secs(0);
+        for event in events {
+            let file = match event.stream {
+                RecordedStream::Stdin => {
+                    let mut input = vec![0u8; event.data.len()];
+                    let stdin = self.stdin.as_mut().ok_or_else(mismatch)?;
+                    stdin.read_exact(&mut input)?;
+                    if input != event.data {
+                        return Err(mismatch());
+                    }
+                    last_time = event.time;
+                    continue;
+                }
+                RecordedStream::Stdout => &mut self.stdout,
+                RecordedStream::Stderr => &mut self.stderr,
+            };
+            thread::sleep(event.time.saturating_sub(last_time));
+            last_time = event.time;
+            file.as_mut().ok_or_else(mismatch)?.write_all(&event.data)?;
+        }
+        Ok(())
+    }
+}
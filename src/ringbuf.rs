@@ -0,0 +1,200 @@
+// Output channel that delivers the output of a child process through a
+// preallocated ring buffer.  The child writes to an ordinary pipe, and a
+// pump thread moves the data from the pipe into the ring, from which the
+// consumer reads without allocating.
+
+use std::cmp::min;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use crate::popen::{make_pipe, set_inheritable};
+
+#[derive(Debug)]
+struct State {
+    buf: Box<[u8]>,
+    start: usize,
+    len: usize,
+    // set by the pump when the pipe is exhausted
+    eof: bool,
+    // set by the reader when it is dropped
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Create a channel that delivers output through a ring buffer of
+/// `capacity` bytes.
+///
+/// Returns a file to be used as the redirection of the standard output or
+/// error of a child process, and a [`RingReader`] from which the output
+/// can be read.  The child sees a plain pipe, which is drained by a
+/// background thread into the ring buffer.  All buffers are allocated up
+/// front, so reading the output involves no further allocation, and data
+/// becomes available to the reader as soon as the child writes it.
+///
+/// When the ring buffer is full, the background thread stops draining the
+/// pipe until the reader catches up, so a child producing output faster
+/// than it is consumed eventually blocks.
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # use std::io::Read;
+/// # fn dummy() -> Result<()> {
+/// let (stdout, mut ring) = output_ring(64 * 1024)?;
+/// let mut p = Exec::cmd("sensor-feed").stdout(stdout).popen()?;
+/// let mut sample = [0u8; 16];
+/// while ring.read(&mut sample)? != 0 {
+///     // process the sample
+/// }
+/// p.wait()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+///
+/// [`RingReader`]: struct.RingReader.html
+pub fn output_ring(capacity: usize) -> io::Result<(File, RingReader)> {
+    assert!(capacity != 0, "ring buffer capacity must not be zero");
+    let (read_end, write_end) = make_pipe()?;
+    set_inheritable(&read_end, false)?;
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            buf: vec![0; capacity].into_boxed_slice(),
+            start: 0,
+            len: 0,
+            eof: false,
+            closed: false,
+        }),
+        cond: Condvar::new(),
+    });
+    let pump_shared = Arc::clone(&shared);
+    thread::spawn(move || pump(read_end, &pump_shared));
+    Ok((write_end, RingReader { shared }))
+}
+
+fn pump(mut source: File, shared: &Shared) {
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = match source.read(&mut chunk) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let mut data = &chunk[..n];
+        let mut state = shared.lock();
+        while !data.is_empty() {
+            while state.len == state.buf.len() && !state.closed {
+                state = shared.cond.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+            if state.closed {
+                // closing the pipe lets the child know no one is reading
+                return;
+            }
+            let capacity = state.buf.len();
+            let end = (state.start + state.len) % capacity;
+            let n = min(data.len(), min(capacity - state.len, capacity - end));
+            state.buf[end..end + n].copy_from_slice(&data[..n]);
+            state.len += n;
+            data = &data[n..];
+            shared.cond.notify_all();
+        }
+    }
+    shared.lock().eof = true;
+    shared.cond.notify_all();
+}
+
+/// Reader of the output delivered through a ring buffer, created by
+/// [`output_ring`].
+///
+/// Reading blocks until some output is available, and returns 0 once the
+/// child has closed the output and everything has been read.
+/// [`try_read`] reads without blocking.
+///
+/// [`output_ring`]: fn.output_ring.html
+/// [`try_read`]: struct.RingReader.html#method.try_read
+#[derive(Debug)]
+pub struct RingReader {
+    shared: Arc<Shared>,
+}
+
+impl RingReader {
+    /// Read the output that is currently available into `buf`, without
+    /// waiting for more.
+    ///
+    /// Returns the number of bytes read, which is 0 if no output is
+    /// available.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> usize {
+        let mut state = self.shared.lock();
+        let n = take(&mut state, buf);
+        if n != 0 {
+            self.shared.cond.notify_all();
+        }
+        n
+    }
+
+    /// Return the number of bytes available for reading without blocking.
+    pub fn available(&self) -> usize {
+        self.shared.lock().len
+    }
+
+    /// Return the capacity of the ring buffer.
+    pub fn capacity(&self) -> usize {
+        self.shared.lock().buf.len()
+    }
+
+    /// Return true if the child has closed the output and all of it has
+    /// been read.
+    pub fn is_finished(&self) -> bool {
+        let state = self.shared.lock();
+        state.eof && state.len == 0
+    }
+}
+
+fn take(state: &mut State, buf: &mut [u8]) -> usize {
+    let capacity = state.buf.len();
+    let n = min(buf.len(), min(state.len, capacity - state.start));
+    buf[..n].copy_from_slice(&state.buf[state.start..state.start + n]);
+    state.start = (state.start + n) % capacity;
+    state.len -= n;
+    n
+}
+
+impl Read for RingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.shared.lock();
+        while state.len == 0 && !state.eof {
+            state = self
+                .shared
+                .cond
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        let n = take(&mut state, buf);
+        self.shared.cond.notify_all();
+        Ok(n)
+    }
+}
+
+impl Drop for RingReader {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.cond.notify_all();
+    }
+}
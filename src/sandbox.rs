@@ -0,0 +1,488 @@
+// Sandbox configuration combining the isolation primitives applied to a
+// child process between fork and exec.  The configuration is validated and
+// converted in the parent to a form that the child can apply without
+// allocating.
+
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::io;
+use std::path::PathBuf;
+
+use crate::posix;
+
+/// Namespace the sandboxed process is moved into, see
+/// [`Sandbox::namespace`].
+///
+/// [`Sandbox::namespace`]: struct.Sandbox.html#method.namespace
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Namespace {
+    /// User and group IDs (`CLONE_NEWUSER`).  The IDs of the parent are
+    /// mapped to themselves, so the process keeps its identity.
+    User,
+    /// Mount points (`CLONE_NEWNS`).
+    Mount,
+    /// Network devices and stack (`CLONE_NEWNET`).  The new namespace
+    /// only has a loopback device, which is down.
+    Network,
+    /// System V IPC and POSIX message queues (`CLONE_NEWIPC`).
+    Ipc,
+    /// Host and domain name (`CLONE_NEWUTS`).
+    Uts,
+    /// Cgroup root directory (`CLONE_NEWCGROUP`).
+    Cgroup,
+}
+
+/// Resource limited with `setrlimit()`, see [`Sandbox::rlimit`].
+///
+/// [`Sandbox::rlimit`]: struct.Sandbox.html#method.rlimit
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Resource {
+    /// CPU time in seconds (`RLIMIT_CPU`).
+    Cpu,
+    /// Size of the virtual memory in bytes (`RLIMIT_AS`).
+    AddressSpace,
+    /// Number of open files (`RLIMIT_NOFILE`).
+    OpenFiles,
+    /// Size of core dumps in bytes (`RLIMIT_CORE`).
+    Core,
+    /// Size of files the process can create in bytes (`RLIMIT_FSIZE`).
+    FileSize,
+    /// Size of the data segment in bytes (`RLIMIT_DATA`).
+    Data,
+    /// Size of the stack in bytes (`RLIMIT_STACK`).
+    Stack,
+    /// Number of processes of the user (`RLIMIT_NPROC`).
+    Processes,
+}
+
+impl Resource {
+    pub(crate) fn number(self) -> i32 {
+        #[allow(clippy::unnecessary_cast)]
+        let number = match self {
+            Resource::Cpu => libc::RLIMIT_CPU,
+            Resource::AddressSpace => libc::RLIMIT_AS,
+            Resource::OpenFiles => libc::RLIMIT_NOFILE,
+            Resource::Core => libc::RLIMIT_CORE,
+            Resource::FileSize => libc::RLIMIT_FSIZE,
+            Resource::Data => libc::RLIMIT_DATA,
+            Resource::Stack => libc::RLIMIT_STACK,
+            Resource::Processes => libc::RLIMIT_NPROC,
+        } as i32;
+        number
+    }
+}
+
+/// Isolation applied to a subprocess before it executes the program.
+///
+/// A `Sandbox` combines namespaces, resource limits, the no-new-privileges
+/// flag, a seccomp filter, cgroup placement, and closing of inherited file
+/// descriptors into a single configuration.  It is applied to a command
+/// with [`ExecExt::sandbox`] or through [`PopenConfig::sandbox`], and is
+/// validated when the process is started, so that inconsistent
+/// configurations are rejected instead of silently weakened.
+///
+/// Namespaces, no-new-privileges, seccomp and cgroups are specific to
+/// Linux, and requesting them on other systems fails with
+/// `ErrorKind::Unsupported`.
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # use subprocess::unix::{ExecExt, Namespace, Resource, Sandbox};
+/// # fn dummy() -> Result<()> {
+/// let sandbox = Sandbox::minimal_io()
+///     .namespace(Namespace::User)
+///     .namespace(Namespace::Network)
+///     .rlimit(Resource::Cpu, 10, 10);
+/// let out = Exec::cmd("/usr/bin/sort")
+///     .stdin("b\na\n")
+///     .sandbox(sandbox)
+///     .capture()?
+///     .stdout_str();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`ExecExt::sandbox`]: trait.ExecExt.html#tymethod.sandbox
+/// [`PopenConfig::sandbox`]: ../struct.PopenConfig.html#structfield.sandbox
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    namespaces: Vec<Namespace>,
+    rlimits: Vec<(Resource, u64, u64)>,
+    no_new_privs: bool,
+    denied_syscalls: Vec<libc::c_long>,
+    cgroup: Option<PathBuf>,
+    close_fds: bool,
+}
+
+// Keeps the jump offsets of the seccomp filter within the range of u8.
+const MAX_DENIED_SYSCALLS: usize = 200;
+
+impl Sandbox {
+    /// Create a sandbox that doesn't restrict anything.
+    pub fn new() -> Sandbox {
+        Sandbox::default()
+    }
+
+    /// Create a sandbox for a program that only processes its standard
+    /// streams.
+    ///
+    /// The program inherits no file descriptors other than the standard
+    /// streams and doesn't dump core.  On Linux it additionally can't gain
+    /// privileges, such as through setuid executables, and can't create
+    /// sockets or trace other processes.  Namespaces aren't used, as
+    /// creating them requires privileges on some systems, but can be added
+    /// with [`namespace`].
+    ///
+    /// [`namespace`]: struct.Sandbox.html#method.namespace
+    pub fn minimal_io() -> Sandbox {
+        let sandbox = Sandbox::new().close_fds().rlimit(Resource::Core, 0, 0);
+        #[cfg(target_os = "linux")]
+        let sandbox = sandbox.no_new_privs().deny_syscalls(&[
+            libc::SYS_socket,
+            libc::SYS_socketpair,
+            libc::SYS_ptrace,
+            libc::SYS_process_vm_readv,
+            libc::SYS_process_vm_writev,
+        ]);
+        sandbox
+    }
+
+    /// Move the process into a new namespace of the given kind.
+    ///
+    /// Namespaces other than `Namespace::User` can only be created by
+    /// privileged processes, unless a user namespace is created along
+    /// with them.
+    pub fn namespace(mut self, namespace: Namespace) -> Sandbox {
+        if !self.namespaces.contains(&namespace) {
+            self.namespaces.push(namespace);
+        }
+        self
+    }
+
+    /// Limit the use of `resource` to the `soft` limit, which the process
+    /// can raise up to the `hard` limit.
+    ///
+    /// `u64::MAX` stands for no limit.  Setting a limit for the same
+    /// resource again replaces the previous one.
+    pub fn rlimit(mut self, resource: Resource, soft: u64, hard: u64) -> Sandbox {
+        self.rlimits.retain(|&(r, _, _)| r != resource);
+        self.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// Prevent the process and its descendants from gaining privileges,
+    /// such as by executing setuid programs.
+    pub fn no_new_privs(mut self) -> Sandbox {
+        self.no_new_privs = true;
+        self
+    }
+
+    /// Make the given system calls, specified by number such as
+    /// `libc::SYS_socket`, fail with `EPERM`.
+    ///
+    /// The calls are denied with a seccomp filter, which requires
+    /// [`no_new_privs`] to be set as well.  System calls made through
+    /// a foreign ABI, such as 32-bit calls on a 64-bit system, aren't
+    /// allowed.
+    ///
+    /// [`no_new_privs`]: struct.Sandbox.html#method.no_new_privs
+    pub fn deny_syscalls(mut self, syscalls: &[libc::c_long]) -> Sandbox {
+        for &nr in syscalls {
+            if !self.denied_syscalls.contains(&nr) {
+                self.denied_syscalls.push(nr);
+            }
+        }
+        self
+    }
+
+    /// Move the process into the cgroup at `path`, such as
+    /// `/sys/fs/cgroup/jobs`, by writing its PID to `cgroup.procs`.
+    pub fn cgroup(mut self, path: impl Into<PathBuf>) -> Sandbox {
+        self.cgroup = Some(path.into());
+        self
+    }
+
+    /// Don't let the process inherit file descriptors other than the
+    /// standard input, output, and error.
+    ///
+    /// This protects against descriptors that were opened without the
+    /// close-on-exec flag, for example by foreign code, leaking into the
+    /// sandboxed program.
+    pub fn close_fds(mut self) -> Sandbox {
+        self.close_fds = true;
+        self
+    }
+
+    /// Check that the configuration is consistent and supported on this
+    /// platform.
+    ///
+    /// This is done automatically when a process is started with the
+    /// sandbox, and is only needed to report errors early.
+    pub fn validate(&self) -> io::Result<()> {
+        let linux_only = !self.namespaces.is_empty()
+            || self.no_new_privs
+            || !self.denied_syscalls.is_empty()
+            || self.cgroup.is_some();
+        if linux_only && !cfg!(target_os = "linux") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "namespaces, no_new_privs, seccomp, and cgroups require Linux",
+            ));
+        }
+        if let Some(&(resource, _, _)) = self.rlimits.iter().find(|&&(_, soft, hard)| soft > hard) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("soft limit of {:?} exceeds the hard limit", resource),
+            ));
+        }
+        if !self.denied_syscalls.is_empty() {
+            if !self.no_new_privs {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "denying system calls requires no_new_privs",
+                ));
+            }
+            if self.denied_syscalls.len() > MAX_DENIED_SYSCALLS {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("at most {} system calls can be denied", MAX_DENIED_SYSCALLS),
+                ));
+            }
+            #[cfg(target_os = "linux")]
+            {
+                if seccomp::AUDIT_ARCH.is_none() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "seccomp filters are not supported on this architecture",
+                    ));
+                }
+            }
+        }
+        if let Some(ref cgroup) = self.cgroup {
+            if !cgroup.is_absolute() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cgroup path must be absolute",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Sandbox prepared for application in the child.
+#[derive(Debug)]
+pub(crate) struct PreparedSandbox {
+    #[cfg(target_os = "linux")]
+    unshare_flags: i32,
+    // (path, content) of the files that set up the user namespace
+    #[cfg(target_os = "linux")]
+    id_maps: Vec<(CString, Vec<u8>)>,
+    #[cfg(target_os = "linux")]
+    cgroup_procs: Option<CString>,
+    #[cfg(target_os = "linux")]
+    no_new_privs: bool,
+    #[cfg(target_os = "linux")]
+    filter: Vec<posix::SockFilter>,
+    rlimits: Vec<(i32, u64, u64)>,
+    close_fds: bool,
+}
+
+impl PreparedSandbox {
+    pub fn new(sandbox: &Sandbox) -> io::Result<PreparedSandbox> {
+        sandbox.validate()?;
+        Ok(PreparedSandbox {
+            #[cfg(target_os = "linux")]
+            unshare_flags: sandbox
+                .namespaces
+                .iter()
+                .map(|ns| match ns {
+                    Namespace::User => libc::CLONE_NEWUSER,
+                    Namespace::Mount => libc::CLONE_NEWNS,
+                    Namespace::Network => libc::CLONE_NEWNET,
+                    Namespace::Ipc => libc::CLONE_NEWIPC,
+                    Namespace::Uts => libc::CLONE_NEWUTS,
+                    Namespace::Cgroup => libc::CLONE_NEWCGROUP,
+                })
+                .fold(0, |flags, flag| flags | flag),
+            #[cfg(target_os = "linux")]
+            id_maps: if sandbox.namespaces.contains(&Namespace::User) {
+                let (uid, gid) = (posix::getuid(), posix::getgid());
+                vec![
+                    (
+                        cstring("/proc/self/uid_map"),
+                        format!("{0} {0} 1", uid).into(),
+                    ),
+                    // writing gid_map requires disabling setgroups()
+                    (cstring("/proc/self/setgroups"), b"deny".to_vec()),
+                    (
+                        cstring("/proc/self/gid_map"),
+                        format!("{0} {0} 1", gid).into(),
+                    ),
+                ]
+            } else {
+                vec![]
+            },
+            #[cfg(target_os = "linux")]
+            cgroup_procs: match sandbox.cgroup {
+                Some(ref cgroup) => {
+                    use std::os::unix::ffi::OsStrExt;
+                    let procs = cgroup.join("cgroup.procs");
+                    Some(CString::new(procs.as_os_str().as_bytes()).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "nul byte in cgroup path")
+                    })?)
+                }
+                None => None,
+            },
+            #[cfg(target_os = "linux")]
+            no_new_privs: sandbox.no_new_privs,
+            #[cfg(target_os = "linux")]
+            filter: if sandbox.denied_syscalls.is_empty() {
+                vec![]
+            } else {
+                seccomp::deny_filter(&sandbox.denied_syscalls)
+            },
+            rlimits: sandbox
+                .rlimits
+                .iter()
+                .map(|&(resource, soft, hard)| (resource.number(), soft, hard))
+                .collect(),
+            close_fds: sandbox.close_fds,
+        })
+    }
+
+    // Called in the child before changing the user and group ID.
+    pub fn enter(&self) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(ref cgroup_procs) = self.cgroup_procs {
+                let mut buf = [0u8; 20];
+                posix::write_file(cgroup_procs, format_u32(posix::getpid(), &mut buf))?;
+            }
+            if self.unshare_flags != 0 {
+                posix::unshare(self.unshare_flags)?;
+            }
+            for (path, content) in &self.id_maps {
+                posix::write_file(path, content)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Called in the child immediately before exec.
+    pub fn restrict(&self) -> io::Result<()> {
+        if self.close_fds {
+            posix::set_cloexec_from(3)?;
+        }
+        for &(resource, soft, hard) in &self.rlimits {
+            posix::setrlimit(resource, soft, hard)?;
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if self.no_new_privs {
+                posix::set_no_new_privs()?;
+            }
+            if !self.filter.is_empty() {
+                posix::set_seccomp_filter(&self.filter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn cstring(s: &str) -> CString {
+    CString::new(s).unwrap()
+}
+
+// Format `n` in decimal without allocating.
+#[cfg(target_os = "linux")]
+fn format_u32(mut n: u32, buf: &mut [u8; 20]) -> &[u8] {
+    let mut pos = buf.len();
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            return &buf[pos..];
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use crate::posix::SockFilter;
+
+    // AUDIT_ARCH_* value identifying the native system call ABI, which
+    // the kernel reports in seccomp_data.arch.
+    #[cfg(target_arch = "x86_64")]
+    pub const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+    #[cfg(target_arch = "x86")]
+    pub const AUDIT_ARCH: Option<u32> = Some(0x4000_0003);
+    #[cfg(target_arch = "aarch64")]
+    pub const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+    #[cfg(all(target_arch = "arm", target_endian = "little"))]
+    pub const AUDIT_ARCH: Option<u32> = Some(0x4000_0028);
+    #[cfg(target_arch = "riscv64")]
+    pub const AUDIT_ARCH: Option<u32> = Some(0xc000_00f3);
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "aarch64",
+        all(target_arch = "arm", target_endian = "little"),
+        target_arch = "riscv64"
+    )))]
+    pub const AUDIT_ARCH: Option<u32> = None;
+
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JMP_JEQ_K: u16 = 0x15;
+    const BPF_JMP_JGE_K: u16 = 0x35;
+    const BPF_RET_K: u16 = 0x06;
+
+    // offsets in struct seccomp_data
+    const NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+    // System calls of the x32 ABI on x86_64 have this bit set in their
+    // number, while reporting the same architecture.
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    fn insn(code: u16, jt: u8, jf: u8, k: u32) -> SockFilter {
+        SockFilter { code, jt, jf, k }
+    }
+
+    // Build a filter that makes `syscalls` fail with EPERM.  The process is
+    // killed on system calls of a foreign architecture, and x32 calls fail
+    // with EPERM.
+    pub fn deny_filter(syscalls: &[libc::c_long]) -> Vec<SockFilter> {
+        let mut filter = vec![
+            insn(BPF_LD_W_ABS, 0, 0, ARCH_OFFSET),
+            insn(BPF_JMP_JEQ_K, 1, 0, AUDIT_ARCH.unwrap()),
+            insn(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS),
+            insn(BPF_LD_W_ABS, 0, 0, NR_OFFSET),
+        ];
+        let x32 = cfg!(target_arch = "x86_64") as usize;
+        // the filter ends with "allow", followed by "deny"
+        let deny = filter.len() + x32 + syscalls.len() + 1;
+        if x32 != 0 {
+            let jt = (deny - filter.len() - 1) as u8;
+            filter.push(insn(BPF_JMP_JGE_K, jt, 0, X32_SYSCALL_BIT));
+        }
+        for &nr in syscalls {
+            let jt = (deny - filter.len() - 1) as u8;
+            filter.push(insn(BPF_JMP_JEQ_K, jt, 0, nr as u32));
+        }
+        filter.push(insn(BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW));
+        filter.push(insn(
+            BPF_RET_K,
+            0,
+            0,
+            SECCOMP_RET_ERRNO | libc::EPERM as u32,
+        ));
+        filter
+    }
+}
@@ -0,0 +1,182 @@
+// Waiting on several processes at once, for callers that race commands
+// against each other.  Output readiness is detected with poll() on Unix
+// and by peeking into the pipes on Windows, and exits by checking the
+// processes between waits.
+
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+use crate::os_common::ExitStatus;
+use crate::popen::{Popen, Result as PopenResult};
+
+// Upper limit of the wait between checks for process exit.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Event reported by [`select`].
+///
+/// Each variant carries the index of the process in the slice passed to
+/// `select`.
+///
+/// [`select`]: fn.select.html
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Selected {
+    /// The standard output of the process can be read without blocking.
+    ///
+    /// The read can return end-of-file, after which the caller should set
+    /// the `stdout` field to `None` to stop it from being selected again.
+    Stdout(usize),
+    /// The standard error of the process can be read without blocking.
+    ///
+    /// Like with `Stdout`, the read can return end-of-file.
+    Stderr(usize),
+    /// The process has exited with the given status.
+    Exited(usize, ExitStatus),
+}
+
+impl Selected {
+    /// Return the index of the process the event refers to.
+    pub fn index(&self) -> usize {
+        match *self {
+            Selected::Stdout(index) | Selected::Stderr(index) | Selected::Exited(index, _) => index,
+        }
+    }
+}
+
+/// Wait until one of `processes` produces output or exits.
+///
+/// The output is looked for in the `stdout` and `stderr` fields of the
+/// processes, which are typically obtained by redirecting them to
+/// `Redirection::Pipe`.  Available output is reported before exits, and
+/// among the events of the same kind the one of the process with the
+/// lowest index is reported.  Returns `Ok(None)` if nothing happens
+/// before `timeout` expires, or waits indefinitely if `timeout` is
+/// `None`.
+///
+/// A process that has exited is reported by every call, so the caller
+/// racing several commands, e.g. querying multiple mirrors, can take the
+/// first responder and terminate the rest:
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # fn dummy() -> Result<()> {
+/// let mut mirrors = ["mirror1", "mirror2", "mirror3"]
+///     .iter()
+///     .map(|host| Exec::cmd("curl").arg(host).stdout(Redirection::Pipe).popen())
+///     .collect::<Result<Vec<_>>>()?;
+/// let first = select(&mut mirrors, None)?.unwrap().index();
+/// for (i, p) in mirrors.iter_mut().enumerate() {
+///     if i != first {
+///         p.terminate()?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Exits are detected by checking the processes between waits for
+/// output, so an exit can take a few tens of milliseconds to be
+/// reported.
+pub fn select(processes: &mut [Popen], timeout: Option<Duration>) -> PopenResult<Option<Selected>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut interval = Duration::from_millis(1);
+    loop {
+        let wait = match deadline {
+            Some(deadline) => min(interval, deadline.saturating_duration_since(Instant::now())),
+            None => interval,
+        };
+        if let Some(selected) = os::wait_output(processes, wait)? {
+            return Ok(Some(selected));
+        }
+        for (index, p) in processes.iter_mut().enumerate() {
+            if let Some(exit_status) = p.wait_timeout(Duration::from_secs(0))? {
+                return Ok(Some(Selected::Exited(index, exit_status)));
+            }
+        }
+        if let Some(deadline) = deadline {
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+        }
+        interval = min(interval * 2, MAX_CHECK_INTERVAL);
+    }
+}
+
+#[cfg(unix)]
+mod os {
+    use std::fs::File;
+    use std::io;
+    use std::time::Duration;
+
+    use super::Selected;
+    use crate::popen::Popen;
+    use crate::posix;
+
+    // Wait up to `timeout` for the output of one of the processes.
+    pub fn wait_output(processes: &[Popen], timeout: Duration) -> io::Result<Option<Selected>> {
+        let mut fds = vec![];
+        let mut events = vec![];
+        for (index, p) in processes.iter().enumerate() {
+            let streams: [(Option<&File>, fn(usize) -> Selected); 2] = [
+                (p.stdout.as_ref(), Selected::Stdout),
+                (p.stderr.as_ref(), Selected::Stderr),
+            ];
+            for (file, event) in streams {
+                if file.is_some() {
+                    fds.push(posix::PollFd::new(file, posix::POLLIN));
+                    events.push(event(index));
+                }
+            }
+        }
+        match posix::poll(&mut fds, Some(timeout)) {
+            // interrupted by a signal such as SIGCHLD, nothing is ready
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(None),
+            result => result?,
+        };
+        Ok(fds
+            .iter()
+            .position(|fd| fd.test(posix::POLLIN | posix::POLLHUP))
+            .map(|pos| events[pos]))
+    }
+}
+
+#[cfg(windows)]
+mod os {
+    use std::fs::File;
+    use std::io;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::Selected;
+    use crate::popen::Popen;
+    use crate::win32;
+
+    // Wait up to `timeout` for the output of one of the processes.  Pipes
+    // can't be waited on together, so check them before and after
+    // sleeping.
+    pub fn wait_output(processes: &[Popen], timeout: Duration) -> io::Result<Option<Selected>> {
+        if let Some(selected) = check_output(processes) {
+            return Ok(Some(selected));
+        }
+        thread::sleep(timeout);
+        Ok(check_output(processes))
+    }
+
+    fn check_output(processes: &[Popen]) -> Option<Selected> {
+        for (index, p) in processes.iter().enumerate() {
+            let streams: [(Option<&File>, fn(usize) -> Selected); 2] = [
+                (p.stdout.as_ref(), Selected::Stdout),
+                (p.stderr.as_ref(), Selected::Stderr),
+            ];
+            for (file, event) in streams {
+                if let Some(file) = file {
+                    // an error means the pipe is closed, and the read
+                    // returns EOF
+                    if win32::PeekNamedPipe(file).map_or(true, |avail| avail > 0) {
+                        return Some(event(index));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,130 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::builder::CaptureData;
+use crate::os_common::ExitStatus;
+
+/// How captured output is represented when serializing [`CaptureData`].
+///
+/// [`CaptureData`]: struct.CaptureData.html
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OutputEncoding {
+    /// Serialize output as a string, converted from bytes using
+    /// `String::from_utf8_lossy`.
+    ///
+    /// This is the encoding used by the `Serialize` implementation of
+    /// `CaptureData`.
+    Lossy,
+
+    /// Serialize output as a string containing the standard base64
+    /// encoding of the bytes, preserving output that isn't valid UTF-8.
+    Base64,
+}
+
+/// Serializable view of [`CaptureData`] with a chosen output encoding.
+///
+/// Created with [`CaptureData::serialize_as`].
+///
+/// [`CaptureData`]: struct.CaptureData.html
+/// [`CaptureData::serialize_as`]: struct.CaptureData.html#method.serialize_as
+#[derive(Debug)]
+pub struct SerializableCapture<'a> {
+    capture: &'a CaptureData,
+    encoding: OutputEncoding,
+}
+
+impl CaptureData {
+    /// Returns a value that serializes the capture with the standard output
+    /// and error represented as specified by `encoding`.
+    ///
+    /// Only available with the `serde` feature.
+    ///
+    /// ```
+    /// # use subprocess::*;
+    /// # fn dummy(capture: CaptureData) {
+    /// let json = serde_json::to_string(&capture.serialize_as(OutputEncoding::Base64));
+    /// # }
+    /// ```
+    pub fn serialize_as(&self, encoding: OutputEncoding) -> SerializableCapture<'_> {
+        SerializableCapture {
+            capture: self,
+            encoding,
+        }
+    }
+}
+
+/// Serializes the standard output and error as lossily converted strings.
+///
+/// Use [`serialize_as`] to choose a different representation.  Only
+/// available with the `serde` feature.
+///
+/// [`serialize_as`]: struct.CaptureData.html#method.serialize_as
+impl Serialize for CaptureData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.serialize_as(OutputEncoding::Lossy)
+            .serialize(serializer)
+    }
+}
+
+impl SerializableCapture<'_> {
+    fn encode(&self, data: &[u8]) -> String {
+        match self.encoding {
+            OutputEncoding::Lossy => String::from_utf8_lossy(data).into_owned(),
+            OutputEncoding::Base64 => base64(data),
+        }
+    }
+}
+
+impl Serialize for SerializableCapture<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CaptureData", 3)?;
+        state.serialize_field("stdout", &self.encode(&self.capture.stdout))?;
+        state.serialize_field("stderr", &self.encode(&self.capture.stderr))?;
+        state.serialize_field("exit_status", &self.capture.exit_status)?;
+        state.end()
+    }
+}
+
+/// Only available with the `serde` feature.
+impl Serialize for ExitStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            ExitStatus::Exited(code) => {
+                serializer.serialize_newtype_variant("ExitStatus", 0, "Exited", &code)
+            }
+            ExitStatus::Signaled(signum) => {
+                serializer.serialize_newtype_variant("ExitStatus", 1, "Signaled", &signum)
+            }
+            ExitStatus::Other(status) => {
+                serializer.serialize_newtype_variant("ExitStatus", 2, "Other", &status)
+            }
+            ExitStatus::Undetermined => {
+                serializer.serialize_unit_variant("ExitStatus", 3, "Undetermined")
+            }
+            ExitStatus::ReapedElsewhere => {
+                serializer.serialize_unit_variant("ExitStatus", 4, "ReapedElsewhere")
+            }
+        }
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(crate::compat::div_ceil(data.len(), 3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        // n chars of output for n-1 bytes of input, the rest is padding
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
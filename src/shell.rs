@@ -0,0 +1,212 @@
+use std::ffi::OsStr;
+use std::sync::Mutex;
+
+use crate::popen::{PopenError, Result as PopenResult};
+
+/// Policy for checking command strings passed to [`Exec::shell`].
+///
+/// Once installed with [`set_shell_guard`], every command started with
+/// `Exec::shell` is checked before it is run, and rejected with
+/// `PopenError::UnsafeShellCommand` if it contains a NUL byte, a newline,
+/// or a character with special meaning to the shell that is not
+/// explicitly allowed.  Letters, digits, spaces, tabs, and the characters
+/// `-_./,:=+@` are always allowed.  On Unix-like systems, anything inside
+/// single quotes, as well as any character escaped with a backslash, is
+/// also allowed, so strings quoted by [`ShellCommand`] pass the check.
+///
+/// This is meant for auditing legacy code that builds shell command
+/// strings, to detect where untrusted input finds its way into them.
+///
+/// ```
+/// # use subprocess::*;
+/// assert!(ShellGuard::new().allow("|").check("ls | wc -l").is_ok());
+/// assert!(ShellGuard::new().allow("|").check("ls; rm -rf ~").is_err());
+/// ```
+///
+/// [`Exec::shell`]: struct.Exec.html#method.shell
+/// [`set_shell_guard`]: fn.set_shell_guard.html
+/// [`ShellCommand`]: struct.ShellCommand.html
+#[derive(Debug, Clone, Default)]
+pub struct ShellGuard {
+    allowed: Vec<char>,
+}
+
+fn is_safe(c: char) -> bool {
+    c.is_alphanumeric() || " \t-_./,:=+@".contains(c)
+}
+
+impl ShellGuard {
+    /// Create a guard that allows no shell metacharacters.
+    pub fn new() -> ShellGuard {
+        ShellGuard::default()
+    }
+
+    /// Allow the characters in `chars` to appear in command strings.
+    ///
+    /// NUL bytes and newlines cannot be allowed.
+    pub fn allow(mut self, chars: &str) -> ShellGuard {
+        self.allowed
+            .extend(chars.chars().filter(|&c| c != '\0' && c != '\n'));
+        self
+    }
+
+    /// Check `command` against the policy, returning an error describing
+    /// the first problem found.
+    pub fn check(&self, command: impl AsRef<OsStr>) -> PopenResult<()> {
+        let reject = |reason: String| Err(PopenError::UnsafeShellCommand(reason));
+        let command = match command.as_ref().to_str() {
+            Some(command) => command,
+            None => return reject("command is not valid Unicode".to_owned()),
+        };
+        let mut chars = command.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\0' => return reject("command contains a NUL byte".to_owned()),
+                '\n' | '\r' => return reject("command contains a newline".to_owned()),
+                '\'' if cfg!(unix) => loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\0') => return reject("command contains a NUL byte".to_owned()),
+                        Some('\n') | Some('\r') => {
+                            return reject("command contains a newline".to_owned())
+                        }
+                        Some(_) => (),
+                        None => return reject("unterminated single quote".to_owned()),
+                    }
+                },
+                '\\' if cfg!(unix) => match chars.next() {
+                    Some('\0') => return reject("command contains a NUL byte".to_owned()),
+                    Some('\n') | Some('\r') => {
+                        return reject("command contains a newline".to_owned())
+                    }
+                    Some(_) => (),
+                    None => return reject("trailing backslash".to_owned()),
+                },
+                c if is_safe(c) || self.allowed.contains(&c) => (),
+                c => return reject(format!("command contains disallowed character {:?}", c)),
+            }
+        }
+        Ok(())
+    }
+}
+
+static SHELL_GUARD: Mutex<Option<ShellGuard>> = Mutex::new(None);
+
+/// Install a guard checking the command strings passed to `Exec::shell`,
+/// or remove it with `None`.
+///
+/// The guard applies to the whole process.  See [`ShellGuard`] for details.
+///
+/// ```
+/// # use subprocess::*;
+/// set_shell_guard(Some(ShellGuard::new()));
+/// match Exec::shell("echo $HOME").join() {
+///     Err(PopenError::UnsafeShellCommand(_)) => (),
+///     _ => panic!("command not rejected"),
+/// }
+/// ```
+///
+/// [`ShellGuard`]: struct.ShellGuard.html
+pub fn set_shell_guard(guard: Option<ShellGuard>) {
+    *SHELL_GUARD.lock().unwrap_or_else(|e| e.into_inner()) = guard;
+}
+
+pub(crate) fn check_shell_command(command: &OsStr) -> PopenResult<()> {
+    match *SHELL_GUARD.lock().unwrap_or_else(|e| e.into_inner()) {
+        Some(ref guard) => guard.check(command),
+        None => Ok(()),
+    }
+}
+
+/// Shell command string built from safely quoted fragments.
+///
+/// Arguments added with [`arg`] are quoted so that the shell passes them to
+/// the command unchanged, whatever characters they contain.  Shell syntax
+/// such as pipes or redirections can only be added with [`raw`], which
+/// accepts string literals, so that untrusted data cannot be interpolated
+/// into the command by mistake.
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # fn dummy(pattern: &str) -> Result<()> {
+/// let cmd = ShellCommand::new("grep").arg(pattern).raw("|").cmd("wc").arg("-l");
+/// assert_eq!(cmd.as_str(), format!("grep {} | wc -l", shell_quote(pattern)));
+/// let count = Exec::shell(cmd).capture()?.stdout_str();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Only available on Unix-like systems.
+///
+/// [`arg`]: struct.ShellCommand.html#method.arg
+/// [`raw`]: struct.ShellCommand.html#method.raw
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct ShellCommand {
+    cmdline: String,
+}
+
+#[cfg(unix)]
+impl ShellCommand {
+    /// Start a command string that runs `program`.
+    pub fn new(program: &str) -> ShellCommand {
+        ShellCommand {
+            cmdline: shell_quote(program),
+        }
+    }
+
+    /// Append `arg`, quoted as a single argument.
+    pub fn arg(mut self, arg: &str) -> ShellCommand {
+        self.cmdline.push(' ');
+        self.cmdline.push_str(&shell_quote(arg));
+        self
+    }
+
+    /// Append each of `args`, quoted as separate arguments.
+    pub fn args(mut self, args: &[&str]) -> ShellCommand {
+        for arg in args {
+            self = self.arg(arg);
+        }
+        self
+    }
+
+    /// Append a new command, such as after `raw("|")`.
+    pub fn cmd(self, program: &str) -> ShellCommand {
+        self.arg(program)
+    }
+
+    /// Append `syntax` unquoted, e.g. `|`, `&&`, or `2>&1`.
+    pub fn raw(mut self, syntax: &'static str) -> ShellCommand {
+        self.cmdline.push(' ');
+        self.cmdline.push_str(syntax);
+        self
+    }
+
+    /// Return the command string.
+    pub fn as_str(&self) -> &str {
+        &self.cmdline
+    }
+}
+
+#[cfg(unix)]
+impl AsRef<OsStr> for ShellCommand {
+    fn as_ref(&self) -> &OsStr {
+        OsStr::new(&self.cmdline)
+    }
+}
+
+/// Quote `s` so that the Unix shell interprets it as a single word with
+/// the literal value `s`.
+///
+/// Strings consisting only of letters, digits, and the characters
+/// `-_./,:+@` are returned unchanged, and others are enclosed in single
+/// quotes.
+///
+/// Only available on Unix-like systems.
+#[cfg(unix)]
+pub fn shell_quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| is_safe(c) && !" \t=".contains(c)) {
+        return s.to_owned();
+    }
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
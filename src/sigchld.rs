@@ -0,0 +1,123 @@
+// Notification of child exit through SIGCHLD.  The signal handler writes
+// to a self-pipe, whose reader thread advances a generation counter and
+// wakes up the threads waiting for a change of the counter.  A waiter
+// notes the generation before checking its child, so an exit between the
+// check and the wait isn't missed.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use libc::c_int;
+
+use crate::posix;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static INSTALL_LOCK: Mutex<()> = Mutex::new(());
+static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+// The handler to pass the signal on to, set before installing ours.
+static PREVIOUS: AtomicPtr<posix::SigAction> = AtomicPtr::new(ptr::null_mut());
+
+static GENERATION: Mutex<u64> = Mutex::new(0);
+static CHANGED: Condvar = Condvar::new();
+
+extern "C" fn on_sigchld(signum: c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    posix::preserving_errno(|| {
+        let fd = PIPE_WRITE.load(Ordering::SeqCst);
+        // a full pipe already has a wakeup pending
+        unsafe {
+            libc::write(fd, b"x".as_ptr() as *const libc::c_void, 1);
+        }
+        let previous = PREVIOUS.load(Ordering::SeqCst);
+        if !previous.is_null() {
+            unsafe { posix::call_signal_handler(&*previous, signum, info, context) };
+        }
+    });
+}
+
+/// Make `Popen::wait_timeout()` wait for the `SIGCHLD` signal instead of
+/// checking the child with increasing delays.
+///
+/// By default, waiting for a child with a timeout is implemented by
+/// checking whether the child has exited, sleeping for up to 100 ms, and
+/// checking again.  This adds latency to detecting the exit, and wakes up
+/// needlessly when many children are waited for concurrently.  Once the
+/// notifier is installed, `wait_timeout()` and the functions built on it
+/// sleep until a child exits or the timeout expires.
+///
+/// This installs a process-wide `SIGCHLD` handler, which is why it's
+/// opt-in.  The handler calls the previously installed handler, if any,
+/// so it can coexist with other users of the signal that install their
+/// handler first.  A background thread named `subprocess-sigchld` relays
+/// the notifications.  Calling the function again has no effect.
+///
+/// # Errors
+///
+/// Returns the error encountered while creating the pipe, starting the
+/// thread or installing the handler.
+pub fn install_sigchld_notifier() -> io::Result<()> {
+    let _lock = INSTALL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if INSTALLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let (read, write) = posix::pipe()?;
+    for end in [&read, &write] {
+        posix::fcntl(end.as_raw_fd(), posix::F_SETFD, Some(posix::FD_CLOEXEC))?;
+    }
+    posix::set_nonblocking(&write)?;
+    thread::Builder::new()
+        .name("subprocess-sigchld".into())
+        .spawn(move || relay(read))?;
+    PIPE_WRITE.store(write.into_raw_fd(), Ordering::SeqCst);
+    let previous = Box::new(posix::sigchld_action()?);
+    PREVIOUS.store(Box::into_raw(previous), Ordering::SeqCst);
+    posix::set_sigchld_handler(on_sigchld)?;
+    INSTALLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+fn relay(mut read: File) {
+    let mut buf = [0u8; 64];
+    loop {
+        match read.read(&mut buf) {
+            Ok(0) => return,
+            Ok(_) => {
+                let mut generation = GENERATION.lock().unwrap_or_else(|e| e.into_inner());
+                *generation += 1;
+                CHANGED.notify_all();
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(_) => return,
+        }
+    }
+}
+
+// Return the current generation if the notifier is installed.
+pub fn generation() -> Option<u64> {
+    if !INSTALLED.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(*GENERATION.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+// Wait for a SIGCHLD received after `seen` was obtained from generation(),
+// or until `deadline`.  Returns false on timeout.
+pub fn wait_for_change(seen: u64, deadline: Instant) -> bool {
+    let mut generation = GENERATION.lock().unwrap_or_else(|e| e.into_inner());
+    while *generation == seen {
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        generation = CHANGED
+            .wait_timeout(generation, deadline - now)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+    true
+}
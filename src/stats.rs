@@ -0,0 +1,189 @@
+// Process-wide statistics of spawns and communication.  The recording
+// functions are always available to the rest of the crate, but only do
+// something when the `stats` feature is enabled.
+
+#[cfg(not(feature = "stats"))]
+mod imp {
+    use std::time::Duration;
+
+    #[inline]
+    pub fn record_spawn(_latency: Duration) {}
+    #[cfg(unix)]
+    #[inline]
+    pub fn record_fork_failure() {}
+    #[inline]
+    pub fn record_exec_failure() {}
+    #[inline]
+    pub fn record_communicate(_bytes: u64, _elapsed: Duration) {}
+}
+
+#[cfg(feature = "stats")]
+mod imp {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    const BUCKETS: usize = 32;
+
+    /// Histogram of durations with power-of-two buckets.
+    ///
+    /// Bucket `i` counts the durations of at least `2**i` and less than
+    /// `2**(i+1)` microseconds, except that bucket 0 also counts durations
+    /// shorter than a microsecond, and the last bucket also counts all
+    /// longer durations.
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub struct Histogram {
+        buckets: [u64; BUCKETS],
+        count: u64,
+        total: Duration,
+    }
+
+    impl Histogram {
+        const fn new() -> Histogram {
+            Histogram {
+                buckets: [0; BUCKETS],
+                count: 0,
+                total: Duration::from_secs(0),
+            }
+        }
+
+        fn record(&mut self, duration: Duration) {
+            let micros = duration.as_micros().max(1);
+            let idx = (127 - micros.leading_zeros() as usize).min(BUCKETS - 1);
+            self.buckets[idx] += 1;
+            self.count += 1;
+            self.total += duration;
+        }
+
+        /// Returns the counts of the buckets.
+        pub fn buckets(&self) -> &[u64] {
+            &self.buckets
+        }
+
+        /// Returns the number of recorded durations.
+        pub fn count(&self) -> u64 {
+            self.count
+        }
+
+        /// Returns the sum of the recorded durations.
+        pub fn total(&self) -> Duration {
+            self.total
+        }
+
+        /// Returns the average of the recorded durations, or `None` if
+        /// nothing has been recorded.
+        pub fn mean(&self) -> Option<Duration> {
+            if self.count == 0 {
+                return None;
+            }
+            Some(Duration::from_nanos(
+                (self.total.as_nanos() / self.count as u128) as u64,
+            ))
+        }
+    }
+
+    /// Snapshot of the statistics returned by [`stats`].
+    ///
+    /// [`stats`]: fn.stats.html
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    #[non_exhaustive]
+    pub struct Stats {
+        /// Number of successfully started processes.
+        pub spawns: u64,
+
+        /// Number of times the child process could not be created, e.g.
+        /// because `fork()` failed.
+        pub fork_failures: u64,
+
+        /// Number of times the child process was created but could not
+        /// execute the program, e.g. because it wasn't found.  On Windows
+        /// this counts all failures of `CreateProcess`.
+        pub exec_failures: u64,
+
+        /// Time from the creation of the child process until it has
+        /// executed the program, for successful spawns.  Preparation done
+        /// before the process is created, such as opening the pipes and
+        /// files of the redirections, isn't included.
+        pub spawn_latency: Histogram,
+
+        /// Duration of calls to `Communicator::read()`, which also underlie
+        /// `Popen::communicate()` and the capturing methods of `Exec` and
+        /// `Pipeline`.
+        pub communicate_time: Histogram,
+
+        /// Total number of bytes written to and read from the subprocesses
+        /// by `Communicator::read()`.
+        pub communicate_bytes: u64,
+    }
+
+    impl Stats {
+        const fn new() -> Stats {
+            Stats {
+                spawns: 0,
+                fork_failures: 0,
+                exec_failures: 0,
+                spawn_latency: Histogram::new(),
+                communicate_time: Histogram::new(),
+                communicate_bytes: 0,
+            }
+        }
+
+        /// Returns the average throughput of communication in bytes per
+        /// second, or `None` if no time was spent communicating.
+        pub fn communicate_throughput(&self) -> Option<f64> {
+            let secs = self.communicate_time.total().as_secs_f64();
+            if secs == 0.0 {
+                return None;
+            }
+            Some(self.communicate_bytes as f64 / secs)
+        }
+    }
+
+    static STATS: Mutex<Stats> = Mutex::new(Stats::new());
+
+    fn update(f: impl FnOnce(&mut Stats)) {
+        let mut stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut stats);
+    }
+
+    /// Returns the statistics gathered since the start of the program, or
+    /// since the last call to [`reset_stats`].
+    ///
+    /// Only available with the `stats` feature.
+    ///
+    /// [`reset_stats`]: fn.reset_stats.html
+    pub fn stats() -> Stats {
+        STATS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Resets all statistics to zero.
+    ///
+    /// Only available with the `stats` feature.
+    pub fn reset_stats() {
+        update(|stats| *stats = Stats::new());
+    }
+
+    pub fn record_spawn(latency: Duration) {
+        update(|stats| {
+            stats.spawns += 1;
+            stats.spawn_latency.record(latency);
+        });
+    }
+
+    #[cfg(unix)]
+    pub fn record_fork_failure() {
+        update(|stats| stats.fork_failures += 1);
+    }
+
+    pub fn record_exec_failure() {
+        update(|stats| stats.exec_failures += 1);
+    }
+
+    pub fn record_communicate(bytes: u64, elapsed: Duration) {
+        update(|stats| {
+            stats.communicate_bytes += bytes;
+            stats.communicate_time.record(elapsed);
+        });
+    }
+}
+
+pub use self::imp::*;
@@ -0,0 +1,303 @@
+use std::future::{self, Future};
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_core::Stream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::tests::common::prog;
+use crate::{
+    AsyncCommunicator, AsyncPipeReader, Exec, ExitStatus, OnOverflow, OutputSource, PopenError,
+    Redirection,
+};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+async fn next(comm: &mut AsyncCommunicator<'_>) -> Option<io::Result<(OutputSource, Bytes)>> {
+    future::poll_fn(|cx| Pin::new(&mut *comm).poll_next(cx)).await
+}
+
+#[test]
+fn capture_async() {
+    let c = block_on(async {
+        Exec::cmd(prog("sort"))
+            .stdin("b\na\n")
+            .start_async()
+            .unwrap()
+            .capture()
+            .await
+    })
+    .unwrap();
+    assert_eq!(c.stdout_str(), "a\nb\n");
+    assert!(c.success());
+}
+
+#[test]
+fn pipeline_capture_async() {
+    let c = block_on(async {
+        (Exec::cmd(prog("printf")).arg("b\\na\\n") | Exec::cmd(prog("sort")))
+            .start_async()
+            .unwrap()
+            .capture()
+            .await
+    })
+    .unwrap();
+    assert_eq!(c.stdout_str(), "a\nb\n");
+}
+
+#[test]
+fn wait_async() {
+    let status = block_on(async {
+        let mut job = Exec::cmd(prog("sh"))
+            .args(["-c", "sleep 0.1; exit 3"])
+            .start_async()
+            .unwrap();
+        assert_eq!(job.try_wait().unwrap(), None);
+        job.wait().await
+    })
+    .unwrap();
+    assert_eq!(status, ExitStatus::Exited(3));
+}
+
+#[test]
+fn popen_wait_async() {
+    let status = block_on(async {
+        let mut p = Exec::cmd(prog("sh"))
+            .args(["-c", "sleep 0.1; exit 3"])
+            .popen()
+            .unwrap();
+        let status = p.wait_async().await.unwrap();
+        assert_eq!(p.exit_status(), Some(status));
+        // waiting again returns the same status
+        assert_eq!(p.wait_async().await.unwrap(), status);
+        status
+    });
+    assert_eq!(status, ExitStatus::Exited(3));
+}
+
+#[test]
+fn popen_wait_async_cancel() {
+    block_on(async {
+        let mut p = Exec::cmd(prog("sleep")).arg("5").popen().unwrap();
+        let waited = tokio::time::timeout(Duration::from_millis(100), p.wait_async()).await;
+        assert!(waited.is_err());
+        // dropping the future leaves the process running
+        assert!(p.poll().is_none());
+        p.kill().unwrap();
+        let start = std::time::Instant::now();
+        assert!(!p.wait_async().await.unwrap().success());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    });
+}
+
+#[test]
+fn communicate_async_twice() {
+    block_on(async {
+        let mut job = Exec::cmd(prog("true")).start_async().unwrap();
+        let (out, err) = job.communicate().await.unwrap();
+        assert_eq!(out, Some(vec![]));
+        assert_eq!(err, None);
+        match job.communicate().await {
+            Err(PopenError::LogicError(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn capture_async_max_output() {
+    let c = block_on(async {
+        Exec::cmd(prog("yes"))
+            .max_output(1000, OnOverflow::Kill)
+            .start_async()
+            .unwrap()
+            .capture()
+            .await
+    })
+    .unwrap();
+    assert_eq!(c.stdout.len(), 1000);
+    assert_eq!(c.overflow, Some(OnOverflow::Kill));
+}
+
+#[test]
+fn drop_async_job_without_blocking() {
+    let job = Exec::cmd(prog("sleep")).arg("5").start_async().unwrap();
+    let start = std::time::Instant::now();
+    drop(job);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn async_job_futures_are_send() {
+    fn assert_send<T: Send>(_: &T) {}
+    let mut job = Exec::cmd(prog("true")).start_async().unwrap();
+    assert_send(&job.communicate());
+    assert_send(&job.wait());
+    assert_send(&job.capture());
+}
+
+#[test]
+fn async_pipes() {
+    let out = block_on(async {
+        let mut job = Exec::cmd(prog("cat"))
+            .stdin(Redirection::Pipe)
+            .start_async()
+            .unwrap();
+        let mut stdin = job.take_stdin().unwrap().unwrap();
+        let mut stdout = job.take_stdout().unwrap().unwrap();
+        assert!(job.take_stdout().unwrap().is_none());
+        stdin.write_all(b"foo\nbar\n").await.unwrap();
+        stdin.shutdown().await.unwrap();
+        drop(stdin);
+        let mut out = String::new();
+        stdout.read_to_string(&mut out).await.unwrap();
+        assert!(job.wait().await.unwrap().success());
+        out
+    });
+    assert_eq!(out, "foo\nbar\n");
+}
+
+#[test]
+fn async_pipes_stdin_data() {
+    block_on(async {
+        let mut job = Exec::cmd(prog("cat")).stdin("foo").start_async().unwrap();
+        // the input data is fed by communicate()
+        assert!(job.take_stdin().unwrap().is_none());
+        let (out, _) = job.communicate().await.unwrap();
+        assert_eq!(out.unwrap(), b"foo");
+    });
+}
+
+#[test]
+fn async_pipe_reader_from_popen() {
+    let out = block_on(async {
+        let mut p = Exec::cmd(prog("printf"))
+            .arg("foo")
+            .stdout(Redirection::Pipe)
+            .popen()
+            .unwrap();
+        let mut stdout = AsyncPipeReader::from_file(p.stdout.take().unwrap()).unwrap();
+        let mut out = vec![];
+        stdout.read_to_end(&mut out).await.unwrap();
+        p.wait().unwrap();
+        out
+    });
+    assert_eq!(out, b"foo");
+}
+
+#[test]
+fn communicate_stream() {
+    let (out, err) = block_on(async {
+        let mut job = Exec::cmd(prog("sh"))
+            .args(["-c", "cat; printf err >&2"])
+            .stdin("foo")
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .start_async()
+            .unwrap();
+        let (mut out, mut err) = (vec![], vec![]);
+        let mut comm = job.communicate_stream().unwrap();
+        while let Some(chunk) = next(&mut comm).await {
+            match chunk.unwrap() {
+                (OutputSource::Stdout, data) => out.extend_from_slice(&data),
+                (OutputSource::Stderr, data) => err.extend_from_slice(&data),
+            }
+        }
+        drop(comm);
+        assert!(job.wait().await.unwrap().success());
+        (out, err)
+    });
+    assert_eq!(out, b"foo");
+    assert_eq!(err, b"err");
+}
+
+#[test]
+fn communicate_stream_limit_size() {
+    let sizes = block_on(async {
+        let mut job = Exec::cmd(prog("printf"))
+            .args(["%100s", ""])
+            .start_async()
+            .unwrap();
+        let mut comm = job.communicate_stream().unwrap().limit_size(10);
+        let mut sizes = vec![];
+        while let Some(chunk) = next(&mut comm).await {
+            sizes.push(chunk.unwrap().1.len());
+        }
+        sizes
+    });
+    assert!(sizes.iter().all(|&size| size <= 10));
+    assert_eq!(sizes.iter().sum::<usize>(), 100);
+}
+
+#[test]
+fn communicate_stream_limit_time() {
+    block_on(async {
+        let mut job = Exec::cmd(prog("sh"))
+            .args(["-c", "printf foo; sleep 0.5; printf bar"])
+            .start_async()
+            .unwrap();
+        let mut comm = job
+            .communicate_stream()
+            .unwrap()
+            .limit_time(Duration::from_millis(100));
+        let (_, data) = next(&mut comm).await.unwrap().unwrap();
+        assert_eq!(&data[..], b"foo");
+        let err = next(&mut comm).await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        // communication resumes after the timeout
+        let mut rest = vec![];
+        while let Some(chunk) = next(&mut comm).await {
+            match chunk {
+                Ok((_, data)) => rest.extend_from_slice(&data),
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            }
+        }
+        assert_eq!(rest, b"bar");
+    });
+}
+
+#[test]
+fn communicate_stream_max_output() {
+    let (total, overflow) = block_on(async {
+        let mut job = Exec::cmd(prog("yes"))
+            .max_output(1000, OnOverflow::Error)
+            .start_async()
+            .unwrap();
+        let mut comm = job.communicate_stream().unwrap();
+        let mut total = 0;
+        loop {
+            match next(&mut comm).await.unwrap() {
+                Ok((_, data)) => total += data.len(),
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::Other);
+                    break;
+                }
+            }
+        }
+        assert!(next(&mut comm).await.is_none());
+        drop(comm);
+        (total, job.overflow())
+    });
+    assert_eq!(total, 1000);
+    assert_eq!(overflow, Some(OnOverflow::Error));
+}
+
+#[test]
+fn communicate_stream_after_communicate() {
+    block_on(async {
+        let mut job = Exec::cmd(prog("true")).start_async().unwrap();
+        job.communicate().await.unwrap();
+        match job.communicate_stream() {
+            Err(PopenError::LogicError(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    });
+}
@@ -0,0 +1,62 @@
+use std::time::{Duration, Instant};
+
+use async_io::block_on;
+
+use crate::tests::common::prog;
+use crate::{Exec, ExitStatus, Redirection, SmolRuntime};
+
+#[test]
+fn capture() {
+    let c = block_on(async {
+        Exec::cmd(prog("sort"))
+            .stdin("b\na\n")
+            .start_async_on::<SmolRuntime>()?
+            .capture()
+            .await
+    })
+    .unwrap();
+    assert_eq!(c.stdout_str(), "a\nb\n");
+    assert!(c.success());
+}
+
+#[test]
+fn communicate_stderr() {
+    let (out, err) = block_on(async {
+        let mut job = Exec::cmd(prog("sh"))
+            .args(["-c", "echo foo; echo bar >&2"])
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .start_async_on::<SmolRuntime>()?;
+        job.communicate().await
+    })
+    .unwrap();
+    assert_eq!(out.unwrap(), b"foo\n");
+    assert_eq!(err.unwrap(), b"bar\n");
+}
+
+#[test]
+fn wait() {
+    let start = Instant::now();
+    let status = block_on(async {
+        let mut job = Exec::cmd(prog("sleep"))
+            .arg("0.2")
+            .start_async_on::<SmolRuntime>()?;
+        assert!(job.try_wait()?.is_none());
+        job.wait().await
+    })
+    .unwrap();
+    assert_eq!(status, ExitStatus::Exited(0));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn pipeline_capture() {
+    let c = block_on(async {
+        (Exec::cmd(prog("printf")).arg("b\\na\\n") | Exec::cmd(prog("sort")))
+            .start_async_on::<SmolRuntime>()?
+            .capture()
+            .await
+    })
+    .unwrap();
+    assert_eq!(c.stdout_str(), "a\nb\n");
+}
@@ -1,27 +1,135 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::env;
+use std::ffi::OsString;
 use std::fs::File;
 use std::sync::Mutex;
 
 use std::io::prelude::*;
 use std::sync::MutexGuard;
+use std::time::{Duration, Instant};
 
-use crate::{Exec, ExitStatus, NullFile, Redirection};
+use crate::{
+    CancelToken, Exec, ExitStatus, NullFile, OnOverflow, OnStartFailure, OnTimeout, OutputSource,
+    Pipeline, Popen, PopenError, Redirection,
+};
 
 use lazy_static::lazy_static;
 use tempfile::TempDir;
 
-use crate::tests::common::read_whole_file;
+use crate::tests::common::{prog, read_whole_file};
 
 #[test]
 fn exec_join() {
-    let status = Exec::cmd("true").join().unwrap();
+    let status = Exec::cmd(prog("true")).join().unwrap();
     assert_eq!(status, ExitStatus::Exited(0));
 }
 
+#[test]
+fn exec_status() {
+    let status = Exec::cmd(prog("sh"))
+        .args(["-c", "exit 3"])
+        .status()
+        .unwrap();
+    assert_eq!(status, ExitStatus::Exited(3));
+}
+
+#[test]
+fn exec_output() {
+    let c = Exec::cmd(prog("sh"))
+        .args(["-c", "cat; echo err >&2; exit 1"])
+        .stdin("in")
+        .output()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "in");
+    assert_eq!(c.stderr_str(), "err\n");
+    assert_eq!(c.exit_status, ExitStatus::Exited(1));
+}
+
+#[test]
+fn exec_output_redirected() {
+    let c = Exec::cmd(prog("sh"))
+        .args(["-c", "echo out; echo err >&2"])
+        .stderr(Redirection::Merge)
+        .output()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "out\nerr\n");
+    assert_eq!(c.stderr_str(), "");
+}
+
+fn sh(script: &str) -> Exec {
+    Exec::cmd(prog("sh")).args(["-c", script])
+}
+
+#[test]
+fn race_first_success() {
+    let start = Instant::now();
+    let c = sh("sleep 5; echo slow")
+        .race(vec![sh("exit 1"), sh("sleep 0.2; echo fast")])
+        .unwrap();
+    assert_eq!(c.stdout_str(), "fast\n");
+    assert!(c.success());
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn race_none_succeeds() {
+    let c = sh("exit 1").race(vec![sh("sleep 0.2; exit 2")]).unwrap();
+    assert_eq!(c.exit_status, ExitStatus::Exited(2));
+}
+
+#[test]
+fn race_by() {
+    let c = sh("sleep 0.2; echo late")
+        .race_by(vec![sh("echo early; exit 1")], |c| !c.stdout.is_empty())
+        .unwrap();
+    assert_eq!(c.stdout_str(), "early\n");
+    assert_eq!(c.exit_status, ExitStatus::Exited(1));
+}
+
+#[test]
+fn race_decided_on_exit() {
+    // closing the output doesn't make a command finish the race
+    let start = Instant::now();
+    let c = sh("exec >&-; sleep 5")
+        .race(vec![sh("sleep 0.2; echo fast")])
+        .unwrap();
+    assert_eq!(c.stdout_str(), "fast\n");
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn stdin_lines() {
+    let items = vec!["foo".to_string(), "bar".to_string(), "".to_string()];
+    let lines = Exec::cmd(prog("cat"))
+        .stdin_lines(items)
+        .capture_lines()
+        .unwrap();
+    assert_eq!(lines, ["foo", "bar", ""]);
+}
+
+#[test]
+fn stdin_lines_lazy() {
+    // the lines are produced only as the child reads them, so an endless
+    // sequence is fine as long as the child stops reading
+    let items = (0..).map(|n| n.to_string());
+    let lines = Exec::cmd(prog("head"))
+        .args(["-n", "3"])
+        .stdin_lines(items)
+        .capture_lines()
+        .unwrap();
+    assert_eq!(lines, ["0", "1", "2"]);
+}
+
+#[test]
+#[should_panic]
+fn stdin_lines_clone() {
+    let _ = Exec::cmd(prog("cat")).stdin_lines(vec![]).clone();
+}
+
 #[test]
 fn null_file() {
-    let mut p = Exec::cmd("cat")
+    let mut p = Exec::cmd(prog("cat"))
         .stdin(NullFile)
         .stdout(Redirection::Pipe)
         .popen()
@@ -30,18 +138,225 @@ fn null_file() {
     assert_eq!(out.unwrap(), "");
 }
 
+#[test]
+#[cfg(not(feature = "no-shell"))]
+fn null_shortcuts() {
+    let c = Exec::shell("cat; echo foo; echo bar >&2")
+        .stdin_null()
+        .stdout_null()
+        .stderr(Redirection::Pipe)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "");
+    assert_eq!(c.stderr_str(), "bar\n");
+}
+
+#[test]
+fn stdout_shared() {
+    use std::fs::OpenOptions;
+    use std::sync::Arc;
+    use std::thread;
+
+    let tmpdir = TempDir::new().unwrap();
+    let log_path = tmpdir.path().join("log");
+    let log = Arc::new(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .unwrap(),
+    );
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let log = Arc::clone(&log);
+            thread::spawn(move || {
+                Exec::cmd(prog("echo"))
+                    .arg(format!("line {}", i))
+                    .stdout_shared(log)
+                    .join()
+                    .unwrap()
+            })
+        })
+        .collect();
+    for t in threads {
+        assert!(t.join().unwrap().success());
+    }
+    let mut lines: Vec<_> = std::fs::read_to_string(&log_path)
+        .unwrap()
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    lines.sort();
+    assert_eq!(lines, ["line 0", "line 1", "line 2", "line 3"]);
+}
+
+#[test]
+fn stdout_path_append() {
+    let tmpdir = TempDir::new().unwrap();
+    let log = tmpdir.path().join("log");
+    for word in &["foo", "bar"] {
+        Exec::cmd(prog("echo"))
+            .arg(word)
+            .stdout(log.as_path())
+            .join()
+            .unwrap();
+    }
+    assert_eq!(read_whole_file(File::open(&log).unwrap()), "foo\nbar\n");
+    Exec::cmd(prog("echo"))
+        .arg("baz")
+        .stdout(log.clone())
+        .append(false)
+        .join()
+        .unwrap();
+    assert_eq!(read_whole_file(File::open(&log).unwrap()), "baz\n");
+}
+
+#[test]
+fn reopen_output() {
+    // the output reaches the file through a background thread
+    fn wait_for_contents(path: &std::path::Path, contents: &str) {
+        let start = Instant::now();
+        while std::fs::read_to_string(path).unwrap_or_default() != contents {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    let tmpdir = TempDir::new().unwrap();
+    let log = tmpdir.path().join("log");
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "echo one; read x; echo two >&2"])
+        .stdin(Redirection::Pipe)
+        .stdout(log.as_path())
+        .stderr(Redirection::Merge)
+        .reopenable_output(true)
+        .popen()
+        .unwrap();
+    wait_for_contents(&log, "one\n");
+
+    let rotated = tmpdir.path().join("log.1");
+    std::fs::rename(&log, &rotated).unwrap();
+    p.reopen_output(&log).unwrap();
+    let err = p.reopen_output(tmpdir.path().join("other")).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    p.stdin.take().unwrap().write_all(b"\n").unwrap();
+    assert!(p.wait().unwrap().success());
+    wait_for_contents(&log, "two\n");
+    assert_eq!(read_whole_file(File::open(&rotated).unwrap()), "one\n");
+}
+
 #[test]
 fn stream_stdout() {
-    let stream = Exec::cmd("printf").arg("foo").stream_stdout().unwrap();
+    let stream = Exec::cmd(prog("printf"))
+        .arg("foo")
+        .stream_stdout()
+        .unwrap();
     assert_eq!(read_whole_file(stream), "foo");
 }
 
 #[test]
-fn stream_stderr() {
-    let stream = Exec::cmd("sh")
+fn exec_current_exe() {
+    let exec = Exec::current_exe().unwrap();
+    assert_eq!(exec.to_cmdline_lossy(), {
+        let exe = env::current_exe().unwrap();
+        Exec::cmd(exe).to_cmdline_lossy()
+    });
+    // the test harness lists its tests
+    let c = exec.arg("--list").capture().unwrap();
+    assert!(c.stdout_str().contains("exec_current_exe"));
+}
+
+#[test]
+fn exec_current_args() {
+    let expected: Vec<OsString> = env::args_os().skip(1).collect();
+    let exec = Exec::cmd(prog("true")).current_args();
+    assert_eq!(
+        exec.to_cmdline_lossy(),
+        Exec::cmd(prog("true")).args(&expected).to_cmdline_lossy()
+    );
+}
+
+#[test]
+fn output_ring_small() {
+    let (stdout, mut ring) = crate::output_ring(7).unwrap();
+    assert_eq!(ring.capacity(), 7);
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "for i in 1 2 3 4 5 6 7 8 9; do echo line$i; done"])
+        .stdout(stdout)
+        .popen()
+        .unwrap();
+    let mut out = String::new();
+    ring.read_to_string(&mut out).unwrap();
+    assert_eq!(out.lines().count(), 9);
+    assert!(out.ends_with("line9\n"));
+    assert!(ring.is_finished());
+    assert_eq!(ring.try_read(&mut [0; 4]), 0);
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn output_ring_reader_dropped() {
+    let (stdout, ring) = crate::output_ring(16).unwrap();
+    let mut p = Exec::cmd(prog("yes")).stdout(stdout).popen().unwrap();
+    drop(ring);
+    // yes is terminated by SIGPIPE or a write error once nobody reads
+    assert!(!p.wait().unwrap().success());
+}
+
+#[test]
+fn stream_stdout_finish() {
+    let mut stream = Exec::cmd(prog("sh"))
+        .args(["-c", "printf foo; exit 3"])
+        .stream_stdout()
+        .unwrap();
+    let mut out = String::new();
+    stream.read_to_string(&mut out).unwrap();
+    assert_eq!(out, "foo");
+    assert_eq!(stream.finish().unwrap(), ExitStatus::Exited(3));
+}
+
+#[test]
+fn stream_stderr_finish() {
+    let mut stream = Exec::cmd(prog("sh"))
         .args(&["-c", "printf foo >&2"])
         .stream_stderr()
         .unwrap();
+    let mut err = String::new();
+    stream.read_to_string(&mut err).unwrap();
+    assert_eq!(err, "foo");
+    assert!(stream.finish().unwrap().success());
+}
+
+#[test]
+fn stream_stdout_with_err() {
+    let (stream, outcome) = Exec::cmd(prog("sh"))
+        .args(["-c", "printf foo; printf bar >&2; exit 3"])
+        .stream_stdout_with_err()
+        .unwrap();
+    assert_eq!(outcome.exit_status(), None);
+    assert_eq!(read_whole_file(stream), "foo");
+    assert_eq!(outcome.exit_status(), Some(ExitStatus::Exited(3)));
+    assert_eq!(outcome.stderr_str().unwrap(), "bar");
+}
+
+#[test]
+fn stream_stdout_with_err_drop() {
+    let (stream, outcome) = Exec::cmd(prog("sh"))
+        .args(["-c", "echo foo >&2; exec yes"])
+        .stream_stdout_with_err()
+        .unwrap();
+    drop(stream);
+    assert!(!outcome.exit_status().unwrap().success());
+    assert_eq!(outcome.stderr().unwrap(), b"foo\n");
+}
+
+#[test]
+fn stream_stderr() {
+    let stream = Exec::cmd(prog("sh"))
+        .args(["-c", "printf foo >&2"])
+        .stream_stderr()
+        .unwrap();
     assert_eq!(read_whole_file(stream), "foo");
 }
 
@@ -50,7 +365,7 @@ fn stream_stdin() {
     let tmpdir = TempDir::new().unwrap();
     let tmpname = tmpdir.path().join("output");
     {
-        let mut stream = Exec::cmd("cat")
+        let mut stream = Exec::cmd(prog("cat"))
             .stdout(File::create(&tmpname).unwrap())
             .stream_stdin()
             .unwrap();
@@ -61,25 +376,85 @@ fn stream_stdin() {
 
 #[test]
 fn communicate_out() {
-    let mut comm = Exec::cmd("printf").arg("foo").communicate().unwrap();
+    let mut comm = Exec::cmd(prog("printf")).arg("foo").communicate().unwrap();
     assert_eq!(comm.read().unwrap(), (Some(b"foo".to_vec()), None));
 }
 
 #[test]
 fn communicate_in_out() {
-    let mut comm = Exec::cmd("cat").stdin("foo").communicate().unwrap();
+    let mut comm = Exec::cmd(prog("cat")).stdin("foo").communicate().unwrap();
     assert_eq!(comm.read().unwrap(), (Some(b"foo".to_vec()), None));
 }
 
+#[test]
+fn communicate_with_input() {
+    let mut comm = Exec::cmd(prog("cat"))
+        .communicate_with()
+        .input("foo")
+        .limit_size(2)
+        .start()
+        .unwrap();
+    assert_eq!(comm.read().unwrap(), (Some(b"fo".to_vec()), None));
+    assert_eq!(comm.read().unwrap(), (Some(b"o".to_vec()), None));
+}
+
+#[test]
+fn communicate_with_idle_timeout_kill() {
+    let start = Instant::now();
+    let mut comm = Exec::cmd(prog("sh"))
+        .args(["-c", "echo foo; exec sleep 5"])
+        .communicate_with()
+        .limit_time(Duration::from_secs(4))
+        .idle_timeout(Duration::from_millis(100))
+        .on_timeout(OnTimeout::Kill)
+        .start()
+        .unwrap();
+    let err = comm.read().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(err.capture, (Some(b"foo\n".to_vec()), None));
+    // the process was killed, so its output is at EOF
+    assert_eq!(comm.read().unwrap(), (Some(vec![]), None));
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
+
 #[test]
 fn capture_out() {
-    let c = Exec::cmd("printf").arg("foo").capture().unwrap();
+    let c = Exec::cmd(prog("printf")).arg("foo").capture().unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+}
+
+#[test]
+fn capture_transcript() {
+    let t = Exec::cmd(prog("sh"))
+        .args([
+            "-c",
+            "printf a; sleep 0.1; printf b >&2; sleep 0.1; printf c",
+        ])
+        .capture_transcript()
+        .unwrap();
+    assert!(t.success());
+    assert_eq!(
+        t.records,
+        vec![
+            (OutputSource::Stdout, b"a".to_vec()),
+            (OutputSource::Stderr, b"b".to_vec()),
+            (OutputSource::Stdout, b"c".to_vec()),
+        ]
+    );
+    assert_eq!(t.stdout(), b"ac");
+    assert_eq!(t.stderr(), b"b");
+}
+
+#[test]
+fn capture_alias() {
+    let c: crate::Capture = Exec::cmd(prog("printf")).arg("foo").capture().unwrap();
+    let c: crate::CaptureData = c;
     assert_eq!(c.stdout_str(), "foo");
 }
 
 #[test]
 fn capture_err() {
-    let c = Exec::cmd("sh")
+    let c = Exec::cmd(prog("sh"))
         .arg("-c")
         .arg("printf foo >&2")
         .stderr(Redirection::Pipe)
@@ -90,35 +465,252 @@ fn capture_err() {
 
 #[test]
 fn capture_out_with_input_data1() {
-    let c = Exec::cmd("cat").stdin("foo").capture().unwrap();
+    let c = Exec::cmd(prog("cat")).stdin("foo").capture().unwrap();
     assert_eq!(c.stdout_str(), "foo");
 }
 
 #[test]
 fn capture_out_with_input_data2() {
-    let c = Exec::cmd("cat").stdin(b"foo".to_vec()).capture().unwrap();
+    let c = Exec::cmd(prog("cat"))
+        .stdin(b"foo".to_vec())
+        .capture()
+        .unwrap();
     assert_eq!(c.stdout_str(), "foo");
 }
 
 #[test]
+#[cfg(not(feature = "no-shell"))]
 fn exec_shell() {
     let stream = Exec::shell("printf foo").stream_stdout().unwrap();
     assert_eq!(read_whole_file(stream), "foo");
 }
 
+#[test]
+fn exec_join_timeout() {
+    let status = Exec::cmd(prog("true"))
+        .join_timeout(Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(status, Some(ExitStatus::Exited(0)));
+
+    let start = Instant::now();
+    let status = Exec::cmd(prog("sleep"))
+        .arg("5")
+        .join_timeout_kill(Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(status, None);
+    assert!(start.elapsed() < Duration::from_secs(3));
+
+    let start = Instant::now();
+    let status = Exec::cmd(prog("sleep"))
+        .arg("0.5")
+        .join_timeout(Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(status, None);
+    assert!(start.elapsed() < Duration::from_millis(400));
+}
+
+#[test]
+#[cfg(not(feature = "no-shell"))]
+fn exec_max_output_truncate() {
+    let c = Exec::shell("yes | head -c 100000")
+        .max_output(1000, OnOverflow::Truncate)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout.len(), 1000);
+    assert_eq!(c.overflow, Some(OnOverflow::Truncate));
+    assert!(c.success());
+
+    // output of exactly the limit is not an overflow
+    let c = Exec::cmd(prog("printf"))
+        .arg("abc")
+        .max_output(3, OnOverflow::Error)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "abc");
+    assert_eq!(c.overflow, None);
+}
+
+#[test]
+fn exec_max_output_kill() {
+    let c = Exec::cmd(prog("yes"))
+        .max_output(1000, OnOverflow::Kill)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout.len(), 1000);
+    assert_eq!(c.overflow, Some(OnOverflow::Kill));
+    assert!(!c.success());
+
+    let mut comm = Exec::cmd(prog("yes"))
+        .max_output(10, OnOverflow::Kill)
+        .communicate()
+        .unwrap();
+    let (out, _) = comm.read().unwrap();
+    assert_eq!(out.unwrap(), b"y\ny\ny\ny\ny\n");
+    assert_eq!(comm.overflow(), Some(OnOverflow::Kill));
+
+    let c = { Exec::cmd(prog("yes")) | Exec::cmd(prog("cat")) }
+        .max_output(100, OnOverflow::Kill)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout.len(), 100);
+    assert_eq!(c.overflow, Some(OnOverflow::Kill));
+}
+
+#[test]
+fn exec_max_output_error() {
+    match Exec::cmd(prog("yes"))
+        .max_output(1000, OnOverflow::Error)
+        .capture()
+    {
+        Err(PopenError::IoError(e)) => assert_eq!(e.kind(), std::io::ErrorKind::Other),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
 #[test]
 fn pipeline_open() {
-    let mut processes = { Exec::cmd("echo").arg("foo\nbar") | Exec::cmd("wc").arg("-l") }
+    let mut processes =
+        { Exec::cmd(prog("echo")).arg("foo\nbar") | Exec::cmd(prog("wc")).arg("-l") }
+            .stdout(Redirection::Pipe)
+            .popen()
+            .unwrap();
+    let (output, _) = processes[1].communicate(None).unwrap();
+    assert_eq!(output.unwrap().trim(), "2");
+}
+
+#[test]
+fn exec_popen_n() {
+    let mut workers = Exec::cmd(prog("cat"))
+        .stdin("foo")
         .stdout(Redirection::Pipe)
+        .popen_n(3)
+        .unwrap();
+    assert_eq!(workers.len(), 3);
+    for p in &mut workers {
+        let (out, _) = p.communicate(None).unwrap();
+        assert_eq!(out.unwrap(), "foo");
+    }
+    let statuses = Popen::wait_all(&mut workers).unwrap();
+    assert!(statuses.iter().all(|s| s.success()));
+}
+
+#[test]
+fn exec_popen_n_terminate_all() {
+    let mut workers = Exec::cmd(prog("sleep")).arg("5").popen_n(2).unwrap();
+    Popen::terminate_all(&mut workers).unwrap();
+    let statuses = Popen::wait_all(&mut workers).unwrap();
+    assert!(statuses.iter().all(|s| !s.success()));
+}
+
+#[test]
+fn pipeline_detach_all() {
+    let mut processes = (Exec::cmd(prog("sleep")).arg("1") | Exec::cmd(prog("sleep")).arg("1"))
         .popen()
         .unwrap();
-    let (output, _) = processes[1].communicate(None).unwrap();
-    assert_eq!(output.unwrap().trim(), "2");
+    Popen::detach_all(&mut processes);
+    let start = Instant::now();
+    drop(processes);
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    let mut processes = (Exec::cmd(prog("sleep")).arg("1").detached()
+        | Exec::cmd(prog("sleep")).arg("1").detached())
+    .popen()
+    .unwrap();
+    Popen::attach_all(&mut processes);
+    let start = Instant::now();
+    drop(processes);
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
+
+#[test]
+#[cfg(not(feature = "no-path-search"))]
+fn exec_fallback() {
+    let exec = Exec::cmd("nosuchcommand1")
+        .arg("x")
+        .fallback(Exec::cmd("nosuchcommand2"))
+        .fallback(Exec::cmd(prog("echo")).arg("fallback"))
+        .stdout(Redirection::Pipe);
+    let mut p = exec.clone().popen().unwrap();
+    assert_eq!(p.program(), Some("echo".as_ref()));
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "fallback\n");
+    assert_eq!(exec.capture().unwrap().stdout_str(), "fallback\n");
+
+    // a program that exists but can't be executed is not skipped
+    let tmpdir = TempDir::new().unwrap();
+    let script = tmpdir.path().join("script");
+    File::create(&script).unwrap();
+    match Exec::cmd(&script).fallback(Exec::cmd(prog("true"))).join() {
+        Err(PopenError::IoError(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied)
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn pipeline_start_failure() {
+    let start = Instant::now();
+    let err = (Exec::cmd(prog("sleep")).arg("5") | Exec::cmd("./nosuchcommand").arg("x"))
+        .popen()
+        .unwrap_err();
+    // the started command is terminated and reaped rather than waited for
+    assert!(start.elapsed() < Duration::from_secs(3));
+    match err {
+        PopenError::PipelineStart {
+            index,
+            ref command,
+            ref error,
+        } => {
+            assert_eq!(index, 1);
+            assert_eq!(command, "./nosuchcommand x");
+            assert!(matches!(**error, PopenError::IoError(_)));
+        }
+        ref other => panic!("unexpected {:?}", other),
+    }
+    assert!(err.to_string().starts_with("command 2 of the pipeline"));
+}
+
+#[test]
+fn pipeline_start_failure_detach() {
+    let tmpdir = TempDir::new().unwrap();
+    let marker = tmpdir.path().join("marker");
+    let script = format!("sleep 0.2; touch {}", marker.display());
+    let start = Instant::now();
+    let err = (sh(&script) | Exec::cmd("nosuchcommand"))
+        .on_start_failure(OnStartFailure::Detach)
+        .popen()
+        .unwrap_err();
+    assert!(matches!(err, PopenError::PipelineStart { index: 1, .. }));
+    assert!(start.elapsed() < Duration::from_millis(200));
+    // the started command keeps running
+    while !marker.exists() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn pipeline_start_failure_detached_command() {
+    let tmpdir = TempDir::new().unwrap();
+    let marker = tmpdir.path().join("marker");
+    let script = format!("sleep 0.2; touch {}", marker.display());
+    let start = Instant::now();
+    let err = (sh(&script).detached() | Exec::cmd("nosuchcommand"))
+        .popen()
+        .unwrap_err();
+    assert!(matches!(err, PopenError::PipelineStart { index: 1, .. }));
+    assert!(start.elapsed() < Duration::from_millis(200));
+    // the detached command is not terminated
+    while !marker.exists() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
 }
 
 #[test]
 fn pipeline_stream_out() {
-    let stream = { Exec::cmd("echo").arg("foo\nbar") | Exec::cmd("wc").arg("-l") }
+    let stream = { Exec::cmd(prog("echo")).arg("foo\nbar") | Exec::cmd(prog("wc")).arg("-l") }
         .stream_stdout()
         .unwrap();
     assert_eq!(read_whole_file(stream).trim(), "2");
@@ -129,7 +721,7 @@ fn pipeline_stream_in() {
     let tmpdir = TempDir::new().unwrap();
     let tmpname = tmpdir.path().join("output");
     {
-        let mut stream = { Exec::cmd("cat") | Exec::cmd("wc").arg("-l") }
+        let mut stream = { Exec::cmd(prog("cat")) | Exec::cmd(prog("wc")).arg("-l") }
             .stdout(File::create(&tmpname).unwrap())
             .stream_stdin()
             .unwrap();
@@ -140,8 +732,8 @@ fn pipeline_stream_in() {
 
 #[test]
 fn pipeline_compose_pipelines() {
-    let pipe1 = Exec::cmd("echo").arg("foo\nbar\nfoo") | Exec::cmd("sort");
-    let pipe2 = Exec::cmd("uniq") | Exec::cmd("wc").arg("-l");
+    let pipe1 = Exec::cmd(prog("echo")).arg("foo\nbar\nfoo") | Exec::cmd(prog("sort"));
+    let pipe2 = Exec::cmd(prog("uniq")) | Exec::cmd(prog("wc")).arg("-l");
     let pipe = pipe1 | pipe2;
     let stream = pipe.stream_stdout().unwrap();
     assert_eq!(read_whole_file(stream).trim(), "2");
@@ -171,7 +763,7 @@ impl Crlf for Vec<u8> {
 
 #[test]
 fn pipeline_communicate_out() {
-    let pipe1 = Exec::cmd("echo").arg("foo\nbar\nfoo") | Exec::cmd("sort");
+    let pipe1 = Exec::cmd(prog("echo")).arg("foo\nbar\nfoo") | Exec::cmd(prog("sort"));
     let mut comm = pipe1.communicate().unwrap();
     assert_eq!(
         comm.read().unwrap(),
@@ -181,15 +773,16 @@ fn pipeline_communicate_out() {
 
 #[test]
 fn pipeline_communicate_in_out() {
-    let pipe1 = Exec::cmd("grep").arg("foo") | Exec::cmd("sort");
+    let pipe1 = Exec::cmd(prog("grep")).arg("foo") | Exec::cmd(prog("sort"));
     let mut comm = pipe1.stdin("foobar\nbaz\nfoo\n").communicate().unwrap();
     let (out, _err) = comm.read().unwrap();
     assert_eq!(out, Some(b"foo\nfoobar\n".to_vec().to_crlf()));
 }
 
 #[test]
+#[cfg(not(feature = "no-shell"))]
 fn pipeline_capture() {
-    let c = { Exec::cmd("cat") | Exec::shell("wc -l") }
+    let c = { Exec::cmd(prog("cat")) | Exec::shell("wc -l") }
         .stdin("foo\nbar\nbaz\n")
         .capture()
         .unwrap();
@@ -198,9 +791,72 @@ fn pipeline_capture() {
 }
 
 #[test]
+fn pipeline_capture_transcript() {
+    let t = {
+        Exec::cmd(prog("sh")).args(["-c", "printf a >&2; sleep 0.1; echo x"])
+            | Exec::cmd(prog("sh")).args(["-c", "cat; sleep 0.1; printf b >&2"])
+    }
+    .capture_transcript()
+    .unwrap();
+    assert_eq!(
+        t.records,
+        vec![
+            (OutputSource::Stderr, b"a".to_vec()),
+            (OutputSource::Stdout, b"x\n".to_vec()),
+            (OutputSource::Stderr, b"b".to_vec()),
+        ]
+    );
+}
+
+#[test]
+#[cfg(not(feature = "no-shell"))]
+fn pipeline_null_shortcuts() {
+    let c = { Exec::shell("cat; echo foo >&2") | Exec::shell("cat; echo bar >&2") }
+        .stdin_null()
+        .stderr_null()
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "");
+    assert_eq!(c.stderr_str(), "");
+
+    let c = { Exec::shell("echo foo") | Exec::cmd(prog("cat")) }
+        .stdout_null()
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "");
+    assert!(c.success());
+}
+
+#[test]
+fn pipeline_single_command() {
+    let c = Pipeline::from_exec_iter(vec![Exec::cmd(prog("cat"))])
+        .stdin("foo")
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+    assert!(c.success());
+}
+
+#[test]
+fn pipeline_empty() {
+    let empty = Pipeline::from_exec_iter(vec![]);
+    match empty.join() {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+    let folded = ["foo\n", "bar\n"]
+        .iter()
+        .fold(Pipeline::from_exec_iter(vec![]), |p, word| {
+            p | Exec::cmd(prog("printf")).arg(word)
+        });
+    assert_eq!(folded.capture().unwrap().stdout_str(), "bar\n");
+}
+
+#[test]
+#[cfg(not(feature = "no-shell"))]
 fn pipeline_capture_error_1() {
     let c = {
-        Exec::cmd("sh")
+        Exec::cmd(prog("sh"))
             .arg("-c")
             .arg("echo foo >&2; printf 'bar\nbaz\n'")
             | Exec::shell("wc -l")
@@ -212,13 +868,14 @@ fn pipeline_capture_error_1() {
 }
 
 #[test]
+#[cfg(not(feature = "no-shell"))]
 fn pipeline_capture_error_2() {
     let c = {
-        Exec::cmd("cat")
-            | Exec::cmd("sh")
+        Exec::cmd(prog("cat"))
+            | Exec::cmd(prog("sh"))
                 .arg("-c")
                 .arg("cat; echo foo >&2; printf 'four\nfive\n'")
-            | Exec::cmd("sh").arg("-c").arg("echo bar >&2; cat")
+            | Exec::cmd(prog("sh")).arg("-c").arg("echo bar >&2; cat")
             | Exec::shell("wc -l")
     }
     .stdin("one\ntwo\nthree\n")
@@ -232,63 +889,320 @@ fn pipeline_capture_error_2() {
     );
 }
 
+#[test]
+#[cfg(not(feature = "no-shell"))]
+fn pipeline_stderr_all_pipe() {
+    let mut procs = { Exec::shell("echo foo >&2") | Exec::shell("sleep 0.1; echo bar >&2") }
+        .stderr_all(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let mut err = String::new();
+    procs[1]
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut err)
+        .unwrap();
+    assert_eq!(err, "foo\nbar\n");
+    assert!(procs[0].stderr.is_none());
+    for p in &mut procs {
+        assert!(p.wait().unwrap().success());
+    }
+}
+
+#[test]
+#[cfg(not(feature = "no-shell"))]
+fn pipeline_stderr_all_merge() {
+    let c = { Exec::shell("echo foo >&2; echo one") | Exec::shell("cat; echo bar >&2") }
+        .stderr_all(Redirection::Merge)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo\none\nbar\n");
+    assert_eq!(c.stderr_str(), "");
+}
+
+#[test]
+#[cfg(not(feature = "no-shell"))]
+fn pipeline_stderr_all_joined() {
+    // appended commands are covered by stderr_all
+    let c = { Exec::shell("echo foo >&2") | Exec::cmd(prog("cat")) }.stderr_all(Redirection::Merge)
+        | Exec::shell("cat; echo bar >&2");
+    let c = c.capture().unwrap();
+    assert_eq!(c.stdout_str(), "foo\nbar\n");
+    assert_eq!(c.stderr_str(), "");
+
+    // the stderr_all of the right-hand pipeline is kept
+    let c = { Exec::shell("echo foo >&2") | Exec::cmd(prog("cat")) } | {
+        Exec::cmd(prog("cat")) | Exec::shell("cat; echo bar >&2")
+    }
+    .stderr_all(Redirection::Merge);
+    let c = c.capture().unwrap();
+    assert_eq!(c.stdout_str(), "foo\nbar\n");
+    assert_eq!(c.stderr_str(), "");
+}
+
+#[test]
+#[cfg(not(feature = "no-shell"))]
+fn pipeline_stderr_last() {
+    let c = { Exec::cmd(prog("true")) | Exec::shell("echo bar >&2") }
+        .stderr(Redirection::Pipe)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stderr_str(), "bar\n");
+
+    let c = { Exec::shell("echo foo >&2") | Exec::shell("echo bar >&2") }
+        .stderr_all(NullFile)
+        .stderr(Redirection::Merge)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "bar\n");
+    assert_eq!(c.stderr_str(), "");
+
+    let p = { Exec::cmd(prog("true")) | Exec::cmd(prog("true")) }
+        .stderr(Redirection::Pipe)
+        .stderr_all(Redirection::Pipe)
+        .popen();
+    assert!(matches!(p, Err(PopenError::LogicError(_))));
+}
+
+#[test]
+fn exec_join_cancellable() {
+    let token = CancelToken::new();
+    let status = Exec::cmd(prog("sh"))
+        .args(["-c", "exit 3"])
+        .join_cancellable(&token)
+        .unwrap();
+    assert_eq!(status, ExitStatus::Exited(3));
+
+    let canceller = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            token.cancel();
+        })
+    };
+    let start = Instant::now();
+    match Exec::cmd(prog("sleep"))
+        .arg("1000")
+        .kill_on_drop()
+        .join_cancellable(&token)
+    {
+        Err(PopenError::Cancelled) => (),
+        other => panic!("expected Cancelled, got {:?}", other),
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+    canceller.join().unwrap();
+}
+
+#[test]
+fn pipeline_join_cancellable() {
+    let token = CancelToken::new();
+    let status = (Exec::cmd(prog("true")) | Exec::cmd(prog("sh")).args(["-c", "exit 3"]))
+        .join_cancellable(&token)
+        .unwrap();
+    assert_eq!(status, ExitStatus::Exited(3));
+
+    token.cancel();
+    let start = Instant::now();
+    match (Exec::cmd(prog("sleep")).arg("1000") | Exec::cmd(prog("sleep")).arg("1000"))
+        .kill_on_drop()
+        .join_cancellable(&token)
+    {
+        Err(PopenError::Cancelled) => (),
+        other => panic!("expected Cancelled, got {:?}", other),
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
 #[test]
 fn pipeline_join() {
-    let status = (Exec::cmd("true") | Exec::cmd("true")).join().unwrap();
+    let status = (Exec::cmd(prog("true")) | Exec::cmd(prog("true")))
+        .join()
+        .unwrap();
     assert_eq!(status, ExitStatus::Exited(0));
 
-    let status = (Exec::cmd("false") | Exec::cmd("true")).join().unwrap();
+    let status = (Exec::cmd(prog("false")) | Exec::cmd(prog("true")))
+        .join()
+        .unwrap();
     assert_eq!(status, ExitStatus::Exited(0));
 
-    let status = (Exec::cmd("true") | Exec::cmd("false")).join().unwrap();
+    let status = (Exec::cmd(prog("true")) | Exec::cmd(prog("false")))
+        .join()
+        .unwrap();
     assert_eq!(status, ExitStatus::Exited(1));
 }
 
 #[test]
 fn pipeline_invalid_1() {
-    let p = (Exec::cmd("echo").arg("foo") | Exec::cmd("no-such-command")).join();
+    let p = (Exec::cmd(prog("echo")).arg("foo") | Exec::cmd("no-such-command")).join();
     assert!(p.is_err());
 }
 
 #[test]
 fn pipeline_invalid_2() {
-    let p = (Exec::cmd("no-such-command") | Exec::cmd("echo").arg("foo")).join();
+    let p = (Exec::cmd("no-such-command") | Exec::cmd(prog("echo")).arg("foo")).join();
     assert!(p.is_err());
 }
 
 #[test]
-#[should_panic]
-fn reject_input_data_popen() {
-    Exec::cmd("true").stdin("xxx").popen().unwrap();
+fn feed_input_data_popen() {
+    let mut p = Exec::cmd(prog("cat"))
+        .stdin("foo")
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    assert!(p.stdin.is_none());
+    let mut out = String::new();
+    p.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+    assert_eq!(out, "foo");
+    assert!(p.wait().unwrap().success());
 }
 
 #[test]
-#[should_panic]
-fn reject_input_data_join() {
-    Exec::cmd("true").stdin("xxx").join().unwrap();
+fn feed_input_data_join() {
+    // would hang if stdin were not fed and closed
+    assert!(Exec::cmd(prog("cat"))
+        .stdin("xxx")
+        .stdout(NullFile)
+        .join()
+        .unwrap()
+        .success());
 }
 
 #[test]
-#[should_panic]
-fn reject_input_data_stream_stdout() {
-    Exec::cmd("true").stdin("xxx").stream_stdout().unwrap();
+fn stdin_file_threshold() {
+    let mut p = Exec::cmd(prog("cat"))
+        .stdin("foobar")
+        .stdin_file_threshold(3)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    // the data is passed through a file, so there is no pipe to feed
+    assert!(p.stdin.is_none());
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "foobar");
+
+    let c = Exec::cmd(prog("cat"))
+        .stdin("foobar")
+        .stdin_file_threshold(3)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foobar");
 }
 
 #[test]
-#[should_panic]
-fn reject_input_data_stream_stderr() {
-    Exec::cmd("true").stdin("xxx").stream_stderr().unwrap();
+fn pipeline_stdin_file_threshold() {
+    let c = { Exec::cmd(prog("cat")) | Exec::cmd(prog("cat")) }
+        .stdin("foobar")
+        .stdin_file_threshold(3)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foobar");
+}
+
+#[test]
+fn feed_input_data_stream_stdout() {
+    let mut out = String::new();
+    Exec::cmd(prog("cat"))
+        .stdin("foo")
+        .stream_stdout()
+        .unwrap()
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(out, "foo");
+}
+
+#[test]
+fn feed_input_data_pipeline() {
+    let mut out = String::new();
+    (Exec::cmd(prog("cat")) | Exec::cmd(prog("wc")).arg("-l"))
+        .stdin("a\nb\n")
+        .stream_stdout()
+        .unwrap()
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(out.trim(), "2");
 }
 
 #[test]
 #[should_panic]
 fn reject_input_data_stream_stdin() {
-    Exec::cmd("true").stdin("xxx").stream_stdin().unwrap();
+    Exec::cmd(prog("true")).stdin("xxx").stream_stdin().unwrap();
+}
+
+#[test]
+fn stdin_inherit_capture() {
+    let exec = Exec::cmd(prog("sh"))
+        .args(["-c", "echo foo; echo bar >&2"])
+        .stdin_inherit()
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe);
+    let c = exec.clone().capture().unwrap();
+    assert_eq!(c.stdout_str(), "foo\n");
+    assert_eq!(c.stderr_str(), "bar\n");
+
+    let mut comm = exec
+        .communicate_with()
+        .limit_time(Duration::from_secs(5))
+        .on_timeout(OnTimeout::Kill)
+        .start()
+        .unwrap();
+    let (out, err) = comm.read_string().unwrap();
+    assert_eq!(out.unwrap(), "foo\n");
+    assert_eq!(err.unwrap(), "bar\n");
+}
+
+#[test]
+fn stdin_inherit_reject_input() {
+    let result = Exec::cmd(prog("true"))
+        .stdin_inherit()
+        .communicate_with()
+        .input("xxx")
+        .start();
+    assert!(matches!(result, Err(PopenError::LogicError(_))));
+}
+
+#[test]
+#[should_panic]
+fn stdin_inherit_reject_stdin() {
+    let _ = Exec::cmd(prog("true")).stdin_inherit().stdin("xxx");
+}
+
+#[test]
+fn stdin_inherit_replaces_stdin() {
+    let mut p = Exec::cmd(prog("true"))
+        .stdin("foo")
+        .stdin_inherit()
+        .popen()
+        .unwrap();
+    assert!(p.stdin.is_none());
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn pipeline_stdin_inherit() {
+    let mut v = { Exec::cmd(prog("true")) | Exec::cmd(prog("cat")) }
+        .stdin("foo")
+        .stdin_inherit()
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    assert!(v[0].stdin.is_none());
+    let c = { Exec::cmd(prog("true")) | Exec::cmd(prog("cat")) }
+        .stdin("foo")
+        .stdin_inherit()
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "");
+    assert!(c.success());
+    for p in &mut v {
+        p.wait().unwrap();
+    }
 }
 
 #[test]
 fn env_set() {
-    assert!(Exec::cmd("sh")
+    assert!(Exec::cmd(prog("sh"))
         .args(&["-c", r#"test "$SOMEVAR" = "foo""#])
         .env("SOMEVAR", "foo")
         .join()
@@ -298,7 +1212,7 @@ fn env_set() {
 
 #[test]
 fn env_extend() {
-    assert!(Exec::cmd("sh")
+    assert!(Exec::cmd(prog("sh"))
         .args(&["-c", r#"test "$VAR1" = "foo" && test "$VAR2" = "bar""#])
         .env_extend(&[("VAR1", "foo"), ("VAR2", "bar")])
         .join()
@@ -341,7 +1255,7 @@ fn env_inherit() {
     // use a unique name to avoid interference with other tests
     let varname = "TEST_ENV_INHERIT_VARNAME";
     let _guard = tmp_env_var(varname, "inherited");
-    assert!(Exec::cmd("sh")
+    assert!(Exec::cmd(prog("sh"))
         .args(&["-c", &format!(r#"test "${}" = "inherited""#, varname)])
         .join()
         .unwrap()
@@ -353,7 +1267,7 @@ fn env_inherit_set() {
     // use a unique name to avoid interference with other tests
     let varname = "TEST_ENV_INHERIT_SET_VARNAME";
     let _guard = tmp_env_var(varname, "inherited");
-    assert!(Exec::cmd("sh")
+    assert!(Exec::cmd(prog("sh"))
         .args(&["-c", &format!(r#"test "${}" = "new""#, varname)])
         .env(varname, "new")
         .join()
@@ -361,6 +1275,35 @@ fn env_inherit_set() {
         .success());
 }
 
+#[test]
+fn pipeline_env() {
+    let out = {
+        Exec::cmd(prog("sh"))
+            .args(["-c", r#"echo "$PIPE_VAR1 $PIPE_VAR2""#])
+            .env("PIPE_VAR2", "own")
+            | Exec::cmd(prog("sh")).args(["-c", r#"cat; echo "$PIPE_VAR1 $PIPE_VAR2""#])
+    }
+    .env("PIPE_VAR1", "foo")
+    .env_extend(&[("PIPE_VAR2", "bar")])
+    .capture()
+    .unwrap()
+    .stdout_str();
+    assert_eq!(out, "foo own\nfoo bar\n");
+}
+
+#[test]
+fn pipeline_env_clear() {
+    let out = (Exec::cmd(prog("sh")).args(["-c", "echo $HOME"])
+        | Exec::cmd(prog("sh"))
+            .args(["-c", "cat; echo $HOME"])
+            .env("HOME", "own"))
+    .env_clear()
+    .capture()
+    .unwrap()
+    .stdout_str();
+    assert_eq!(out, "\nown\n");
+}
+
 // XXX move tests under the builder module so we can call
 // Exec::display_escape() instead of copying it.
 fn display_escape(s: &str) -> Cow<'_, str> {
@@ -415,6 +1358,104 @@ fn exec_to_string() {
     );
 }
 
+#[test]
+#[cfg(not(feature = "no-path-search"))]
+fn explain() {
+    let _guard = MUTATE_ENV.lock().unwrap();
+    let tmpdir = TempDir::new().unwrap();
+    let plan = Exec::cmd(prog("sh"))
+        .args(["-c", "cat"])
+        .env("FOO", "bar")
+        .cwd(tmpdir.path())
+        .stdin("abc")
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .detached()
+        .explain();
+    assert!(plan.executable.is_absolute());
+    assert!(plan.executable.ends_with("sh"));
+    assert_eq!(plan.argv, ["sh", "-c", "cat"]);
+    assert_eq!(plan.env, [("FOO".to_string(), Some("bar".to_string()))]);
+    assert_eq!(plan.cwd.as_deref(), Some(tmpdir.path()));
+    assert_eq!(
+        plan.streams,
+        ["pipe, fed 3 bytes", "pipe", "merged into stdout"]
+    );
+    assert_eq!(plan.options, [("detached", "true".to_string())]);
+    let report = plan.to_string();
+    assert!(report.contains("argv: sh -c cat\n"));
+    assert!(report.contains("stderr (fd 2): merged into stdout\n"));
+}
+
+#[test]
+#[allow(deprecated)]
+fn kill_on_parent_exit_alias() {
+    let plan = Exec::cmd("true").kill_on_parent_exit().explain();
+    assert!(plan
+        .options
+        .contains(&("die_with_parent", "true".to_string())));
+}
+
+#[test]
+fn explain_not_found() {
+    let plan = Exec::cmd("nonexistent-program").explain();
+    assert_eq!(plan.executable, std::path::Path::new("nonexistent-program"));
+    assert_eq!(plan.streams, ["inherited", "inherited", "inherited"]);
+}
+
+#[test]
+fn pipeline_explain() {
+    let tmpdir = TempDir::new().unwrap();
+    let out = tmpdir.path().join("out");
+    let plans = (Exec::cmd("echo").arg("foo") | Exec::cmd("cat") | Exec::cmd("cat"))
+        .stdout(out.as_path())
+        .stderr_all(NullFile)
+        .explain();
+    assert_eq!(plans.len(), 3);
+    assert_eq!(plans[0].argv, ["echo", "foo"]);
+    assert_eq!(plans[0].streams[..2], ["inherited", "pipe to command 2"]);
+    assert_eq!(
+        plans[1].streams[..2],
+        ["pipe from command 1", "pipe to command 3"]
+    );
+    assert_eq!(
+        plans[2].streams[..2],
+        [
+            "pipe from command 2".to_string(),
+            format!("file {} (appended)", out.display())
+        ]
+    );
+    assert!(plans
+        .iter()
+        .all(|plan| plan.streams[2].starts_with("open file")));
+}
+
+#[test]
+fn exec_to_string_env_delta() {
+    let _guard = MUTATE_ENV.lock().unwrap();
+    let cmd = Exec::cmd("true")
+        .env("API_TOKEN", "hunter2")
+        .env("DEPLOY_KEY", "hunter3")
+        .env("PATH", env::var_os("PATH").unwrap_or_default())
+        .env("foo", "bar");
+    // unchanged variables are omitted, and secrets redacted
+    assert_eq!(
+        format!("{:?}", cmd),
+        "Exec { API_TOKEN='***' DEPLOY_KEY='***' foo=bar true }"
+    );
+
+    let config = crate::PopenConfig {
+        env: Some(vec![("SECRET".into(), "hunter2".into())]),
+        ..Default::default()
+    };
+    let debug = format!("{:?}", config);
+    assert!(debug.contains(r#""SECRET": Some("***")"#));
+    assert!(!debug.contains("hunter2"));
+    if let Some((name, _)) = env::vars().next() {
+        assert!(debug.contains(&format!("{:?}: None", name)));
+    }
+}
+
 #[test]
 fn pipeline_to_string() {
     let pipeline = { Exec::cmd("command with space").arg("arg") | Exec::cmd("wc").arg("-l") };
@@ -423,3 +1464,206 @@ fn pipeline_to_string() {
         "Pipeline { 'command with space' arg | wc -l }"
     )
 }
+
+#[test]
+fn expand_env_args() {
+    let c = Exec::cmd(prog("printf"))
+        .args(["%s|%s|%s|%s", "$FOO", "${BAR}x", "$UNDEFINED_VAR", "$5 ${"])
+        .env("FOO", "foo")
+        .env("BAR", "bar baz")
+        .env_remove("UNDEFINED_VAR")
+        .expand_env(true)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo|bar bazx||$5 ${");
+}
+
+#[test]
+fn expand_env_escape() {
+    let c = Exec::cmd(prog("printf"))
+        .args(["%s|%s", "$${FOO}", "$$$FOO"])
+        .env("FOO", "foo")
+        .expand_env(true)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "${FOO}|$foo");
+}
+
+#[test]
+fn substitute_placeholders() {
+    let mut map = HashMap::new();
+    map.insert("cmd".to_string(), OsString::from(prog("printf")));
+    map.insert("fmt".to_string(), OsString::from("%s|%s|%s"));
+    map.insert("name".to_string(), OsString::from("a b; rm -rf x"));
+    let c = Exec::cmd("{cmd}")
+        .args(["{fmt}", "<{name}>", "{{name}} {missing}"])
+        .substitute(&map)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "<a b; rm -rf x>|{name} {missing}|");
+}
+
+#[test]
+fn expand_env_disabled() {
+    let c = Exec::cmd(prog("printf"))
+        .args(["%s", "$FOO"])
+        .env("FOO", "foo")
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "$FOO");
+}
+
+#[test]
+fn args_from_iterator() {
+    let words = ["foo", "bar"];
+    let c = Exec::cmd(prog("printf"))
+        .args(["%s-"])
+        .args(words.iter().map(|w| w.to_uppercase()))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "FOO-BAR-");
+}
+
+#[test]
+fn cwd_create() {
+    let tmpdir = TempDir::new().unwrap();
+    let workdir = tmpdir.path().join("a").join("b");
+    Exec::cmd(prog("touch"))
+        .arg("here")
+        .cwd_create(&workdir)
+        .join()
+        .unwrap();
+    assert!(workdir.join("here").exists());
+    // an existing directory is fine too
+    Exec::cmd(prog("touch"))
+        .arg("there")
+        .cwd_create(&workdir)
+        .join()
+        .unwrap();
+    assert!(workdir.join("there").exists());
+}
+
+#[test]
+fn temp_cwd() {
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "touch here; pwd"])
+        .temp_cwd()
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let dir = p.temp_cwd().unwrap().to_owned();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = dir.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+    let out = read_whole_file(p.stdout.take().unwrap());
+    assert_eq!(
+        std::path::Path::new(out.trim()).canonicalize().unwrap(),
+        dir.canonicalize().unwrap()
+    );
+    assert!(p.wait().unwrap().success());
+    assert!(p.temp_cwd().is_none());
+    assert!(!dir.exists());
+}
+
+#[test]
+fn temp_cwd_keep_on_failure() {
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "touch here; exit 1"])
+        .temp_cwd()
+        .keep_temp_cwd_on_failure()
+        .popen()
+        .unwrap();
+    let dir = p.temp_cwd().unwrap().to_owned();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(1));
+    assert!(dir.join("here").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // a successful run is cleaned up regardless
+    let mut p = Exec::cmd(prog("true"))
+        .temp_cwd()
+        .keep_temp_cwd_on_failure()
+        .popen()
+        .unwrap();
+    let dir = p.temp_cwd().unwrap().to_owned();
+    p.wait().unwrap();
+    assert!(!dir.exists());
+}
+
+#[test]
+fn temp_cwd_dropped() {
+    let p = Exec::cmd(prog("true")).temp_cwd().popen().unwrap();
+    let dir = p.temp_cwd().unwrap().to_owned();
+    drop(p);
+    assert!(!dir.exists());
+}
+
+#[test]
+fn cwd_missing() {
+    let tmpdir = TempDir::new().unwrap();
+    let workdir = tmpdir.path().join("missing");
+    match Exec::cmd(prog("true")).cwd(&workdir).join() {
+        Err(PopenError::CwdNotFound(dir)) => assert_eq!(dir, workdir),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(!workdir.exists());
+}
+
+#[test]
+fn cwd_create_fails() {
+    let tmpdir = TempDir::new().unwrap();
+    let blocker = tmpdir.path().join("file");
+    File::create(&blocker).unwrap();
+    let workdir = blocker.join("sub");
+    match Exec::cmd(prog("true")).cwd_create(&workdir).join() {
+        Err(PopenError::IoError(e)) => {
+            assert!(e.to_string().contains(&*workdir.to_string_lossy()));
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+#[cfg(not(feature = "no-shell"))]
+fn shell_guard_check() {
+    use crate::ShellGuard;
+
+    let guard = ShellGuard::new().allow("|");
+    assert!(guard.check("ls -l /tmp | wc -l").is_ok());
+    assert!(guard.check("echo 'a; b' \\$HOME").is_ok());
+    for bad in &[
+        "ls; rm x",
+        "echo $HOME",
+        "a\nb",
+        "a\0b",
+        "echo 'x",
+        "echo `id`",
+    ] {
+        match guard.check(bad) {
+            Err(PopenError::UnsafeShellCommand(_)) => (),
+            other => panic!("{:?} not rejected: {:?}", bad, other),
+        }
+    }
+}
+
+#[test]
+#[cfg(all(unix, not(feature = "no-shell")))]
+fn shell_command_quoting() {
+    use crate::{shell_quote, ShellCommand, ShellGuard};
+
+    assert_eq!(shell_quote("foo/bar.txt"), "foo/bar.txt");
+    assert_eq!(shell_quote(""), "''");
+    assert_eq!(shell_quote("a=b"), "'a=b'");
+    assert_eq!(shell_quote("it's $HOME"), r"'it'\''s $HOME'");
+
+    let nasty = "x'; touch pwned; echo '\n$(id)";
+    let cmd = ShellCommand::new("printf")
+        .args(&["%s", nasty])
+        .raw("|")
+        .cmd("cat");
+    assert!(ShellGuard::new().allow("|").check(&cmd).is_err()); // newline
+    let out = Exec::shell(cmd).capture().unwrap().stdout_str();
+    assert_eq!(out, nasty);
+}
@@ -4,9 +4,42 @@ use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::Write;
 use std::io::{self, Read};
+use std::path::Path;
 use std::time::Duration;
 
-use crate::{ExitStatus, Popen, PopenConfig, PopenError, Redirection};
+use crate::{
+    CancelToken, ExitStatus, IoStats, OnOverflow, Popen, PopenConfig, PopenError, Redirection,
+};
+
+// Returns the program `name` in the form tests run it: as is, or, with the
+// no-path-search feature, which rejects programs given by name, by path.
+#[cfg(not(feature = "no-path-search"))]
+pub fn prog(name: &'static str) -> &'static str {
+    name
+}
+
+#[cfg(feature = "no-path-search")]
+pub fn prog(name: &'static str) -> &'static str {
+    use std::sync::Mutex;
+
+    static FOUND: Mutex<Vec<(&str, &str)>> = Mutex::new(Vec::new());
+    let mut found = FOUND.lock().unwrap();
+    if let Some(&(_, path)) = found.iter().find(|&&(n, _)| n == name) {
+        return path;
+    }
+    let path = std::env::var_os("PATH").and_then(|path| {
+        std::env::split_paths(&path)
+            .map(|dir| dir.join(format!("{}{}", name, std::env::consts::EXE_SUFFIX)))
+            .find(|candidate| candidate.is_file())
+    });
+    // a missing program is left for the test to fail on
+    let path = match path.as_ref().and_then(|path| path.to_str()) {
+        Some(path) => Box::leak(path.into()),
+        None => name,
+    };
+    found.push((name, path));
+    path
+}
 
 pub fn read_whole_file<T: Read>(mut f: T) -> String {
     let mut content = String::new();
@@ -16,7 +49,7 @@ pub fn read_whole_file<T: Read>(mut f: T) -> String {
 
 #[test]
 fn good_cmd() {
-    let mut p = Popen::create(&["true"], PopenConfig::default()).unwrap();
+    let mut p = Popen::create(&[prog("true")], PopenConfig::default()).unwrap();
     assert!(p.wait().unwrap().success());
 }
 
@@ -31,19 +64,19 @@ fn reject_empty_argv() {
     let test = Popen::create(&[""; 0], PopenConfig::default());
     if let Err(PopenError::LogicError(..)) = test {
     } else {
-        assert!(false, "didn't get LogicError for empty argv");
+        panic!("didn't get LogicError for empty argv");
     }
 }
 
 #[test]
 fn err_exit() {
-    let mut p = Popen::create(&["sh", "-c", "exit 13"], PopenConfig::default()).unwrap();
+    let mut p = Popen::create(&[prog("sh"), "-c", "exit 13"], PopenConfig::default()).unwrap();
     assert_eq!(p.wait().unwrap(), ExitStatus::Exited(13));
 }
 
 #[test]
 fn terminate() {
-    let mut p = Popen::create(&["sleep", "1000"], PopenConfig::default()).unwrap();
+    let mut p = Popen::create(&[prog("sleep"), "1000"], PopenConfig::default()).unwrap();
     p.terminate().unwrap();
     p.wait().unwrap();
 }
@@ -53,7 +86,7 @@ fn terminate_twice() {
     use std::thread;
     use std::time::Duration;
 
-    let mut p = Popen::create(&["sleep", "1000"], PopenConfig::default()).unwrap();
+    let mut p = Popen::create(&[prog("sleep"), "1000"], PopenConfig::default()).unwrap();
     p.terminate().unwrap();
     thread::sleep(Duration::from_millis(100));
     p.terminate().unwrap();
@@ -62,7 +95,7 @@ fn terminate_twice() {
 #[test]
 fn read_from_stdout() {
     let mut p = Popen::create(
-        &["echo", "foo"],
+        &[prog("echo"), "foo"],
         PopenConfig {
             stdout: Redirection::Pipe,
             ..Default::default()
@@ -82,7 +115,7 @@ fn input_from_file() {
         outfile.write_all(b"foo").unwrap();
     }
     let mut p = Popen::create(
-        &["cat", tmpname.to_str().unwrap()],
+        &[prog("cat"), tmpname.to_str().unwrap()],
         PopenConfig {
             stdin: Redirection::File(File::open(&tmpname).unwrap()),
             stdout: Redirection::Pipe,
@@ -100,7 +133,7 @@ fn output_to_file() {
     let tmpname = tmpdir.path().join("output");
     let outfile = File::create(&tmpname).unwrap();
     let mut p = Popen::create(
-        &["printf", "foo"],
+        &[prog("printf"), "foo"],
         PopenConfig {
             stdout: Redirection::File(outfile),
             ..Default::default()
@@ -121,7 +154,7 @@ fn input_output_from_file() {
         f.write_all(b"foo").unwrap();
     }
     let mut p = Popen::create(
-        &["cat"],
+        &[prog("cat")],
         PopenConfig {
             stdin: Redirection::File(File::open(&tmpname_in).unwrap()),
             stdout: Redirection::File(File::create(&tmpname_out).unwrap()),
@@ -138,7 +171,7 @@ fn write_to_subprocess() {
     let tmpdir = TempDir::new().unwrap();
     let tmpname = tmpdir.path().join("output");
     let mut p = Popen::create(
-        &[r"uniq", "-", tmpname.to_str().unwrap()],
+        &[prog("uniq"), "-", tmpname.to_str().unwrap()],
         PopenConfig {
             stdin: Redirection::Pipe,
             ..Default::default()
@@ -159,7 +192,7 @@ fn communicate_input() {
     let tmpdir = TempDir::new().unwrap();
     let tmpname = tmpdir.path().join("input");
     let mut p = Popen::create(
-        &["cat"],
+        &[prog("cat")],
         PopenConfig {
             stdin: Redirection::Pipe,
             stdout: Redirection::File(File::create(&tmpname).unwrap()),
@@ -169,7 +202,7 @@ fn communicate_input() {
     .unwrap();
     if let (None, None) = p.communicate_bytes(Some(b"hello world")).unwrap() {
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
     assert_eq!(
@@ -181,7 +214,7 @@ fn communicate_input() {
 #[test]
 fn communicate_output() {
     let mut p = Popen::create(
-        &["sh", "-c", "echo foo; echo bar >&2"],
+        &[prog("sh"), "-c", "echo foo; echo bar >&2"],
         PopenConfig {
             stdout: Redirection::Pipe,
             stderr: Redirection::Pipe,
@@ -193,7 +226,7 @@ fn communicate_output() {
         assert_eq!(out, b"foo\n");
         assert_eq!(err, b"bar\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -201,7 +234,7 @@ fn communicate_output() {
 #[test]
 fn communicate_input_output() {
     let mut p = Popen::create(
-        &["sh", "-c", "cat; echo foo >&2"],
+        &[prog("sh"), "-c", "cat; echo foo >&2"],
         PopenConfig {
             stdin: Redirection::Pipe,
             stdout: Redirection::Pipe,
@@ -214,7 +247,7 @@ fn communicate_input_output() {
         assert_eq!(out, b"hello world");
         assert_eq!(err, b"foo\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -222,7 +255,7 @@ fn communicate_input_output() {
 #[test]
 fn communicate_input_output_long() {
     let mut p = Popen::create(
-        &["sh", "-c", "cat; printf '%100000s' '' >&2"],
+        &[prog("sh"), "-c", "cat; printf '%100000s' '' >&2"],
         PopenConfig {
             stdin: Redirection::Pipe,
             stdout: Redirection::Pipe,
@@ -236,15 +269,71 @@ fn communicate_input_output_long() {
         assert_eq!(&out[..], &input[..]);
         assert_eq!(&err[..], &[32u8; 100_000][..]);
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
 
+#[test]
+fn communicate_io_stats() {
+    use std::sync::{Arc, Mutex};
+
+    let mut p = Popen::create(
+        [prog("sh"), "-c", "cat; echo foo >&2"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            stderr: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let reported = Arc::new(Mutex::new(vec![]));
+    let mut comm = p
+        .communicate_start(Some(b"hello world".to_vec()))
+        .on_io_stats({
+            let reported = Arc::clone(&reported);
+            move |delta| reported.lock().unwrap().push(delta)
+        });
+    comm.read().unwrap();
+    let expected = IoStats {
+        stdin_written: 11,
+        stdout_read: 11,
+        stderr_read: 4,
+    };
+    assert_eq!(comm.io_stats(), expected);
+    assert_eq!(*reported.lock().unwrap(), vec![expected]);
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn communicate_keep_stdin_open() {
+    let mut p = Popen::create(
+        [prog("cat")],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut comm = p
+        .communicate_start(Some(b"foo\n".to_vec()))
+        .keep_stdin_open(true)
+        .limit_size(4);
+    assert_eq!(comm.read().unwrap(), (Some(b"foo\n".to_vec()), None));
+    comm.feed(b"bar\n");
+    assert_eq!(comm.read().unwrap(), (Some(b"bar\n".to_vec()), None));
+    comm.close_stdin();
+    assert_eq!(comm.read().unwrap(), (Some(vec![]), None));
+    assert_eq!(comm.io_stats().stdin_written, 8);
+    assert!(p.wait().unwrap().success());
+}
+
 #[test]
 fn communicate_timeout() {
     let mut p = Popen::create(
-        &["sh", "-c", "printf foo; sleep 1"],
+        &[prog("sh"), "-c", "printf foo; sleep 1"],
         PopenConfig {
             stdout: Redirection::Pipe,
             stderr: Redirection::Pipe,
@@ -269,7 +358,7 @@ fn communicate_timeout() {
 #[test]
 fn communicate_size_limit_small() {
     let mut p = Popen::create(
-        &["sh", "-c", "printf '%5s' a"],
+        &[prog("sh"), "-c", "printf '%5s' a"],
         PopenConfig {
             stdout: Redirection::Pipe,
             stderr: Redirection::Pipe,
@@ -280,10 +369,41 @@ fn communicate_size_limit_small() {
     let mut comm = p.communicate_start(None).limit_size(2);
     assert_eq!(comm.read().unwrap(), (Some(vec![32; 2]), Some(vec![])));
     assert_eq!(comm.read().unwrap(), (Some(vec![32; 2]), Some(vec![])));
-    assert_eq!(comm.read().unwrap(), (Some(vec!['a' as u8]), Some(vec![])));
+    assert_eq!(comm.read().unwrap(), (Some(vec![b'a']), Some(vec![])));
     p.kill().unwrap();
 }
 
+#[test]
+fn communicate_max_output_read_again() {
+    for &on_overflow in &[OnOverflow::Kill, OnOverflow::Error] {
+        let mut p = Popen::create(
+            [prog("yes")],
+            PopenConfig {
+                stdout: Redirection::Pipe,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut comm = p.communicate_start(None).max_output(10, on_overflow);
+        let first = comm.read();
+        let second = comm.read();
+        match on_overflow {
+            OnOverflow::Kill => {
+                assert_eq!(first.unwrap().0.unwrap().len(), 10);
+                assert_eq!(second.unwrap(), (Some(vec![]), None));
+            }
+            _ => {
+                assert_eq!(first.unwrap_err().capture.0.unwrap().len(), 10);
+                let err = second.unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::Other);
+                assert_eq!(err.capture, (Some(vec![]), None));
+            }
+        }
+        assert_eq!(comm.overflow(), Some(on_overflow));
+        p.kill().unwrap();
+    }
+}
+
 fn check_vec(v: Option<Vec<u8>>, size: usize, content: u8) {
     assert_eq!(v.as_ref().unwrap().len(), size);
     assert!(v.as_ref().unwrap().iter().all(|&c| c == content));
@@ -292,7 +412,7 @@ fn check_vec(v: Option<Vec<u8>>, size: usize, content: u8) {
 #[test]
 fn communicate_size_limit_large() {
     let mut p = Popen::create(
-        &["sh", "-c", "printf '%20001s' a"],
+        &[prog("sh"), "-c", "printf '%20001s' a"],
         PopenConfig {
             stdout: Redirection::Pipe,
             stderr: Redirection::Pipe,
@@ -310,14 +430,14 @@ fn communicate_size_limit_large() {
     check_vec(out, 10_000, 32);
     assert_eq!(err, Some(vec![]));
 
-    assert_eq!(comm.read().unwrap(), (Some(vec!['a' as u8]), Some(vec![])));
+    assert_eq!(comm.read().unwrap(), (Some(vec![b'a']), Some(vec![])));
     p.kill().unwrap();
 }
 
 #[test]
 fn communicate_size_limit_different_sizes() {
     let mut p = Popen::create(
-        &["sh", "-c", "printf '%20001s' a"],
+        &[prog("sh"), "-c", "printf '%20001s' a"],
         PopenConfig {
             stdout: Redirection::Pipe,
             stderr: Redirection::Pipe,
@@ -347,21 +467,21 @@ fn communicate_size_limit_different_sizes() {
     check_vec(out, 8_900, 32);
     assert_eq!(err, Some(vec![]));
 
-    assert_eq!(comm.read().unwrap(), (Some(vec!['a' as u8]), Some(vec![])));
+    assert_eq!(comm.read().unwrap(), (Some(vec![b'a']), Some(vec![])));
     assert_eq!(comm.read().unwrap(), (Some(vec![]), Some(vec![])));
     p.kill().unwrap();
 }
 
 #[test]
 fn null_byte_in_cmd() {
-    let try_p = Popen::create(&["echo\0foo"], PopenConfig::default());
+    let try_p = Popen::create(&[prog("echo\0foo")], PopenConfig::default());
     assert!(try_p.is_err());
 }
 
 #[test]
 fn merge_err_to_out_pipe() {
     let mut p = Popen::create(
-        &["sh", "-c", "echo foo; echo bar >&2"],
+        &[prog("sh"), "-c", "echo foo; echo bar >&2"],
         PopenConfig {
             stdout: Redirection::Pipe,
             stderr: Redirection::Merge,
@@ -372,7 +492,7 @@ fn merge_err_to_out_pipe() {
     if let (Some(out), None) = p.communicate_bytes(None).unwrap() {
         assert_eq!(out, b"foo\nbar\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -380,7 +500,7 @@ fn merge_err_to_out_pipe() {
 #[test]
 fn merge_out_to_err_pipe() {
     let mut p = Popen::create(
-        &["sh", "-c", "echo foo; echo bar >&2"],
+        &[prog("sh"), "-c", "echo foo; echo bar >&2"],
         PopenConfig {
             stdout: Redirection::Merge,
             stderr: Redirection::Pipe,
@@ -391,7 +511,7 @@ fn merge_out_to_err_pipe() {
     if let (None, Some(err)) = p.communicate_bytes(None).unwrap() {
         assert_eq!(err, b"foo\nbar\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -401,7 +521,7 @@ fn merge_err_to_out_file() {
     let tmpdir = TempDir::new().unwrap();
     let tmpname = tmpdir.path().join("output");
     let mut p = Popen::create(
-        &["sh", "-c", "printf foo; printf bar >&2"],
+        &[prog("sh"), "-c", "printf foo; printf bar >&2"],
         PopenConfig {
             stdout: Redirection::File(File::create(&tmpname).unwrap()),
             stderr: Redirection::Merge,
@@ -416,7 +536,7 @@ fn merge_err_to_out_file() {
 #[test]
 fn simple_pipe() {
     let mut c1 = Popen::create(
-        &["printf", "foo\\nbar\\nbaz\\n"],
+        &[prog("printf"), "foo\\nbar\\nbaz\\n"],
         PopenConfig {
             stdout: Redirection::Pipe,
             ..Default::default()
@@ -424,7 +544,7 @@ fn simple_pipe() {
     )
     .unwrap();
     let mut c2 = Popen::create(
-        &["wc", "-l"],
+        &[prog("wc"), "-l"],
         PopenConfig {
             stdin: Redirection::File(c1.stdout.take().unwrap()),
             stdout: Redirection::Pipe,
@@ -438,7 +558,7 @@ fn simple_pipe() {
 
 #[test]
 fn wait_timeout() {
-    let mut p = Popen::create(&["sleep", "0.5"], PopenConfig::default()).unwrap();
+    let mut p = Popen::create(&[prog("sleep"), "0.5"], PopenConfig::default()).unwrap();
     let ret = p.wait_timeout(Duration::from_millis(100)).unwrap();
     assert!(ret.is_none());
     // We sleep for a very long time to avoid flaky failures when we get a slow
@@ -447,12 +567,94 @@ fn wait_timeout() {
     assert_eq!(ret, Some(ExitStatus::Exited(0)));
 }
 
+#[test]
+fn waitable() {
+    use crate::Waitable;
+
+    fn reap<W: Waitable>(w: &mut W) -> crate::Result<ExitStatus> {
+        assert!(w.try_wait()?.is_none());
+        assert!(w.wait_timeout(Duration::from_millis(100))?.is_none());
+        w.kill()?;
+        w.wait()
+    }
+
+    let mut p = Popen::create([prog("sleep"), "1000"], PopenConfig::default()).unwrap();
+    let status = reap(&mut p).unwrap();
+    assert!(!status.success());
+    assert_eq!(Waitable::try_wait(&mut p).unwrap(), Some(status));
+}
+
+#[test]
+fn wait_cancellable() {
+    use std::thread;
+
+    let mut p = Popen::create([prog("sleep"), "1000"], PopenConfig::default()).unwrap();
+    let token = CancelToken::new();
+    let canceller = {
+        let token = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            token.cancel();
+        })
+    };
+    match p.wait_cancellable(&token) {
+        Err(PopenError::Cancelled) => (),
+        other => panic!("expected Cancelled, got {:?}", other),
+    }
+    canceller.join().unwrap();
+    assert!(p.poll().is_none());
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn wait_cancellable_finished() {
+    let mut p = Popen::create([prog("true")], PopenConfig::default()).unwrap();
+    let token = CancelToken::new();
+    assert!(p.wait_cancellable(&token).unwrap().success());
+}
+
+#[test]
+fn exit_channel() {
+    use std::thread;
+
+    let mut p = Popen::create([prog("sh"), "-c", "exit 3"], PopenConfig::default()).unwrap();
+    let rx = p.exit_channel().unwrap();
+    let waiter = thread::spawn(move || rx.recv().unwrap());
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(3));
+    assert_eq!(waiter.join().unwrap(), ExitStatus::Exited(3));
+    // after the exit, the status is available immediately
+    let rx = p.exit_channel().unwrap();
+    assert_eq!(rx.try_recv().unwrap(), ExitStatus::Exited(3));
+}
+
+#[test]
+fn exit_channel_detached() {
+    let mut p = Popen::create([prog("true")], PopenConfig::default()).unwrap();
+    p.detach();
+    let rx = p.exit_channel().unwrap();
+    p.wait().unwrap();
+    assert!(rx.recv().unwrap().success());
+}
+
+#[test]
+fn start_time_elapsed() {
+    use std::time::Instant;
+
+    let before = Instant::now();
+    let mut p = Popen::create([prog("sleep"), "0.1"], PopenConfig::default()).unwrap();
+    assert!(p.start_time() >= before);
+    assert!(p.start_time() <= Instant::now());
+    p.wait().unwrap();
+    assert!(p.elapsed() >= Duration::from_millis(100));
+}
+
 #[test]
 fn setup_executable() {
     let mut p = Popen::create(
         &["foobar", "-c", r#"printf %s "$0""#],
         PopenConfig {
-            executable: Some(OsStr::new("sh").to_owned()),
+            executable: Some(OsStr::new(prog("sh")).to_owned()),
             stdout: Redirection::Pipe,
             ..Default::default()
         },
@@ -466,7 +668,7 @@ fn env_add() {
     let mut env = PopenConfig::current_env();
     env.push((OsString::from("SOMEVAR"), OsString::from("foo")));
     let mut p = Popen::create(
-        &["sh", "-c", r#"test "$SOMEVAR" = "foo""#],
+        &[prog("sh"), "-c", r#"test "$SOMEVAR" = "foo""#],
         PopenConfig {
             env: Some(env),
             ..Default::default()
@@ -483,7 +685,7 @@ fn env_dup() {
         (OsString::from("SOMEVAR"), OsString::from("bar")),
     ];
     let mut p = Popen::create(
-        &["sh", "-c", r#"test "$SOMEVAR" = "bar""#],
+        &[prog("sh"), "-c", r#"test "$SOMEVAR" = "bar""#],
         PopenConfig {
             stdout: Redirection::Pipe,
             env: Some(dups),
@@ -505,7 +707,7 @@ fn cwd() {
     // interference on Windows and symlinks on Unix.
 
     Popen::create(
-        &["touch", "here"],
+        &[prog("touch"), "here"],
         PopenConfig {
             stdout: Redirection::Pipe,
             cwd: Some(tmpdir_name),
@@ -519,18 +721,41 @@ fn cwd() {
 
 #[test]
 fn failed_cwd() {
-    use crate::popen::PopenError::IoError;
     let ret = Popen::create(
-        &["anything"],
+        &[prog("true")],
         PopenConfig {
             stdout: Redirection::Pipe,
             cwd: Some("/nosuchdir".into()),
             ..Default::default()
         },
     );
-    let err_num = match ret {
-        Err(IoError(e)) => e.raw_os_error().unwrap_or(-1),
+    match ret {
+        Err(PopenError::CwdNotFound(dir)) => assert_eq!(dir, Path::new("/nosuchdir")),
         _ => panic!("expected error return"),
     };
-    assert_eq!(err_num, libc::ENOENT);
+}
+
+#[test]
+fn create_from_iterator() {
+    let mut p = Popen::create(
+        vec![prog("sh"), "-c"].into_iter().chain(Some("exit 7")),
+        PopenConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(7));
+
+    let mut p = Popen::create([prog("true")], PopenConfig::default()).unwrap();
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn exit_status_compare_code() {
+    let mut p = Popen::create([prog("sh"), "-c", "exit 42"], PopenConfig::default()).unwrap();
+    let status = p.wait().unwrap();
+    assert_eq!(status, 42);
+    assert_eq!(status, 42u32);
+    assert_ne!(status, 0);
+    assert_eq!(status, ExitStatus::from_code(42));
+    assert_ne!(ExitStatus::Signaled(42), 42);
+    assert_ne!(ExitStatus::Undetermined, 0);
 }
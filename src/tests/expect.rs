@@ -0,0 +1,98 @@
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use regex::Regex;
+
+use crate::tests::common::prog;
+use crate::{Exec, Expect, Popen, Redirection};
+
+fn spawn(script: &str) -> (Popen, Expect) {
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", script])
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let session = Expect::new(&mut p).unwrap();
+    (p, session)
+}
+
+#[test]
+fn expect_prompt() {
+    let (mut p, mut session) = spawn("printf 'Name: '; read name; echo \"hello $name\"; echo bye");
+    let m = session
+        .expect_regex(&Regex::new(r"Name: $").unwrap())
+        .unwrap();
+    assert_eq!(m.before, "");
+    assert_eq!(m.matched, "Name: ");
+    session.send_line("world").unwrap();
+    let m = session
+        .expect_regex(&Regex::new(r"hello (\w+)\n").unwrap())
+        .unwrap();
+    assert_eq!(
+        m.groups,
+        [Some("hello world\n".to_owned()), Some("world".to_owned())]
+    );
+    let m = session.expect_regex(&Regex::new("e").unwrap()).unwrap();
+    assert_eq!((&m.before[..], &m.matched[..]), ("by", "e"));
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn expect_timeout_keeps_output() {
+    let (_p, mut session) = spawn("printf 'partial '; sleep 0.5; echo done");
+    let done = Regex::new("done").unwrap();
+    let err = session
+        .expect_regex_timeout(&done, Duration::from_millis(100))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert_eq!(err.buffer, "partial ");
+    let mut session = session.timeout(Duration::from_secs(5));
+    let m = session.expect_regex(&done).unwrap();
+    assert_eq!(m.before, "partial ");
+}
+
+#[test]
+fn expect_eof() {
+    let (_p, mut session) = spawn("printf 'abc'");
+    let err = session
+        .expect_regex(&Regex::new("xyz").unwrap())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(err.buffer, "abc");
+    // the unmatched output is still available
+    let m = session.expect_regex(&Regex::new("b").unwrap()).unwrap();
+    assert_eq!(m.before, "a");
+}
+
+#[test]
+fn expect_split_utf8() {
+    // "č" is split between two writes
+    let (_p, mut session) = spawn("printf '\\304'; sleep 0.1; printf '\\215 \\377.'");
+    let m = session.expect_regex(&Regex::new(r"\.").unwrap()).unwrap();
+    assert_eq!(m.before, "č \u{FFFD}");
+}
+
+#[test]
+fn expect_pty() {
+    let mut p = Exec::cmd(prog("sh"))
+        .args([
+            "-c",
+            "printf 'Password: '; stty -echo; read pw; stty echo; echo; echo \"got $pw\"",
+        ])
+        .pty()
+        .popen()
+        .unwrap();
+    let mut session = Expect::new(&mut p).unwrap().timeout(Duration::from_secs(5));
+    session
+        .expect_regex(&Regex::new("Password: ").unwrap())
+        .unwrap();
+    session.send_line("secret").unwrap();
+    let m = session
+        .expect_regex(&Regex::new(r"got (\w+)").unwrap())
+        .unwrap();
+    assert_eq!(m.groups[1].as_deref(), Some("secret"));
+    let err = session.expect_regex(&Regex::new("x").unwrap()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    p.wait().unwrap();
+}
@@ -0,0 +1,33 @@
+use crate::{Exec, Popen, PopenConfig, PopenError};
+
+#[test]
+fn program_name_rejected() {
+    match Exec::cmd("true").join() {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("unexpected {:?}", other),
+    }
+    match Popen::create(["true"], PopenConfig::default()) {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn program_path() {
+    assert!(Exec::cmd("/bin/sh")
+        .args(["-c", "true"])
+        .join()
+        .unwrap()
+        .success());
+    // the executable is used as given
+    let plan = Exec::cmd("sh").explain();
+    assert_eq!(plan.executable, std::path::Path::new("sh"));
+}
+
+#[test]
+#[cfg(all(unix, not(feature = "no-shell")))]
+fn shell_by_path() {
+    let c = Exec::shell("echo foo").capture().unwrap();
+    assert_eq!(c.stdout_str(), "foo\n");
+}
@@ -1,40 +1,275 @@
 use std::ffi::OsString;
+use std::io;
+use std::os::unix::io::AsRawFd;
 
-use crate::unix::PopenExt;
-use crate::{ExitStatus, Popen, PopenConfig, Redirection};
-
-use libc;
+use crate::tests::common::prog;
+use crate::unix::{ExecExt, JobExt, PopenExt, Resource, Sandbox, SigPipePolicy, Signal};
+use crate::{Exec, ExitStatus, NullFile, Popen, PopenConfig, PopenError, Redirection};
 
 #[test]
 fn err_terminate() {
-    let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
+    let mut p = Popen::create(&[prog("sleep"), "5"], PopenConfig::default()).unwrap();
     assert!(p.poll().is_none());
     p.terminate().unwrap();
     assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGTERM as u8));
 }
 
+#[test]
+fn terminate_signal() {
+    let mut p = Popen::create(
+        [prog("sleep"), "5"],
+        PopenConfig {
+            terminate_signal: libc::SIGINT,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    p.terminate().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGINT as u8));
+}
+
+#[test]
+fn exec_terminate_signal() {
+    let mut p = Exec::cmd(prog("sleep"))
+        .arg("5")
+        .terminate_signal(libc::SIGUSR1)
+        .popen()
+        .unwrap();
+    p.terminate().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGUSR1 as u8));
+}
+
+#[test]
+fn join_timeout_kill_terminate_signal() {
+    use std::time::Duration;
+
+    let tmpdir = tempfile::TempDir::new().unwrap();
+    let marker = tmpdir.path().join("marker");
+    let script = format!(
+        "trap 'touch {}; exit' USR1; sleep 5 & wait",
+        marker.display()
+    );
+    let status = Exec::cmd(prog("sh"))
+        .args(["-c", &script])
+        .terminate_signal(libc::SIGUSR1)
+        .join_timeout_kill(Duration::from_millis(200))
+        .unwrap();
+    assert_eq!(status, None);
+    assert!(marker.exists());
+}
+
+#[test]
+fn wait_in_background() {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let p = Exec::cmd(prog("sleep"))
+        .arg("0.2")
+        .wait_in_background()
+        .popen()
+        .unwrap();
+    let pid = p.pid().unwrap() as i32;
+    let start = Instant::now();
+    drop(p);
+    assert!(start.elapsed() < Duration::from_millis(100));
+    // a zombie can still be signaled, a reaped process can't
+    while unsafe { libc::kill(pid, 0) } == 0 {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn kill_on_drop() {
+    use std::time::{Duration, Instant};
+
+    let p = Exec::cmd(prog("sleep"))
+        .arg("5")
+        .kill_on_drop()
+        .popen()
+        .unwrap();
+    let pid = p.pid().unwrap() as i32;
+    let start = Instant::now();
+    drop(p);
+    assert!(start.elapsed() < Duration::from_secs(2));
+    // killed and reaped
+    assert_ne!(unsafe { libc::kill(pid, 0) }, 0);
+}
+
+#[test]
+fn pipeline_kill_on_drop() {
+    use std::time::{Duration, Instant};
+
+    let v = (Exec::cmd(prog("sleep")).arg("5") | Exec::cmd(prog("sleep")).arg("5"))
+        .kill_on_drop()
+        .popen()
+        .unwrap();
+    let pids: Vec<_> = v.iter().map(|p| p.pid().unwrap() as i32).collect();
+    let start = Instant::now();
+    drop(v);
+    assert!(start.elapsed() < Duration::from_secs(2));
+    for pid in pids {
+        assert_ne!(unsafe { libc::kill(pid, 0) }, 0);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn exit_channel_without_wait() {
+    use std::time::Duration;
+
+    let mut p = Exec::cmd(prog("sh"))
+        .arg("-c")
+        .arg("kill $$")
+        .popen()
+        .unwrap();
+    let rx = p.exit_channel().unwrap();
+    // reported while the owner isn't waiting, and left for wait() to reap
+    let status = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(status, ExitStatus::Signaled(libc::SIGTERM as u8));
+    assert_eq!(p.wait().unwrap(), status);
+}
+
 #[test]
 fn waitpid_echild() {
-    let mut p = Popen::create(&["true"], PopenConfig::default()).unwrap();
+    let mut p = Popen::create(&[prog("true")], PopenConfig::default()).unwrap();
     let pid = p.pid().unwrap() as i32;
     let mut status = 0 as libc::c_int;
     let wpid = unsafe { libc::waitpid(pid, &mut status, 0) };
     assert_eq!(wpid, pid);
     assert_eq!(status, 0);
-    assert_eq!(p.wait().unwrap(), ExitStatus::Undetermined);
+    assert_eq!(p.wait().unwrap(), ExitStatus::ReapedElsewhere);
+}
+
+#[test]
+#[cfg(not(feature = "no-shell"))]
+fn fd_audit() {
+    crate::set_fd_audit(true);
+    let c = { Exec::shell("cat; echo foo >&2") | Exec::cmd(prog("cat")) }
+        .stdin("bar\n")
+        .capture()
+        .unwrap();
+    let merged = Exec::shell("echo foo >&2")
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture();
+    let out = Exec::cmd(prog("true")).stdin_null().stdout_null().join();
+    crate::set_fd_audit(false);
+    assert_eq!(c.stdout_str(), "bar\n");
+    assert_eq!(c.stderr_str(), "foo\n");
+    assert_eq!(merged.unwrap().stdout_str(), "foo\n");
+    assert!(out.unwrap().success());
+}
+
+fn is_cloexec(f: &impl AsRawFd) -> bool {
+    let flags = unsafe { libc::fcntl(f.as_raw_fd(), libc::F_GETFD) };
+    flags & libc::FD_CLOEXEC != 0
+}
+
+#[test]
+fn shared_file_left_cloexec() {
+    use std::sync::Arc;
+
+    let tmpdir = tempfile::TempDir::new().unwrap();
+    let file = Arc::new(std::fs::File::create(tmpdir.path().join("out")).unwrap());
+    let status = Exec::cmd(prog("sh"))
+        .args(["-c", "echo foo"])
+        .stdout(Redirection::SharedFile(Arc::clone(&file)))
+        .join()
+        .unwrap();
+    assert!(status.success());
+    assert!(is_cloexec(&*file));
+    let contents = std::fs::read_to_string(tmpdir.path().join("out")).unwrap();
+    assert_eq!(contents, "foo\n");
+}
+
+#[test]
+fn pipeline_stderr_pipe_cloexec() {
+    let p = { Exec::cmd(prog("true")) | Exec::cmd(prog("true")) }
+        .stderr_all(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    assert!(is_cloexec(p.last().unwrap().stderr.as_ref().unwrap()));
+}
+
+#[test]
+fn assert_no_child_fds() {
+    let mut p = Exec::cmd(prog("cat"))
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    p.assert_no_child_fds();
+    p.stdin.take();
+    p.wait().unwrap();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "still open in the parent")]
+fn assert_no_child_fds_leak() {
+    let (read, write) = crate::make_pipe().unwrap();
+    let _leaked = write.try_clone().unwrap();
+    let p = Exec::cmd(prog("true"))
+        .stdout(Redirection::File(write))
+        .detached()
+        .popen()
+        .unwrap();
+    drop(read);
+    p.assert_no_child_fds();
+}
+
+#[test]
+fn sigchld_notifier() {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    crate::unix::install_sigchld_notifier().unwrap();
+    crate::unix::install_sigchld_notifier().unwrap();
+    let mut p = Exec::cmd(prog("sleep")).arg("0.2").popen().unwrap();
+    assert_eq!(p.wait_timeout(Duration::from_millis(20)).unwrap(), None);
+    let start = Instant::now();
+    let status = p.wait_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(status, Some(ExitStatus::Exited(0)));
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    let waiters: Vec<_> = (0..8)
+        .map(|i| {
+            thread::spawn(move || {
+                let mut p = Exec::cmd(prog("sh"))
+                    .args(["-c", &format!("sleep 0.{}; exit {}", i, i)])
+                    .popen()
+                    .unwrap();
+                p.wait_timeout(Duration::from_secs(5)).unwrap()
+            })
+        })
+        .collect();
+    for (i, waiter) in waiters.into_iter().enumerate() {
+        assert_eq!(waiter.join().unwrap(), Some(ExitStatus::Exited(i as u32)));
+    }
 }
 
 #[test]
 fn send_signal() {
-    let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
+    let mut p = Popen::create(&[prog("sleep"), "5"], PopenConfig::default()).unwrap();
     p.send_signal(libc::SIGUSR1).unwrap();
     assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGUSR1 as u8));
 }
 
+#[test]
+fn send_signal_enum() {
+    let mut p = Popen::create([prog("sleep"), "5"], PopenConfig::default()).unwrap();
+    p.send_signal(Signal::Hup).unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGHUP as u8));
+    assert_eq!(Signal::from(libc::SIGTERM), Signal::Term);
+    assert_eq!(Signal::from(libc::SIGQUIT), Signal::Custom(libc::SIGQUIT));
+    assert_eq!(i32::from(Signal::Kill), libc::SIGKILL);
+}
+
 #[test]
 fn env_set_all_1() {
     let mut p = Popen::create(
-        &["env"],
+        &[prog("env")],
         PopenConfig {
             stdout: Redirection::Pipe,
             env: Some(Vec::new()),
@@ -49,7 +284,7 @@ fn env_set_all_1() {
 #[test]
 fn env_set_all_2() {
     let mut p = Popen::create(
-        &["env"],
+        &[prog("env")],
         PopenConfig {
             stdout: Redirection::Pipe,
             env: Some(vec![(OsString::from("FOO"), OsString::from("bar"))]),
@@ -60,3 +295,1083 @@ fn env_set_all_2() {
     let (out, _err) = p.communicate(None).unwrap();
     assert_eq!(out.unwrap().trim_end(), "FOO=bar");
 }
+
+#[test]
+fn exec_setpgid() {
+    let mut p = Exec::cmd(prog("sleep")).arg("5").setpgid().popen().unwrap();
+    let pid = p.pid().unwrap() as libc::pid_t;
+    assert_eq!(unsafe { libc::getpgid(pid) }, pid);
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn resolve_program_in_cwd() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    let tmpdir = TempDir::new().unwrap();
+    fs::create_dir(tmpdir.path().join("bin")).unwrap();
+    let script = tmpdir.path().join("bin").join("hello");
+    fs::write(&script, "#!/bin/sh\nprintf hello\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let c = Exec::cmd("bin/hello")
+        .cwd(tmpdir.path())
+        .resolve_program_in_cwd(true)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "hello");
+
+    let ret = Exec::cmd("bin/hello")
+        .cwd(tmpdir.path())
+        .resolve_program_in_cwd(false)
+        .join();
+    assert!(ret.is_err());
+}
+
+#[test]
+#[cfg(not(feature = "no-path-search"))]
+fn child_path_search() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    let tmpdir = TempDir::new().unwrap();
+    let script = tmpdir.path().join("hello-from-child-path");
+    fs::write(&script, "#!/bin/sh\nprintf hello\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let cmd = Exec::cmd("hello-from-child-path").env("PATH", tmpdir.path());
+    assert_eq!(cmd.clone().explain().executable, script);
+    assert_eq!(cmd.clone().capture().unwrap().stdout_str(), "hello");
+    let c = cmd.fast_spawn().capture().unwrap();
+    assert_eq!(c.stdout_str(), "hello");
+}
+
+#[test]
+fn long_cwd() {
+    use std::fs;
+    use tempfile::TempDir;
+
+    // longer than the path std converts on the stack, so the child must
+    // change to a directory prepared by the parent
+    let tmpdir = TempDir::new().unwrap();
+    let mut dir = tmpdir.path().to_path_buf();
+    for _ in 0..8 {
+        dir.push("d".repeat(100));
+    }
+    fs::create_dir_all(&dir).unwrap();
+    let c = Exec::cmd(prog("pwd")).cwd(&dir).capture().unwrap();
+    assert_eq!(
+        fs::canonicalize(c.stdout_str().trim_end()).unwrap(),
+        fs::canonicalize(&dir).unwrap()
+    );
+}
+
+#[test]
+#[cfg(not(feature = "no-path-search"))]
+fn exec_script() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    let tmpdir = TempDir::new().unwrap();
+    let script = tmpdir.path().join("script");
+    fs::write(&script, "#!/bin/sh\nprintf '%s' \"$1\"\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let c = Exec::script(&script).arg("foo").capture().unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+}
+
+#[test]
+fn make_pipe_buffer_size() {
+    use crate::{make_pipe_with, PipeOptions};
+    use std::io::{Read, Write};
+
+    let (mut read, mut write) = make_pipe_with(&PipeOptions {
+        buffer_size: 256 * 1024,
+        ..Default::default()
+    })
+    .unwrap();
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        let size = unsafe { libc::fcntl(write.as_raw_fd(), libc::F_GETPIPE_SZ) };
+        assert!(size >= 256 * 1024, "size {}", size);
+        // the whole buffer can be filled without a reader
+        write.write_all(&vec![b'x'; 256 * 1024]).unwrap();
+    }
+    #[cfg(not(target_os = "linux"))]
+    write.write_all(b"foo").unwrap();
+    drop(write);
+    let mut data = vec![];
+    read.read_to_end(&mut data).unwrap();
+    assert!(!data.is_empty());
+}
+
+#[test]
+fn os_util_killpg() {
+    let mut p = Exec::cmd(prog("sleep")).arg("5").setpgid().popen().unwrap();
+    crate::os_util::killpg(p.pid().unwrap(), libc::SIGTERM).unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGTERM as u8));
+}
+
+#[test]
+fn send_signal_group() {
+    let mut p = Exec::cmd(prog("sleep")).arg("5").setpgid().popen().unwrap();
+    p.send_signal_group(Signal::Term).unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGTERM as u8));
+    // finished, nothing to do
+    p.send_signal_group(Signal::Term).unwrap();
+}
+
+#[test]
+fn send_signal_group_own_group() {
+    let mut p = Exec::cmd(prog("sleep")).arg("5").popen().unwrap();
+    assert!(p.send_signal_group(Signal::Term).is_err());
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn signal_group_checked() {
+    let mut job = [
+        Exec::cmd(prog("sleep")).arg("5").setpgid().popen().unwrap(),
+        Exec::cmd(prog("sleep")).arg("5").popen().unwrap(),
+        Exec::cmd(prog("true")).popen().unwrap(),
+    ];
+    job[2].wait().unwrap();
+    assert_eq!(
+        job.signal_group_checked(Signal::Term),
+        vec![true, false, false]
+    );
+    assert_eq!(
+        job[0].wait().unwrap(),
+        ExitStatus::Signaled(libc::SIGTERM as u8)
+    );
+    job[1].kill().unwrap();
+    job[1].wait().unwrap();
+}
+
+#[test]
+fn os_util_dup2() {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+    let (mut read, write) = crate::os_util::make_pipe().unwrap();
+    // a descriptor the kernel has allocated to us, which dup2() replaces
+    let target = File::open("/dev/null").unwrap().into_raw_fd();
+    unsafe {
+        crate::os_util::dup2(&write, target).unwrap();
+    }
+    drop(write);
+    let mut target = unsafe { File::from_raw_fd(target) };
+    std::io::Write::write_all(&mut target, b"foo").unwrap();
+    drop(target);
+    let mut s = String::new();
+    read.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "foo");
+}
+
+#[test]
+fn substitute_non_utf8() {
+    use std::collections::HashMap;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut map = HashMap::new();
+    map.insert("name", OsStr::from_bytes(b"\xfdx"));
+    let c = Exec::cmd(prog("printf"))
+        .args([OsStr::new("%s"), OsStr::from_bytes(b"\xff{name}\xfe")])
+        .substitute(&map)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout, b"\xff\xfdx\xfe");
+}
+
+#[test]
+fn exec_foreground() {
+    // The test process doesn't necessarily have a controlling terminal,
+    // so the test runs itself under a pty, on which it is in the
+    // foreground, and checks that the terminal is handed over to the
+    // subprocess and back.
+    const INNER: &str = "SUBPROCESS_TEST_FOREGROUND_INNER";
+    if std::env::var_os(INNER).is_some() {
+        let tty = std::fs::File::open("/dev/tty").unwrap();
+        let own_pgrp = crate::posix::getpgrp();
+        assert_eq!(crate::posix::tcgetpgrp(tty.as_raw_fd()).unwrap(), own_pgrp);
+        let mut p = Exec::cmd(prog("sleep"))
+            .arg("5")
+            .foreground()
+            .popen()
+            .unwrap();
+        let pid = p.pid().unwrap();
+        assert_eq!(crate::posix::tcgetpgrp(tty.as_raw_fd()).unwrap(), pid);
+        p.kill().unwrap();
+        p.wait().unwrap();
+        drop(p);
+        assert_eq!(crate::posix::tcgetpgrp(tty.as_raw_fd()).unwrap(), own_pgrp);
+        return;
+    }
+    // the pty is kept open until the test has finished, as closing it
+    // would hang up the test
+    let mut p = Exec::cmd(std::env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "tests::posix::exec_foreground",
+            "--test-threads=1",
+        ])
+        .env(INNER, "1")
+        .stdin(Redirection::Pty)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .popen()
+        .unwrap();
+    let mut output = String::new();
+    io::Read::read_to_string(&mut p.stdout.take().unwrap(), &mut output).unwrap();
+    assert!(p.wait().unwrap().success(), "{}", output);
+    assert!(output.contains("1 passed"), "{}", output);
+}
+
+#[cfg(feature = "ctrlc-guard")]
+#[test]
+fn ctrlc_guard_forwards() {
+    let p = Exec::cmd(prog("sleep")).arg("5").setpgid().popen().unwrap();
+    let guard = crate::ctrlc_guard(vec![p]).unwrap();
+    assert!(!guard.interrupted());
+    // call the handler directly, as raising SIGINT would also reach the
+    // guards of the tests running in parallel
+    crate::ctrlc::os::on_sigint(libc::SIGINT);
+    assert!(guard.interrupted());
+    assert_eq!(
+        guard.wait().unwrap(),
+        ExitStatus::Signaled(libc::SIGINT as u8)
+    );
+}
+
+#[cfg(target_os = "macos")]
+#[test]
+fn exec_sandbox_profile() {
+    let status = Exec::cmd(prog("cat"))
+        .arg("/etc/hosts")
+        .stdout(Redirection::Pipe)
+        .sandbox_profile("(version 1) (allow default) (deny file-read* (literal \"/etc/hosts\") (literal \"/private/etc/hosts\"))")
+        .capture()
+        .unwrap()
+        .exit_status;
+    assert!(!status.success());
+    assert!(Exec::cmd(prog("true"))
+        .sandbox_profile("(version")
+        .join()
+        .is_err());
+}
+
+#[cfg(target_os = "openbsd")]
+#[test]
+fn exec_pledge() {
+    assert!(Exec::cmd(prog("true"))
+        .pledge("stdio")
+        .join()
+        .unwrap()
+        .success());
+    let status = Exec::cmd(prog("cat"))
+        .arg("/etc/hosts")
+        .stdout(Redirection::Pipe)
+        .pledge("stdio")
+        .capture()
+        .unwrap()
+        .exit_status;
+    assert!(!status.success());
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[test]
+fn communicate_uring_large() {
+    let input: Vec<u8> = (0..4_000_000u32).map(|i| (i % 251) as u8).collect();
+    let mut p = Popen::create(
+        [prog("sh"), "-c", "tee /dev/stderr"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            stderr: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut comm = p.communicate_start(Some(input.clone())).limit_size(100_000);
+    let (mut out, mut err) = (vec![], vec![]);
+    loop {
+        let (o, e) = comm.read().unwrap();
+        let (o, e) = (o.unwrap(), e.unwrap());
+        if o.is_empty() && e.is_empty() {
+            break;
+        }
+        assert!(o.len() + e.len() <= 100_000);
+        out.extend(o);
+        err.extend(e);
+    }
+    assert!(out == input);
+    assert!(err == input);
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn sandbox_rlimit() {
+    let c = Exec::cmd(prog("sh"))
+        .args(["-c", "ulimit -c; ulimit -n"])
+        .sandbox(
+            Sandbox::new()
+                .rlimit(Resource::Core, 0, 0)
+                .rlimit(Resource::OpenFiles, 100, 200),
+        )
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "0\n100\n");
+}
+
+#[test]
+fn exec_limit() {
+    let c = Exec::cmd(prog("sh"))
+        .args(["-c", "ulimit -c; ulimit -n"])
+        .sandbox(Sandbox::new().rlimit(Resource::Core, 0, 0))
+        .limit(Resource::OpenFiles, 100, 200)
+        .limit(Resource::OpenFiles, 50, 200)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "0\n50\n");
+}
+
+#[test]
+fn sandbox_close_fds() {
+    let (read_end, _write_end) = crate::make_pipe().unwrap();
+    let fd = read_end.as_raw_fd();
+    let check = format!("test -e /dev/fd/{}", fd);
+    // the pipe is inheritable, so the child sees it unless it's closed
+    assert!(Exec::cmd(prog("sh"))
+        .args(["-c", &check])
+        .join()
+        .unwrap()
+        .success());
+    let status = Exec::cmd(prog("sh"))
+        .args(["-c", &check])
+        .sandbox(Sandbox::new().close_fds())
+        .join()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn sandbox_validate() {
+    let invalid = Sandbox::new().rlimit(Resource::Cpu, 10, 5);
+    assert!(invalid.validate().is_err());
+    match Exec::cmd(prog("true")).sandbox(invalid).join() {
+        Err(PopenError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(Sandbox::minimal_io().validate().is_ok());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn sandbox_seccomp() {
+    let status = |line: &str| {
+        let c = Exec::cmd(prog("grep"))
+            .arg(line)
+            .arg("/proc/self/status")
+            .sandbox(Sandbox::minimal_io())
+            .capture()
+            .unwrap();
+        c.stdout_str().split_whitespace().nth(1).unwrap().to_owned()
+    };
+    assert_eq!(status("NoNewPrivs:"), "1");
+    // 2 is SECCOMP_MODE_FILTER
+    assert_eq!(status("Seccomp:"), "2");
+
+    // seccomp requires no_new_privs
+    let invalid = Sandbox::new().deny_syscalls(&[libc::SYS_socket]);
+    assert!(invalid.validate().is_err());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn sandbox_namespaces() {
+    use crate::unix::Namespace;
+
+    let sandbox = Sandbox::new()
+        .namespace(Namespace::User)
+        .namespace(Namespace::Uts)
+        .namespace(Namespace::Network);
+    let c = match Exec::cmd(prog("sh"))
+        .args(["-c", "id -u; cut -d: -f1 -s /proc/self/net/dev"])
+        .sandbox(sandbox)
+        .capture()
+    {
+        Ok(c) => c,
+        // unprivileged user namespaces are disabled on some systems
+        Err(PopenError::IoError(ref e)) if e.kind() == io::ErrorKind::PermissionDenied => return,
+        Err(e) => panic!("{}", e),
+    };
+    let uid = unsafe { libc::getuid() };
+    assert_eq!(c.stdout_str().replace(' ', ""), format!("{}\nlo\n", uid));
+}
+
+#[test]
+fn setid_failed() {
+    // setgid() is called first, and as root only fails for an invalid id
+    let root = unsafe { libc::getuid() } == 0;
+    let gid = if root { u32::MAX } else { 12345 };
+    let err = Exec::cmd(prog("true"))
+        .setuid(65534)
+        .setgid(gid)
+        .join()
+        .unwrap_err();
+    match err {
+        PopenError::SetIdFailed {
+            call,
+            id,
+            ref error,
+        } => {
+            assert_eq!((call, id), ("setgid", gid));
+            if !root {
+                assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+                assert!(err.to_string().contains("requires running as root"));
+            }
+        }
+        ref other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn setid_as_root() {
+    if unsafe { libc::getuid() } != 0 {
+        return;
+    }
+    // the group is changed while still root, and the groups of root are
+    // dropped
+    let c = Exec::cmd(prog("sh"))
+        .args(["-c", "id -u; id -g; id -G"])
+        .setuid(65534)
+        .setgid(12345)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "65534\n12345\n12345\n");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn sandbox_cgroup_missing() {
+    match Exec::cmd(prog("true"))
+        .sandbox(Sandbox::new().cgroup("/nonexistent/cgroup"))
+        .join()
+    {
+        Err(PopenError::IoError(e)) => {
+            assert_eq!(e.kind(), io::ErrorKind::NotFound);
+            // names the step that failed
+            assert!(e.to_string().starts_with("applying the sandbox failed"));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn pty_isatty() {
+    let c = Exec::cmd(prog("sh"))
+        .args(["-c", "test -t 0 && test -t 1 && test -t 2 && echo yes"])
+        .pty()
+        .capture()
+        .unwrap();
+    // the terminal translates \n to \r\n
+    assert_eq!(c.stdout_str(), "yes\r\n");
+    assert!(c.stderr.is_empty());
+}
+
+#[test]
+fn pty_controlling_terminal() {
+    // /dev/tty can only be opened by a process with a controlling terminal
+    let c = Exec::cmd(prog("sh"))
+        .args(["-c", "echo foo >/dev/tty"])
+        .pty()
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo\r\n");
+
+    let c = Exec::cmd(prog("tty")).pty().capture().unwrap();
+    assert!(c.stdout_str().starts_with("/dev/"));
+    assert!(c.success());
+}
+
+#[test]
+fn pty_interactive() {
+    use std::io::Write;
+
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "read x; echo $x; echo done >&2"])
+        .pty()
+        .popen()
+        .unwrap();
+    assert!(p.stderr.is_none());
+    p.stdin.take().unwrap().write_all(b"hello\n").unwrap();
+    let (out, _) = p.communicate(None).unwrap();
+    // the input is echoed by the terminal before it is printed
+    assert_eq!(out.unwrap(), "hello\r\nhello\r\ndone\r\n");
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(0));
+}
+
+#[test]
+fn pty_stdout_only() {
+    let c = Exec::cmd(prog("sh"))
+        .args([
+            "-c",
+            "test -t 0 || echo in; test -t 1 && echo out; echo err >&2",
+        ])
+        .stdin(NullFile)
+        .stdout(Redirection::Pty)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "in\r\nout\r\n");
+    assert_eq!(c.stderr_str(), "err\n");
+}
+
+#[test]
+fn pty_reject_foreground() {
+    let result = Popen::create(
+        [prog("true")],
+        PopenConfig {
+            stdout: Redirection::Pty,
+            foreground: true,
+            ..Default::default()
+        },
+    );
+    assert!(matches!(result, Err(PopenError::LogicError(_))));
+}
+
+// Return the session ID from `stat`, the contents of a /proc/<pid>/stat
+// file.
+#[cfg(target_os = "linux")]
+fn stat_session(stat: &str) -> u32 {
+    let after_comm = &stat[stat.rfind(')').unwrap() + 1..];
+    after_comm
+        .split_whitespace()
+        .nth(3)
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn setsid() {
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "read x; cat /proc/$$/stat"])
+        .setsid()
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let pid = p.pid().unwrap();
+    let (out, _) = p.communicate(Some("\n")).unwrap();
+    assert_eq!(stat_session(&out.unwrap()), pid);
+}
+
+#[test]
+fn race_kills_tree() {
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+    let loser = Exec::cmd(prog("sh")).args(["-c", "sleep 100 & echo $! > \"$1\"; wait", "sh"]);
+    let loser = loser.arg(&path);
+    Exec::cmd(prog("sh"))
+        .args(["-c", "sleep 0.5"])
+        .race(vec![loser])
+        .unwrap();
+    let pid: i32 = std::fs::read_to_string(&path)
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    let start = Instant::now();
+    while unsafe { libc::kill(pid, 0) } == 0 {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn clone3_pidfd() {
+    // the child is created with a pidfd of its own, found among the open
+    // descriptors of the current process
+    let mut p = Exec::cmd(prog("sleep")).arg("100").popen().unwrap();
+    let pid_line = format!("Pid:\t{}", p.pid().unwrap());
+    let has_pidfd = std::fs::read_dir("/proc/self/fdinfo")
+        .unwrap()
+        .any(|entry| {
+            std::fs::read_to_string(entry.unwrap().path())
+                .map(|info| info.lines().any(|line| line == pid_line))
+                .unwrap_or(false)
+        });
+    assert!(has_pidfd);
+    p.send_signal(Signal::Term).unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGTERM as u8));
+}
+
+#[test]
+fn fast_spawn() {
+    let c = Exec::cmd(prog("sh"))
+        .args(["-c", "read x; echo $x $FOO; echo err >&2"])
+        .env("FOO", "bar")
+        .fast_spawn()
+        .stdin("foo\n")
+        .output()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo bar\n");
+    assert_eq!(c.stderr_str(), "err\n");
+}
+
+#[test]
+#[cfg(not(feature = "no-path-search"))]
+fn fast_spawn_not_found() {
+    let err = Exec::cmd("nosuchcommand").fast_spawn().popen().unwrap_err();
+    match err {
+        PopenError::IoError(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        other => panic!("unexpected error {:?}", other),
+    }
+}
+
+#[test]
+fn fast_spawn_default_sigpipe() {
+    let status = Exec::cmd(prog("sh"))
+        .args(["-c", "kill -PIPE $$"])
+        .fast_spawn()
+        .join()
+        .unwrap();
+    assert_eq!(status, ExitStatus::Signaled(libc::SIGPIPE as u8));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn fast_spawn_setpgid() {
+    let mut p = Exec::cmd(prog("cat"))
+        .arg("/proc/self/stat")
+        .fast_spawn()
+        .setpgid()
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let pid = p.pid().unwrap();
+    let (out, _) = p.communicate(None).unwrap();
+    let out = out.unwrap();
+    let after_comm = &out[out.rfind(')').unwrap() + 1..];
+    let pgrp: u32 = after_comm
+        .split_whitespace()
+        .nth(2)
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(pgrp, pid);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn priority() {
+    use crate::Priority;
+
+    let nice = |priority| {
+        let out = Exec::cmd(prog("cat"))
+            .arg("/proc/self/stat")
+            .priority(priority)
+            .capture()
+            .unwrap()
+            .stdout_str();
+        let after_comm = &out[out.rfind(')').unwrap() + 1..];
+        after_comm.split_whitespace().nth(16).unwrap().to_owned()
+    };
+    assert_eq!(nice(Priority::Idle), "19");
+    assert_eq!(nice(Priority::BelowNormal), "10");
+    assert!(Exec::cmd(prog("true"))
+        .priority(Priority::Idle)
+        .fast_spawn()
+        .join()
+        .is_err());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn io_priority() {
+    use crate::unix::IoPriority;
+
+    let ioprio = |priority| {
+        let mut p = Exec::cmd(prog("sleep"))
+            .arg("5")
+            .io_priority(priority)
+            .kill_on_drop()
+            .popen()
+            .unwrap();
+        let pid = p.pid().unwrap();
+        let value = unsafe { libc::syscall(libc::SYS_ioprio_get, 1, pid) };
+        p.kill().unwrap();
+        value
+    };
+    assert_eq!(ioprio(IoPriority::Idle), 3 << 13);
+    assert_eq!(ioprio(IoPriority::BestEffort(6)), 2 << 13 | 6);
+    match Exec::cmd(prog("true"))
+        .io_priority(IoPriority::BestEffort(8))
+        .join()
+    {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+#[test]
+fn umask() {
+    let out = Exec::cmd(prog("sh"))
+        .args(["-c", "umask"])
+        .umask(0o027)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str().trim(), "0027");
+    match Exec::cmd(prog("true")).fast_spawn().umask(0o027).popen() {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+#[test]
+fn fast_spawn_unsupported() {
+    match Exec::cmd(prog("true")).fast_spawn().cwd("/").popen() {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+fn raise_sigpipe(exec: Exec) -> ExitStatus {
+    // a signal ignored on entry to the shell stays ignored
+    exec.args(["-c", "kill -PIPE $$"]).join().unwrap()
+}
+
+#[test]
+fn sigpipe_policy() {
+    assert_eq!(
+        raise_sigpipe(Exec::cmd(prog("sh"))),
+        ExitStatus::Signaled(libc::SIGPIPE as u8)
+    );
+    assert_eq!(
+        raise_sigpipe(Exec::cmd(prog("sh")).sigpipe(SigPipePolicy::Ignore)),
+        ExitStatus::Exited(0)
+    );
+    // the test harness, like other Rust programs, ignores SIGPIPE
+    assert_eq!(
+        raise_sigpipe(Exec::cmd(prog("sh")).sigpipe(SigPipePolicy::Inherit)),
+        ExitStatus::Exited(0)
+    );
+}
+
+#[test]
+fn fast_spawn_sigpipe_policy() {
+    assert_eq!(
+        raise_sigpipe(
+            Exec::cmd(prog("sh"))
+                .fast_spawn()
+                .sigpipe(SigPipePolicy::Inherit)
+        ),
+        ExitStatus::Exited(0)
+    );
+    match Exec::cmd(prog("true"))
+        .fast_spawn()
+        .sigpipe(SigPipePolicy::Ignore)
+        .popen()
+    {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+#[test]
+fn signal_mask_block() {
+    // a blocked signal stays pending, so the shell survives it
+    for fast_spawn in [false, true] {
+        let mut exec = Exec::cmd(prog("sh")).args(["-c", "kill -USR1 $$; echo survived"]);
+        if fast_spawn {
+            exec = exec.fast_spawn();
+        }
+        let c = exec
+            .signal_mask(false, &[Signal::Usr1])
+            .stdout(Redirection::Pipe)
+            .capture()
+            .unwrap();
+        assert_eq!(c.stdout_str(), "survived\n");
+        assert!(c.success());
+    }
+}
+
+#[test]
+fn signal_mask_reset_all() {
+    // no other test uses SIGUSR2, so ignoring it briefly is harmless
+    let previous = unsafe { libc::signal(libc::SIGUSR2, libc::SIG_IGN) };
+    let status = |fast_spawn: bool, reset_all: bool| {
+        let mut exec = Exec::cmd(prog("sh")).args(["-c", "kill -USR2 $$"]);
+        if fast_spawn {
+            exec = exec.fast_spawn();
+        }
+        exec.signal_mask(reset_all, &[]).join().unwrap()
+    };
+    let results: Vec<_> = [(false, false), (false, true), (true, false), (true, true)]
+        .iter()
+        .map(|&(fast_spawn, reset_all)| status(fast_spawn, reset_all))
+        .collect();
+    unsafe { libc::signal(libc::SIGUSR2, previous) };
+    let killed = ExitStatus::Signaled(libc::SIGUSR2 as u8);
+    assert_eq!(
+        results,
+        [ExitStatus::Exited(0), killed, ExitStatus::Exited(0), killed]
+    );
+    // SIGPIPE is still governed by the sigpipe policy
+    assert_eq!(
+        raise_sigpipe(
+            Exec::cmd(prog("sh"))
+                .signal_mask(true, &[])
+                .sigpipe(SigPipePolicy::Inherit)
+        ),
+        ExitStatus::Exited(0)
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn spawn_detached() {
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("info");
+    let script = format!(
+        "{{ cat /proc/$$/stat; readlink /proc/$$/fd/0 /proc/$$/fd/2; }} > {}.tmp; mv {0}.tmp {0}",
+        path.display()
+    );
+    let pid = Exec::cmd(prog("sh"))
+        .args(["-c", &script])
+        .spawn_detached()
+        .unwrap();
+    let start = Instant::now();
+    while !path.exists() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let info = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = info.lines().collect();
+    assert_eq!(stat_session(lines[0]), pid);
+    assert_eq!(&lines[1..], ["/dev/null", "/dev/null"]);
+}
+
+#[test]
+fn spawn_detached_pipe() {
+    assert!(matches!(
+        Exec::cmd(prog("true"))
+            .stdout(Redirection::Pipe)
+            .spawn_detached(),
+        Err(PopenError::LogicError(_))
+    ));
+}
+
+#[test]
+fn setsid_foreground() {
+    assert!(matches!(
+        Exec::cmd(prog("true")).setsid().foreground().popen(),
+        Err(PopenError::LogicError(_))
+    ));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn die_with_parent() {
+    use std::thread;
+    use std::time::Duration;
+
+    // Linux sends the signal once the thread that started the child exits
+    let mut p = thread::spawn(|| {
+        Exec::cmd(prog("sleep"))
+            .arg("1000")
+            .die_with_parent()
+            .popen()
+            .unwrap()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(
+        p.wait_timeout(Duration::from_secs(5)).unwrap(),
+        Some(ExitStatus::Signaled(libc::SIGKILL as u8))
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn parent_death_signal() {
+    use std::thread;
+    use std::time::Duration;
+
+    let mut p = thread::spawn(|| {
+        Exec::cmd(prog("sleep"))
+            .arg("1000")
+            .parent_death_signal(Signal::Term)
+            .die_with_parent()
+            .popen()
+            .unwrap()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(
+        p.wait_timeout(Duration::from_secs(5)).unwrap(),
+        Some(ExitStatus::Signaled(libc::SIGTERM as u8))
+    );
+    match Exec::cmd(prog("true"))
+        .fast_spawn()
+        .parent_death_signal(Signal::Term)
+        .popen()
+    {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+#[test]
+fn die_with_parent_runs() {
+    // the process runs normally, and on systems without PR_SET_PDEATHSIG
+    // its watcher is released once it has been waited for
+    let c = Exec::cmd(prog("echo"))
+        .arg("foo")
+        .die_with_parent()
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo\n");
+    assert!(c.success());
+}
+
+#[test]
+fn die_with_parent_fast_spawn() {
+    match Exec::cmd(prog("true"))
+        .fast_spawn()
+        .die_with_parent()
+        .popen()
+    {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+// Start a shell running a background sleep, and return it along with the
+// pid of the sleep.
+#[cfg(target_os = "linux")]
+fn start_with_grandchild() -> (Popen, u32) {
+    use std::io::{BufRead, BufReader};
+
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "sleep 1000 & echo $!; wait"])
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(p.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    (p, line.trim().parse().unwrap())
+}
+
+// Wait for the process to exit, after which it is either reaped by its new
+// parent or left as a zombie.
+#[cfg(target_os = "linux")]
+fn wait_gone(pid: u32) {
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    while let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        if stat[stat.rfind(')').unwrap()..].starts_with(") Z") {
+            break;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn terminate_tree() {
+    let (mut p, grandchild) = start_with_grandchild();
+    p.terminate_tree().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGTERM as u8));
+    wait_gone(grandchild);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn kill_tree() {
+    let (mut p, grandchild) = start_with_grandchild();
+    p.kill_tree().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGKILL as u8));
+    wait_gone(grandchild);
+}
+
+// Start a shell that runs `trap` and then loops forever, returning once
+// the trap is in place.
+fn start_trapping(trap: &str) -> Popen {
+    use std::io::{BufRead, BufReader};
+
+    let script = format!("{}; echo ready; while :; do sleep 0.1; done", trap);
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", &script])
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(p.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    p
+}
+
+#[test]
+fn shutdown_graceful() {
+    use std::time::Duration;
+
+    let mut p = start_trapping("trap 'exit 3' TERM");
+    let status = p.shutdown(Duration::from_secs(5)).unwrap();
+    assert_eq!(status, ExitStatus::Exited(3));
+}
+
+#[test]
+fn shutdown_escalates() {
+    use std::time::{Duration, Instant};
+
+    let mut p = start_trapping("trap '' TERM");
+    let start = Instant::now();
+    let status = p.shutdown(Duration::from_millis(200)).unwrap();
+    assert_eq!(status, ExitStatus::Signaled(libc::SIGKILL as u8));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn shutdown_all() {
+    use std::time::Duration;
+
+    let mut processes = Exec::cmd(prog("sleep")).arg("1000").popen_n(2).unwrap();
+    let statuses = Popen::shutdown_all(&mut processes, Duration::from_secs(5)).unwrap();
+    assert_eq!(
+        statuses,
+        [
+            ExitStatus::Signaled(libc::SIGTERM as u8),
+            ExitStatus::Signaled(libc::SIGTERM as u8)
+        ]
+    );
+    // finished processes report their status again
+    assert_eq!(
+        processes[0].shutdown(Duration::from_secs(0)).unwrap(),
+        statuses[0]
+    );
+}
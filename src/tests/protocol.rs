@@ -0,0 +1,161 @@
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use crate::tests::common::prog;
+use crate::{Exec, FramedProtocol, LineProtocol, Popen, PopenError, Redirection};
+
+// Answer each line with the line itself and "ok", or with "late" and "ok"
+// after a delay if the line is "slow".
+const RESPONDER: &str = r#"
+while read line; do
+    if [ "$line" = slow ]; then
+        echo late; sleep 0.5; echo ok
+    else
+        echo "$line"; echo ok
+    fi
+done
+"#;
+
+fn responder() -> Popen {
+    Exec::cmd(prog("sh"))
+        .args(["-c", RESPONDER])
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap()
+}
+
+#[test]
+fn line_protocol_request() {
+    let mut p = responder();
+    let mut proto = LineProtocol::new(&mut p).unwrap();
+    assert_eq!(proto.request("foo", |l| l == "ok").unwrap(), ["foo", "ok"]);
+    assert_eq!(proto.request("bar", |l| l == "ok").unwrap(), ["bar", "ok"]);
+    proto.close_stdin();
+    assert_eq!(proto.read_line().unwrap(), None);
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn line_protocol_send_read_line() {
+    let mut p = responder();
+    let mut proto = LineProtocol::new(&mut p).unwrap();
+    proto.send("foo").unwrap();
+    proto.send("bar").unwrap();
+    for expected in &["foo", "ok", "bar", "ok"] {
+        assert_eq!(proto.read_line().unwrap().unwrap(), *expected);
+    }
+    proto.close_stdin();
+    assert_eq!(proto.send("baz").unwrap_err().kind(), ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn line_protocol_timeout() {
+    let mut p = responder();
+    let mut proto = LineProtocol::new(&mut p)
+        .unwrap()
+        .timeout(Duration::from_millis(100));
+    let err = proto.request("slow", |l| l == "ok").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    // the partial response isn't lost
+    let mut proto = proto.timeout(Duration::from_secs(5));
+    assert_eq!(proto.read_line().unwrap().unwrap(), "late");
+    assert_eq!(proto.read_line().unwrap().unwrap(), "ok");
+}
+
+#[test]
+fn line_protocol_eof() {
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "read line; printf 'a\\r\\nb'"])
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let mut proto = LineProtocol::new(&mut p).unwrap();
+    let err = proto.request("go", |l| l == "end").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(proto.read_line().unwrap().unwrap(), "a");
+    assert_eq!(proto.read_line().unwrap().unwrap(), "b");
+    assert_eq!(proto.read_line().unwrap(), None);
+}
+
+#[test]
+fn line_protocol_requires_pipes() {
+    let mut p = Exec::cmd(prog("true"))
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    assert!(matches!(
+        LineProtocol::new(&mut p),
+        Err(PopenError::LogicError(_))
+    ));
+}
+
+fn framed(script: &str) -> (Popen, FramedProtocol) {
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", script])
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let proto = FramedProtocol::new(&mut p).unwrap();
+    (p, proto)
+}
+
+#[test]
+fn framed_protocol_roundtrip() {
+    // cat echoes the framed messages back
+    let (mut p, mut proto) = framed("cat");
+    proto.send(b"{\"id\":1}").unwrap();
+    proto.send(b"").unwrap();
+    proto.send(b"two\r\n\r\nlines").unwrap();
+    assert_eq!(proto.read_message().unwrap().unwrap(), b"{\"id\":1}");
+    assert_eq!(proto.read_message().unwrap().unwrap(), b"");
+    assert_eq!(proto.read_message().unwrap().unwrap(), b"two\r\n\r\nlines");
+    proto.close_stdin();
+    assert_eq!(proto.read_message().unwrap(), None);
+    assert_eq!(proto.send(b"x").unwrap_err().kind(), ErrorKind::BrokenPipe);
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn framed_protocol_headers() {
+    let (_p, mut proto) = framed(
+        "printf 'Content-Type: application/json\\r\\ncontent-length:  3\\r\\n\\r\\nabc'; \
+         printf 'Content-Length: 2\\n\\nde'",
+    );
+    assert_eq!(proto.read_message().unwrap().unwrap(), b"abc");
+    assert_eq!(proto.read_message().unwrap().unwrap(), b"de");
+    assert_eq!(proto.read_message().unwrap(), None);
+}
+
+#[test]
+fn framed_protocol_timeout() {
+    let (_p, proto) = framed("printf 'Content-Length: 4\\r\\n\\r\\nab'; sleep 0.5; printf cd");
+    let mut proto = proto.timeout(Duration::from_millis(100));
+    let err = proto.read_message().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    let mut proto = proto.timeout(Duration::from_secs(5));
+    assert_eq!(proto.read_message().unwrap().unwrap(), b"abcd");
+}
+
+#[test]
+fn framed_protocol_truncated() {
+    let (_p, mut proto) = framed("printf 'Content-Length: 10\\r\\n\\r\\nabc'");
+    let err = proto.read_message().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(proto.read_message().unwrap(), None);
+}
+
+#[test]
+fn framed_protocol_invalid_header() {
+    for script in &[
+        "printf 'Content-Type: text/plain\\r\\n\\r\\nabc'",
+        "printf 'Content-Length: many\\r\\n\\r\\nabc'",
+        "printf 'garbage\\r\\n\\r\\n'",
+    ] {
+        let (_p, mut proto) = framed(script);
+        let err = proto.read_message().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
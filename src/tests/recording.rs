@@ -0,0 +1,117 @@
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::tests::common::prog;
+use crate::{
+    Exec, ExitStatus, LineProtocol, RecordedEvent, RecordedStream, Recorder, Recording, Redirection,
+};
+
+fn record_session() -> Recording {
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "read x; echo \"got $x\"; echo warn >&2; exit 2"])
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let recorder = Recorder::attach(&mut p).unwrap();
+    let (out, err) = p.communicate(Some("foo\n")).unwrap();
+    assert_eq!(out.unwrap(), "got foo\n");
+    assert_eq!(err.unwrap(), "warn\n");
+    recorder.finish(&mut p).unwrap()
+}
+
+fn collect(recording: &Recording, stream: RecordedStream) -> Vec<u8> {
+    recording
+        .events
+        .iter()
+        .filter(|e| e.stream == stream)
+        .flat_map(|e| e.data.iter().copied())
+        .collect()
+}
+
+#[test]
+fn record() {
+    let recording = record_session();
+    assert_eq!(
+        recording.streams,
+        [
+            RecordedStream::Stdin,
+            RecordedStream::Stdout,
+            RecordedStream::Stderr
+        ]
+    );
+    assert_eq!(collect(&recording, RecordedStream::Stdin), b"foo\n");
+    assert_eq!(collect(&recording, RecordedStream::Stdout), b"got foo\n");
+    assert_eq!(collect(&recording, RecordedStream::Stderr), b"warn\n");
+    assert_eq!(recording.events[0].stream, RecordedStream::Stdin);
+    assert!(recording.events.windows(2).all(|w| w[0].time <= w[1].time));
+    assert_eq!(recording.exit_status, ExitStatus::Exited(2));
+}
+
+#[test]
+fn replay() {
+    let recording = record_session();
+    let mut p = recording.replay().unwrap();
+    assert_eq!(p.pid(), None);
+    let (out, err) = p.communicate(Some("foo\n")).unwrap();
+    assert_eq!(out.unwrap(), "got foo\n");
+    assert_eq!(err.unwrap(), "warn\n");
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(2));
+}
+
+#[test]
+fn replay_protocol() {
+    let mut p = Exec::cmd(prog("sh"))
+        .args(["-c", "while read x; do echo \"<$x>\"; done"])
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    let recorder = Recorder::attach(&mut p).unwrap();
+    let mut proto = LineProtocol::new(&mut p).unwrap();
+    assert_eq!(proto.request("a", |_| true).unwrap(), ["<a>"]);
+    assert_eq!(proto.request("b", |_| true).unwrap(), ["<b>"]);
+    proto.close_stdin();
+    let recording = recorder.finish(&mut p).unwrap();
+
+    let mut p = recording.replay().unwrap();
+    let mut proto = LineProtocol::new(&mut p).unwrap();
+    assert_eq!(proto.request("a", |_| true).unwrap(), ["<a>"]);
+    assert_eq!(proto.request("b", |_| true).unwrap(), ["<b>"]);
+    assert_eq!(proto.read_line().unwrap(), None);
+}
+
+#[test]
+fn replay_delays_and_mismatch() {
+    let event = |ms, stream, data: &[u8]| RecordedEvent {
+        time: Duration::from_millis(ms),
+        stream,
+        data: data.to_vec(),
+    };
+    let recording = Recording {
+        streams: vec![RecordedStream::Stdin, RecordedStream::Stdout],
+        events: vec![
+            event(0, RecordedStream::Stdout, b"prompt> "),
+            event(10, RecordedStream::Stdin, b"yes\n"),
+            event(300, RecordedStream::Stdout, b"ok\n"),
+        ],
+        exit_status: ExitStatus::Exited(0),
+    };
+
+    let mut p = recording.replay().unwrap();
+    let start = Instant::now();
+    p.stdin.as_ref().unwrap().write_all(b"yes\n").unwrap();
+    let mut out = String::new();
+    p.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+    assert_eq!(out, "prompt> ok\n");
+    assert!(start.elapsed() >= Duration::from_millis(250));
+
+    let mut p = recording.replay().unwrap();
+    p.stdin.as_ref().unwrap().write_all(b"no\n\n").unwrap();
+    let mut out = String::new();
+    p.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+    assert_eq!(out, "prompt> ");
+    let err = p.stdin.as_ref().unwrap().write_all(b"x").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+}
@@ -0,0 +1,62 @@
+use std::io::Read;
+use std::time::Duration;
+
+use crate::tests::common::prog;
+use crate::{select, Exec, ExitStatus, Popen, Redirection, Selected};
+
+fn spawn(script: &str) -> Popen {
+    Exec::cmd(prog("sh"))
+        .arg("-c")
+        .arg(script)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap()
+}
+
+#[test]
+fn select_first_output() {
+    let mut ps = vec![spawn("sleep 5"), spawn("sleep 0.1; echo fast; sleep 5")];
+    let selected = select(&mut ps, Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(selected, Some(Selected::Stdout(1)));
+    let mut buf = [0u8; 5];
+    ps[1].stdout.as_ref().unwrap().read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"fast\n");
+    for p in &mut ps {
+        p.kill().unwrap();
+    }
+}
+
+#[test]
+fn select_exit() {
+    let mut ps = vec![
+        Exec::cmd(prog("sleep")).arg("5").popen().unwrap(),
+        Exec::cmd(prog("sh"))
+            .arg("-c")
+            .arg("exit 4")
+            .popen()
+            .unwrap(),
+    ];
+    let selected = select(&mut ps, None).unwrap().unwrap();
+    assert_eq!(selected, Selected::Exited(1, ExitStatus::Exited(4)));
+    assert_eq!(selected.index(), 1);
+    ps[0].kill().unwrap();
+}
+
+#[test]
+fn select_timeout() {
+    let mut ps = vec![spawn("sleep 5")];
+    let selected = select(&mut ps, Some(Duration::from_millis(100))).unwrap();
+    assert_eq!(selected, None);
+    ps[0].kill().unwrap();
+}
+
+#[test]
+fn select_eof() {
+    let mut ps = vec![spawn("exec >/dev/null; sleep 5")];
+    let selected = select(&mut ps, Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(selected, Some(Selected::Stdout(0)));
+    let mut rest = vec![];
+    ps[0].stdout.take().unwrap().read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    ps[0].kill().unwrap();
+}
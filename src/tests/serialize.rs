@@ -0,0 +1,42 @@
+use crate::{CaptureData, ExitStatus, OutputEncoding};
+
+fn capture() -> CaptureData {
+    CaptureData {
+        stdout: b"foo\n".to_vec(),
+        stderr: b"\xffba".to_vec(),
+        exit_status: ExitStatus::Exited(1),
+        overflow: None,
+    }
+}
+
+#[test]
+fn serialize_lossy() {
+    let json = serde_json::to_value(capture()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "stdout": "foo\n",
+            "stderr": "\u{fffd}ba",
+            "exit_status": {"Exited": 1},
+        })
+    );
+}
+
+#[test]
+fn serialize_base64() {
+    let json = serde_json::to_value(capture().serialize_as(OutputEncoding::Base64)).unwrap();
+    assert_eq!(json["stdout"], "Zm9vCg==");
+    assert_eq!(json["stderr"], "/2Jh");
+}
+
+#[test]
+fn serialize_exit_status() {
+    assert_eq!(
+        serde_json::to_string(&ExitStatus::Signaled(9)).unwrap(),
+        r#"{"Signaled":9}"#
+    );
+    assert_eq!(
+        serde_json::to_string(&ExitStatus::Undetermined).unwrap(),
+        r#""Undetermined""#
+    );
+}
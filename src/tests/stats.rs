@@ -0,0 +1,33 @@
+use crate::tests::common::prog;
+use crate::{stats, Exec};
+
+// Statistics are process-wide and other tests run concurrently, so only
+// check that the counters grow at least by the expected amount.
+
+#[test]
+fn spawn_stats() {
+    let before = stats();
+    Exec::cmd(prog("true")).join().unwrap();
+    assert!(Exec::cmd("./nonexistent-command").join().is_err());
+    let after = stats();
+    assert!(after.spawns > before.spawns);
+    assert!(after.exec_failures > before.exec_failures);
+    assert!(after.spawn_latency.count() > before.spawn_latency.count());
+    assert_eq!(
+        after.spawn_latency.buckets().iter().sum::<u64>(),
+        after.spawn_latency.count()
+    );
+    assert!(after.spawn_latency.mean().is_some());
+}
+
+#[test]
+fn communicate_stats() {
+    let before = stats();
+    let c = Exec::cmd(prog("cat")).stdin("foo").capture().unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+    let after = stats();
+    // 3 bytes written, 3 bytes read
+    assert!(after.communicate_bytes >= before.communicate_bytes + 6);
+    assert!(after.communicate_time.count() > before.communicate_time.count());
+    assert!(after.communicate_throughput().is_some());
+}
@@ -1,9 +1,136 @@
+use crate::tests::common::prog;
 use crate::{ExitStatus, Popen, PopenConfig};
 
 #[test]
 fn err_terminate() {
-    let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
+    let mut p = Popen::create(&[prog("sleep"), "5"], PopenConfig::default()).unwrap();
     assert!(p.poll().is_none());
     p.terminate().unwrap();
     assert!(p.wait().unwrap() == ExitStatus::Exited(1));
 }
+
+#[test]
+#[cfg(not(feature = "no-path-search"))]
+fn exec_script() {
+    use crate::Exec;
+    use std::fs;
+    use tempfile::TempDir;
+
+    let tmpdir = TempDir::new().unwrap();
+    let script = tmpdir.path().join("script.bat");
+    fs::write(&script, "@echo off\r\necho %1\r\n").unwrap();
+    let c = Exec::script(&script).arg("foo").capture().unwrap();
+    assert_eq!(c.stdout_str().trim(), "foo");
+}
+
+#[test]
+fn wsl_output_decoding() {
+    use crate::windows::wsl_output_to_string;
+
+    assert_eq!(wsl_output_to_string(b"Ubuntu\n"), "Ubuntu\n");
+    assert_eq!(wsl_output_to_string(b"U\0b\0u\0n\0t\0u\0\n\0"), "Ubuntu\n");
+    assert_eq!(wsl_output_to_string(b"\xff\xfeO\0K\0"), "OK");
+    assert_eq!(wsl_output_to_string(b""), "");
+}
+
+#[test]
+fn make_pipe_message_mode() {
+    use crate::{make_pipe_with, PipeOptions};
+    use std::io::{Read, Write};
+
+    let (mut read, mut write) = make_pipe_with(&PipeOptions {
+        buffer_size: 4096,
+        message_mode: true,
+        ..Default::default()
+    })
+    .unwrap();
+    write.write_all(b"foo").unwrap();
+    write.write_all(b"barbaz").unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(read.read(&mut buf).unwrap(), 3);
+    assert_eq!(read.read(&mut buf).unwrap(), 6);
+    assert_eq!(&buf[..6], b"barbaz");
+}
+
+#[test]
+fn os_util_wait_for_object() {
+    use std::time::Duration;
+
+    let thread = std::thread::spawn(|| std::thread::sleep(Duration::from_millis(200)));
+    let wait = |timeout| crate::os_util::wait_for_object(&thread, timeout).unwrap();
+    assert!(!wait(Some(Duration::from_millis(10))));
+    assert!(wait(None));
+    thread.join().unwrap();
+}
+
+#[test]
+fn exec_console_utf8() {
+    use crate::windows::ExecExt;
+    use crate::Exec;
+
+    let orig_cp = crate::win32::GetConsoleOutputCP();
+    let c = Exec::cmd(prog("cmd"))
+        .args(["/c", "chcp"])
+        .console_utf8()
+        .capture()
+        .unwrap();
+    if orig_cp != 0 {
+        // only switched with a console, and restored afterwards
+        assert!(c.stdout_str().contains("65001"));
+        assert_eq!(crate::win32::GetConsoleOutputCP(), orig_cp);
+    }
+}
+
+#[test]
+fn communicate_channel_capacity() {
+    use crate::Redirection;
+
+    for &capacity in &[0, 1, 64] {
+        let mut p = Popen::create(
+            [
+                prog("sh"),
+                "-c",
+                "printf '%100000s' ''; printf '%1000s' '' >&2",
+            ],
+            PopenConfig {
+                stdout: Redirection::Pipe,
+                stderr: Redirection::Pipe,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let (out, err) = p
+            .communicate_start(None)
+            .channel_capacity(capacity)
+            .read()
+            .unwrap();
+        assert_eq!(out.unwrap().len(), 100_000);
+        assert_eq!(err.unwrap().len(), 1000);
+        assert!(p.wait().unwrap().success());
+    }
+}
+
+#[test]
+fn die_with_parent() {
+    use crate::Exec;
+
+    // the process runs normally, only inside the job
+    let c = Exec::cmd(prog("cmd"))
+        .args(["/c", "echo foo"])
+        .die_with_parent()
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str().trim(), "foo");
+}
+
+#[test]
+fn priority() {
+    use crate::{Exec, Priority};
+
+    let c = Exec::cmd(prog("cmd"))
+        .args(["/c", "echo foo"])
+        .priority(Priority::BelowNormal)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str().trim(), "foo");
+}
@@ -0,0 +1,429 @@
+// Minimal io_uring driver used by the communicate backend on Linux.  It
+// supports just what Communicator needs: one write to stdin and one read
+// from each of stdout and stderr in flight at a time, waiting with a
+// timeout, and cancellation.  The buffers are owned by the ring, so that
+// they outlive any operation the kernel might still be carrying out.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+
+use libc::{c_long, c_uint, c_void};
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+
+const IORING_OP_ASYNC_CANCEL: u8 = 14;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+const IORING_ENTER_GETEVENTS: c_uint = 1 << 0;
+const IORING_ENTER_EXT_ARG: c_uint = 1 << 3;
+
+const IORING_FEAT_EXT_ARG: u32 = 1 << 8;
+
+// Size of each of the buffers, and the maximum transfer of a single
+// operation.
+pub const BUF_SIZE: usize = 64 * 1024;
+
+// Slots of the operations, also used as their user_data.
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+const CANCEL: u64 = 1 << 32;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+struct GeteventsArg {
+    sigmask: u64,
+    sigmask_sz: u32,
+    pad: u32,
+    ts: u64,
+}
+
+struct Mmap {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize, offset: i64) -> Result<Mmap> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset as libc::off_t,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        (self.ptr as *mut u8).add(offset as usize) as *mut T
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+pub struct Ring {
+    // declared first so the ring is torn down before the mappings go away
+    fd: File,
+    params: Params,
+    sq: Mmap,
+    cq: Mmap,
+    sqes: Mmap,
+    // entries queued in the submission ring but not yet submitted
+    queued: u32,
+    busy: [bool; 3],
+    cancels: usize,
+    bufs: [Vec<u8>; 3],
+}
+
+// The ring is only used by the thread that owns the communicator.
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl fmt::Debug for Ring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ring")
+            .field("fd", &self.fd)
+            .field("busy", &self.busy)
+            .finish()
+    }
+}
+
+impl Ring {
+    // Set up a ring, failing if the kernel doesn't support io_uring or the
+    // features we need.
+    pub fn new() -> Result<Ring> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                8 as c_long,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let fd = unsafe { File::from_raw_fd(fd as RawFd) };
+        if params.features & IORING_FEAT_EXT_ARG == 0 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "io_uring lacks IORING_FEAT_EXT_ARG",
+            ));
+        }
+        let raw = fd.as_raw_fd();
+        let sq = Mmap::new(
+            raw,
+            params.sq_off.array as usize + params.sq_entries as usize * mem::size_of::<u32>(),
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq = Mmap::new(
+            raw,
+            params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>(),
+            IORING_OFF_CQ_RING,
+        )?;
+        let sqes = Mmap::new(
+            raw,
+            params.sq_entries as usize * mem::size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+        Ok(Ring {
+            fd,
+            params,
+            sq,
+            cq,
+            sqes,
+            queued: 0,
+            busy: [false; 3],
+            cancels: 0,
+            bufs: [vec![0; BUF_SIZE], vec![0; BUF_SIZE], vec![0; BUF_SIZE]],
+        })
+    }
+
+    fn push(&mut self, sqe: Sqe) -> Result<()> {
+        if self.queued == self.params.sq_entries {
+            self.enter(0, None)?;
+        }
+        unsafe {
+            let tail = &*self.sq.at::<AtomicU32>(self.params.sq_off.tail);
+            let mask = *self.sq.at::<u32>(self.params.sq_off.ring_mask);
+            let t = tail.load(Ordering::Relaxed);
+            let index = t & mask;
+            ptr::write(self.sqes.at::<Sqe>(0).add(index as usize), sqe);
+            *self
+                .sq
+                .at::<u32>(self.params.sq_off.array)
+                .add(index as usize) = index;
+            tail.store(t.wrapping_add(1), Ordering::Release);
+        }
+        self.queued += 1;
+        Ok(())
+    }
+
+    pub fn busy(&self, slot: usize) -> bool {
+        self.busy[slot]
+    }
+
+    pub fn idle(&self) -> bool {
+        self.busy.iter().all(|&b| !b) && self.cancels == 0
+    }
+
+    // Queue a write of (a prefix of) `data` to `fd`.
+    pub fn start_write(&mut self, fd: RawFd, data: &[u8]) -> Result<()> {
+        let len = data.len().min(BUF_SIZE);
+        self.bufs[STDIN][..len].copy_from_slice(&data[..len]);
+        self.start(IORING_OP_WRITE, STDIN, fd, len)
+    }
+
+    // Queue a read of up to `len` bytes from `fd` into the buffer of `slot`.
+    pub fn start_read(&mut self, slot: usize, fd: RawFd, len: usize) -> Result<()> {
+        self.start(IORING_OP_READ, slot, fd, len.min(BUF_SIZE))
+    }
+
+    fn start(&mut self, opcode: u8, slot: usize, fd: RawFd, len: usize) -> Result<()> {
+        assert!(!self.busy[slot]);
+        let addr = self.bufs[slot].as_mut_ptr() as u64;
+        self.push(Sqe {
+            opcode,
+            fd,
+            // -1 means the current file position, as with read() and write()
+            off: u64::MAX,
+            addr,
+            len: len as u32,
+            user_data: slot as u64,
+            ..Sqe::default()
+        })?;
+        self.busy[slot] = true;
+        Ok(())
+    }
+
+    // Return the data read by a completed read in `slot`.
+    pub fn data(&self, slot: usize, len: usize) -> &[u8] {
+        &self.bufs[slot][..len]
+    }
+
+    fn enter(&mut self, min_complete: u32, deadline: Option<Instant>) -> Result<bool> {
+        loop {
+            let mut flags = 0;
+            let mut ts = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            let mut arg = GeteventsArg {
+                sigmask: 0,
+                sigmask_sz: 0,
+                pad: 0,
+                ts: 0,
+            };
+            if min_complete != 0 {
+                flags |= IORING_ENTER_GETEVENTS | IORING_ENTER_EXT_ARG;
+                if let Some(deadline) = deadline {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    ts.tv_sec = timeout.as_secs() as libc::time_t;
+                    ts.tv_nsec = timeout.subsec_nanos() as _;
+                    arg.ts = &ts as *const libc::timespec as u64;
+                }
+            }
+            let (arg_ptr, arg_size) = if flags & IORING_ENTER_EXT_ARG != 0 {
+                (&arg as *const GeteventsArg, mem::size_of::<GeteventsArg>())
+            } else {
+                (ptr::null(), 0)
+            };
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd() as c_long,
+                    self.queued as c_long,
+                    min_complete as c_long,
+                    flags as c_long,
+                    arg_ptr,
+                    arg_size,
+                )
+            };
+            if ret >= 0 {
+                self.queued -= ret as u32;
+                return Ok(true);
+            }
+            let err = Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                // The kernel reports the number of submitted entries in
+                // preference to the timeout, so none were submitted.
+                Some(libc::ETIME) => return Ok(false),
+                _ => return Err(err),
+            }
+        }
+    }
+
+    fn cq_ready(&self) -> bool {
+        unsafe {
+            let head = (*self.cq.at::<AtomicU32>(self.params.cq_off.head)).load(Ordering::Relaxed);
+            let tail = (*self.cq.at::<AtomicU32>(self.params.cq_off.tail)).load(Ordering::Acquire);
+            head != tail
+        }
+    }
+
+    // Submit the queued operations and wait until at least one completes
+    // or `deadline` passes.  Returns false on timeout.
+    pub fn wait(&mut self, deadline: Option<Instant>) -> Result<bool> {
+        if self.cq_ready() {
+            if self.queued != 0 {
+                self.enter(0, None)?;
+            }
+            return Ok(true);
+        }
+        self.enter(1, deadline)
+    }
+
+    // Collect the completed operations as (slot, result) pairs.
+    pub fn reap(&mut self) -> Vec<(usize, i32)> {
+        let mut done = vec![];
+        unsafe {
+            let head = &*self.cq.at::<AtomicU32>(self.params.cq_off.head);
+            let tail = (*self.cq.at::<AtomicU32>(self.params.cq_off.tail)).load(Ordering::Acquire);
+            let mask = *self.cq.at::<u32>(self.params.cq_off.ring_mask);
+            let mut h = head.load(Ordering::Relaxed);
+            while h != tail {
+                let cqe = &*self
+                    .cq
+                    .at::<Cqe>(self.params.cq_off.cqes)
+                    .add((h & mask) as usize);
+                if cqe.user_data & CANCEL != 0 {
+                    self.cancels -= 1;
+                } else {
+                    let slot = cqe.user_data as usize;
+                    self.busy[slot] = false;
+                    done.push((slot, cqe.res));
+                }
+                h = h.wrapping_add(1);
+            }
+            head.store(h, Ordering::Release);
+        }
+        done
+    }
+
+    // Cancel the operations in flight and wait for them to finish,
+    // returning their results as `reap()` does.  An operation may complete
+    // successfully before it is cancelled.
+    pub fn cancel_all(&mut self) -> Result<Vec<(usize, i32)>> {
+        for slot in 0..self.busy.len() {
+            if self.busy[slot] {
+                self.push(Sqe {
+                    opcode: IORING_OP_ASYNC_CANCEL,
+                    fd: -1,
+                    addr: slot as u64,
+                    user_data: CANCEL | slot as u64,
+                    ..Sqe::default()
+                })?;
+                self.cancels += 1;
+            }
+        }
+        let mut done = vec![];
+        while !self.idle() {
+            self.wait(None)?;
+            done.extend(self.reap());
+        }
+        Ok(done)
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        if !self.idle() {
+            // The kernel may still write to the buffers after the ring is
+            // closed, so they must never be freed.
+            for buf in &mut self.bufs {
+                mem::forget(mem::take(buf));
+            }
+        }
+    }
+}
@@ -8,20 +8,31 @@ use std::iter;
 use std::mem;
 use std::os::windows::ffi::OsStrExt;
 use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::process;
 use std::ptr;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
 use winapi;
 use winapi::shared::minwindef::{BOOL, DWORD, LPVOID};
+use winapi::um::fileapi::{self, OPEN_EXISTING};
 use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
 use winapi::um::minwinbase::{LPSECURITY_ATTRIBUTES, SECURITY_ATTRIBUTES};
 use winapi::um::processthreadsapi::{CreateProcessW, PROCESS_INFORMATION, STARTUPINFOW};
-use winapi::um::winbase::CREATE_UNICODE_ENVIRONMENT;
-use winapi::um::winnt::PHANDLE;
-use winapi::um::{handleapi, namedpipeapi, processenv, processthreadsapi, synchapi};
+use winapi::um::winbase::{
+    CREATE_SUSPENDED, CREATE_UNICODE_ENVIRONMENT, FILE_FLAG_FIRST_PIPE_INSTANCE,
+    FILE_FLAG_OVERLAPPED, PIPE_ACCESS_INBOUND, PIPE_READMODE_BYTE, PIPE_READMODE_MESSAGE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_TYPE_MESSAGE, PIPE_WAIT,
+};
+use winapi::um::winnt::{GENERIC_WRITE, PHANDLE};
+use winapi::um::{handleapi, jobapi2, namedpipeapi, processenv, processthreadsapi, synchapi};
 
 pub use winapi::shared::winerror::{ERROR_ACCESS_DENIED, ERROR_BAD_PATHNAME};
+pub use winapi::um::winbase::{
+    ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, CREATE_NEW_PROCESS_GROUP,
+    DETACHED_PROCESS, HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS,
+};
 pub const STILL_ACTIVE: u32 = 259;
 
 use crate::os_common::StandardStream;
@@ -40,6 +51,25 @@ impl Drop for Handle {
     }
 }
 
+impl Handle {
+    pub fn try_clone(&self) -> Result<Handle> {
+        let mut raw_handle = ptr::null_mut();
+        unsafe {
+            let process = processthreadsapi::GetCurrentProcess();
+            check(handleapi::DuplicateHandle(
+                process,
+                self.as_raw_handle(),
+                process,
+                &mut raw_handle,
+                0,
+                0,
+                winapi::um::winnt::DUPLICATE_SAME_ACCESS,
+            ))?;
+        }
+        Ok(Handle(raw_handle))
+    }
+}
+
 impl AsRawHandle for Handle {
     fn as_raw_handle(&self) -> RawHandle {
         self.0
@@ -77,6 +107,10 @@ fn to_nullterm(s: &OsStr) -> Vec<u16> {
 }
 
 pub fn CreatePipe(inherit_handle: bool) -> Result<(File, File)> {
+    CreatePipeSized(inherit_handle, 0)
+}
+
+pub fn CreatePipeSized(inherit_handle: bool, size: u32) -> Result<(File, File)> {
     let mut attributes = SECURITY_ATTRIBUTES {
         nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as DWORD,
         lpSecurityDescriptor: ptr::null_mut(),
@@ -88,12 +122,92 @@ pub fn CreatePipe(inherit_handle: bool) -> Result<(File, File)> {
             &mut r as PHANDLE,
             &mut w as PHANDLE,
             &mut attributes as LPSECURITY_ATTRIBUTES,
-            0,
+            size,
         )
     })?;
     Ok(unsafe { (File::from_raw_handle(r), File::from_raw_handle(w)) })
 }
 
+// Anonymous pipes support neither message mode nor overlapped I/O, so
+// create a uniquely named pipe and open its other end, which is also what
+// CreatePipe does internally.
+pub fn CreateNamedPipePair(
+    inherit_handle: bool,
+    size: u32,
+    message_mode: bool,
+    overlapped_read: bool,
+    overlapped_write: bool,
+) -> Result<(File, File)> {
+    static PIPE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = to_nullterm(OsStr::new(&format!(
+        r"\\.\pipe\subprocess.{}.{}",
+        process::id(),
+        PIPE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )));
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as DWORD,
+        lpSecurityDescriptor: ptr::null_mut(),
+        bInheritHandle: inherit_handle as BOOL,
+    };
+    let mut open_mode = PIPE_ACCESS_INBOUND | FILE_FLAG_FIRST_PIPE_INSTANCE;
+    if overlapped_read {
+        open_mode |= FILE_FLAG_OVERLAPPED;
+    }
+    let pipe_mode = if message_mode {
+        PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE
+    } else {
+        PIPE_TYPE_BYTE | PIPE_READMODE_BYTE
+    } | PIPE_WAIT
+        | PIPE_REJECT_REMOTE_CLIENTS;
+    let read = check_handle(unsafe {
+        namedpipeapi::CreateNamedPipeW(
+            name.as_ptr(),
+            open_mode,
+            pipe_mode,
+            1,
+            size,
+            size,
+            0,
+            &mut attributes as LPSECURITY_ATTRIBUTES,
+        )
+    })?;
+    let read = unsafe { File::from_raw_handle(read) };
+    let write = check_handle(unsafe {
+        fileapi::CreateFileW(
+            name.as_ptr(),
+            GENERIC_WRITE,
+            0,
+            &mut attributes as LPSECURITY_ATTRIBUTES,
+            OPEN_EXISTING,
+            if overlapped_write {
+                FILE_FLAG_OVERLAPPED
+            } else {
+                0
+            },
+            ptr::null_mut(),
+        )
+    })?;
+    let write = unsafe { File::from_raw_handle(write) };
+    Ok((read, write))
+}
+
+// Return the number of bytes that can be read from the pipe without
+// blocking.
+pub fn PeekNamedPipe(handle: &File) -> Result<u32> {
+    let mut avail = 0u32;
+    check(unsafe {
+        namedpipeapi::PeekNamedPipe(
+            handle.as_raw_handle(),
+            ptr::null_mut(),
+            0,
+            ptr::null_mut(),
+            &mut avail,
+            ptr::null_mut(),
+        )
+    })?;
+    Ok(avail)
+}
+
 pub fn SetHandleInformation(handle: &File, dwMask: u32, dwFlags: u32) -> Result<()> {
     check(unsafe { handleapi::SetHandleInformation(handle.as_raw_handle(), dwMask, dwFlags) })?;
     Ok(())
@@ -110,6 +224,7 @@ pub fn CreateProcess(
     stdout: Option<RawHandle>,
     stderr: Option<RawHandle>,
     sinfo_flags: u32,
+    job: Option<&Handle>,
 ) -> Result<(Handle, u64)> {
     let mut sinfo: STARTUPINFOW = unsafe { mem::zeroed() };
     sinfo.cb = mem::size_of::<STARTUPINFOW>() as DWORD;
@@ -126,6 +241,12 @@ pub fn CreateProcess(
         .unwrap_or(ptr::null()) as LPVOID;
     let cwd = cwd.map(to_nullterm);
     creation_flags |= CREATE_UNICODE_ENVIRONMENT;
+    // A process is assigned to the job while suspended, so that the
+    // processes it starts also end up in the job.
+    let resume = job.is_some() && creation_flags & CREATE_SUSPENDED == 0;
+    if job.is_some() {
+        creation_flags |= CREATE_SUSPENDED;
+    }
     check(unsafe {
         CreateProcessW(
             wc_appname
@@ -143,13 +264,52 @@ pub fn CreateProcess(
             &mut pinfo,
         )
     })?;
-    unsafe {
-        drop(Handle::from_raw_handle(pinfo.hThread));
-        Ok((
+    let (process, thread) = unsafe {
+        (
             Handle::from_raw_handle(pinfo.hProcess),
-            pinfo.dwProcessId as u64,
-        ))
+            Handle::from_raw_handle(pinfo.hThread),
+        )
+    };
+    if let Some(job) = job {
+        let assigned = check(unsafe {
+            jobapi2::AssignProcessToJobObject(job.as_raw_handle(), process.as_raw_handle())
+        });
+        if let Err(e) = assigned {
+            TerminateProcess(&process, 1).ok();
+            return Err(e);
+        }
+    }
+    if resume && unsafe { processthreadsapi::ResumeThread(thread.as_raw_handle()) } == DWORD::MAX {
+        let e = Error::last_os_error();
+        TerminateProcess(&process, 1).ok();
+        return Err(e);
     }
+    Ok((process, pinfo.dwProcessId as u64))
+}
+
+// Create a job object that kills the processes assigned to it when its
+// last handle is closed.
+pub fn CreateKillOnCloseJob() -> Result<Handle> {
+    use winapi::um::winnt::{
+        JobObjectExtendedLimitInformation, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    };
+    let raw_handle = unsafe { jobapi2::CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+    if raw_handle.is_null() {
+        return Err(Error::last_os_error());
+    }
+    let job = unsafe { Handle::from_raw_handle(raw_handle) };
+    let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { mem::zeroed() };
+    info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+    check(unsafe {
+        jobapi2::SetInformationJobObject(
+            job.as_raw_handle(),
+            JobObjectExtendedLimitInformation,
+            &mut info as *mut _ as LPVOID,
+            mem::size_of_val(&info) as DWORD,
+        )
+    })?;
+    Ok(job)
 }
 
 pub enum WaitEvent {
@@ -158,7 +318,10 @@ pub enum WaitEvent {
     TIMEOUT,
 }
 
-pub fn WaitForSingleObject(handle: &Handle, mut timeout: Option<Duration>) -> Result<WaitEvent> {
+pub fn WaitForSingleObject(
+    handle: &impl AsRawHandle,
+    mut timeout: Option<Duration>,
+) -> Result<WaitEvent> {
     use winapi::shared::winerror::WAIT_TIMEOUT;
     use winapi::um::winbase::{INFINITE, WAIT_ABANDONED, WAIT_FAILED, WAIT_OBJECT_0};
     let deadline = timeout.map(|timeout| Instant::now() + timeout);
@@ -198,10 +361,58 @@ pub fn WaitForSingleObject(handle: &Handle, mut timeout: Option<Duration>) -> Re
     } else if result == WAIT_FAILED {
         Err(Error::last_os_error())
     } else {
-        panic!(format!("WaitForSingleObject returned {}", result));
+        panic!("WaitForSingleObject returned {}", result);
     }
 }
 
+// Callback invoked on a thread pool thread once the awaited object is
+// signaled, receiving the context pointer given at registration.
+#[cfg(any(feature = "async", feature = "async-smol"))]
+pub type WaitCallback = unsafe extern "system" fn(context: *mut std::ffi::c_void, timed_out: u8);
+
+// Wait registered with RegisterWaitForSingleObject.  Dropping it cancels
+// the wait, and blocks until a running callback has returned, so that the
+// callback's context can be freed afterwards.
+#[cfg(any(feature = "async", feature = "async-smol"))]
+#[derive(Debug)]
+pub struct RegisteredWait(RawHandle);
+
+#[cfg(any(feature = "async", feature = "async-smol"))]
+unsafe impl Send for RegisteredWait {}
+#[cfg(any(feature = "async", feature = "async-smol"))]
+unsafe impl Sync for RegisteredWait {}
+
+#[cfg(any(feature = "async", feature = "async-smol"))]
+impl Drop for RegisteredWait {
+    fn drop(&mut self) {
+        unsafe {
+            winapi::um::threadpoollegacyapiset::UnregisterWaitEx(self.0, INVALID_HANDLE_VALUE);
+        }
+    }
+}
+
+// Call `callback` once `handle` is signaled.  `context` must stay valid
+// until the returned RegisteredWait is dropped.
+#[cfg(any(feature = "async", feature = "async-smol"))]
+pub unsafe fn RegisterWaitForSingleObject(
+    handle: &Handle,
+    callback: WaitCallback,
+    context: *mut std::ffi::c_void,
+) -> Result<RegisteredWait> {
+    use winapi::um::winbase::INFINITE;
+    use winapi::um::winnt::WT_EXECUTEONLYONCE;
+    let mut wait = ptr::null_mut();
+    check(winapi::um::winbase::RegisterWaitForSingleObject(
+        &mut wait,
+        handle.as_raw_handle(),
+        Some(callback),
+        context,
+        INFINITE,
+        WT_EXECUTEONLYONCE,
+    ))?;
+    Ok(RegisteredWait(wait))
+}
+
 pub fn GetExitCodeProcess(handle: &Handle) -> Result<u32> {
     let mut exit_code = 0u32;
     check(unsafe {
@@ -214,6 +425,117 @@ pub fn TerminateProcess(handle: &Handle, exit_code: u32) -> Result<()> {
     check(unsafe { processthreadsapi::TerminateProcess(handle.as_raw_handle(), exit_code) })
 }
 
+pub use winapi::um::winnt::{PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE};
+
+pub fn OpenProcess(access: DWORD, pid: u32) -> Result<Handle> {
+    let raw_handle = unsafe { processthreadsapi::OpenProcess(access, 0, pid) };
+    if raw_handle.is_null() {
+        return Err(Error::last_os_error());
+    }
+    Ok(unsafe { Handle::from_raw_handle(raw_handle) })
+}
+
+pub fn TerminateProcessId(pid: u32, exit_code: u32) -> Result<()> {
+    TerminateProcess(&OpenProcess(PROCESS_TERMINATE, pid)?, exit_code)
+}
+
+// Return the creation time of the process as a FILETIME value, the
+// number of 100-nanosecond intervals since January 1, 1601.
+pub fn GetProcessCreationTime(handle: &Handle) -> Result<u64> {
+    use winapi::shared::minwindef::FILETIME;
+    let mut creation: FILETIME = unsafe { mem::zeroed() };
+    let mut exit: FILETIME = unsafe { mem::zeroed() };
+    let mut kernel: FILETIME = unsafe { mem::zeroed() };
+    let mut user: FILETIME = unsafe { mem::zeroed() };
+    check(unsafe {
+        processthreadsapi::GetProcessTimes(
+            handle.as_raw_handle(),
+            &mut creation,
+            &mut exit,
+            &mut kernel,
+            &mut user,
+        )
+    })?;
+    Ok((creation.dwHighDateTime as u64) << 32 | creation.dwLowDateTime as u64)
+}
+
+// Return the ids of the running processes, each paired with the id of its
+// parent.  Note that the parent can have exited, and its id been reused,
+// which GetProcessCreationTime() can detect.
+pub fn ProcessParents() -> Result<Vec<(u32, u32)>> {
+    use winapi::um::tlhelp32::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+    let snapshot = check_handle(unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) })?;
+    let snapshot = unsafe { Handle::from_raw_handle(snapshot) };
+    let mut entry: PROCESSENTRY32W = unsafe { mem::zeroed() };
+    entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as DWORD;
+    let mut processes = vec![];
+    let mut more = unsafe { Process32FirstW(snapshot.as_raw_handle(), &mut entry) };
+    while more != 0 {
+        processes.push((entry.th32ProcessID, entry.th32ParentProcessID));
+        more = unsafe { Process32NextW(snapshot.as_raw_handle(), &mut entry) };
+    }
+    Ok(processes)
+}
+
+#[cfg(all(feature = "no-path-search", not(feature = "no-shell")))]
+pub fn GetSystemDirectory() -> Result<std::path::PathBuf> {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStringExt;
+    let mut buf = vec![0u16; winapi::shared::minwindef::MAX_PATH];
+    loop {
+        let len = unsafe {
+            winapi::um::sysinfoapi::GetSystemDirectoryW(buf.as_mut_ptr(), buf.len() as u32)
+        } as usize;
+        if len == 0 {
+            return Err(Error::last_os_error());
+        }
+        if len < buf.len() {
+            buf.truncate(len);
+            return Ok(OsString::from_wide(&buf).into());
+        }
+        // buffer too small, len is the required size
+        buf.resize(len, 0);
+    }
+}
+
+pub const CP_UTF8: u32 = 65001;
+
+// Return 0 if the process has no console.
+pub fn GetConsoleCP() -> u32 {
+    unsafe { winapi::um::consoleapi::GetConsoleCP() }
+}
+
+pub fn GetConsoleOutputCP() -> u32 {
+    unsafe { winapi::um::consoleapi::GetConsoleOutputCP() }
+}
+
+pub fn SetConsoleCP(code_page: u32) -> Result<()> {
+    check(unsafe { winapi::um::wincon::SetConsoleCP(code_page) })
+}
+
+pub fn SetConsoleOutputCP(code_page: u32) -> Result<()> {
+    check(unsafe { winapi::um::wincon::SetConsoleOutputCP(code_page) })
+}
+
+pub use winapi::um::wincon::CTRL_BREAK_EVENT;
+#[cfg(feature = "ctrlc-guard")]
+pub use winapi::um::wincon::CTRL_C_EVENT;
+
+pub fn GenerateConsoleCtrlEvent(event: u32, process_group: u32) -> Result<()> {
+    check(unsafe { winapi::um::wincon::GenerateConsoleCtrlEvent(event, process_group) })
+}
+
+#[cfg(feature = "ctrlc-guard")]
+pub type HandlerRoutine = unsafe extern "system" fn(DWORD) -> BOOL;
+
+#[cfg(feature = "ctrlc-guard")]
+pub fn SetConsoleCtrlHandler(handler: HandlerRoutine, add: bool) -> Result<()> {
+    check(unsafe { winapi::um::consoleapi::SetConsoleCtrlHandler(Some(handler), add as BOOL) })
+}
+
 unsafe fn GetStdHandle(which: StandardStream) -> Result<RawHandle> {
     // private/unsafe because the raw handle it returns must be
     // duplicated or leaked before converting to an owned Handle.
@@ -24,7 +24,7 @@ fn escape_args() {
         "本", "❤", "☃",
     ] {
         let mut p = Popen::create(
-            &[just_echo_path().as_ref(), arg],
+            [just_echo_path().as_ref(), arg],
             PopenConfig {
                 stdout: Redirection::Pipe,
                 ..Default::default()